@@ -0,0 +1,8 @@
+#![no_main]
+
+use feboy_fuzz::cpu;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|code: &[u8]| {
+    cpu(code);
+});
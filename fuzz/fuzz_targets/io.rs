@@ -0,0 +1,8 @@
+#![no_main]
+
+use feboy_fuzz::io;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|writes: &[u8]| {
+    io(writes);
+});
@@ -0,0 +1,64 @@
+use feboy::cartridge::LoadOptions;
+use feboy::gameboy::{step, Gameboy};
+use feboy::memory_map::MemoryMap;
+use feboy::register::WordRegister::ProgramCounter;
+
+const CODE: u16 = 0xC000;
+const MAX_CODE: usize = 0x1000;
+const STEPS: usize = 1000;
+const STEPS_PER_WRITE: usize = 64;
+
+// Every input gets a core of its own on a cartridge full of NOPs, so nothing carries over between them.
+fn gameboy() -> Gameboy {
+    let rom = vec![0; 0x8000];
+    Gameboy::new(MemoryMap::new(&rom, &"fuzz".to_owned(), &LoadOptions::default()).unwrap())
+}
+
+// PC and SP can't leave their range by construction, and fuzz builds turn on overflow checks, so anything
+// that would wrap them wrongly panics on its own. What's left to check is that time keeps moving.
+fn run(gameboy: &mut Gameboy, steps: usize) {
+    for _ in 0..steps {
+        let before = gameboy.clock().cycles;
+        match step(gameboy) {
+            Ok(cycles) => assert!(cycles > 0, "step took no time"),
+            // Undefined opcodes are reported rather than executed, which is a fine place to stop.
+            Err(_) => return,
+        }
+        assert!(gameboy.clock().cycles > before, "clock stood still");
+    }
+}
+
+// Random instruction streams, run from WRAM so they can also rewrite themselves.
+pub fn cpu(code: &[u8]) {
+    let mut gameboy = gameboy();
+    let len = code.len().min(MAX_CODE);
+    gameboy.mem.wram[..len].copy_from_slice(&code[..len]);
+    gameboy.reg.pc = ProgramCounter(CODE);
+    run(&mut gameboy, STEPS);
+}
+
+// Pairs of IO register (0xFF00 + the first byte) and value, written one at a time while the cartridge's
+// NOPs keep the rest of the hardware running.
+pub fn io(writes: &[u8]) {
+    let mut gameboy = gameboy();
+    for write in writes.chunks_exact(2) {
+        gameboy.mem.write(write[0], write[1]);
+        // The write took a machine cycle outside of step(), which would count it against the next
+        // instruction.
+        gameboy.mem.cycles = 0;
+        run(&mut gameboy, STEPS_PER_WRITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu, io};
+
+    #[test]
+    fn targets_run_short_inputs() {
+        // LCDC on, a timer start, then IE.
+        io(&[0x40, 0x91, 0x07, 0x05, 0xFF, 0x1F]);
+        // LD A, 5; INC A; JR -3
+        cpu(&[0x3E, 0x05, 0x3C, 0x18, 0xFD]);
+    }
+}
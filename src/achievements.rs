@@ -0,0 +1,498 @@
+use crate::memory_map::MemoryMap;
+use serde_json::Value;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+const API_URL: &str = "https://retroachievements.org/dorequest.php";
+const USER_AGENT: &str = concat!("feboy/", env!("CARGO_PKG_VERSION"));
+const CORE_SET: u64 = 3;
+
+#[derive(Clone, Default)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Size {
+    Bit(u8),
+    Lower,
+    Upper,
+    BitCount,
+    Byte,
+    Word,
+    TByte,
+    DWord,
+}
+
+impl Size {
+    fn read(self, peek: &dyn Fn(usize) -> u8, address: usize) -> u32 {
+        let byte = |offset: usize| peek(address + offset) as u32;
+        match self {
+            Size::Bit(bit) => byte(0) >> bit & 1,
+            Size::Lower => byte(0) & 0x0F,
+            Size::Upper => byte(0) >> 4,
+            Size::BitCount => byte(0).count_ones(),
+            Size::Byte => byte(0),
+            Size::Word => byte(0) | byte(1) << 8,
+            Size::TByte => byte(0) | byte(1) << 8 | byte(2) << 16,
+            Size::DWord => byte(0) | byte(1) << 8 | byte(2) << 16 | byte(3) << 24,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum History {
+    Current,
+    Delta,
+    Prior,
+}
+
+// A memory reference remembers last frame's value (delta) and the last value it changed from (prior).
+#[derive(Clone, Copy, Debug)]
+struct Memory {
+    size: Size,
+    address: usize,
+    history: History,
+    current: u32,
+    delta: u32,
+    prior: u32,
+}
+
+impl Memory {
+    fn new(size: Size, address: usize, history: History) -> Self {
+        Self {
+            size,
+            address,
+            history,
+            current: 0,
+            delta: 0,
+            prior: 0,
+        }
+    }
+
+    fn update(&mut self, peek: &dyn Fn(usize) -> u8) {
+        let value = self.size.read(peek, self.address);
+        self.delta = self.current;
+        if value != self.current {
+            self.prior = self.current;
+        }
+        self.current = value;
+    }
+
+    fn value(&self) -> u32 {
+        match self.history {
+            History::Current => self.current,
+            History::Delta => self.delta,
+            History::Prior => self.prior,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Value(u32),
+    Memory(Memory),
+}
+
+impl Operand {
+    // 0xH1234 is a byte at $1234, d0xH1234 its value last frame, h1F and 31 are constants.
+    fn parse(text: &str) -> Option<Operand> {
+        let (history, text) = match text.as_bytes().first()? {
+            b'd' => (History::Delta, &text[1..]),
+            b'p' => (History::Prior, &text[1..]),
+            _ => (History::Current, text),
+        };
+        let memory = match text.strip_prefix("0x") {
+            Some(memory) => memory,
+            None if history == History::Current => {
+                return match text.strip_prefix('h') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => text.parse().ok(),
+                }
+                .map(Operand::Value);
+            }
+            None => return None,
+        };
+        let size = match memory.chars().next()?.to_ascii_uppercase() {
+            'H' => Size::Byte,
+            'W' => Size::TByte,
+            'X' => Size::DWord,
+            'L' => Size::Lower,
+            'U' => Size::Upper,
+            'K' => Size::BitCount,
+            bit @ 'M'..='T' => Size::Bit(bit as u8 - b'M'),
+            ' ' => Size::Word,
+            _ => {
+                return usize::from_str_radix(memory, 16)
+                    .ok()
+                    .map(|address| Operand::Memory(Memory::new(Size::Word, address, history)))
+            }
+        };
+        let address = usize::from_str_radix(&memory[1..], 16).ok()?;
+        Some(Operand::Memory(Memory::new(size, address, history)))
+    }
+
+    fn update(&mut self, peek: &dyn Fn(usize) -> u8) {
+        if let Operand::Memory(memory) = self {
+            memory.update(peek);
+        }
+    }
+
+    fn value(&self) -> u32 {
+        match self {
+            Operand::Value(value) => *value,
+            Operand::Memory(memory) => memory.value(),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Flag {
+    None,
+    ResetIf,
+    PauseIf,
+}
+
+#[derive(Clone, Debug)]
+struct Condition {
+    flag: Flag,
+    left: Operand,
+    comparison: &'static str,
+    right: Operand,
+    target_hits: u32,
+    hits: u32,
+}
+
+impl Condition {
+    // R:0xH1234=5.10. resets the trigger once the byte at $1234 has been 5 on ten frames.
+    fn parse(text: &str) -> Option<Condition> {
+        let (flag, text) = match text.get(..2) {
+            Some("R:") => (Flag::ResetIf, &text[2..]),
+            Some("P:") => (Flag::PauseIf, &text[2..]),
+            _ if text.get(1..2) == Some(":") => return None,
+            _ => (Flag::None, text),
+        };
+        let (text, target_hits) = match text.strip_suffix('.') {
+            Some(text) => {
+                let split = text.rfind('.')?;
+                (&text[..split], text[split + 1..].parse().ok()?)
+            }
+            None => (text, 0),
+        };
+        let start = text.find(['=', '!', '<', '>'])?;
+        let comparison = ["!=", "<=", ">=", "=", "<", ">"]
+            .iter()
+            .copied()
+            .find(|comparison| text[start..].starts_with(comparison))?;
+        Some(Condition {
+            flag,
+            left: Operand::parse(&text[..start])?,
+            comparison,
+            right: Operand::parse(&text[start + comparison.len()..])?,
+            target_hits,
+            hits: 0,
+        })
+    }
+
+    fn compare(&self) -> bool {
+        let (left, right) = (self.left.value(), self.right.value());
+        match self.comparison {
+            "=" => left == right,
+            "!=" => left != right,
+            "<" => left < right,
+            "<=" => left <= right,
+            ">" => left > right,
+            _ => left >= right,
+        }
+    }
+
+    // With a hit target the condition latches once it has been true on that many frames.
+    fn test(&mut self) -> bool {
+        if self.target_hits == 0 {
+            return self.compare();
+        }
+        if self.hits < self.target_hits && self.compare() {
+            self.hits += 1;
+        }
+        self.hits == self.target_hits
+    }
+}
+
+// A core group of conditions plus alternates separated by S; the trigger fires when the core group and
+// any one alternate are true on the same frame.
+#[derive(Clone, Debug)]
+struct Trigger {
+    groups: Vec<Vec<Condition>>,
+}
+
+impl Trigger {
+    fn parse(text: &str) -> Option<Trigger> {
+        let mut groups = vec![];
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            // 0xS is a bit size, not a separator.
+            if c == 'S' && !text[..i].ends_with("0x") {
+                groups.push(&text[start..i]);
+                start = i + 1;
+            }
+        }
+        groups.push(&text[start..]);
+        let groups = groups
+            .iter()
+            .map(|group| {
+                group
+                    .split('_')
+                    .filter(|condition| !condition.is_empty())
+                    .map(Condition::parse)
+                    .collect::<Option<Vec<Condition>>>()
+            })
+            .collect::<Option<Vec<Vec<Condition>>>>()?;
+        Some(Trigger { groups })
+    }
+
+    fn test(&mut self, peek: &dyn Fn(usize) -> u8) -> bool {
+        let conditions = self.groups.iter_mut().flatten();
+        conditions.for_each(|condition| {
+            condition.left.update(peek);
+            condition.right.update(peek);
+        });
+        let mut reset = false;
+        let mut results = vec![];
+        for group in self.groups.iter_mut() {
+            // Every pause condition is tested so each one keeps counting its hits.
+            let mut paused = false;
+            for condition in group.iter_mut().filter(|c| c.flag == Flag::PauseIf) {
+                paused |= condition.test();
+            }
+            if paused {
+                results.push(false);
+                continue;
+            }
+            let mut result = true;
+            for condition in group.iter_mut() {
+                match condition.flag {
+                    Flag::None => result &= condition.test(),
+                    Flag::ResetIf => reset |= condition.test(),
+                    Flag::PauseIf => (),
+                }
+            }
+            results.push(result);
+        }
+        if reset {
+            self.groups
+                .iter_mut()
+                .flatten()
+                .for_each(|condition| condition.hits = 0);
+            return false;
+        }
+        results[0] && (results.len() == 1 || results[1..].contains(&true))
+    }
+}
+
+struct Achievement {
+    id: u64,
+    title: String,
+    trigger: Trigger,
+    armed: bool,
+}
+
+struct Session {
+    username: String,
+    token: String,
+}
+
+type Loaded = Result<(Session, Vec<Achievement>), String>;
+
+// Logs in and downloads the game's achievement set in the background, then checks every trigger once per
+// frame. Unlocks are submitted as softcore, so save states and rewind stay available.
+pub struct Achievements {
+    session: Option<Session>,
+    achievements: Vec<Achievement>,
+    loading: Option<Receiver<Loaded>>,
+}
+
+impl Achievements {
+    pub fn connect(credentials: &Credentials, rom: &[u8]) -> Option<Achievements> {
+        if credentials.username.is_empty() {
+            return None;
+        }
+        let credentials = credentials.clone();
+        let hash = format!("{:x}", md5::compute(rom));
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let _ = sender.send(load(&credentials, &hash));
+        });
+        Some(Achievements {
+            session: None,
+            achievements: vec![],
+            loading: Some(receiver),
+        })
+    }
+
+    // Returns the notifications to show this frame.
+    pub fn update(&mut self, mem: &MemoryMap) -> Vec<String> {
+        let mut messages = vec![];
+        if let Some(loaded) = self.loading.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.loading = None;
+            match loaded {
+                Ok((session, achievements)) => {
+                    messages.push(format!("{} achievements to unlock", achievements.len()));
+                    self.session = Some(session);
+                    self.achievements = achievements;
+                }
+                Err(e) => println!("RetroAchievements unavailable: {}", e),
+            }
+        }
+        let session = match &self.session {
+            Some(session) => session,
+            None => return messages,
+        };
+        let peek = |address| mem.peek(address);
+        self.achievements.retain_mut(|achievement| {
+            let triggered = achievement.trigger.test(&peek);
+            // Achievements already true when the set loads have to turn false once before they can unlock.
+            if !achievement.armed {
+                achievement.armed = !triggered;
+                return true;
+            }
+            if triggered {
+                messages.push(format!("Achievement unlocked: {}", achievement.title));
+                award(session, achievement.id);
+            }
+            !triggered
+        });
+        messages
+    }
+}
+
+fn request(params: &[(&str, &str)]) -> Result<Value, String> {
+    let mut request = ureq::get(API_URL).set("User-Agent", USER_AGENT);
+    for (key, value) in params {
+        request = request.query(key, value);
+    }
+    parse(request.call())
+}
+
+// The login goes in a form body so the password stays out of URLs and server logs.
+fn post(params: &[(&str, &str)]) -> Result<Value, String> {
+    parse(
+        ureq::post(API_URL)
+            .set("User-Agent", USER_AGENT)
+            .send_form(params),
+    )
+}
+
+fn parse(response: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    let body = response
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string())?;
+    let response: Value = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+    if response["Success"].as_bool() == Some(false) {
+        return Err(response["Error"]
+            .as_str()
+            .unwrap_or("request failed")
+            .to_owned());
+    }
+    Ok(response)
+}
+
+fn load(credentials: &Credentials, hash: &str) -> Loaded {
+    let login = post(&[
+        ("r", "login"),
+        ("u", credentials.username.as_str()),
+        ("p", credentials.password.as_str()),
+    ])?;
+    let session = Session {
+        username: credentials.username.clone(),
+        token: login["Token"].as_str().ok_or("no token")?.to_owned(),
+    };
+    let game = request(&[("r", "gameid"), ("m", hash)])?["GameID"]
+        .as_u64()
+        .filter(|id| *id != 0)
+        .ok_or("unknown ROM")?
+        .to_string();
+    let user = [
+        ("u", session.username.as_str()),
+        ("t", session.token.as_str()),
+    ];
+    let patch = request(&[&user[..], &[("r", "patch"), ("g", game.as_str())]].concat())?;
+    let unlocks = request(
+        &[
+            &user[..],
+            &[("r", "unlocks"), ("g", game.as_str()), ("h", "0")],
+        ]
+        .concat(),
+    )?;
+    let unlocked = unlocks["UserUnlocks"]
+        .as_array()
+        .map(|ids| ids.iter().filter_map(Value::as_u64).collect::<Vec<u64>>())
+        .unwrap_or_default();
+
+    let mut achievements = vec![];
+    for achievement in patch["PatchData"]["Achievements"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        let id = achievement["ID"].as_u64().unwrap_or_default();
+        let title = achievement["Title"].as_str().unwrap_or_default().to_owned();
+        if achievement["Flags"].as_u64() != Some(CORE_SET) || unlocked.contains(&id) {
+            continue;
+        }
+        match Trigger::parse(achievement["MemAddr"].as_str().unwrap_or_default()) {
+            Some(trigger) => achievements.push(Achievement {
+                id,
+                title,
+                trigger,
+                armed: false,
+            }),
+            None => println!("Skipping achievement {}: unsupported trigger", title),
+        }
+    }
+    Ok((session, achievements))
+}
+
+fn award(session: &Session, id: u64) {
+    let (username, token) = (session.username.clone(), session.token.clone());
+    thread::spawn(move || {
+        let id = id.to_string();
+        let params = [
+            ("r", "awardachievement"),
+            ("u", username.as_str()),
+            ("t", token.as_str()),
+        ];
+        if let Err(e) = request(&[&params[..], &[("a", id.as_str()), ("h", "0")]].concat()) {
+            println!("Failed to submit achievement {}: {}", id, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::achievements::Trigger;
+
+    #[test]
+    fn evaluates_hits_resets_and_alternates() {
+        let mut trigger =
+            Trigger::parse("0xH0000=1.3._R:0xH0001=1S0xS0002=1S0x 0003=h0102").unwrap();
+        let mut memory = [1, 0, 0, 0x02, 0x01];
+        let mut frame = |memory: &[u8; 5]| trigger.test(&|address| memory[address]);
+
+        assert!(!frame(&memory));
+        assert!(!frame(&memory));
+        assert!(frame(&memory));
+        memory[1] = 1;
+        assert!(!frame(&memory));
+        memory[1] = 0;
+        memory[3] = 0;
+        assert!(!frame(&memory));
+        assert!(!frame(&memory));
+        memory[2] = 0x40;
+        assert!(frame(&memory));
+
+        assert!(Trigger::parse("A:0xH0000=1_0xH0001=1").is_none());
+        assert!(Trigger::parse("d0xH0000>0xH0000").is_some());
+    }
+}
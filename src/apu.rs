@@ -0,0 +1,169 @@
+use crate::error::FeboyError;
+use crate::state::{StateReader, StateWriter};
+use core::ops::RangeInclusive;
+
+pub const SOUND_REGISTERS: RangeInclusive<usize> = 0xFF10..=0xFF3F;
+const BASE: usize = 0xFF10;
+const NR52: usize = 0xFF26;
+const POWER: u8 = 0x80;
+// Bits that always read back as 1, from NR10 up to the start of wave RAM. Unused addresses read all ones.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+// Each channel's NRx4, whose top bit triggers it, and the register holding its DAC enable.
+const CHANNELS: [(usize, usize); 4] = [
+    (0xFF14, 0xFF12),
+    (0xFF19, 0xFF17),
+    (0xFF1E, 0xFF1A),
+    (0xFF23, 0xFF21),
+];
+const WAVE_CHANNEL: usize = 2;
+
+// The sound registers as the CPU sees them. Nothing is synthesized, but games probe these to detect the
+// APU, so reads are masked like the hardware's and NR52 reports which channels were triggered. Without
+// length timers a channel only stops when its DAC or the APU is switched off.
+pub struct Apu {
+    // FF10 to FF3F, wave RAM included. NR52 only keeps its power bit.
+    registers: [u8; 0x30],
+    channels: u8,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        let mut registers = [0; 0x30];
+        registers[NR52 - BASE] = POWER;
+        Self {
+            registers,
+            channels: 0,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.memory("sound registers", 0xFF10, &self.registers);
+        state.u8("sound channels", self.channels);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        state.bytes(&mut self.registers)?;
+        self.channels = state.u8()?;
+        Ok(())
+    }
+
+    fn powered(&self) -> bool {
+        self.registers[NR52 - BASE] & POWER != 0
+    }
+
+    fn dac_enabled(&self, channel: usize) -> bool {
+        let dac = self.registers[CHANNELS[channel].1 - BASE];
+        match channel {
+            WAVE_CHANNEL => dac & 0x80 != 0,
+            _ => dac & 0xF8 != 0,
+        }
+    }
+
+    // The value last written, without the read masks, for replaying the register state elsewhere.
+    pub fn register(&self, address: usize) -> u8 {
+        self.registers[address - BASE]
+    }
+
+    pub fn read(&self, address: usize) -> Option<u8> {
+        match address {
+            NR52 => Some(self.registers[NR52 - BASE] | READ_MASKS[NR52 - BASE] | self.channels),
+            0xFF10..=0xFF2F => Some(self.registers[address - BASE] | READ_MASKS[address - BASE]),
+            // A DMG only lets the CPU at wave RAM in the cycle the playing channel reads it, which isn't
+            // tracked, so it's locked out for as long as the channel is on.
+            0xFF30..=0xFF3F if self.channels & (1 << WAVE_CHANNEL) != 0 => Some(0xFF),
+            0xFF30..=0xFF3F => Some(self.registers[address - BASE]),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) -> bool {
+        match address {
+            NR52 => {
+                // Powering off clears every register except wave RAM.
+                if value & POWER == 0 {
+                    self.registers[..NR52 - BASE].fill(0);
+                    self.channels = 0;
+                }
+                self.registers[NR52 - BASE] = value & POWER;
+            }
+            0xFF30..=0xFF3F if self.channels & (1 << WAVE_CHANNEL) != 0 => (),
+            0xFF30..=0xFF3F => self.registers[address - BASE] = value,
+            // While off, a DMG still takes writes to the length timers, but not the duty cycles beside them.
+            0xFF10..=0xFF2F if !self.powered() => match address {
+                0xFF11 | 0xFF16 => self.registers[address - BASE] = value & 0x3F,
+                0xFF1B | 0xFF20 => self.registers[address - BASE] = value,
+                _ => (),
+            },
+            0xFF10..=0xFF2F => {
+                self.registers[address - BASE] = value;
+                for (channel, (trigger, dac)) in CHANNELS.iter().enumerate() {
+                    if address == *trigger && value & 0x80 != 0 && self.dac_enabled(channel) {
+                        self.channels |= 1 << channel;
+                    }
+                    if address == *dac && !self.dac_enabled(channel) {
+                        self.channels &= !(1 << channel);
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apu::Apu;
+
+    #[test]
+    fn reads_back_through_the_register_masks() {
+        let mut apu = Apu::new();
+        for address in 0xFF10..=0xFF25 {
+            apu.write(address, 0x00);
+        }
+        let read = (0xFF10..=0xFF2F)
+            .map(|address| apu.read(address).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(&read[..5], &[0x80, 0x3F, 0x00, 0xFF, 0xBF]);
+        assert_eq!(&read[0x0A..0x0F], &[0x7F, 0xFF, 0x9F, 0xFF, 0xBF]);
+        assert_eq!(read[0x16], 0xF0);
+        assert!(read[0x17..].iter().all(|byte| *byte == 0xFF));
+
+        // Triggering a channel needs its DAC on.
+        apu.write(0xFF23, 0x80);
+        assert_eq!(apu.read(0xFF26), Some(0xF0));
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF23, 0x80);
+        assert_eq!(apu.read(0xFF26), Some(0xF8));
+
+        // Wave RAM is locked out while channel 3 plays.
+        apu.write(0xFF30, 0x12);
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1E, 0x80);
+        assert_eq!(apu.read(0xFF26), Some(0xFC));
+        assert_eq!(apu.read(0xFF30), Some(0xFF));
+        apu.write(0xFF1A, 0x00);
+        assert_eq!(apu.read(0xFF30), Some(0x12));
+
+        // Powering off clears the registers and ignores writes until it's back on.
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.read(0xFF26), Some(0x70));
+        assert_eq!(apu.read(0xFF24), Some(0x00));
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF11, 0xFF);
+        assert_eq!(apu.read(0xFF24), Some(0x00));
+        assert_eq!(apu.register(0xFF11), 0x3F);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF24, 0x77);
+        assert_eq!(apu.read(0xFF24), Some(0x77));
+        assert_eq!(apu.read(0xFF30), Some(0x12));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::error::FeboyError;
+use crate::prelude::*;
+
+const REGISTERS: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
+const PAIRS: [&str; 4] = ["bc", "de", "hl", "sp"];
+const STACK_PAIRS: [&str; 4] = ["bc", "de", "hl", "af"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const SHIFTS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const IMPLIED: [(&str, &[u8]); 15] = [
+    ("nop", &[0x00]),
+    ("stop", &[0x10, 0x00]),
+    ("halt", &[0x76]),
+    ("di", &[0xF3]),
+    ("ei", &[0xFB]),
+    ("rlca", &[0x07]),
+    ("rrca", &[0x0F]),
+    ("rla", &[0x17]),
+    ("rra", &[0x1F]),
+    ("daa", &[0x27]),
+    ("cpl", &[0x2F]),
+    ("scf", &[0x37]),
+    ("ccf", &[0x3F]),
+    ("ret", &[0xC9]),
+    ("reti", &[0xD9]),
+];
+const INDIRECT_LOADS: [(&str, &str, u8); 11] = [
+    ("(bc)", "a", 0x02),
+    ("(de)", "a", 0x12),
+    ("(hl+)", "a", 0x22),
+    ("(hl-)", "a", 0x32),
+    ("a", "(bc)", 0x0A),
+    ("a", "(de)", 0x1A),
+    ("a", "(hl+)", 0x2A),
+    ("a", "(hl-)", 0x3A),
+    ("(c)", "a", 0xE2),
+    ("a", "(c)", 0xF2),
+    ("sp", "hl", 0xF9),
+];
+
+// Assembles one or more instructions separated by `;` or newlines, e.g. "ld a, 5; ld (0xC000), a".
+// `origin` is the address the first instruction runs from, which relative jumps are measured against.
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, FeboyError> {
+    let mut code = vec![];
+    for line in source.split([';', '\n']) {
+        let address = origin.wrapping_add(code.len() as u16);
+        let bytes = instruction(line.trim(), address).map_err(|message| {
+            FeboyError::InvalidAssembly(format!("{}: {}", line.trim(), message))
+        })?;
+        code.extend(bytes);
+    }
+    Ok(code)
+}
+
+// Overwrites the ROM image at a file offset. Code past the first bank is assembled as if its bank were
+// mapped at 0x4000.
+pub fn patch_rom(rom: &mut [u8], offset: usize, source: &str) -> Result<(), FeboyError> {
+    let origin = if offset < 0x4000 {
+        offset
+    } else {
+        0x4000 + offset % 0x4000
+    };
+    let code = assemble(source, origin as u16)?;
+    match rom.get_mut(offset..offset + code.len()) {
+        Some(target) => target.copy_from_slice(&code),
+        None => {
+            return Err(FeboyError::InvalidAssembly(format!(
+                "patch at 0x{:X} runs past the end of the ROM",
+                offset
+            )))
+        }
+    }
+    Ok(())
+}
+
+// Accepts decimal, 0x or $ prefixed hex and % prefixed binary.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix('$'))
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix('%') {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn instruction(line: &str, address: u16) -> Result<Vec<u8>, String> {
+    if line.is_empty() {
+        return Ok(vec![]);
+    }
+    let line = line.to_lowercase();
+    let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+    let operands = operands
+        .split(',')
+        .map(normalize)
+        .filter(|operand| !operand.is_empty())
+        .collect::<Vec<_>>();
+    let ops = operands.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let code = match (mnemonic, ops.as_slice()) {
+        (mnemonic, []) => IMPLIED
+            .iter()
+            .find(|(name, _)| *name == mnemonic)
+            .map(|(_, code)| code.to_vec()),
+        ("ld", [dst, src]) => load(dst, src)?,
+        ("ldh", [dst, "a"]) => memory(dst)
+            .map(|n| high_page(n).map(|b| vec![0xE0, b]))
+            .transpose()?,
+        ("ldh", ["a", src]) => memory(src)
+            .map(|n| high_page(n).map(|b| vec![0xF0, b]))
+            .transpose()?,
+        ("inc", [op]) | ("dec", [op]) => {
+            let dec = mnemonic == "dec";
+            match (index(&REGISTERS, op), index(&PAIRS, op)) {
+                (Some(r), _) => Some(vec![0x04 | r << 3 | dec as u8]),
+                (_, Some(p)) => Some(vec![0x03 | p << 4 | (dec as u8) << 3]),
+                _ => None,
+            }
+        }
+        ("add", ["hl", pair]) => index(&PAIRS, pair).map(|p| vec![0x09 | p << 4]),
+        ("add", ["sp", offset]) => number(offset)
+            .map(|n| signed(n).map(|b| vec![0xE8, b]))
+            .transpose()?,
+        (mnemonic, ["a", op]) | (mnemonic, [op]) if ALU.contains(&mnemonic) => {
+            let i = index(&ALU, mnemonic).unwrap();
+            match index(&REGISTERS, op) {
+                Some(r) => Some(vec![0x80 | i << 3 | r]),
+                None => number(op)
+                    .map(|n| byte(n).map(|b| vec![0xC6 | i << 3, b]))
+                    .transpose()?,
+            }
+        }
+        ("jp", ["hl"]) | ("jp", ["(hl)"]) => Some(vec![0xE9]),
+        ("jp", [target]) => number(target).map(|n| word(0xC3, n)).transpose()?,
+        ("jp", [cc, target]) => conditional(0xC2, cc, target)?,
+        ("call", [target]) => number(target).map(|n| word(0xCD, n)).transpose()?,
+        ("call", [cc, target]) => conditional(0xC4, cc, target)?,
+        ("jr", [target]) => relative(0x18, target, address)?,
+        ("jr", [cc, target]) => match index(&CONDITIONS, cc) {
+            Some(c) => relative(0x20 | c << 3, target, address)?,
+            None => None,
+        },
+        ("ret", [cc]) => index(&CONDITIONS, cc).map(|c| vec![0xC0 | c << 3]),
+        ("rst", [vector]) => match number(vector) {
+            Some(n) if n & !0x38 == 0 => Some(vec![0xC7 | n as u8]),
+            Some(_) => return Err(format!("invalid restart vector {}", vector)),
+            None => None,
+        },
+        ("push", [pair]) => index(&STACK_PAIRS, pair).map(|q| vec![0xC5 | q << 4]),
+        ("pop", [pair]) => index(&STACK_PAIRS, pair).map(|q| vec![0xC1 | q << 4]),
+        (mnemonic, [op]) if SHIFTS.contains(&mnemonic) => {
+            let i = index(&SHIFTS, mnemonic).unwrap();
+            index(&REGISTERS, op).map(|r| vec![0xCB, i << 3 | r])
+        }
+        ("bit", [bit, op]) | ("res", [bit, op]) | ("set", [bit, op]) => {
+            let base = match mnemonic {
+                "bit" => 0x40,
+                "res" => 0x80,
+                _ => 0xC0,
+            };
+            match (number(bit), index(&REGISTERS, op)) {
+                (Some(b), Some(r)) if (0..8).contains(&b) => {
+                    Some(vec![0xCB, base | (b as u8) << 3 | r])
+                }
+                (Some(_), Some(_)) => return Err(format!("invalid bit {}", bit)),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    code.ok_or_else(|| "unknown instruction".to_owned())
+}
+
+fn load(dst: &str, src: &str) -> Result<Option<Vec<u8>>, String> {
+    if let Some((_, _, opcode)) = INDIRECT_LOADS
+        .iter()
+        .find(|(d, s, _)| *d == dst && *s == src)
+    {
+        return Ok(Some(vec![*opcode]));
+    }
+    let code = match (index(&REGISTERS, dst), index(&REGISTERS, src)) {
+        (Some(6), Some(6)) => None,
+        (Some(d), Some(s)) => Some(vec![0x40 | d << 3 | s]),
+        (Some(d), None) => match (number(src), memory(src)) {
+            (Some(n), _) => Some(vec![0x06 | d << 3, byte(n)?]),
+            (_, Some(n)) if d == 7 => Some(word(0xFA, n)?),
+            _ => None,
+        },
+        (None, Some(7)) => memory(dst).map(|n| word(0xEA, n)).transpose()?,
+        _ => match (index(&PAIRS, dst), number(src)) {
+            (Some(p), Some(n)) => Some(word(0x01 | p << 4, n)?),
+            _ if dst == "hl" && src.starts_with("sp") => number(src[2..].trim_start_matches('+'))
+                .map(|n| signed(n).map(|b| vec![0xF8, b]))
+                .transpose()?,
+            _ if src == "sp" => memory(dst).map(|n| word(0x08, n)).transpose()?,
+            _ => None,
+        },
+    };
+    Ok(code)
+}
+
+fn conditional(base: u8, cc: &str, target: &str) -> Result<Option<Vec<u8>>, String> {
+    match (index(&CONDITIONS, cc), number(target)) {
+        (Some(c), Some(n)) => Ok(Some(word(base | c << 3, n)?)),
+        _ => Ok(None),
+    }
+}
+
+// Relative jumps take the absolute target address.
+fn relative(opcode: u8, target: &str, address: u16) -> Result<Option<Vec<u8>>, String> {
+    let target = match number(target) {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+    let offset = target - (address as i64 + 2);
+    if !(-128..=127).contains(&offset) {
+        return Err(format!("jump target 0x{:04X} out of range", target));
+    }
+    Ok(Some(vec![opcode, offset as u8]))
+}
+
+// Square brackets, HLI/HLD and the explicit 0xFF00+C spelling all mean the same thing as the usual forms.
+fn normalize(operand: &str) -> String {
+    let operand = operand
+        .split_whitespace()
+        .collect::<String>()
+        .replace('[', "(")
+        .replace(']', ")");
+    match operand.as_str() {
+        "(hli)" | "(hl++)" => "(hl+)".to_owned(),
+        "(hld)" | "(hl--)" => "(hl-)".to_owned(),
+        "(0xff00+c)" | "($ff00+c)" => "(c)".to_owned(),
+        _ => operand,
+    }
+}
+
+fn index(names: &[&str], name: &str) -> Option<u8> {
+    names.iter().position(|n| *n == name).map(|i| i as u8)
+}
+
+// Numbers may be written as a sum, so `(0xFF00+0x40)` works as an address.
+fn number(operand: &str) -> Option<i64> {
+    if operand.starts_with('(') {
+        return None;
+    }
+    operand.split('+').map(parse_number).sum::<Option<i64>>()
+}
+
+fn memory(operand: &str) -> Option<i64> {
+    number(operand.strip_prefix('(')?.strip_suffix(')')?)
+}
+
+fn byte(n: i64) -> Result<u8, String> {
+    match n {
+        -128..=255 => Ok(n as u8),
+        _ => Err(format!("{} does not fit in a byte", n)),
+    }
+}
+
+fn signed(n: i64) -> Result<u8, String> {
+    match n {
+        -128..=127 => Ok(n as u8),
+        _ => Err(format!("offset {} out of range", n)),
+    }
+}
+
+fn word(opcode: u8, n: i64) -> Result<Vec<u8>, String> {
+    match n {
+        -32768..=65535 => Ok(vec![opcode, n as u8, (n >> 8) as u8]),
+        _ => Err(format!("{} does not fit in a word", n)),
+    }
+}
+
+fn high_page(n: i64) -> Result<u8, String> {
+    match n {
+        0..=0xFF => Ok(n as u8),
+        0xFF00..=0xFFFF => Ok((n - 0xFF00) as u8),
+        _ => Err(format!("0x{:X} is not in high RAM or IO", n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble, patch_rom};
+
+    #[test]
+    fn assembles_instructions() {
+        let cases: [(&str, &[u8]); 16] = [
+            ("ld a, 5", &[0x3E, 0x05]),
+            ("LD [HL+], A", &[0x22]),
+            ("ld (hl), b", &[0x70]),
+            ("ld hl, $C000", &[0x21, 0x00, 0xC0]),
+            ("ld (0xFF40), a", &[0xEA, 0x40, 0xFF]),
+            ("ldh (0xFF00+0x40), a", &[0xE0, 0x40]),
+            ("ld hl, sp-2", &[0xF8, 0xFE]),
+            ("dec bc", &[0x0B]),
+            ("inc (hl)", &[0x34]),
+            ("xor a", &[0xAF]),
+            ("cp a, %1010", &[0xFE, 0x0A]),
+            ("jp nz, 0x0150", &[0xC2, 0x50, 0x01]),
+            ("ret c", &[0xD8]),
+            ("rst 0x38", &[0xFF]),
+            ("swap a", &[0xCB, 0x37]),
+            ("set 7, (hl)", &[0xCB, 0xFE]),
+        ];
+        for (source, code) in cases.iter() {
+            assert_eq!(assemble(source, 0).unwrap(), *code, "{}", source);
+        }
+
+        assert_eq!(
+            assemble("nop; jr 0xC100; jr nz, 0xC110", 0xC100).unwrap(),
+            [0x00, 0x18, 0xFD, 0x20, 0x0B]
+        );
+        assert!(assemble("ld (hl), (hl)", 0).is_err());
+        assert!(assemble("jr 0xD000", 0xC000).is_err());
+        assert!(assemble("bit 8, a", 0).is_err());
+
+        let mut rom = vec![0; 0x8000];
+        patch_rom(&mut rom, 0x4010, "jr 0x4010").unwrap();
+        assert_eq!(rom[0x4010..0x4012], [0x18, 0xFE]);
+        assert!(patch_rom(&mut rom, 0x7FFF, "ld a, 5").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::cartridge::LoadOptions;
+use crate::error::FeboyError;
+use crate::gameboy::{step, Gameboy};
+use crate::memory_map::MemoryMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+pub const BENCH_FRAMES: u64 = 3600;
+const FRAME_CYCLES: u64 = 70224;
+const FRAMES_PER_SECOND: f64 = 4194304.0 / FRAME_CYCLES as f64;
+
+pub struct BenchResult {
+    pub frames: u64,
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl Display for BenchResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        writeln!(f, "{} frames in {:.2}s", self.frames, seconds)?;
+        writeln!(f, "{:.1} frames/s", self.frames as f64 / seconds)?;
+        writeln!(
+            f,
+            "{:.2} emulated seconds per real second",
+            self.frames as f64 / FRAMES_PER_SECOND / seconds
+        )?;
+        writeln!(
+            f,
+            "{} instructions retired ({:.2} MIPS)",
+            self.instructions,
+            self.instructions as f64 / seconds / 1_000_000.0
+        )
+    }
+}
+
+// Runs the ROM unthrottled and without a window. Frames are counted in cycles rather than V-blanks so games
+// that keep the LCD off still finish, and steps spent halted don't count as retired instructions.
+pub fn bench(
+    rom: &Vec<u8>,
+    rom_name: &String,
+    frames: u64,
+    frame_skip: usize,
+) -> Result<BenchResult, FeboyError> {
+    let mut gameboy = Gameboy::new(MemoryMap::new(rom, rom_name, &LoadOptions::default())?);
+    gameboy.mem.ppu.set_frame_skip(frame_skip);
+    let target = gameboy.clock().cycles + frames * FRAME_CYCLES;
+    let mut instructions = 0;
+    let start = Instant::now();
+    while gameboy.clock().cycles < target {
+        let halted = gameboy.halted;
+        step(&mut gameboy)?;
+        instructions += !halted as u64;
+    }
+    Ok(BenchResult {
+        frames,
+        instructions,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::bench;
+
+    #[test]
+    fn runs_the_requested_frames() {
+        let rom = vec![0; 0x8000];
+        let result = bench(&rom, &"bench".to_owned(), 2, 1).unwrap();
+        assert_eq!(result.frames, 2);
+        // An empty ROM is all NOPs, about one instruction per machine cycle.
+        assert!(result.instructions > 2 * 70224 / 4 - 16);
+    }
+}
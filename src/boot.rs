@@ -0,0 +1,102 @@
+use crate::prelude::*;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+// Where the boot ROM's tile map leaves the logo once it stops scrolling, 12 tiles wide and 2 tall.
+const LOGO_X: usize = 32;
+const LOGO_Y: usize = 64;
+const LOGO_TILES: usize = 12;
+// SCY starts this far down and counts to 0, a pixel per frame.
+const SCROLL: usize = 0x64;
+// How long the logo rests after the scroll, while the real boot ROM plays its chime.
+const HOLD_FRAMES: usize = 64;
+const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+// Plays the boot ROM's logo scroll without running one, drawing the logo from the cartridge header the
+// way the boot ROM unpacks it: every nibble is a row of 4 pixels, each doubled in both directions. There's
+// no sound output, so the chime is left out.
+pub struct BootAnimation {
+    // The logo and the registered mark, one bool per screen pixel in a 2 tile tall strip.
+    logo: Vec<bool>,
+    frame: usize,
+}
+
+impl BootAnimation {
+    pub fn new(header_logo: &[u8; 48]) -> Self {
+        let strip_width = (LOGO_TILES + 1) * 8;
+        let mut logo = vec![false; strip_width * 16];
+        for (tile, bytes) in header_logo.chunks(2).enumerate() {
+            let (tile_x, tile_y) = (tile % LOGO_TILES * 8, tile / LOGO_TILES * 8);
+            let rows = [bytes[0] >> 4, bytes[0] & 0xF, bytes[1] >> 4, bytes[1] & 0xF];
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..4 {
+                    if bits & (0x8 >> column) == 0 {
+                        continue;
+                    }
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let (x, y) = (tile_x + column * 2 + dx, tile_y + row * 2 + dy);
+                        logo[y * strip_width + x] = true;
+                    }
+                }
+            }
+        }
+        for (y, bits) in REGISTERED.iter().enumerate() {
+            for x in 0..8 {
+                logo[y * strip_width + LOGO_TILES * 8 + x] = bits & (0x80 >> x) != 0;
+            }
+        }
+        Self { logo, frame: 0 }
+    }
+
+    // The next frame in the given palette, or None once the animation is over and the game should start.
+    pub fn next_frame(&mut self, palette: [u32; 4]) -> Option<Vec<u32>> {
+        if self.frame >= SCROLL + HOLD_FRAMES {
+            return None;
+        }
+        let scroll = SCROLL - self.frame.min(SCROLL);
+        self.frame += 1;
+        let strip_width = (LOGO_TILES + 1) * 8;
+        let mut pixels = vec![palette[0]; WIDTH * HEIGHT];
+        for (row, line) in self.logo.chunks(strip_width).enumerate() {
+            // Rows still above the top of the screen wrap around the 256 pixel background, out of view.
+            let y = match (LOGO_Y + row).checked_sub(scroll) {
+                Some(y) if y < HEIGHT => y,
+                _ => continue,
+            };
+            for (x, set) in line.iter().enumerate() {
+                if *set {
+                    pixels[y * WIDTH + LOGO_X + x] = palette[3];
+                }
+            }
+        }
+        Some(pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boot::{BootAnimation, HOLD_FRAMES, SCROLL};
+    use crate::cartridge::NINTENDO_LOGO;
+
+    #[test]
+    fn scrolls_the_logo_into_place() {
+        let palette = [0, 1, 2, 3];
+        let mut animation = BootAnimation::new(&NINTENDO_LOGO);
+        let first = animation.next_frame(palette).unwrap();
+        assert!(first.iter().all(|pixel| *pixel == 0));
+
+        let last = (1..SCROLL + HOLD_FRAMES)
+            .map(|_| animation.next_frame(palette).unwrap())
+            .last()
+            .unwrap();
+        assert_eq!(animation.next_frame(palette), None);
+        // The top left of the N, from 0xCE: 1100 doubled.
+        let row = |y: usize, x: usize| &last[y * 160 + x..y * 160 + x + 8];
+        assert_eq!(row(64, 32), [3, 3, 3, 3, 0, 0, 0, 0]);
+        assert_eq!(row(65, 32), [3, 3, 3, 3, 0, 0, 0, 0]);
+        assert_eq!(row(66, 32), [3, 3, 3, 3, 3, 3, 0, 0]);
+        // The registered mark's top row, 0x3C.
+        assert_eq!(row(64, 128), [0, 0, 3, 3, 3, 3, 0, 0]);
+        assert!(last[80 * 160..].iter().all(|pixel| *pixel == 0));
+    }
+}
@@ -0,0 +1,432 @@
+use crate::error::FeboyError;
+#[cfg(feature = "std")]
+use crate::game_db::GameDb;
+use crate::mbc::{Mbc, Mbc1, Mbc3, Mmm01};
+use crate::prelude::*;
+use crate::rtc::{Rtc, RtcClock};
+use crate::state::{StateReader, StateWriter};
+use core::convert::TryInto;
+use core::fmt::{Display, Formatter};
+
+const HEADER_LOGO: core::ops::Range<usize> = 0x0104..0x0134;
+const MBC1M_LOGO_OFFSET: usize = 0x40000;
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// Strict loading refuses ROMs the boot ROM would lock up on; permissive loading only warns about them.
+#[derive(Default)]
+pub struct LoadOptions {
+    #[cfg(feature = "std")]
+    pub game_db: GameDb,
+    pub strict: bool,
+}
+
+impl LoadOptions {
+    #[cfg(feature = "std")]
+    fn quirks(&self, header: &CartridgeHeader) -> Quirks {
+        self.game_db.quirks(header)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn quirks(&self, _: &CartridgeHeader) -> Quirks {
+        Quirks::default()
+    }
+}
+
+// Corrections for carts whose headers lie, applied before the mapper is picked.
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub struct Quirks {
+    pub cartridge_type: Option<u8>,
+    // Uses the header's 0x0149 size codes.
+    pub ram_size: Option<u8>,
+    pub multicart: Option<bool>,
+}
+
+impl Quirks {
+    pub fn apply_to(&self, header: &mut CartridgeHeader) {
+        if let Some(cartridge_type) = self.cartridge_type {
+            header.cartridge_type = cartridge_type;
+        }
+        if let Some(ram_size) = self.ram_size {
+            header.ram_size = ram_size;
+        }
+    }
+}
+
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub global_checksum: u16,
+    pub logo: [u8; 48],
+    pub logo_valid: bool,
+    pub header_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    fn new(header: &[u8]) -> Self {
+        let title = header[0x0134..0x0144]
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as char)
+            .collect::<String>();
+        Self {
+            title: title.trim().to_owned(),
+            cartridge_type: header[0x0147],
+            rom_size: header[0x0148],
+            ram_size: header[0x0149],
+            global_checksum: u16::from_be_bytes([header[0x014E], header[0x014F]]),
+            logo: header[HEADER_LOGO].try_into().unwrap(),
+            logo_valid: header[HEADER_LOGO] == NINTENDO_LOGO,
+            header_checksum_valid: CartridgeHeader::checksum(header) == header[0x014D],
+        }
+    }
+
+    fn checksum(header: &[u8]) -> u8 {
+        header[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+    }
+
+    // The checks the boot ROM makes before handing over to the cartridge.
+    pub fn problems(&self) -> Vec<&'static str> {
+        let mut problems = vec![];
+        if !self.logo_valid {
+            problems.push("logo does not match");
+        }
+        if !self.header_checksum_valid {
+            problems.push("header checksum is wrong");
+        }
+        problems
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13)
+    }
+
+    pub fn rom_bytes(&self) -> usize {
+        match self.rom_size {
+            0x00..=0x08 => 0x8000 << self.rom_size,
+            _ => 0,
+        }
+    }
+
+    pub fn ram_bytes(&self) -> usize {
+        match self.ram_size {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+}
+
+// A CPU address with the ROM bank that was mapped there at the time, since 4000-7FFF alone is ambiguous in
+// banked games. Shown as bank:addr, e.g. 05:4A10; anything outside ROM has bank 00.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct BankedAddress {
+    pub bank: usize,
+    pub address: u16,
+}
+
+impl Display for BankedAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.address)
+    }
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_dirty: bool,
+    mbc: Mbc,
+}
+
+impl Cartridge {
+    pub fn new(mut rom: Vec<u8>, options: &LoadOptions) -> Result<Self, FeboyError> {
+        if rom.len() < 0x8000 {
+            rom.resize(0x8000, 0xFF);
+        }
+        let mut header = CartridgeHeader::new(&rom[Cartridge::header_offset(&rom)..]);
+        let problems = header.problems();
+        if !problems.is_empty() {
+            if options.strict {
+                return Err(FeboyError::InvalidHeader(problems.join(", ")));
+            }
+            #[cfg(feature = "std")]
+            println!("Booting despite a bad header: {}", problems.join(", "));
+        }
+        let quirks = options.quirks(&header);
+        if quirks != Quirks::default() {
+            #[cfg(feature = "std")]
+            println!("Applying game database fixes for {}", header.title);
+            quirks.apply_to(&mut header);
+        }
+        if rom.len() < header.rom_bytes() {
+            return Err(FeboyError::RomTruncated {
+                expected: header.rom_bytes(),
+                actual: rom.len(),
+            });
+        }
+        let mbc = match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Mbc::NoMbc,
+            0x01..=0x03 => Mbc::Mbc1(Mbc1::new(
+                quirks
+                    .multicart
+                    .unwrap_or_else(|| Cartridge::is_mbc1_multicart(&rom)),
+            )),
+            0x0F | 0x10 => Mbc::Mbc3(Mbc3::new(Some(Rtc::new()))),
+            0x11..=0x13 => Mbc::Mbc3(Mbc3::new(None)),
+            0x0B..=0x0D => Mbc::Mmm01(Mmm01::new(rom.len() / 0x4000)),
+            cartridge_type => return Err(FeboyError::UnsupportedMapper(cartridge_type)),
+        };
+        let ram = vec![0; header.ram_bytes()];
+        Ok(Self {
+            header,
+            rom,
+            ram,
+            ram_dirty: false,
+            mbc,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.mbc.reset();
+    }
+
+    #[cfg(feature = "achievements")]
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Cartridge RAM followed by the clock, if there is one.
+    pub fn battery_ram(&mut self) -> Option<Vec<u8>> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.mbc.rtc() {
+            data.extend(rtc.battery_data());
+        }
+        if self.header.has_battery() && !data.is_empty() {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        if let Some(rtc) = self.mbc.rtc() {
+            rtc.load_battery_data(&data[len..]);
+        }
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = self.mbc.rtc() {
+            rtc.set_clock(clock);
+        }
+    }
+
+    pub fn rtc_clock(&mut self) -> Option<RtcClock> {
+        self.mbc.rtc().map(|rtc| rtc.clock())
+    }
+
+    pub fn machine_cycle(&mut self) {
+        if let Some(rtc) = self.mbc.rtc() {
+            rtc.machine_cycle();
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.memory("cartridge RAM", 0xA000, &self.ram);
+        self.mbc.save_state(state);
+    }
+
+    // Loaded RAM counts as a write so the battery save catches up with it.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        state.bytes(&mut self.ram)?;
+        self.ram_dirty = true;
+        self.mbc.load_state(state)
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // Writes straight to cartridge RAM, ignoring banking and the enable register, for editing saves.
+    pub fn write_ram(&mut self, offset: usize, value: u8) {
+        if let Some(byte) = self.ram.get_mut(offset) {
+            *byte = value;
+            self.ram_dirty = true;
+        }
+    }
+
+    pub fn take_ram_dirty(&mut self) -> bool {
+        core::mem::replace(&mut self.ram_dirty, false)
+    }
+
+    // MMM01 carts boot into a menu stored in the last 32KB, which is also where their header lives.
+    fn header_offset(rom: &[u8]) -> usize {
+        let menu_offset = rom.len() - 0x8000;
+        match rom[menu_offset + 0x0147] {
+            0x0B..=0x0D if menu_offset != 0 => menu_offset,
+            _ => 0,
+        }
+    }
+
+    // MBC1M carts repeat the boot logo at the start of every 256KB game they contain.
+    fn is_mbc1_multicart(rom: &[u8]) -> bool {
+        rom.len() >= MBC1M_LOGO_OFFSET + HEADER_LOGO.end
+            && rom[HEADER_LOGO] == rom[MBC1M_LOGO_OFFSET + HEADER_LOGO.start..][..HEADER_LOGO.len()]
+    }
+
+    pub fn banked(&self, address: u16) -> BankedAddress {
+        let bank = match address {
+            0x0000..=0x7FFF => (self.mbc.rom_offset(address as usize) % self.rom.len()) / 0x4000,
+            _ => 0,
+        };
+        BankedAddress { bank, address }
+    }
+
+    pub fn read(&self, address: usize) -> Option<u8> {
+        match address {
+            0x0000..=0x7FFF => Some(self.rom[self.mbc.rom_offset(address) % self.rom.len()]),
+            // Carts without RAM, or with it disabled, leave the bus floating. Smaller RAM chips don't decode
+            // the upper address lines, so they repeat through the whole window.
+            0xA000..=0xBFFF => Some(match (self.mbc.read_rtc(), self.mbc.ram_offset(address)) {
+                (Some(value), _) => value,
+                (_, Some(offset)) if !self.ram.is_empty() => self.ram[offset % self.ram.len()],
+                _ => 0xFF,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x7FFF => self.mbc.write(address, value),
+            0xA000..=0xBFFF if self.mbc.write_rtc(value) => (),
+            0xA000..=0xBFFF => match self.mbc.ram_offset(address) {
+                Some(offset) if !self.ram.is_empty() => {
+                    let len = self.ram.len();
+                    self.ram[offset % len] = value;
+                    self.ram_dirty = true;
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{Cartridge, LoadOptions, HEADER_LOGO, NINTENDO_LOGO};
+    use crate::rtc::{RtcClock, BATTERY_LEN};
+
+    #[test]
+    fn addresses_carry_the_mapped_rom_bank() {
+        // MBC1 with 8 banks.
+        let mut rom = vec![0; 0x20000];
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x02;
+        let mut cartridge = Cartridge::new(rom, &LoadOptions::default()).unwrap();
+        assert_eq!(cartridge.banked(0x4A10).to_string(), "01:4A10");
+        cartridge.write(0x2000, 0x05);
+        assert_eq!(cartridge.banked(0x4A10).to_string(), "05:4A10");
+        assert_eq!(cartridge.banked(0x0150).to_string(), "00:0150");
+        // Banks past the end of the ROM wrap around like the reads do.
+        cartridge.write(0x2000, 0x0B);
+        assert_eq!(cartridge.banked(0x7FFF).to_string(), "03:7FFF");
+        assert_eq!(cartridge.banked(0xC000).to_string(), "00:C000");
+    }
+
+    #[test]
+    fn external_ram_matches_the_header() {
+        // MBC1 without RAM.
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x01;
+        let mut cartridge = Cartridge::new(rom.clone(), &LoadOptions::default()).unwrap();
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0xA000, 0x12);
+        assert_eq!(cartridge.read(0xA000), Some(0xFF));
+
+        // 2KB repeats through the window, and only answers while enabled.
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x01;
+        let mut cartridge = Cartridge::new(rom.clone(), &LoadOptions::default()).unwrap();
+        assert_eq!(cartridge.ram().len(), 0x800);
+        cartridge.write(0xA000, 0x12);
+        assert_eq!(cartridge.read(0xA000), Some(0xFF));
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0xA801, 0x34);
+        assert_eq!(cartridge.read(0xA001), Some(0x34));
+        assert_eq!(cartridge.read(0xB801), Some(0x34));
+        cartridge.write(0x0000, 0x00);
+        assert_eq!(cartridge.read(0xA001), Some(0xFF));
+
+        // 32KB is banked in 8KB at a time.
+        rom[0x0149] = 0x03;
+        let mut cartridge = Cartridge::new(rom, &LoadOptions::default()).unwrap();
+        assert_eq!(cartridge.ram().len(), 0x8000);
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x6000, 0x01);
+        cartridge.write(0x4000, 0x02);
+        cartridge.write(0xA000, 0x56);
+        assert_eq!(cartridge.ram()[0x4000], 0x56);
+        cartridge.write(0x4000, 0x00);
+        assert_eq!(cartridge.read(0xA000), Some(0x00));
+    }
+
+    #[test]
+    fn mbc3_maps_ram_banks_and_the_clock() {
+        // MBC3 with a clock, 32KB of RAM and 128 banks.
+        let mut rom = vec![0; 0x200000];
+        rom[0x0147] = 0x10;
+        rom[0x0148] = 0x06;
+        rom[0x0149] = 0x03;
+        rom[0x7F * 0x4000] = 0x7F;
+        let mut cartridge = Cartridge::new(rom, &LoadOptions::default()).unwrap();
+        cartridge.set_rtc_clock(RtcClock::Emulated);
+        cartridge.write(0x2000, 0xFF);
+        assert_eq!(cartridge.read(0x4000), Some(0x7F));
+
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x03);
+        cartridge.write(0xA000, 0x12);
+        assert_eq!(cartridge.ram()[0x6000], 0x12);
+        // Selecting a clock register maps it over RAM.
+        cartridge.write(0x4000, 0x09);
+        cartridge.write(0xA000, 0x2A);
+        cartridge.write(0x6000, 0x00);
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.read(0xA000), Some(0x2A));
+        assert_eq!(cartridge.ram()[0x6000], 0x12);
+
+        let battery = cartridge.battery_ram().unwrap();
+        assert_eq!(battery.len(), 0x8000 + BATTERY_LEN);
+        assert_eq!(battery[0x8000 + 4], 0x2A);
+    }
+
+    #[test]
+    fn strict_loading_checks_logo_and_checksum() {
+        let strict = LoadOptions {
+            strict: true,
+            ..LoadOptions::default()
+        };
+        let mut rom = vec![0; 0x8000];
+        assert!(Cartridge::new(rom.clone(), &LoadOptions::default()).is_ok());
+        assert!(Cartridge::new(rom.clone(), &strict).is_err());
+
+        rom[HEADER_LOGO].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x014D] = 0xE7;
+        let cartridge = Cartridge::new(rom, &strict).unwrap();
+        assert!(cartridge.header.problems().is_empty());
+    }
+}
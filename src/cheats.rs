@@ -0,0 +1,109 @@
+use crate::prelude::*;
+use crate::regions::{region, Region};
+#[cfg(feature = "std")]
+use std::fs::read_to_string;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Cheat {
+    GameShark {
+        address: u16,
+        value: u8,
+    },
+    GameGenie {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+}
+
+impl Cheat {
+    pub fn parse(code: &str) -> Option<Cheat> {
+        let code = code.trim().to_uppercase();
+        let digits = code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u16))
+            .collect::<Option<Vec<u16>>>()?;
+
+        match (digits.len(), code.contains('-')) {
+            (8, false) => Some(Cheat::GameShark {
+                value: (digits[2] << 4 | digits[3]) as u8,
+                address: digits[6] << 12 | digits[7] << 8 | digits[4] << 4 | digits[5],
+            }),
+            (6, true) | (9, true) => Some(Cheat::GameGenie {
+                value: (digits[0] << 4 | digits[1]) as u8,
+                address: (digits[5] << 12 | digits[2] << 8 | digits[3] << 4 | digits[4]) ^ 0xF000,
+                compare: if digits.len() == 9 {
+                    Some(((digits[6] << 4 | digits[8]) as u8).rotate_right(2) ^ 0xBA)
+                } else {
+                    None
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn patch_rom_read(cheats: &[Cheat], address: usize, value: u8) -> u8 {
+        cheats
+            .iter()
+            .find_map(|cheat| match *cheat {
+                Cheat::GameGenie {
+                    address: a,
+                    value: v,
+                    compare,
+                } if a as usize == address && compare.is_none_or(|c| c == value) => Some(v),
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+}
+
+// Holds a RAM address at one value. The value goes straight back after every CPU write to the address,
+// and once a frame for anything else that changes RAM, like DMA or loading a state.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Freeze {
+    pub address: u16,
+    pub value: u8,
+}
+
+impl Freeze {
+    // Hex address and value, e.g. C0A0=99. Only RAM can be frozen, since writing a register back would
+    // repeat whatever writing it does.
+    pub fn parse(freeze: &str) -> Option<Freeze> {
+        let (address, value) = freeze.split_once('=')?;
+        let address = u16::from_str_radix(address.trim(), 16).ok()?;
+        let value = u8::from_str_radix(value.trim(), 16).ok()?;
+        let ram = matches!(
+            region(address as usize),
+            Region::CartridgeRam | Region::Wram | Region::Hram
+        );
+        ram.then_some(Freeze { address, value })
+    }
+}
+
+// Cheat files hold one code per line; lines starting with '#' are comments.
+#[cfg(feature = "std")]
+pub fn load_cheat_file(path: &Path) -> Vec<Cheat> {
+    let contents = match read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return vec![],
+    };
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let cheat = Cheat::parse(line);
+            if cheat.is_none() {
+                println!(
+                    "Ignoring invalid cheat code in {}: {}",
+                    path.display(),
+                    line
+                );
+            }
+            cheat
+        })
+        .collect()
+}
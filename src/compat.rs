@@ -0,0 +1,191 @@
+use crate::cartridge::LoadOptions;
+use crate::error::FeboyError;
+use crate::gameboy::{step, Gameboy};
+use crate::memory_map::MemoryMap;
+use crate::rom_loader::load_rom;
+use crate::watchdog::Watchdog;
+use std::fmt::{Display, Formatter};
+use std::fs::read_dir;
+use std::io;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+pub const COMPAT_FRAMES: u64 = 600;
+const FRAME_CYCLES: u64 = 70224;
+const EXTENSIONS: [&str; 4] = ["gb", "gbc", "zip", "gz"];
+
+pub enum Outcome {
+    // Rendered something other than a single flat colour at least once.
+    Ok,
+    Blank,
+    LockedUp(u16),
+    Crashed(String),
+    LoadFailed(String),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::Blank => write!(f, "blank"),
+            Outcome::LockedUp(pc) => write!(f, "locked up at {:04X}", pc),
+            Outcome::Crashed(e) => write!(f, "crashed: {}", e),
+            Outcome::LoadFailed(e) => write!(f, "failed to load: {}", e),
+        }
+    }
+}
+
+pub struct CompatEntry {
+    pub rom: String,
+    pub title: String,
+    pub cartridge_type: Option<u8>,
+    // Frames emulated before the run ended, and how many of them weren't blank.
+    pub frames: u64,
+    pub drawn_frames: u64,
+    pub outcome: Outcome,
+}
+
+// Boots every ROM in the directory headlessly, one after the other, sorted by file name.
+pub fn check_dir(dir: &Path, frames: u64) -> io::Result<Vec<CompatEntry>> {
+    let mut roms = read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect::<Vec<_>>();
+    roms.sort();
+    Ok(roms.iter().map(|rom| check_rom(rom, frames)).collect())
+}
+
+pub fn check_rom(path: &Path, frames: u64) -> CompatEntry {
+    let rom_name = path.to_string_lossy().into_owned();
+    let mut entry = CompatEntry {
+        rom: path
+            .file_name()
+            .map_or(rom_name.clone(), |name| name.to_string_lossy().into_owned()),
+        title: String::new(),
+        cartridge_type: None,
+        frames: 0,
+        drawn_frames: 0,
+        outcome: Outcome::Ok,
+    };
+    let mut gameboy = match load_rom(&rom_name, None)
+        .and_then(|rom| MemoryMap::new(&rom, &rom_name, &LoadOptions::default()))
+    {
+        Ok(mem) => Gameboy::new(mem),
+        Err(e) => {
+            entry.outcome = Outcome::LoadFailed(e.to_string());
+            return entry;
+        }
+    };
+    entry.title = gameboy.mem.cartridge.header.title.clone();
+    entry.cartridge_type = Some(gameboy.mem.cartridge.header.cartridge_type);
+    // Emulation bugs tend to surface as panics in the core, which count as crashes rather than aborting the
+    // whole report.
+    let result = catch_unwind(AssertUnwindSafe(|| run(&mut gameboy, &mut entry, frames)));
+    entry.outcome = match result {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => Outcome::Crashed(e.to_string()),
+        Err(panic) => Outcome::Crashed(
+            panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "panic".to_owned()),
+        ),
+    };
+    entry
+}
+
+// Frames are counted in cycles so a ROM that turns the LCD off for good still finishes.
+fn run(gameboy: &mut Gameboy, entry: &mut CompatEntry, frames: u64) -> Result<Outcome, FeboyError> {
+    let mut watchdog = Watchdog::new();
+    let start = gameboy.clock().cycles;
+    while entry.frames < frames {
+        while gameboy.clock().cycles < start + (entry.frames + 1) * FRAME_CYCLES {
+            step(gameboy)?;
+        }
+        entry.frames += 1;
+        let pixels = &gameboy.mem.ppu.pixels;
+        if pixels.iter().any(|pixel| *pixel != pixels[0]) {
+            entry.drawn_frames += 1;
+        }
+        if let Some(pc) = watchdog.update(gameboy) {
+            return Ok(Outcome::LockedUp(pc));
+        }
+    }
+    Ok(if entry.drawn_frames == 0 {
+        Outcome::Blank
+    } else {
+        Outcome::Ok
+    })
+}
+
+fn cartridge_type(entry: &CompatEntry) -> String {
+    entry
+        .cartridge_type
+        .map_or(String::new(), |code| format!("{:02X}", code))
+}
+
+pub fn to_csv(entries: &[CompatEntry]) -> String {
+    let field = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+    let mut csv = "rom,title,cartridge_type,frames,drawn_frames,outcome\n".to_owned();
+    for entry in entries {
+        csv += &format!(
+            "{},{},{},{},{},{}\n",
+            field(&entry.rom),
+            field(&entry.title),
+            cartridge_type(entry),
+            entry.frames,
+            entry.drawn_frames,
+            field(&entry.outcome.to_string())
+        );
+    }
+    csv
+}
+
+pub fn to_markdown(entries: &[CompatEntry]) -> String {
+    let cell = |value: &str| value.replace('|', "\\|");
+    let mut markdown = "| ROM | Title | Type | Frames | Drawn | Outcome |\n".to_owned();
+    markdown += "|---|---|---|---|---|---|\n";
+    for entry in entries {
+        markdown += &format!(
+            "| {} | {} | {} | {} | {} | {} |\n",
+            cell(&entry.rom),
+            cell(&entry.title),
+            cartridge_type(entry),
+            entry.frames,
+            entry.drawn_frames,
+            cell(&entry.outcome.to_string())
+        );
+    }
+    let working = entries
+        .iter()
+        .filter(|entry| matches!(entry.outcome, Outcome::Ok))
+        .count();
+    markdown += &format!("\n{} of {} ROMs ran\n", working, entries.len());
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compat::{check_rom, to_csv, Outcome};
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn reports_lock_ups() {
+        let path = temp_dir().join("feboy_compat_test.gb");
+        let mut rom = vec![0; 0x8000];
+        // DI; JR -2
+        rom[0x100..0x103].copy_from_slice(&[0xF3, 0x18, 0xFE]);
+        write(&path, &rom).unwrap();
+        let entry = check_rom(&path, 300);
+        remove_file(&path).unwrap();
+        assert!(matches!(entry.outcome, Outcome::LockedUp(0x101)));
+        assert!(to_csv(&[entry]).ends_with(",\"locked up at 0101\"\n"));
+    }
+}
@@ -0,0 +1,448 @@
+#[cfg(feature = "achievements")]
+use crate::achievements::Credentials;
+use crate::cartridge::CartridgeHeader;
+use crate::cheats::{Cheat, Freeze};
+use crate::dot_matrix::parse_dot_matrix;
+use crate::hotkeys::{Hotkey, Hotkeys};
+use crate::input::{InputLatency, InputMap};
+use crate::joypad::Button;
+use crate::link::valid_barcode;
+use crate::memory_map::RamFill;
+use crate::osd::{parse_timeout, Corner, OsdSettings};
+use crate::pacing::{parse_speed, FRAME_RATE};
+use crate::paths::{config_dir, SaveDir, CONFIG_FILE};
+use crate::picture::{parse_control, Picture, BRIGHTNESS, GAMMA, SATURATION};
+use crate::rtc::RtcClock;
+use crate::viewport::Scaling;
+use std::collections::HashMap;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+const GLOBAL_SECTION: &str = "global";
+
+// The accessibility palettes keep every shade apart by brightness and use blues against oranges or yellows,
+// which stay distinct without red-green vision. High contrast folds the four shades into black and white.
+pub const PALETTES: [(&str, [u32; 4]); 6] = [
+    ("Green", [0xE0F8D0, 0x88C070, 0x275046, 0x081820]),
+    ("Grey", [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]),
+    ("Pocket", [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+    ("Deuteranopia", [0xFFF5D6, 0xE8A33D, 0x3B6EA8, 0x141432]),
+    ("Protanopia", [0xFFFBE0, 0xF0D060, 0x4A78B8, 0x101838]),
+    ("High contrast", [0xFFFFFF, 0xFFFFFF, 0x000000, 0x000000]),
+];
+
+// What happens while the window is in the background.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Background {
+    Run,
+    Pause,
+    // Runs on at a quarter of the speed, so the game keeps going without eating the battery.
+    Throttle,
+}
+
+// Whether a state is saved when the game closes, and what happens to it the next time the game is opened.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AutoState {
+    Off,
+    Ask,
+    Resume,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Model {
+    Dmg,
+}
+
+pub struct Settings {
+    pub palette: [u32; 4],
+    pub speed: f64,
+    // Runs frames at the display's refresh rate instead of the Game Boy's, so each one lands on a refresh.
+    pub refresh_rate: Option<f64>,
+    // Draws one frame in this many, e.g. frame_skip = 3 emulates every frame but renders only a third.
+    pub frame_skip: usize,
+    // Draws every other frame at most and sleeps between frames instead of spinning, for laptops.
+    pub battery_saver: bool,
+    // Raises the emulation thread's priority so other programs are less likely to make it miss frames.
+    pub high_priority: bool,
+    // Which debug windows were open when the last game was closed, to open them again.
+    pub heat_map_open: bool,
+    pub sram_editor_open: bool,
+    // Scrolls the Nintendo logo down before the game starts, like the boot ROM does.
+    pub boot_animation: bool,
+    pub picture: Picture,
+    // The intensity of the LCD grid filter, if it's on.
+    pub dot_matrix: Option<f64>,
+    pub scaling: Scaling,
+    pub background: Background,
+    pub auto_state: AutoState,
+    pub osd: OsdSettings,
+    pub cheats: Vec<Cheat>,
+    pub freezes: Vec<Freeze>,
+    pub inputs: InputMap,
+    pub input_latency: InputLatency,
+    pub model: Model,
+    pub save_dir: SaveDir,
+    pub ram_fill: RamFill,
+    // Makes a random fill the same every boot.
+    pub ram_seed: Option<u32>,
+    pub rtc: RtcClock,
+    pub hotkeys: Hotkeys,
+    pub barcodes: Vec<String>,
+    pub discord: bool,
+    pub discord_app_id: String,
+    #[cfg(feature = "achievements")]
+    pub achievements: Credentials,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            palette: PALETTES[0].1,
+            speed: 1.0,
+            refresh_rate: None,
+            frame_skip: 1,
+            battery_saver: false,
+            high_priority: false,
+            heat_map_open: false,
+            sram_editor_open: false,
+            boot_animation: false,
+            picture: Picture::new(),
+            dot_matrix: None,
+            scaling: Scaling::Aspect,
+            background: Background::Run,
+            auto_state: AutoState::Off,
+            osd: OsdSettings::new(),
+            cheats: vec![],
+            freezes: vec![],
+            inputs: InputMap::new(),
+            input_latency: InputLatency::Frame,
+            model: Model::Dmg,
+            save_dir: SaveDir::NextToRom,
+            ram_fill: RamFill::Zero,
+            ram_seed: None,
+            rtc: RtcClock::Host,
+            hotkeys: Hotkeys::new(),
+            barcodes: vec![],
+            discord: false,
+            discord_app_id: String::new(),
+            #[cfg(feature = "achievements")]
+            achievements: Credentials::default(),
+        }
+    }
+
+    fn apply(&mut self, key: &str, value: &str) {
+        let parsed = match key {
+            "palette" => parse_palette(value).map(|palette| self.palette = palette),
+            "speed" => parse_speed(value).map(|speed| self.speed = speed),
+            "frame_skip" => parse_frame_skip(value).map(|n| self.frame_skip = n),
+            "refresh_rate" => parse_refresh_rate(value).map(|rate| self.refresh_rate = rate),
+            "cheats" => value
+                .split(',')
+                .filter(|code| !code.trim().is_empty())
+                .map(Cheat::parse)
+                .collect::<Option<Vec<Cheat>>>()
+                .map(|cheats| self.cheats = cheats),
+            "freeze" => value
+                .split(',')
+                .filter(|freeze| !freeze.trim().is_empty())
+                .map(Freeze::parse)
+                .collect::<Option<Vec<Freeze>>>()
+                .map(|freezes| self.freezes = freezes),
+            "model" => parse_model(value).map(|model| self.model = model),
+            // wram_fill is what it was called before HRAM was filled too.
+            "ram_init" | "wram_fill" => RamFill::parse(value).map(|fill| self.ram_fill = fill),
+            "ram_seed" => value.parse().ok().map(|seed| self.ram_seed = Some(seed)),
+            "rtc" => RtcClock::parse(value).map(|clock| self.rtc = clock),
+            "auto_state" => parse_auto_state(value).map(|auto| self.auto_state = auto),
+            "save_dir" => SaveDir::parse(value).map(|dir| self.save_dir = dir),
+            "input_latency" => InputLatency::parse(value).map(|l| self.input_latency = l),
+            "barcodes" => value
+                .split(',')
+                .map(str::trim)
+                .filter(|code| !code.is_empty())
+                .map(|code| Some(code.to_owned()).filter(|code| valid_barcode(code)))
+                .collect::<Option<Vec<String>>>()
+                .map(|codes| self.barcodes = codes),
+            "brightness" => parse_control(value, BRIGHTNESS).map(|b| self.picture.brightness = b),
+            "gamma" => parse_control(value, GAMMA).map(|gamma| self.picture.gamma = gamma),
+            "saturation" => parse_control(value, SATURATION).map(|s| self.picture.saturation = s),
+            "dot_matrix" => parse_dot_matrix(value).map(|dot_matrix| self.dot_matrix = dot_matrix),
+            "scaling" => Scaling::parse(value).map(|scaling| self.scaling = scaling),
+            "background" => parse_background(value).map(|b| self.background = b),
+            "osd.position" => Corner::parse(value).map(|corner| self.osd.corner = corner),
+            "osd.timeout" => parse_timeout(value).map(|timeout| self.osd.timeout = timeout),
+            "osd.fps" => parse_bool(value).map(|enabled| self.osd.fps = enabled),
+            "osd.input" => parse_bool(value).map(|enabled| self.osd.input = enabled),
+            "boot_animation" => parse_bool(value).map(|enabled| self.boot_animation = enabled),
+            "discord" => parse_bool(value).map(|enabled| self.discord = enabled),
+            "battery_saver" => parse_bool(value).map(|enabled| self.battery_saver = enabled),
+            "high_priority" => parse_bool(value).map(|enabled| self.high_priority = enabled),
+            "heat_map_open" => parse_bool(value).map(|open| self.heat_map_open = open),
+            "sram_editor_open" => parse_bool(value).map(|open| self.sram_editor_open = open),
+            "discord_app_id" => Some(value.to_owned())
+                .filter(|id| id.bytes().all(|digit| digit.is_ascii_digit()))
+                .map(|id| self.discord_app_id = id),
+            #[cfg(feature = "achievements")]
+            "achievements.username" => {
+                Some(value.to_owned()).map(|u| self.achievements.username = u)
+            }
+            #[cfg(feature = "achievements")]
+            "achievements.password" => {
+                Some(value.to_owned()).map(|p| self.achievements.password = p)
+            }
+            _ => match (key.strip_prefix("keys."), key.strip_prefix("hotkeys.")) {
+                (Some(button), _) => Button::parse(button).and_then(|b| self.inputs.bind(b, value)),
+                (_, Some(hotkey)) => {
+                    Hotkey::parse(hotkey).and_then(|h| self.hotkeys.bind(h, value))
+                }
+                _ => None,
+            },
+        };
+        if parsed.is_none() {
+            println!("Ignoring invalid config entry: {} = {}", key, value);
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// Only rates close to the Game Boy's 59.73 Hz, like 60 or 59.94, since anything else would change the
+// game's speed noticeably.
+fn parse_refresh_rate(value: &str) -> Option<Option<f64>> {
+    if let Some(false) = parse_bool(value) {
+        return Some(None);
+    }
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| (rate / FRAME_RATE - 1.0).abs() < 0.02)
+        .map(Some)
+}
+
+pub fn parse_frame_skip(value: &str) -> Option<usize> {
+    value.parse::<usize>().ok().filter(|n| *n >= 1)
+}
+
+fn parse_model(value: &str) -> Option<Model> {
+    match value.to_lowercase().as_str() {
+        "dmg" => Some(Model::Dmg),
+        _ => None,
+    }
+}
+
+fn parse_auto_state(value: &str) -> Option<AutoState> {
+    match value.to_lowercase().as_str() {
+        "off" => Some(AutoState::Off),
+        "ask" => Some(AutoState::Ask),
+        "resume" => Some(AutoState::Resume),
+        _ => None,
+    }
+}
+
+fn parse_background(value: &str) -> Option<Background> {
+    match value.to_lowercase().as_str() {
+        "run" => Some(Background::Run),
+        "pause" => Some(Background::Pause),
+        "throttle" => Some(Background::Throttle),
+        _ => None,
+    }
+}
+
+// Either one of the named palettes, e.g. high_contrast, or four colors from lightest to darkest.
+fn parse_palette(value: &str) -> Option<[u32; 4]> {
+    let named = PALETTES
+        .iter()
+        .find(|(name, _)| name.replace(' ', "_").eq_ignore_ascii_case(value));
+    if let Some((_, palette)) = named {
+        return Some(*palette);
+    }
+    let colors = value
+        .split(',')
+        .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let mut palette = [0; 4];
+    if colors.len() != palette.len() {
+        return None;
+    }
+    palette.copy_from_slice(&colors);
+    Some(palette)
+}
+
+#[derive(Default)]
+pub struct Config {
+    sections: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Config {
+    pub fn load(portable: bool) -> Self {
+        let contents = read_to_string(Config::path(portable)).unwrap_or_default();
+        Config::parse(&contents)
+    }
+
+    pub fn path(portable: bool) -> PathBuf {
+        if let Ok(path) = env::var("FEBOY_CONFIG") {
+            return PathBuf::from(path);
+        }
+        config_dir(portable).join(CONFIG_FILE)
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut sections: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        let mut section = GLOBAL_SECTION.to_owned();
+        for line in contents.lines().map(|line| line.trim()) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_owned();
+            } else if let Some((key, value)) = line.split_once('=') {
+                sections
+                    .entry(section.clone())
+                    .or_default()
+                    .push((key.trim().to_lowercase(), value.trim().to_owned()));
+            }
+        }
+        Self { sections }
+    }
+
+    // Game sections are named after the header title and global checksum, e.g. [TETRIS:0A6B].
+    pub fn game_section(header: &CartridgeHeader) -> String {
+        format!("{}:{:04X}", header.title, header.global_checksum)
+    }
+
+    pub fn section(&self, name: &str) -> &[(String, String)] {
+        self.sections.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    // Sets a key in the global section of the config file, keeping everything else as it was.
+    pub fn store(path: &Path, key: &str, value: &str) -> io::Result<()> {
+        let contents = read_to_string(path).unwrap_or_default();
+        write(path, Config::set_global(&contents, key, value))
+    }
+
+    fn set_global(contents: &str, key: &str, value: &str) -> String {
+        let mut lines = contents.lines().map(str::to_owned).collect::<Vec<_>>();
+        let global_end = lines
+            .iter()
+            .position(|line| line.trim().starts_with('['))
+            .unwrap_or(lines.len());
+        let existing = lines[..global_end].iter().rposition(|line| {
+            line.split_once('=')
+                .is_some_and(|(k, _)| k.trim().eq_ignore_ascii_case(key))
+        });
+        let line = format!("{} = {}", key, value);
+        match existing {
+            Some(index) => lines[index] = line,
+            None => lines.insert(global_end, line),
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn settings(&self, header: &CartridgeHeader) -> Settings {
+        let mut settings = Settings::new();
+        for section in [GLOBAL_SECTION.to_owned(), Config::game_section(header)] {
+            for (key, value) in self.section(&section) {
+                settings.apply(key, value);
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{CartridgeHeader, NINTENDO_LOGO};
+    use crate::cheats::{Cheat, Freeze};
+    use crate::config::{parse_palette, AutoState, Background, Config, PALETTES};
+    use crate::input::InputLatency;
+    use crate::memory_map::RamFill;
+    use crate::osd::Corner;
+    use crate::rtc::RtcClock;
+
+    #[test]
+    fn game_section_overrides_global() {
+        let config = Config::parse(
+            "speed = 2\npalette = ffffff, aaaaaa, 555555, 000000\ninput_latency = read\n\
+             [TETRIS:0A6B]\nspeed = 0.5\ncheats = 01FF00C0\nkeys.a = X, Pad:South\n\
+             barcodes = 4902370501315, 4905040352507\n\
+             discord = on\nboot_animation = yes\nbackground = Pause\nrtc = emulated\nosd.position = top_right\nosd.fps = on\nauto_state = ask\nwram_fill = ff\nram_seed = 42\nframe_skip = 0\nframe_skip = 2\nbattery_saver = on\nhigh_priority = yes\nheat_map_open = on\nsram_editor_open = on\nsram_editor_open = off\nrefresh_rate = 144\nrefresh_rate = 60\nfreeze = C0A0=99, ffb6 = 1\n\
+             [OTHER:0000]\nspeed = 4",
+        );
+        let header = CartridgeHeader {
+            title: "TETRIS".to_owned(),
+            cartridge_type: 0,
+            rom_size: 0,
+            ram_size: 0,
+            global_checksum: 0x0A6B,
+            logo: NINTENDO_LOGO,
+            logo_valid: true,
+            header_checksum_valid: true,
+        };
+        let settings = config.settings(&header);
+
+        assert_eq!(settings.speed, 0.5);
+        assert_eq!(settings.palette, [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]);
+        assert_eq!(settings.inputs.keys()[0], minifb::Key::X);
+        assert_eq!(settings.input_latency, InputLatency::Read);
+        assert_eq!(settings.barcodes, ["4902370501315", "4905040352507"]);
+        assert!(settings.discord);
+        assert!(settings.boot_animation);
+        assert_eq!(settings.background, Background::Pause);
+        assert_eq!(settings.rtc, RtcClock::Emulated);
+        assert_eq!(settings.osd.corner, Corner::TopRight);
+        assert!(settings.osd.fps);
+        assert_eq!(settings.refresh_rate, Some(60.0));
+        assert_eq!(settings.frame_skip, 2);
+        assert!(settings.battery_saver);
+        assert!(settings.high_priority);
+        assert!(settings.heat_map_open);
+        assert!(!settings.sram_editor_open);
+        assert_eq!(settings.ram_fill, RamFill::Ones);
+        assert_eq!(settings.ram_seed, Some(42));
+        assert_eq!(settings.auto_state, AutoState::Ask);
+        assert_eq!(parse_palette("Deuteranopia"), Some(PALETTES[3].1));
+        assert_eq!(
+            parse_palette("high_contrast"),
+            Some([0xFFFFFF, 0xFFFFFF, 0x000000, 0x000000])
+        );
+        assert_eq!(parse_palette("sepia"), None);
+
+        let contents = "gamma = 1.2\n[TETRIS:0A6B]\ngamma = 2";
+        assert_eq!(
+            Config::set_global(contents, "gamma", "1.4"),
+            "gamma = 1.4\n[TETRIS:0A6B]\ngamma = 2\n"
+        );
+        assert_eq!(
+            Config::set_global(contents, "brightness", "0.8"),
+            "gamma = 1.2\nbrightness = 0.8\n[TETRIS:0A6B]\ngamma = 2\n"
+        );
+        assert_eq!(
+            settings.cheats,
+            vec![Cheat::GameShark {
+                address: 0xC000,
+                value: 0xFF
+            }]
+        );
+        assert_eq!(
+            settings.freezes,
+            vec![
+                Freeze {
+                    address: 0xC0A0,
+                    value: 0x99
+                },
+                Freeze {
+                    address: 0xFFB6,
+                    value: 0x01
+                }
+            ]
+        );
+        assert_eq!(Freeze::parse("8000=01"), None);
+    }
+}
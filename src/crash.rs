@@ -0,0 +1,120 @@
+use crate::gameboy::Gameboy;
+use crate::labels::Labels;
+use crate::register::RegisterId::{A, B, C, D, E, H, L};
+use std::backtrace::Backtrace;
+use std::fmt::Write;
+use std::fs::write;
+use std::panic;
+use std::path::Path;
+use std::sync::Mutex;
+
+static PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+// The hook only keeps the message and backtrace; the machine state is dumped once the panic has unwound
+// back to main, where the cores are reachable again.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let report = format!("{}\n\n{}", info, Backtrace::force_capture());
+        if let Ok(mut panic) = PANIC.lock() {
+            *panic = Some(report);
+        }
+        default_hook(info);
+    }));
+}
+
+// Writes a text report next to a save state taken at the moment of the crash, and the trace if one was
+// being kept.
+pub fn write_crash_report(gameboy: &mut Gameboy, labels: &Labels, path: &Path) {
+    let state_path = path.with_extension("state");
+    let state = gameboy.save_state();
+    let panic = PANIC
+        .lock()
+        .ok()
+        .and_then(|panic| panic.clone())
+        .unwrap_or_default();
+    let report = crash_report(gameboy, labels, &panic, &state_path);
+    let trace = gameboy.trace().map(|trace| trace.dump());
+    let trace_path = path.with_extension("trace");
+    match write(path, report)
+        .and_then(|_| write(&state_path, state))
+        .and_then(|_| trace.map_or(Ok(()), |trace| write(&trace_path, trace)))
+    {
+        Ok(_) => println!("Crash report written to {}", path.display()),
+        Err(e) => println!("Failed to write crash report {}: {}", path.display(), e),
+    }
+}
+
+fn crash_report(gameboy: &mut Gameboy, labels: &Labels, panic: &str, state_path: &Path) -> String {
+    let header = &gameboy.mem.cartridge.header;
+    let mut report = format!(
+        "feboy {} crash report\n\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        panic
+    );
+    let _ = writeln!(
+        report,
+        "Cartridge: {} (type {:02X}, ROM {:02X}, RAM {:02X}, checksum {:04X})",
+        header.title,
+        header.cartridge_type,
+        header.rom_size,
+        header.ram_size,
+        header.global_checksum
+    );
+    let _ = write!(report, "Registers:");
+    for (name, id) in [
+        ("A", A),
+        ("B", B),
+        ("C", C),
+        ("D", D),
+        ("E", E),
+        ("H", H),
+        ("L", L),
+    ] {
+        let _ = write!(report, " {}={:02X}", name, gameboy.reg[id].value);
+    }
+    let _ = writeln!(
+        report,
+        " F={:02X} SP={:04X} PC={:04X} IME={} HALT={}",
+        gameboy.reg.flags.value(),
+        gameboy.reg.sp.value(),
+        gameboy.reg.pc.value(),
+        gameboy.ime,
+        gameboy.halted
+    );
+    let clock = gameboy.clock();
+    let _ = writeln!(
+        report,
+        "Clock: frame {}, scanline {}, cycle {}",
+        clock.frames, clock.scanlines, clock.cycles
+    );
+    let _ = write!(report, "\nLast executed PCs:");
+    for (i, pc) in gameboy.pc_history().iter().enumerate() {
+        let separator = if i % 12 == 0 { "\n" } else { " " };
+        let _ = write!(report, "{}{}", separator, pc);
+    }
+    let _ = write!(report, "\n\nIO registers:");
+    for address in (0xFF00..0xFF80).chain([0xFFFF]) {
+        if address % 16 == 0 || address == 0xFFFF {
+            let _ = write!(report, "\n{:04X}:", address);
+        }
+        let _ = write!(
+            report,
+            " {:02X}",
+            gameboy.mem.read_without_cycle(address as u16)
+        );
+    }
+    if !labels.is_empty() {
+        let _ = write!(report, "\n\nLabels:");
+    }
+    for label in labels.iter() {
+        let value = label.value(|address| gameboy.mem.peek(address as usize));
+        let _ = write!(
+            report,
+            "\n{:04X} {} ({:?}) = {}",
+            label.address, label.name, label.kind, value
+        );
+    }
+    let _ = writeln!(report, "\n\nSave state: {}", state_path.display());
+    report
+}
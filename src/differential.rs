@@ -0,0 +1,218 @@
+use crate::error::FeboyError;
+use crate::gameboy::{step, Gameboy};
+use crate::regions::Region;
+use crate::register::RegisterId::{A, B, C, D, E, H, L};
+use std::ffi::{c_void, CString};
+use std::fmt::Write;
+use std::io;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const GB_MODEL_DMG_B: c_int = 0x002;
+const GB_DIRECT_ACCESS_RAM: c_int = 1;
+const GB_DIRECT_ACCESS_HRAM: c_int = 4;
+// The reference core runs its boot ROM first, which is capped so a bad boot ROM can't hang the runner.
+const BOOT_STEPS: usize = 10_000_000;
+const MEMORY: [(Region, c_int); 2] = [
+    (Region::Wram, GB_DIRECT_ACCESS_RAM),
+    (Region::Hram, GB_DIRECT_ACCESS_HRAM),
+];
+
+#[repr(C)]
+struct GbGameboy {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct GbRegisters {
+    af: u16,
+    bc: u16,
+    de: u16,
+    hl: u16,
+    sp: u16,
+    pc: u16,
+}
+
+#[link(name = "sameboy")]
+extern "C" {
+    fn GB_alloc() -> *mut GbGameboy;
+    fn GB_init(gb: *mut GbGameboy, model: c_int);
+    fn GB_free(gb: *mut GbGameboy);
+    fn GB_dealloc(gb: *mut GbGameboy);
+    fn GB_load_boot_rom(gb: *mut GbGameboy, path: *const c_char) -> c_int;
+    fn GB_load_rom(gb: *mut GbGameboy, path: *const c_char) -> c_int;
+    fn GB_run(gb: *mut GbGameboy) -> c_uint;
+    fn GB_get_registers(gb: *mut GbGameboy) -> *mut GbRegisters;
+    fn GB_get_direct_access(
+        gb: *mut GbGameboy,
+        access: c_int,
+        size: *mut usize,
+        bank: *mut u16,
+    ) -> *mut c_void;
+}
+
+// SameBoy, linked through its C API, stepping one instruction at a time alongside feboy.
+struct Reference {
+    gb: *mut GbGameboy,
+}
+
+impl Reference {
+    fn new(boot_rom: &str, rom: &str) -> Result<Self, FeboyError> {
+        let path = |path: &str| CString::new(path).map_err(|_| reference_error("invalid path"));
+        let (boot_rom, rom) = (path(boot_rom)?, path(rom)?);
+        unsafe {
+            let gb = GB_alloc();
+            GB_init(gb, GB_MODEL_DMG_B);
+            let reference = Self { gb };
+            if GB_load_boot_rom(gb, boot_rom.as_ptr()) != 0 || GB_load_rom(gb, rom.as_ptr()) != 0 {
+                return Err(reference_error(
+                    "SameBoy failed to load the ROM or boot ROM",
+                ));
+            }
+            Ok(reference)
+        }
+    }
+
+    fn step(&mut self) {
+        unsafe {
+            GB_run(self.gb);
+        }
+    }
+
+    fn registers(&self) -> [u16; 6] {
+        let registers = unsafe { &*GB_get_registers(self.gb) };
+        [
+            registers.af,
+            registers.bc,
+            registers.de,
+            registers.hl,
+            registers.sp,
+            registers.pc,
+        ]
+    }
+
+    fn memory(&self, access: c_int) -> &[u8] {
+        let (mut size, mut bank) = (0, 0);
+        unsafe {
+            let data = GB_get_direct_access(self.gb, access, &mut size, &mut bank);
+            std::slice::from_raw_parts(data as *const u8, size)
+        }
+    }
+}
+
+impl Drop for Reference {
+    fn drop(&mut self) {
+        unsafe {
+            GB_free(self.gb);
+            GB_dealloc(self.gb);
+        }
+    }
+}
+
+fn reference_error(message: &str) -> FeboyError {
+    FeboyError::Io(io::Error::other(message))
+}
+
+fn registers(gameboy: &Gameboy) -> [u16; 6] {
+    let pair = |high, low| u16::from_be_bytes([gameboy.reg[high].value, gameboy.reg[low].value]);
+    [
+        u16::from_be_bytes([gameboy.reg[A].value, gameboy.reg.flags.value()]),
+        pair(B, C),
+        pair(D, E),
+        pair(H, L),
+        gameboy.reg.sp.value(),
+        gameboy.reg.pc.value(),
+    ]
+}
+
+// Steps both cores one instruction at a time from the cartridge entry point and stops at the first
+// instruction after which the CPU registers, WRAM or HRAM differ. Returns the divergence report, or None
+// if the run was interrupted first.
+pub fn run_differential(
+    gameboy: &mut Gameboy,
+    rom: &str,
+    boot_rom: &str,
+    running: &AtomicBool,
+) -> Result<Option<String>, FeboyError> {
+    let mut reference = Reference::new(boot_rom, rom)?;
+    let mut boot_steps = 0;
+    while reference.registers()[5] != 0x0100 {
+        reference.step();
+        boot_steps += 1;
+        if boot_steps == BOOT_STEPS {
+            return Err(reference_error(&format!(
+                "{} never reached the cartridge entry point",
+                Path::new(boot_rom).display()
+            )));
+        }
+    }
+    // Power-on RAM contents are undefined, so feboy starts from whatever SameBoy's boot left behind.
+    let ours = [&mut gameboy.mem.wram, &mut gameboy.mem.hram];
+    for ((_, access), ours) in MEMORY.iter().zip(ours) {
+        let theirs = reference.memory(*access);
+        let len = theirs.len().min(ours.len());
+        ours[..len].copy_from_slice(&theirs[..len]);
+    }
+    let mut executed = 0u64;
+    while running.load(Ordering::SeqCst) {
+        step(gameboy)?;
+        reference.step();
+        executed += 1;
+        if let Some(report) = divergence(gameboy, &reference) {
+            return Ok(Some(format!(
+                "Diverged after {} instructions\n{}",
+                executed, report
+            )));
+        }
+    }
+    println!("No divergence after {} instructions", executed);
+    Ok(None)
+}
+
+fn divergence(gameboy: &Gameboy, reference: &Reference) -> Option<String> {
+    let mut report = String::new();
+    let names = ["AF", "BC", "DE", "HL", "SP", "PC"];
+    for ((name, ours), theirs) in names
+        .iter()
+        .zip(registers(gameboy))
+        .zip(reference.registers())
+    {
+        if ours != theirs {
+            let _ = writeln!(
+                report,
+                "{}: feboy {:04X}, SameBoy {:04X}",
+                name, ours, theirs
+            );
+        }
+    }
+    let ours = [&gameboy.mem.wram, &gameboy.mem.hram];
+    for (((region, access), name), ours) in MEMORY.iter().zip(["WRAM", "HRAM"]).zip(ours) {
+        let theirs = reference.memory(*access);
+        let differences = ours
+            .iter()
+            .zip(theirs)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b);
+        for (offset, (ours, theirs)) in differences.take(16) {
+            let _ = writeln!(
+                report,
+                "{} {:04X}: feboy {:02X}, SameBoy {:02X}",
+                name,
+                region.start() + offset,
+                ours,
+                theirs
+            );
+        }
+    }
+    if report.is_empty() {
+        return None;
+    }
+    let history = gameboy
+        .pc_history()
+        .iter()
+        .map(|pc| format!("{:02X}:{:04X}", pc.bank, pc.address))
+        .collect::<Vec<_>>();
+    let _ = writeln!(report, "Last instructions: {}", history.join(" "));
+    Some(report)
+}
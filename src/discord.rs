@@ -0,0 +1,140 @@
+use std::io::{Read, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HANDSHAKE: u32 = 0;
+const FRAME: u32 = 1;
+const CLOSE: u32 = 2;
+
+trait Pipe: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Pipe for T {}
+
+// Rich Presence talks to the local Discord client over its IPC socket from a background thread, so a
+// missing or unresponsive client never stalls emulation.
+pub struct Presence {
+    games: Sender<String>,
+}
+
+impl Presence {
+    pub fn start(app_id: String) -> Self {
+        let (games, titles) = channel::<String>();
+        thread::spawn(move || {
+            let mut pipe = match connect(&app_id) {
+                Some(pipe) => pipe,
+                None => {
+                    println!("Discord presence unavailable: client not running");
+                    return;
+                }
+            };
+            for title in titles {
+                if send(&mut pipe, FRAME, &activity(&title)).is_none() {
+                    println!("Discord presence stopped: connection closed");
+                    return;
+                }
+            }
+        });
+        Self { games }
+    }
+
+    // Play time counts from the moment the game is set.
+    pub fn set_game(&self, title: &str) {
+        let _ = self.games.send(title.to_owned());
+    }
+}
+
+fn connect(app_id: &str) -> Option<Box<dyn Pipe>> {
+    let handshake = format!("{{\"v\":1,\"client_id\":{}}}", json_string(app_id));
+    (0..10).find_map(|i| {
+        let mut pipe = open(i)?;
+        send(&mut pipe, HANDSHAKE, &handshake)?;
+        Some(pipe)
+    })
+}
+
+#[cfg(unix)]
+fn open(index: usize) -> Option<Box<dyn Pipe>> {
+    let dir = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .unwrap_or_else(|| "/tmp".to_owned());
+    let path = std::path::Path::new(&dir).join(format!("discord-ipc-{}", index));
+    let stream = std::os::unix::net::UnixStream::connect(path).ok()?;
+    Some(Box::new(stream))
+}
+
+#[cfg(windows)]
+fn open(index: usize) -> Option<Box<dyn Pipe>> {
+    let path = format!(r"\\.\pipe\discord-ipc-{}", index);
+    let pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .ok()?;
+    Some(Box::new(pipe))
+}
+
+// Discord answers every frame, so the reply is read back before the next one goes out.
+fn send(pipe: &mut Box<dyn Pipe>, opcode: u32, payload: &str) -> Option<()> {
+    pipe.write_all(&encode_frame(opcode, payload)).ok()?;
+    let mut header = [0; 8];
+    pipe.read_exact(&mut header).ok()?;
+    let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let mut reply = vec![0; len as usize];
+    pipe.read_exact(&mut reply).ok()?;
+    if opcode == CLOSE {
+        return None;
+    }
+    Some(())
+}
+
+fn encode_frame(opcode: u32, payload: &str) -> Vec<u8> {
+    let mut frame = opcode.to_le_bytes().to_vec();
+    frame.extend((payload.len() as u32).to_le_bytes());
+    frame.extend(payload.as_bytes());
+    frame
+}
+
+fn activity(title: &str) -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    format!(
+        "{{\"cmd\":\"SET_ACTIVITY\",\"nonce\":\"{}\",\"args\":{{\"pid\":{},\"activity\":{{\
+         \"details\":{},\"timestamps\":{{\"start\":{}}}}}}}}}",
+        started,
+        std::process::id(),
+        json_string(title),
+        started
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discord::{encode_frame, json_string};
+
+    #[test]
+    fn encodes_frames_and_escapes_titles() {
+        assert_eq!(
+            json_string("POKEMON \"RED\"\\\n"),
+            "\"POKEMON \\\"RED\\\"\\\\\\u000a\""
+        );
+        assert_eq!(encode_frame(1, "{}"), [1, 0, 0, 0, 2, 0, 0, 0, b'{', b'}']);
+    }
+}
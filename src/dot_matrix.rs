@@ -0,0 +1,114 @@
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+// Every Game Boy pixel becomes a block this big, its last row and column left for the grid.
+pub const SCALE: usize = 4;
+
+// Mimics the DMG's LCD on the way to the window: every pixel bleeds a little into its neighbours and is
+// drawn as a dot with a darker gap around it. Intensity runs from 0, the plain picture scaled up, to 1.
+pub struct DotMatrix {
+    intensity: f64,
+}
+
+impl DotMatrix {
+    pub fn new(intensity: f64) -> Self {
+        Self { intensity }
+    }
+
+    pub fn apply(&self, pixels: &[u32]) -> Vec<u32> {
+        let bleed = self.intensity / 4.0;
+        let grid = 1.0 - self.intensity / 2.0;
+        let width = WIDTH * SCALE;
+        let mut output = vec![0; width * HEIGHT * SCALE];
+        // Past the edges the picture repeats its border.
+        let at = |x: usize, y: usize| pixels[y.min(HEIGHT - 1) * WIDTH + x.min(WIDTH - 1)];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let neighbours = [
+                    at(x.saturating_sub(1), y),
+                    at(x + 1, y),
+                    at(x, y.saturating_sub(1)),
+                    at(x, y + 1),
+                ];
+                let dot = mix(at(x, y), average(&neighbours), bleed);
+                let gap = scale(dot, grid);
+                for dy in 0..SCALE {
+                    let row = (y * SCALE + dy) * width + x * SCALE;
+                    for dx in 0..SCALE {
+                        let edge = dx == SCALE - 1 || dy == SCALE - 1;
+                        output[row + dx] = if edge { gap } else { dot };
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+fn channels(color: u32) -> [f64; 3] {
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b].map(|c| c as f64)
+}
+
+fn pack([r, g, b]: [f64; 3]) -> u32 {
+    u32::from_be_bytes([0, r.round() as u8, g.round() as u8, b.round() as u8])
+}
+
+fn average(colors: &[u32]) -> u32 {
+    let mut sum = [0.0; 3];
+    for color in colors {
+        for (total, c) in sum.iter_mut().zip(channels(*color)) {
+            *total += c;
+        }
+    }
+    pack(sum.map(|total| total / colors.len() as f64))
+}
+
+fn mix(a: u32, b: u32, amount: f64) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    pack([0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * amount))
+}
+
+fn scale(color: u32, factor: f64) -> u32 {
+    pack(channels(color).map(|c| c * factor))
+}
+
+// "off" or an intensity from 0 to 1.
+pub fn parse_dot_matrix(value: &str) -> Option<Option<f64>> {
+    match value.to_lowercase().as_str() {
+        "off" => Some(None),
+        value => value
+            .parse::<f64>()
+            .ok()
+            .filter(|intensity| (0.0..=1.0).contains(intensity))
+            .map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dot_matrix::{parse_dot_matrix, DotMatrix, SCALE};
+
+    #[test]
+    fn draws_dots_with_a_grid_between_them() {
+        let mut pixels = vec![0xFFFFFF; 160 * 144];
+        pixels[0] = 0x000000;
+        let width = 160 * SCALE;
+
+        let plain = DotMatrix::new(0.0).apply(&pixels);
+        assert_eq!(plain.len(), width * 144 * SCALE);
+        assert_eq!(plain[SCALE - 1], 0x000000);
+        assert_eq!(plain[SCALE], 0xFFFFFF);
+
+        let lcd = DotMatrix::new(1.0).apply(&pixels);
+        // The black pixel picks up some of its white neighbours, and the white one beside it darkens.
+        assert_eq!(lcd[0], 0x202020);
+        assert_eq!(lcd[SCALE], 0xEFEFEF);
+        // Grid lines are half as bright.
+        assert_eq!(lcd[SCALE - 1], 0x101010);
+        assert_eq!(lcd[(SCALE - 1) * width + SCALE], 0x787878);
+
+        assert_eq!(parse_dot_matrix("OFF"), Some(None));
+        assert_eq!(parse_dot_matrix("0.5"), Some(Some(0.5)));
+        assert_eq!(parse_dot_matrix("2"), None);
+    }
+}
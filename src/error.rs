@@ -0,0 +1,61 @@
+use crate::prelude::*;
+use core::fmt::{Display, Formatter};
+#[cfg(feature = "std")]
+use std::io;
+
+#[derive(Debug)]
+pub enum FeboyError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    InvalidArchive(String),
+    InvalidPatch(String),
+    RomTruncated {
+        expected: usize,
+        actual: usize,
+    },
+    UnsupportedMapper(u8),
+    InvalidOpcode {
+        pc: u16,
+        opcode: u8,
+    },
+    InvalidState(String),
+    InvalidAssembly(String),
+    InvalidHeader(String),
+    InvalidSave(String),
+}
+
+impl Display for FeboyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            FeboyError::Io(e) => write!(f, "{}", e),
+            FeboyError::InvalidArchive(message) => write!(f, "invalid archive: {}", message),
+            FeboyError::InvalidPatch(message) => write!(f, "invalid patch: {}", message),
+            FeboyError::RomTruncated { expected, actual } => write!(
+                f,
+                "ROM truncated: header declares {} bytes but only {} were found",
+                expected, actual
+            ),
+            FeboyError::UnsupportedMapper(cartridge_type) => {
+                write!(f, "unsupported mapper 0x{:02X}", cartridge_type)
+            }
+            FeboyError::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode 0x{:02X} at PC={:04X}", opcode, pc)
+            }
+            FeboyError::InvalidState(message) => write!(f, "invalid save state: {}", message),
+            FeboyError::InvalidAssembly(message) => write!(f, "invalid assembly: {}", message),
+            FeboyError::InvalidHeader(message) => write!(f, "invalid header: {}", message),
+            FeboyError::InvalidSave(message) => write!(f, "invalid save file: {}", message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FeboyError {}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for FeboyError {
+    fn from(e: io::Error) -> Self {
+        FeboyError::Io(e)
+    }
+}
@@ -0,0 +1,66 @@
+use crate::video::{VideoFrame, VideoSink};
+use std::io::{self, Write};
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+// FNV-1a over the frame's pixels, fast enough to run every frame and stable across builds and platforms.
+pub fn frame_hash(pixels: &[u32]) -> u64 {
+    pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .fold(FNV_OFFSET, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+}
+
+// One line per rendered frame, its number and hash, e.g. "120 5b2e3d9f01c4a7e8". Two runs can be compared
+// with diff, and the first differing line is where they diverged.
+pub struct HashStream<W: Write> {
+    out: W,
+    // The first write that failed, after which the stream stops writing.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> HashStream<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, error: None }
+    }
+
+    pub fn write_frame(&mut self, frame: u64, pixels: &[u32]) -> io::Result<()> {
+        writeln!(self.out, "{} {:016x}", frame, frame_hash(pixels))
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+impl<W: Write> VideoSink for HashStream<W> {
+    fn frame(&mut self, frame: &VideoFrame) {
+        if self.error.is_none() {
+            self.error = self.write_frame(frame.number, frame.pixels).err();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame_hash::{frame_hash, HashStream};
+
+    #[test]
+    fn hashes_each_frame_on_its_own_line() {
+        let blank = vec![0xFFFFFFFF; 160 * 144];
+        let mut dot = blank.clone();
+        dot[160 * 72 + 80] = 0xFF000000;
+        assert_eq!(frame_hash(&[]), 0xCBF29CE484222325);
+        assert_ne!(frame_hash(&blank), frame_hash(&dot));
+
+        let mut stream = HashStream::new(vec![]);
+        stream.write_frame(1, &blank).unwrap();
+        stream.write_frame(2, &blank).unwrap();
+        let lines = String::from_utf8(stream.out).unwrap();
+        let hash = format!("{:016x}", frame_hash(&blank));
+        assert_eq!(lines, format!("1 {}\n2 {}\n", hash, hash));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::video::{VideoFrame, VideoSink};
+use std::mem::swap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+pub const FRAME_PIXELS: usize = 160 * 144;
+
+#[derive(Clone)]
+pub struct Frame {
+    pub pixels: Vec<u32>,
+    // The palette the frame was drawn with, for anything the frontend draws on top.
+    pub palette: [u32; 4],
+    // The buttons the game saw during the frame, laid out like the joypad's.
+    pub buttons: u8,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Self {
+            pixels: vec![0; FRAME_PIXELS],
+            palette: [0; 4],
+            buttons: 0,
+        }
+    }
+}
+
+struct Slot {
+    frame: Frame,
+    fresh: bool,
+}
+
+struct Shared {
+    slot: Mutex<Slot>,
+    ready: Condvar,
+}
+
+// Hands finished frames from the emulation thread to the one presenting them, triple buffered: each side
+// owns a buffer and the third sits between them. Sending swaps the finished frame into the middle and
+// receiving swaps it out, so neither side waits on the other and a frame the presenter missed is simply
+// replaced by the next one.
+pub fn frame_channel() -> (FrameSender, FrameReceiver) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(Slot {
+            frame: Frame::new(),
+            fresh: false,
+        }),
+        ready: Condvar::new(),
+    });
+    let sender = FrameSender {
+        shared: shared.clone(),
+        back: Frame::new(),
+    };
+    let receiver = FrameReceiver {
+        shared,
+        front: Frame::new(),
+        received: 0,
+    };
+    (sender, receiver)
+}
+
+pub struct FrameSender {
+    shared: Arc<Shared>,
+    back: Frame,
+}
+
+impl FrameSender {
+    pub fn send(&mut self, pixels: &[u32], palette: [u32; 4], buttons: u8) {
+        self.back.pixels.copy_from_slice(pixels);
+        self.back.palette = palette;
+        self.back.buttons = buttons;
+        let mut slot = self.shared.slot.lock().unwrap();
+        swap(&mut slot.frame, &mut self.back);
+        slot.fresh = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+impl VideoSink for FrameSender {
+    fn frame(&mut self, frame: &VideoFrame) {
+        self.send(frame.pixels, frame.palette, frame.buttons);
+    }
+}
+
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+    front: Frame,
+    received: usize,
+}
+
+impl FrameReceiver {
+    // Blocks until a frame is sent that hasn't been received yet, or the timeout runs out.
+    pub fn wait(&self, timeout: Duration) {
+        let slot = self.shared.slot.lock().unwrap();
+        let _ = self
+            .shared
+            .ready
+            .wait_timeout_while(slot, timeout, |slot| !slot.fresh);
+    }
+
+    // The newest frame sent, which stays the same until another one arrives.
+    pub fn latest(&mut self) -> &Frame {
+        let mut slot = self.shared.slot.lock().unwrap();
+        if slot.fresh {
+            swap(&mut slot.frame, &mut self.front);
+            slot.fresh = false;
+            self.received += 1;
+        }
+        &self.front
+    }
+
+    // How many frames have been received, which is fewer than were sent if the presenter fell behind.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frames::{frame_channel, FRAME_PIXELS};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn presents_only_the_newest_frame() {
+        let (mut sender, mut receiver) = frame_channel();
+        let start = Instant::now();
+        receiver.wait(Duration::from_millis(20));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for shade in 1..=3 {
+                    sender.send(&[shade; FRAME_PIXELS], [shade; 4], 0);
+                }
+            });
+        });
+        receiver.wait(Duration::from_secs(1));
+        assert_eq!(receiver.latest().pixels, vec![3; FRAME_PIXELS]);
+
+        sender.send(&[4; FRAME_PIXELS], [4; 4], 0x81);
+        assert_eq!(receiver.latest().palette, [4; 4]);
+        assert_eq!(receiver.latest().buttons, 0x81);
+        assert_eq!(receiver.latest().pixels, vec![4; FRAME_PIXELS]);
+        assert_eq!(receiver.received(), 2);
+        let start = Instant::now();
+        receiver.wait(Duration::from_millis(20));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
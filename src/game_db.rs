@@ -0,0 +1,90 @@
+use crate::cartridge::{CartridgeHeader, Quirks};
+use crate::config::Config;
+use crate::paths::config_dir;
+use std::fs::read_to_string;
+
+pub const GAME_DB_FILE: &str = "gamedb.ini";
+
+fn apply_entry(quirks: &mut Quirks, key: &str, value: &str) {
+    let code = || u8::from_str_radix(value.trim_start_matches("0x"), 16).ok();
+    match key {
+        "mapper" => quirks.cartridge_type = code(),
+        "ram_size" => quirks.ram_size = code(),
+        "multicart" => quirks.multicart = Some(matches!(value, "true" | "on" | "yes" | "1")),
+        _ => println!("Unknown game database key: {}", key),
+    }
+}
+
+// Entries are INI sections keyed by the header's global checksum, e.g. [0A6B], or by title and checksum
+// like game sections in the config, e.g. [TETRIS:0A6B]. The more specific section wins.
+#[derive(Default)]
+pub struct GameDb {
+    entries: Config,
+}
+
+impl GameDb {
+    pub fn load(portable: bool) -> Self {
+        let contents = read_to_string(config_dir(portable).join(GAME_DB_FILE)).unwrap_or_default();
+        GameDb::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            entries: Config::parse(contents),
+        }
+    }
+
+    pub fn quirks(&self, header: &CartridgeHeader) -> Quirks {
+        let mut quirks = Quirks::default();
+        let sections = [
+            format!("{:04X}", header.global_checksum),
+            Config::game_section(header),
+        ];
+        for section in sections.iter() {
+            for (key, value) in self.entries.section(section) {
+                apply_entry(&mut quirks, key, value);
+            }
+        }
+        quirks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{CartridgeHeader, Quirks, NINTENDO_LOGO};
+    use crate::game_db::GameDb;
+
+    #[test]
+    fn looks_up_quirks_by_checksum() {
+        let db = GameDb::parse(
+            "[0A6B]\nmapper = 03\nram_size = 0x02\n\
+             [TETRIS:0A6B]\nram_size = 03\nmulticart = true\n\
+             [1234]\nmapper = 01",
+        );
+        let mut header = CartridgeHeader {
+            title: "TETRIS".to_owned(),
+            cartridge_type: 0x01,
+            rom_size: 0,
+            ram_size: 0,
+            global_checksum: 0x0A6B,
+            logo: NINTENDO_LOGO,
+            logo_valid: true,
+            header_checksum_valid: true,
+        };
+        let quirks = db.quirks(&header);
+        assert_eq!(
+            quirks,
+            Quirks {
+                cartridge_type: Some(0x03),
+                ram_size: Some(0x03),
+                multicart: Some(true),
+            }
+        );
+        quirks.apply_to(&mut header);
+        assert!(header.has_battery());
+        assert_eq!(header.ram_bytes(), 0x8000);
+
+        header.global_checksum = 0;
+        assert_eq!(db.quirks(&header), Quirks::default());
+    }
+}
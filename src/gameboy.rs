@@ -1,20 +1,27 @@
-use std::ops::{Index, IndexMut};
+use core::ops::{Index, IndexMut};
 
+use crate::cartridge::{BankedAddress, Cartridge, LoadOptions};
+use crate::error::FeboyError;
 use crate::instruction::Command::*;
 use crate::instruction_fetcher::InstructionFetcher;
-use crate::interrupt::InterruptState::*;
 use crate::interrupt::IE_ADDRESS;
 use crate::interrupt::IF_ADDRESS;
-use crate::memory_map::MemoryMap;
+use crate::memory_map::{Clock, MemoryMap};
+use crate::prelude::*;
+use crate::profiler::{HandlerRun, InterruptProfiler};
 use crate::register::RegisterId::*;
 use crate::register::WordRegister::{ProgramCounter, StackPointer};
-use crate::register::{ByteRegister, Register, RegisterId, WordRegister};
-use std::cmp::max;
+use crate::register::{
+    Bit, ByteRegister, ConditionCode, FlagRegister, Register, RegisterId, WordRegister,
+};
+use crate::state::{StateField, StateReader, StateWriter};
+use crate::trace::{Trace, TraceEntry, TRACE_LENGTH};
+use core::cmp::max;
+
+const PC_HISTORY: usize = 64;
 
 use crate::instruction::InstructionOperand::{OpByte, OpHL, OpRegister};
-use crate::instruction::{Command, InstructionOperand};
-use crate::interrupt::InterruptId;
-use crate::interrupt::InterruptId::{JoypadInt, SerialInt, StatInt, TimerInt, VBlankInt};
+use crate::instruction::{Command, InstructionOperand, RstVec};
 
 pub struct Gameboy {
     pub reg: Register,
@@ -22,7 +29,13 @@ pub struct Gameboy {
     pub ime: bool,
     pub mem: MemoryMap,
     pub halted: bool,
-    bugged_pc: Option<WordRegister>,
+    halt_bug: bool,
+    locked: bool,
+    pc_history: [BankedAddress; PC_HISTORY],
+    executed: usize,
+    pc_range: Option<(u16, u16)>,
+    trace: Option<Trace>,
+    profiler: Option<InterruptProfiler>,
 }
 
 impl Gameboy {
@@ -33,14 +46,168 @@ impl Gameboy {
             ei_counter: -1,
             ime: false,
             halted: false,
-            bugged_pc: None,
+            halt_bug: false,
+            locked: false,
+            pc_history: [BankedAddress::default(); PC_HISTORY],
+            executed: 0,
+            pc_range: None,
+            trace: None,
+            profiler: None,
+        }
+    }
+
+    pub fn soft_reset(&mut self) {
+        self.mem.soft_reset();
+        self.reset_cpu();
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.mem.power_cycle();
+        self.reset_cpu();
+    }
+
+    // Pulls the cartridge and plugs in another without turning the console off, the trick some glitches and
+    // exploits rely on. The CPU, RAM and PPU carry on as they were; a ROM that fails to load leaves the old
+    // cartridge in.
+    pub fn swap_cartridge(&mut self, rom: &[u8], options: &LoadOptions) -> Result<(), FeboyError> {
+        self.mem.cartridge = Cartridge::new(rom.to_vec(), options)?;
+        Ok(())
+    }
+
+    fn reset_cpu(&mut self) {
+        self.reg = Register::new();
+        self.ei_counter = -1;
+        self.ime = false;
+        self.halted = false;
+        self.halt_bug = false;
+        self.locked = false;
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.mem.cartridge.header.global_checksum);
+        self.write_state(&mut state);
+        state.finish()
+    }
+
+    // For states saved to a file, which carry a thumbnail of the screen to pick them by.
+    pub fn save_state_with_thumbnail(&self) -> Vec<u8> {
+        let checksum = self.mem.cartridge.header.global_checksum;
+        let mut state = StateWriter::with_thumbnail(checksum, &self.mem.ppu.pixels);
+        self.write_state(&mut state);
+        state.finish()
+    }
+
+    // Every field a state holds, named by the component that saves it.
+    pub fn state_fields(&self) -> Vec<StateField> {
+        let mut state = StateWriter::labelled(self.mem.cartridge.header.global_checksum);
+        self.write_state(&mut state);
+        state.fields()
+    }
+
+    fn write_state(&self, state: &mut StateWriter) {
+        for (name, id) in [
+            ("A", A),
+            ("B", B),
+            ("C", C),
+            ("D", D),
+            ("E", E),
+            ("H", H),
+            ("L", L),
+        ] {
+            state.u8(name, self.reg[id].value);
         }
+        state.u8("F", self.reg.flags.value());
+        state.u16("SP", self.reg.sp.value());
+        state.u16("PC", self.reg.pc.value());
+        state.u8("EI delay", self.ei_counter as u8);
+        state.bool("IME", self.ime);
+        state.bool("halted", self.halted);
+        state.bool("halt bug", self.halt_bug);
+        state.bool("locked", self.locked);
+        self.mem.save_state(state);
+    }
+
+    // A state that fails halfway through leaves the machine as it was before the load.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), FeboyError> {
+        let backup = self.save_state();
+        let checksum = self.mem.cartridge.header.global_checksum;
+        let result =
+            StateReader::new(data, checksum).and_then(|mut state| self.read_state(&mut state));
+        if result.is_err() {
+            StateReader::new(&backup, checksum)
+                .and_then(|mut state| self.read_state(&mut state))?;
+        }
+        result
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        for id in [A, B, C, D, E, H, L] {
+            self.reg[id].value = state.u8()?;
+        }
+        self.reg.flags.set(state.u8()?);
+        self.reg.sp = StackPointer(state.u16()?);
+        self.reg.pc = ProgramCounter(state.u16()?);
+        self.ei_counter = state.u8()? as i8;
+        self.ime = state.bool()?;
+        self.halted = state.bool()?;
+        self.halt_bug = state.bool()?;
+        self.locked = state.bool()?;
+        self.mem.load_state(state)
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.mem.clock()
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    // The lowest and highest address fetched from since the last call, None if nothing ran.
+    pub fn take_pc_range(&mut self) -> Option<(u16, u16)> {
+        self.pc_range.take()
+    }
+
+    // The addresses of the last instructions fetched, oldest first.
+    pub fn pc_history(&self) -> Vec<BankedAddress> {
+        let start = self.executed.saturating_sub(PC_HISTORY);
+        (start..self.executed)
+            .map(|i| self.pc_history[i % PC_HISTORY])
+            .collect()
+    }
+
+    // Off by default, since it costs a copy of the registers for every instruction.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = enabled.then(|| Trace::new(TRACE_LENGTH));
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    pub fn set_interrupt_profiling(&mut self, enabled: bool) {
+        self.profiler = enabled.then(InterruptProfiler::new);
+    }
+
+    pub fn profiling_interrupts(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    // The V-blank and STAT handlers that returned since the last call, None unless profiling.
+    pub fn take_handler_runs(&mut self) -> Option<Vec<HandlerRun>> {
+        self.profiler.as_mut().map(InterruptProfiler::take_runs)
     }
 }
 
 impl Gameboy {
     #[deny(unreachable_patterns)]
-    pub fn cycle(&mut self) -> u8 {
+    pub fn cycle(&mut self) -> Result<u8, FeboyError> {
+        // Undefined opcodes hang the CPU for good, ignoring interrupts, while the rest of the hardware keeps running.
+        if self.locked {
+            self.micro_cycle();
+            return Ok(1);
+        }
+
         let interrupt_cycles = if self.handle_interrupts() { 5 } else { 0 };
 
         if self.halted {
@@ -54,16 +221,44 @@ impl Gameboy {
                     self.halted = false;
                 }
             }
-            return 1 + interrupt_cycles;
+            return Ok(1 + interrupt_cycles);
         }
 
         if interrupt_cycles != 0 {
-            return interrupt_cycles;
+            return Ok(interrupt_cycles);
         }
 
-        let instruction =
-            InstructionFetcher::fetch_instruction(self.reg.pc.value(), &self.reg, &mut self.mem);
+        let pc = self.reg.pc.value();
+        self.pc_history[self.executed % PC_HISTORY] = self.mem.cartridge.banked(pc);
+        self.executed += 1;
+        self.pc_range = Some(
+            self.pc_range
+                .map_or((pc, pc), |(low, high)| (low.min(pc), high.max(pc))),
+        );
+        let instruction = InstructionFetcher::fetch_instruction(
+            self.reg.pc.value(),
+            &self.reg,
+            &mut self.mem,
+            self.halt_bug,
+        )?;
         let (opcode, command) = (instruction.0, instruction.1);
+        if let Some(trace) = &mut self.trace {
+            trace.record(TraceEntry {
+                pc: self.mem.cartridge.banked(pc),
+                opcode,
+                registers: [
+                    self.reg[A].value,
+                    self.reg.flags.value(),
+                    self.reg[B].value,
+                    self.reg[C].value,
+                    self.reg[D].value,
+                    self.reg[E].value,
+                    self.reg[H].value,
+                    self.reg[L].value,
+                ],
+                sp: self.reg.sp.value(),
+            });
+        }
         let line = self.mem.ppu.ly();
         let _log = format!(
             "op:0x{:02x}|pc:{}|sp:{}|a:{}|b:{}|c:{}|d:{}|e:{}|h:{}|l:{}|f:{}|ly:{}|lt:{}",
@@ -83,27 +278,21 @@ impl Gameboy {
         );
         //println!("{}", log);
         //println!("{:?}", command);
-        self.set_pc(self.reg.pc.value() + command.size() as u16, false);
-
-        self.execute_instruction(command)
+        let size = command.size() as u16 - if self.halt_bug { 1 } else { 0 };
+        self.halt_bug = false;
+        self.set_pc(self.reg.pc.value() + size, false);
+
+        let cycles = self.execute_instruction(command);
+        if let Some(profiler) = &mut self.profiler {
+            let clock = self.mem.clock();
+            profiler.executed(self.reg.sp.value(), self.mem.ppu.mode, clock.cycles);
+        }
+        Ok(cycles)
     }
 
     fn execute_instruction(&mut self, command: Command) -> u8 {
         let command_cycles = self.handle_command(command);
 
-        match self.bugged_pc {
-            Some(ProgramCounter(pc)) => {
-                self.mem.memory.remove(pc as usize);
-                if pc < self.reg.pc.value() {
-                    self.set_pc(self.reg.pc.value() - 1, false);
-                }
-            }
-            None => {}
-            _ => panic!(),
-        }
-
-        self.bugged_pc = None;
-
         if !self.ime
             && self.halted
             && self.mem.read_without_cycle(IE_ADDRESS as u16)
@@ -112,9 +301,8 @@ impl Gameboy {
                 != 0
         {
             self.halted = false;
-            self.bugged_pc = Some(self.reg.pc);
-            let x = self.mem.read(self.reg.pc);
-            self.mem.memory.insert(self.reg.pc.value() as usize, x);
+            self.halt_bug = true;
+            self.micro_cycle();
         }
         if command != HALT {
             command_cycles
@@ -140,492 +328,483 @@ impl Gameboy {
         if !self.ime {
             return false;
         }
-        for interrupt_id in self.get_interrupts() {
-            if self.trigger_interrupt(&interrupt_id) {
-                return true;
-            }
+        if self.mem.interrupt_handler.highest_priority().is_none() {
+            return false;
         }
-        false
-    }
-
-    fn get_interrupts(&self) -> [InterruptId; 5] {
-        [VBlankInt, StatInt, TimerInt, SerialInt, JoypadInt]
+        self.dispatch_interrupt();
+        true
     }
 
-    fn trigger_interrupt(&mut self, interrupt_id: &InterruptId) -> bool {
-        let state = self.mem.interrupt_handler.get_state(*interrupt_id);
-        match state {
-            Active => {
-                self.micro_cycle();
-                self.micro_cycle();
-                self.ime = false;
-                self.mem.interrupt_handler.set(vec![*interrupt_id], false);
-                let [lo, hi] = self.reg.pc.value().to_le_bytes();
-                self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                self.mem.write(self.reg.sp, hi);
-                self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                self.mem.write(self.reg.sp, lo);
-                self.set_pc(*interrupt_id as u16, true);
-                true
+    fn dispatch_interrupt(&mut self) {
+        self.micro_cycle();
+        self.mem.trigger_oam_inc_dec_corruption(self.reg.sp);
+        self.micro_cycle();
+        self.ime = false;
+        let [lo, hi] = self.reg.pc.value().to_le_bytes();
+        self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
+        self.mem.write(self.reg.sp, hi);
+        // The vector is only picked once the high byte is pushed, so a push landing on IE can redirect or cancel the dispatch.
+        let interrupt = self.mem.interrupt_handler.highest_priority();
+        self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
+        self.mem.write(self.reg.sp, lo);
+        match interrupt {
+            Some(interrupt_id) => {
+                if let Some(profiler) = &mut self.profiler {
+                    let clock = self.mem.clock();
+                    let (sp, mode) = (self.reg.sp.value(), self.mem.ppu.mode);
+                    profiler.dispatched(interrupt_id, sp, mode, clock.cycles);
+                }
+                self.mem.interrupt_handler.set(vec![interrupt_id], false);
+                self.set_pc(interrupt_id as u16, true);
             }
-            Priority(priority_id) => self.trigger_interrupt(&priority_id),
-            Inactive | Enabled | Requested => false,
+            None => self.set_pc(0x0000, true),
         }
     }
 
+    // The opcode table: every command goes to the function that runs it. Conditional jumps, calls and
+    // returns report whether they were taken, which decides how long they take.
     fn handle_command(&mut self, command: Command) -> u8 {
-        let hl = self.reg.hl();
         let mut branch_taken = true;
 
         match command {
             JR_CC_I8(cc, _) | JP_CC_U16(cc, _) | RET_CC(cc) | CALL_CC_U16(cc, _) => {
                 if self.reg.cc_flag(cc) {
+                    if let CALL_CC_U16(..) = command {
+                        self.mem.trigger_oam_inc_dec_corruption(self.reg.sp);
+                    }
                     self.micro_cycle();
                 }
             }
             _ => {}
         }
         match command {
-            NOP => {}
-
-            ADD_A(op) => {
-                let n = self.get_op(op);
-                let (add, carry) =
-                    calc_with_carry(vec![self[A].value, n, 0], |a, b| a.overflowing_add(b));
-                self.reg.set_flags(
-                    add == 0,
-                    false,
-                    half_carry_8_add(self[A].value, n, 0),
-                    carry,
-                );
-                self[A].value = add;
+            NOP | STOP => {}
+            ADD_A(op) => self.add_a(op),
+            ADC_A(op) => self.adc_a(op),
+            SUB_A(op) => self.sub_a(op),
+            SBC_A(op) => self.sbc_a(op),
+            CP_A(op) => self.cp_a(op),
+            AND_A(op) => self.and_a(op),
+            OR_A(op) => self.or_a(op),
+            XOR_A(op) => self.xor_a(op),
+            INC_R8(id) => self.inc_r8(id),
+            DEC_R8(id) => self.dec_r8(id),
+            INCH_HL => self.inc_hl(),
+            DECH_HL => self.dec_hl(),
+            ADD_HL_R16(reg) => self.add_hl_r16(reg),
+            INC_R16(reg) => self.inc_r16(reg),
+            DEC_R16(reg) => self.dec_r16(reg),
+            ADD_SP_I8(n) => self.add_sp_i8(n),
+            LD_HL_SP_I8(n) => self.ld_hl_sp_i8(n),
+            DAA => self.daa(),
+            CPL => self.cpl(),
+            CCF => self.ccf(),
+            SCF => self.scf(),
+            RLC(op, small) | RRC(op, small) | RL(op, small) | RR(op, small) => {
+                self.rotate(command, op, small)
             }
+            SLA(op) | SRA(op) | SRL(op) => self.shift(command, op),
+            SWAP_R8(id) => self.swap_r8(id),
+            SWAP_HL => self.swap_hl(),
+            BIT_U3(bit, op) => self.bit_u3(bit, op),
+            RES_U3_R8(bit, id) => self[id].value &= !bit.0,
+            RES_U3_HL(bit) => self.res_u3_hl(bit),
+            SET_U3_R8(bit, id) => self[id].value |= bit.0,
+            SET_U3_HL(bit) => self.set_u3_hl(bit),
+            LD_R8_R8(a, b) => self[a].value = self[b].value,
+            LD_R8_U8(id, n) => self[id].value = n,
+            LD_R8_HL(id) => self[id].value = self.mem.read(self.reg.hl()),
+            LD_HL_R8(id) => self.mem.write(self.reg.hl(), self[id].value),
+            LDH_HL_U8(n) => self.mem.write(self.reg.hl(), n),
+            LD_R16_U16(reg, n) => self.set_word_register(n, reg),
+            LD_A_U8(n) => self[A].value = n,
+            LD_A_R16(reg) => self[A].value = self.mem.read(reg),
+            LD_R16_A(reg) => self.mem.write(reg, self[A]),
+            LDH_A_U16(n) => self[A].value = self.mem.read(n),
+            LDH_U16_A(n) => self.mem.write(n, self[A]),
+            LDH_A_U8(n) => self[A].value = self.mem.read(n),
+            LDH_U8_A(n) => self.mem.write(n, self[A].value),
+            LDH_A_C => self[A].value = self.mem.read(self[C]),
+            LDH_C_A => self.mem.write(self[C], self[A]),
+            LD_A_HLI => self.ld_a_hli(),
+            LD_A_HLD => self.ld_a_hld(),
+            LD_HLI_A => self.ld_hli_a(),
+            LD_HLD_A => self.ld_hld_a(),
+            LD_U16_SP(n) => self.ld_u16_sp(n),
+            LD_SP_HL => self.set_word_register_with_micro_cycle(self.reg.hl().value(), self.reg.sp),
+            PUSH_AF => self.push_af(),
+            PUSH_R16(reg) => self.push_r16(reg),
+            POP_R16(reg) => self.pop_r16(reg),
+            JP_U16(n) => self.set_pc(n, true),
+            JP_HL => self.set_pc(self.reg.hl().value(), false),
+            JR_I8(n) => self.set_pc(relative(self.reg.pc.value(), n), true),
+            CALL_U16(n) => self.call_u16(n),
+            RET => self.ret(),
+            RETI => self.reti(),
+            RST(rst_vec) => self.rst(rst_vec),
+            JP_CC_U16(cc, n) => branch_taken = self.jp_cc_u16(cc, n),
+            JR_CC_I8(cc, n) => branch_taken = self.jr_cc_i8(cc, n),
+            CALL_CC_U16(cc, n) => branch_taken = self.call_cc_u16(cc, n),
+            RET_CC(cc) => branch_taken = self.ret_cc(cc),
+            DI => self.ime = false,
+            EI => self.ei_counter = 2,
+            HALT => self.halted = true,
+            ILLEGAL => self.locked = true,
+        };
+        command.cycles(branch_taken)
+    }
 
-            ADC_A(op) => {
-                let carry = if self.reg.flags.c { 1 } else { 0 };
-                let n = self.get_op(op);
-                let (add, new_carry) =
-                    calc_with_carry(vec![self[A].value, n, carry], |a, b| a.overflowing_add(b));
-                self.reg.set_flags(
-                    add == 0,
-                    false,
-                    half_carry_8_add(self[A].value, n, carry),
-                    new_carry,
-                );
-                self[A].value = add;
-            }
+    fn add_a(&mut self, op: InstructionOperand) {
+        let n = self.get_op(op);
+        let (value, flags) = add8(self[A].value, n, false);
+        self[A].value = value;
+        self.reg.flags = flags;
+    }
 
-            AND_A(op) => {
-                self[A].value &= self.get_op(op);
-                self.reg.set_flags(self[A].value == 0, false, true, false);
-            }
+    fn adc_a(&mut self, op: InstructionOperand) {
+        let n = self.get_op(op);
+        let (value, flags) = add8(self[A].value, n, self.reg.flags.c);
+        self[A].value = value;
+        self.reg.flags = flags;
+    }
 
-            CP_A(op) => {
-                let n = self.get_op(op);
-                self.reg.set_flags(
-                    self[A].value == n,
-                    true,
-                    half_carry_8_sub(self[A].value, n, 0),
-                    n > self[A].value,
-                )
-            }
+    fn sub_a(&mut self, op: InstructionOperand) {
+        let n = self.get_op(op);
+        let (value, flags) = sub8(self[A].value, n, false);
+        self[A].value = value;
+        self.reg.flags = flags;
+    }
 
-            DEC_R8(id) => {
-                let reg = self[id].value;
-                self[id].value = reg.wrapping_sub(1);
-                let z = self[id].value == 0;
-                self.reg
-                    .set_flags(z, true, half_carry_8_sub(reg, 1, 0), self.reg.flags.c);
-            }
+    fn sbc_a(&mut self, op: InstructionOperand) {
+        let n = self.get_op(op);
+        let (value, flags) = sub8(self[A].value, n, self.reg.flags.c);
+        self[A].value = value;
+        self.reg.flags = flags;
+    }
 
-            INC_R8(id) => {
-                let reg = self[id].value;
-                self[id].value = reg.wrapping_add(1);
-                let z = self[id].value == 0;
-                let hc = half_carry_8_add(reg, 1, 0);
-                self.reg.set_flags(z, false, hc, self.reg.flags.c);
-            }
-            OR_A(op) => {
-                self[A].value |= self.get_op(op);
-                self.reg.set_flags(self[A].value == 0, false, false, false);
-            }
+    // A subtraction that only keeps the flags.
+    fn cp_a(&mut self, op: InstructionOperand) {
+        let n = self.get_op(op);
+        self.reg.flags = sub8(self[A].value, n, false).1;
+    }
 
-            SUB_A(op) => {
-                let n = self.get_op(op);
-                let (sub, c) =
-                    calc_with_carry(vec![self[A].value, n, 0], |a, b| a.overflowing_sub(b));
-                self.reg
-                    .set_flags(sub == 0, true, half_carry_8_sub(self[A].value, n, 0), c);
-                self[A].value = sub;
-            }
-            SBC_A(op) => {
-                let n = self.get_op(op);
-                let carry = if self.reg.flags.c { 1 } else { 0 };
-                let (sub, new_carry) =
-                    calc_with_carry(vec![self[A].value, n, carry], |a, b| a.overflowing_sub(b));
-                self.reg.set_flags(
-                    sub == 0,
-                    true,
-                    half_carry_8_sub(self[A].value, n, carry),
-                    new_carry,
-                );
-                self[A].value = sub;
-            }
+    fn and_a(&mut self, op: InstructionOperand) {
+        self[A].value &= self.get_op(op);
+        self.reg.set_flags(self[A].value == 0, false, true, false);
+    }
 
-            XOR_A(op) => {
-                self[A].value ^= self.get_op(op);
-                self.reg.set_flags(self[A].value == 0, false, false, false);
-            }
+    fn or_a(&mut self, op: InstructionOperand) {
+        self[A].value |= self.get_op(op);
+        self.reg.set_flags(self[A].value == 0, false, false, false);
+    }
 
-            ADD_HL_R16(reg) => {
-                let hc = half_carry_16_add(hl.value(), reg.value(), 0);
-                let (hl, carry) = hl.value().overflowing_add(reg.value());
-                self.set_word_register_with_micro_cycle(hl, self.reg.hl());
-                self.reg.set_flags(self.reg.flags.z, false, hc, carry);
-            }
+    fn xor_a(&mut self, op: InstructionOperand) {
+        self[A].value ^= self.get_op(op);
+        self.reg.set_flags(self[A].value == 0, false, false, false);
+    }
 
-            DECH_HL => {
-                let old = self.mem.read(hl);
-                self.mem.write(hl, old.wrapping_sub(1));
-                let hc = half_carry_8_sub(old, 1, 0);
-                self.reg
-                    .set_flags(old.wrapping_sub(1) == 0, true, hc, self.reg.flags.c);
-            }
+    fn inc_r8(&mut self, id: RegisterId) {
+        let (value, flags) = inc8(self[id].value, self.reg.flags);
+        self[id].value = value;
+        self.reg.flags = flags;
+    }
 
-            INCH_HL => {
-                let old = self.mem.read(hl);
-                self.mem.write(hl, old.wrapping_add(1));
-                let hc = half_carry_8_add(old, 1, 0);
-                self.reg
-                    .set_flags(old.wrapping_add(1) == 0, false, hc, self.reg.flags.c);
-            }
+    fn dec_r8(&mut self, id: RegisterId) {
+        let (value, flags) = dec8(self[id].value, self.reg.flags);
+        self[id].value = value;
+        self.reg.flags = flags;
+    }
 
-            DEC_R16(reg) => {
-                self.mem.trigger_oam_inc_dec_corruption(reg);
-                self.set_word_register_with_micro_cycle(reg.value().wrapping_sub(1), reg)
-            }
+    fn inc_hl(&mut self) {
+        let hl = self.reg.hl();
+        let (value, flags) = inc8(self.mem.read(hl), self.reg.flags);
+        self.mem.write(hl, value);
+        self.reg.flags = flags;
+    }
 
-            INC_R16(reg) => {
-                self.mem.trigger_oam_inc_dec_corruption(reg);
-                self.set_word_register_with_micro_cycle(reg.value().wrapping_add(1), reg)
-            }
+    fn dec_hl(&mut self) {
+        let hl = self.reg.hl();
+        let (value, flags) = dec8(self.mem.read(hl), self.reg.flags);
+        self.mem.write(hl, value);
+        self.reg.flags = flags;
+    }
 
-            RR(op, small) | RL(op, small) | RRC(op, small) | RLC(op, small) => {
-                let mut value = self.get_op(op);
-                let carry = if let RLC(..) | RL(..) = command {
-                    value & 128 != 0
-                } else {
-                    value & 1 != 0
-                };
-                let mask_condition = if let RRC(..) | RLC(..) = command {
-                    carry
-                } else {
-                    self.reg.flags.c
-                };
-                let mask = if mask_condition {
-                    if let RR(..) | RRC(..) = command {
-                        128
-                    } else {
-                        1
-                    }
-                } else {
-                    0
-                };
-
-                value = (if let RR(..) | RRC(..) = command {
-                    value >> 1
-                } else {
-                    value << 1
-                }) | mask;
-
-                let z = !small && value == 0;
-
-                match op {
-                    OpRegister(id) => self[id].value = value,
-                    OpHL => self.mem.write(hl, value),
-                    _ => panic!(),
-                };
-                self.reg.set_flags(z, false, false, carry);
-            }
+    fn add_hl_r16(&mut self, reg: WordRegister) {
+        let (value, flags) = add16(self.reg.hl().value(), reg.value(), self.reg.flags);
+        self.set_word_register_with_micro_cycle(value, self.reg.hl());
+        self.reg.flags = flags;
+    }
 
-            SRA(op) | SLA(op) | SRL(op) => {
-                let mut value = self.get_op(op);
-                let carry = value & if let SLA(_) = command { 128 } else { 1 } != 0;
-
-                value = if let SRA(_) = command {
-                    (value as i8 >> 1) as u8
-                } else if let SRL(_) = command {
-                    value >> 1
-                } else {
-                    ((value as i8) << 1) as u8
-                };
-
-                match op {
-                    OpHL => self.mem.write(hl, value),
-                    OpRegister(id) => self[id].value = value,
-                    _ => panic!(),
-                };
-
-                self.reg.set_flags(value == 0, false, false, carry);
-            }
+    fn inc_r16(&mut self, reg: WordRegister) {
+        self.mem.trigger_oam_inc_dec_corruption(reg);
+        self.set_word_register_with_micro_cycle(reg.value().wrapping_add(1), reg)
+    }
 
-            BIT_U3(bit, op) => {
-                self.reg.flags.z = (self.get_op(op) & bit.0) ^ bit.0 == bit.0;
-                self.reg.flags.n = false;
-                self.reg.flags.h = true;
-            }
+    fn dec_r16(&mut self, reg: WordRegister) {
+        self.mem.trigger_oam_inc_dec_corruption(reg);
+        self.set_word_register_with_micro_cycle(reg.value().wrapping_sub(1), reg)
+    }
 
-            RES_U3_R8(bit, id) => self[id].value &= !bit.0,
+    fn add_sp_i8(&mut self, n: i8) {
+        let (value, flags) = add_sp(self.reg.sp.value(), n);
+        self.reg.flags = flags;
+        self.micro_cycle();
+        self.set_word_register_with_micro_cycle(value, self.reg.sp);
+    }
 
-            RES_U3_HL(bit) => {
-                let x = self.mem.read(hl);
-                self.mem.write(hl, x & !bit.0)
-            }
+    fn ld_hl_sp_i8(&mut self, n: i8) {
+        let (value, flags) = add_sp(self.reg.sp.value(), n);
+        self.reg.flags = flags;
+        self.set_word_register_with_micro_cycle(value, self.reg.hl());
+    }
 
-            SET_U3_R8(bit, id) => self[id].value |= bit.0,
+    fn daa(&mut self) {
+        let (value, flags) = daa(self[A].value, self.reg.flags);
+        self[A].value = value;
+        self.reg.flags = flags;
+    }
 
-            SET_U3_HL(bit) => {
-                let x = self.mem.read(hl);
-                self.mem.write(hl, x | bit.0)
-            }
+    fn cpl(&mut self) {
+        self[A].value = !self[A].value;
+        self.reg.flags.n = true;
+        self.reg.flags.h = true;
+    }
 
-            SWAP_R8(id) => {
-                self.reg.set_flags(self[id].value == 0, false, false, false);
-                self[id].value = self[id].value.rotate_left(4);
-            }
+    fn ccf(&mut self) {
+        self.reg.flags.n = false;
+        self.reg.flags.h = false;
+        self.reg.flags.c = !self.reg.flags.c;
+    }
 
-            SWAP_HL => {
-                let x = self.mem.read(hl);
-                self.mem.write(hl, x.rotate_left(4));
-                self.reg.set_flags(x == 0, false, false, false);
-            }
+    fn scf(&mut self) {
+        self.reg.flags.n = false;
+        self.reg.flags.h = false;
+        self.reg.flags.c = true;
+    }
 
-            LD_R8_R8(a, b) => self[a].value = self[b].value,
+    // RLCA, RRCA, RLA and RRA are the small versions, which always clear Z.
+    fn rotate(&mut self, command: Command, op: InstructionOperand, small: bool) {
+        let value = self.get_op(op);
+        let left = matches!(command, RLC(..) | RL(..));
+        let carry = if left { value & 0x80 } else { value & 0x01 } != 0;
+        // RLC and RRC wrap the bit that falls out around, RL and RR rotate through the carry.
+        let fill = match command {
+            RLC(..) | RRC(..) => carry,
+            _ => self.reg.flags.c,
+        };
+        let value = match left {
+            true => value << 1 | fill as u8,
+            false => value >> 1 | (fill as u8) << 7,
+        };
+        self.write_op(op, value);
+        self.reg
+            .set_flags(!small && value == 0, false, false, carry);
+    }
 
-            LD_R8_U8(a, b) => self[a].value = b,
-            LD_R16_U16(a, b) => self.set_word_register(b, a),
-            LD_HL_R8(id) => {
-                self.mem.write(hl, self[id].value);
-            }
-            LD_R8_HL(id) => self[id].value = self.mem.read(hl),
-            LD_R16_A(n) => self.mem.write(n, self[A]),
-            LDH_U16_A(n) => self.mem.write(n, self[A]),
-            LDH_C_A => self.mem.write(self[C], self[A]),
-            LD_A_U8(n) => self[A].value = n,
-            LD_A_R16(n) => self[A].value = self.mem.read(n),
-            LDH_A_U16(n) => self[A].value = self.mem.read(n),
-            LDH_A_U8(n) => {
-                let x = self.mem.read(n);
-                self[A].value = x;
-            }
-            LDH_U8_A(n) => self.mem.write(n, self[A].value),
-            LDH_HL_U8(n) => self.mem.write(hl, n),
-            LDH_A_C => self[A].value = self.mem.read(self[C]),
-            LD_A_HLD => {
-                self.set_word_register(hl.value().wrapping_sub(1), self.reg.hl());
-                self[A].value = self.mem.read(hl);
-            }
-            LD_HLD_A => {
-                self.set_word_register(hl.value().wrapping_sub(1), self.reg.hl());
-                self.mem.write(hl, self[A]);
-            }
-            LD_A_HLI => {
-                self[A].value = self.mem.read(hl);
-                self.set_word_register(hl.value().wrapping_add(1), self.reg.hl());
-            }
-            LD_HLI_A => {
-                self.mem.write(hl, self[A]);
-                self.set_word_register(hl.value().wrapping_add(1), self.reg.hl());
-            }
-            CALL_U16(n) => {
-                self.micro_cycle();
-                let [lo, hi] = self.reg.pc.value().to_le_bytes();
-                self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                self.mem.write(self.reg.sp, hi);
-                self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                self.mem.write(self.reg.sp, lo);
-                self.set_pc(n, false);
-            }
+    fn shift(&mut self, command: Command, op: InstructionOperand) {
+        let value = self.get_op(op);
+        let (value, carry) = match command {
+            SLA(_) => (value << 1, value & 0x80 != 0),
+            // SRA keeps the sign bit.
+            SRA(_) => ((value as i8 >> 1) as u8, value & 0x01 != 0),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+        self.write_op(op, value);
+        self.reg.set_flags(value == 0, false, false, carry);
+    }
 
-            JP_HL => self.set_pc(self.reg.hl().value(), false),
-            JP_U16(n) => self.set_pc(n, true),
-            JR_I8(n) => self.set_pc((self.reg.pc.value() as i16 + n as i16) as u16, true),
-            CPL => {
-                self[A].value = !self[A].value;
-                self.reg
-                    .set_flags(self.reg.flags.z, true, true, self.reg.flags.c);
-            }
-            RET => {
-                let lo = self.mem.read(self.reg.sp);
-                let hi = self.mem.read(self.reg.sp.value().wrapping_add(1));
-                self.set_pc(u16::from_le_bytes([lo, hi]), true);
-                self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
-            }
-            RETI => {
-                let lo = self.mem.read(self.reg.sp);
-                let hi = self.mem.read(self.reg.sp.value().wrapping_add(1));
-                self.set_pc(u16::from_le_bytes([lo, hi]), true);
-                self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
-                self.ei_counter = 1;
-                self.ime = true;
-            }
-            RST(rst_vec) => {
-                let [lo, hi] = self.reg.pc.value().to_le_bytes();
-                self.set_pc(rst_vec as u16, true);
-                self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                self.mem.write(self.reg.sp, hi);
-                self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                self.mem.write(self.reg.sp, lo);
-            }
-            ADD_SP_I8(n) | LD_HL_SP_I8(n) => {
-                let a = self.reg.sp.value();
-                let b = n as i8 as i16 as u16;
-                let h = (a & 0x000F) + (b & 0x000F) > 0x000F;
-                let c = (a & 0x00FF) + (b & 0x00FF) > 0x00FF;
-                self.reg.set_flags(false, false, h, c);
-                if let ADD_SP_I8(_) = command {
-                    self.micro_cycle()
-                }
-                self.set_word_register_with_micro_cycle(
-                    a.wrapping_add(b),
-                    if let ADD_SP_I8(_) = command {
-                        self.reg.sp
-                    } else {
-                        self.reg.hl()
-                    },
-                )
-            }
-            LD_U16_SP(n) => {
-                let [lo, hi] = self.reg.sp.value().to_le_bytes();
-                self.mem.write(n, lo);
-                self.mem.write(n + 1, hi);
-            }
-            LD_SP_HL => self.set_word_register_with_micro_cycle(self.reg.hl().value(), self.reg.sp),
+    fn swap_r8(&mut self, id: RegisterId) {
+        self.reg.set_flags(self[id].value == 0, false, false, false);
+        self[id].value = self[id].value.rotate_left(4);
+    }
 
-            POP_R16(reg) => match reg {
-                WordRegister::Double(
-                    ByteRegister { value: _, id: high },
-                    ByteRegister { value: _, id: low },
-                ) => {
-                    for id in &[low, high] {
-                        self[*id].value = self.mem.read(self.reg.sp);
-                        self.set_word_register(self.reg.sp.value().wrapping_add(1), self.reg.sp);
-                    }
-                }
-                WordRegister::AccFlag(..) => {
-                    self.reg.flags.set(self.mem.read(self.reg.sp));
-                    self[A].value = self.mem.read(self.reg.sp.value().wrapping_add(1));
-                    self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
-                }
+    fn swap_hl(&mut self) {
+        let hl = self.reg.hl();
+        let value = self.mem.read(hl);
+        self.mem.write(hl, value.rotate_left(4));
+        self.reg.set_flags(value == 0, false, false, false);
+    }
 
-                _ => panic!(),
-            },
-            PUSH_AF => {
-                self.micro_cycle();
-                self.set_word_register(self.reg.sp.value().wrapping_sub(1), self.reg.sp);
-                self.mem.write(self.reg.sp, self[A]);
-                self.set_word_register(self.reg.sp.value().wrapping_sub(1), self.reg.sp);
-                self.mem.write(self.reg.sp, self.reg.flags.value());
-            }
-            PUSH_R16(reg) => {
-                self.micro_cycle();
-                match reg {
-                    WordRegister::Double(
-                        ByteRegister { value: _, id: high },
-                        ByteRegister { value: _, id: low },
-                    ) => {
-                        for id in &[high, low] {
-                            self.set_word_register(
-                                self.reg.sp.value().wrapping_sub(1),
-                                self.reg.sp,
-                            );
-                            let value = self[*id].value;
-                            self.mem.write(self.reg.sp, value);
-                        }
-                    }
-                    _ => panic!(),
-                }
-            }
-            CCF => {
-                self.reg.flags.n = false;
-                self.reg.flags.h = false;
-                self.reg.flags.c = !self.reg.flags.c;
-            }
-            DAA => {
-                // note: assumes a is a uint8_t and wraps from 0xff to 0
-                if !self.reg.flags.n {
-                    // after an addition, adjust if (half-)carry occurred or if result is out of bounds
-                    if self.reg.flags.c || self[A].value > 0x99 {
-                        self[A].value = self[A].value.wrapping_add(0x60);
-                        self.reg.flags.c = true;
-                    }
-                    if self.reg.flags.h || (self[A].value & 0x0f) > 0x09 {
-                        self[A].value = self[A].value.wrapping_add(0x6);
-                    }
-                } else {
-                    if self.reg.flags.c {
-                        self[A].value = self[A].value.wrapping_sub(0x60);
-                    }
-                    if self.reg.flags.h {
-                        self[A].value = self[A].value.wrapping_sub(0x6);
-                    }
-                }
-                self.reg.flags.z = self[A].value == 0;
-                self.reg.flags.h = false;
+    fn bit_u3(&mut self, bit: Bit, op: InstructionOperand) {
+        self.reg.flags.z = self.get_op(op) & bit.0 == 0;
+        self.reg.flags.n = false;
+        self.reg.flags.h = true;
+    }
+
+    fn res_u3_hl(&mut self, bit: Bit) {
+        let hl = self.reg.hl();
+        let value = self.mem.read(hl);
+        self.mem.write(hl, value & !bit.0)
+    }
+
+    fn set_u3_hl(&mut self, bit: Bit) {
+        let hl = self.reg.hl();
+        let value = self.mem.read(hl);
+        self.mem.write(hl, value | bit.0)
+    }
+
+    fn ld_a_hli(&mut self) {
+        let hl = self.reg.hl();
+        self.mem.trigger_oam_inc_dec_corruption(hl);
+        self[A].value = self.mem.read(hl);
+        self.set_word_register(hl.value().wrapping_add(1), hl);
+    }
+
+    fn ld_a_hld(&mut self) {
+        let hl = self.reg.hl();
+        self.mem.trigger_oam_inc_dec_corruption(hl);
+        self.set_word_register(hl.value().wrapping_sub(1), hl);
+        self[A].value = self.mem.read(hl);
+    }
+
+    fn ld_hli_a(&mut self) {
+        let hl = self.reg.hl();
+        self.mem.write(hl, self[A]);
+        self.set_word_register(hl.value().wrapping_add(1), hl);
+    }
+
+    fn ld_hld_a(&mut self) {
+        let hl = self.reg.hl();
+        self.set_word_register(hl.value().wrapping_sub(1), hl);
+        self.mem.write(hl, self[A]);
+    }
+
+    fn ld_u16_sp(&mut self, n: u16) {
+        let [lo, hi] = self.reg.sp.value().to_le_bytes();
+        self.mem.write(n, lo);
+        self.mem.write(n.wrapping_add(1), hi);
+    }
+
+    fn push_af(&mut self) {
+        self.mem.trigger_oam_inc_dec_corruption(self.reg.sp);
+        self.micro_cycle();
+        self.set_word_register(self.reg.sp.value().wrapping_sub(1), self.reg.sp);
+        self.mem.write(self.reg.sp, self[A]);
+        self.set_word_register(self.reg.sp.value().wrapping_sub(1), self.reg.sp);
+        self.mem.write(self.reg.sp, self.reg.flags.value());
+    }
+
+    fn push_r16(&mut self, reg: WordRegister) {
+        self.mem.trigger_oam_inc_dec_corruption(self.reg.sp);
+        self.micro_cycle();
+        let (high, low) = match reg {
+            WordRegister::Double(high, low) => (high.id, low.id),
+            _ => panic!(),
+        };
+        for id in [high, low] {
+            self.set_word_register(self.reg.sp.value().wrapping_sub(1), self.reg.sp);
+            self.mem.write(self.reg.sp, self[id].value);
+        }
+    }
+
+    fn pop_r16(&mut self, reg: WordRegister) {
+        let (lo, hi) = self.pop_word();
+        match reg {
+            WordRegister::Double(high, low) => {
+                self[low.id].value = lo;
+                self[high.id].value = hi;
             }
-            DI => self.ime = false,
-            EI => self.ei_counter = 2,
-            HALT => self.halted = true,
-            SCF => {
-                self.reg.flags.n = false;
-                self.reg.flags.h = false;
-                self.reg.flags.c = true;
+            WordRegister::AccFlag(..) => {
+                self.reg.flags.set(lo);
+                self[A].value = hi;
             }
+            _ => panic!(),
+        }
+        self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
+    }
 
-            RET_CC(cc) => {
-                if self.reg.cc_flag(cc) {
-                    let lo = self.mem.read(self.reg.sp);
-                    let hi = self.mem.read(self.reg.sp.value().wrapping_add(1));
-                    self.set_pc(u16::from_le_bytes([lo, hi]), false);
-                    self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
-                } else {
-                    branch_taken = false
-                }
-                self.micro_cycle();
-            }
+    fn push_pc(&mut self) {
+        let [lo, hi] = self.reg.pc.value().to_le_bytes();
+        self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
+        self.mem.write(self.reg.sp, hi);
+        self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
+        self.mem.write(self.reg.sp, lo);
+    }
 
-            JP_CC_U16(cc, n) => {
-                if self.reg.cc_flag(cc) {
-                    self.set_pc(n, false)
-                } else {
-                    branch_taken = false
-                }
-            }
+    fn call_u16(&mut self, n: u16) {
+        self.mem.trigger_oam_inc_dec_corruption(self.reg.sp);
+        self.micro_cycle();
+        self.push_pc();
+        self.set_pc(n, false);
+    }
 
-            JR_CC_I8(cc, n) => {
-                if self.reg.cc_flag(cc) {
-                    self.set_pc((self.reg.pc.value() as i16 + n as i16) as u16, false)
-                } else {
-                    branch_taken = false
-                }
-            }
+    fn ret(&mut self) {
+        let (lo, hi) = self.pop_word();
+        self.set_pc(u16::from_le_bytes([lo, hi]), true);
+        self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
+    }
 
-            CALL_CC_U16(cc, n) => {
-                if self.reg.cc_flag(cc) {
-                    let [lo, hi] = self.reg.pc.value().to_le_bytes();
-                    self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                    self.mem.write(self.reg.sp, hi);
-                    self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
-                    self.mem.write(self.reg.sp, lo);
-                    self.set_pc(n, false);
-                } else {
-                    branch_taken = false
-                }
-            }
+    fn reti(&mut self) {
+        self.ret();
+        self.ei_counter = 1;
+        self.ime = true;
+    }
 
-            STOP => {}
-        };
-        command.cycles(branch_taken)
+    // The jump's internal cycle comes before the pushes.
+    fn rst(&mut self, rst_vec: RstVec) {
+        let pc = self.reg.pc.value();
+        self.mem.trigger_oam_inc_dec_corruption(self.reg.sp);
+        self.set_pc(rst_vec as u16, true);
+        let [lo, hi] = pc.to_le_bytes();
+        self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
+        self.mem.write(self.reg.sp, hi);
+        self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
+        self.mem.write(self.reg.sp, lo);
+    }
+
+    // The extra cycle a taken branch takes is spent before the opcode's own function runs.
+    fn jp_cc_u16(&mut self, cc: ConditionCode, n: u16) -> bool {
+        let taken = self.reg.cc_flag(cc);
+        if taken {
+            self.set_pc(n, false)
+        }
+        taken
+    }
+
+    fn jr_cc_i8(&mut self, cc: ConditionCode, n: i8) -> bool {
+        let taken = self.reg.cc_flag(cc);
+        if taken {
+            self.set_pc(relative(self.reg.pc.value(), n), false)
+        }
+        taken
+    }
+
+    fn call_cc_u16(&mut self, cc: ConditionCode, n: u16) -> bool {
+        let taken = self.reg.cc_flag(cc);
+        if taken {
+            self.push_pc();
+            self.set_pc(n, false);
+        }
+        taken
+    }
+
+    fn ret_cc(&mut self, cc: ConditionCode) -> bool {
+        let taken = self.reg.cc_flag(cc);
+        if taken {
+            let (lo, hi) = self.pop_word();
+            self.set_pc(u16::from_le_bytes([lo, hi]), false);
+            self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
+        }
+        self.micro_cycle();
+        taken
+    }
+
+    fn write_op(&mut self, op: InstructionOperand, value: u8) {
+        match op {
+            OpRegister(id) => self[id].value = value,
+            OpHL => self.mem.write(self.reg.hl(), value),
+            OpByte(_) => panic!(),
+        }
+    }
+
+    // Both stack reads increment SP on the bus, so each one can trigger the OAM bug's read-during-increment pattern.
+    fn pop_word(&mut self) -> (u8, u8) {
+        let sp = self.reg.sp.value();
+        self.mem.trigger_oam_inc_dec_corruption(sp);
+        let lo = self.mem.read(sp);
+        self.mem.trigger_oam_inc_dec_corruption(sp.wrapping_add(1));
+        let hi = self.mem.read(sp.wrapping_add(1));
+        (lo, hi)
     }
 
     fn micro_cycle(&mut self) {
@@ -664,29 +843,286 @@ impl IndexMut<RegisterId> for Gameboy {
     }
 }
 
-fn calc_with_carry<T: Copy>(operands: Vec<T>, op: fn(T, T) -> (T, bool)) -> (T, bool) {
-    let mut c = false;
-    let mut acc = operands[0];
-    for x in operands[1..].iter() {
-        if !c {
-            let res = op(acc, *x);
-            acc = res.0;
-            c = res.1;
-        } else {
-            acc = op(acc, *x).0
+fn relative(pc: u16, n: i8) -> u16 {
+    pc.wrapping_add(n as i16 as u16)
+}
+
+// The arithmetic returns its flags rather than setting them, so each instruction's rules can be checked
+// on their own.
+fn add8(a: u8, b: u8, carry: bool) -> (u8, FlagRegister) {
+    let sum = a as u16 + b as u16 + carry as u16;
+    let h = (a & 0x0F) + (b & 0x0F) + carry as u8 > 0x0F;
+    let value = sum as u8;
+    (value, flags(value == 0, false, h, sum > 0xFF))
+}
+
+fn sub8(a: u8, b: u8, carry: bool) -> (u8, FlagRegister) {
+    let difference = a as i16 - b as i16 - carry as i16;
+    let h = (a & 0x0F) < (b & 0x0F) + carry as u8;
+    let value = difference as u8;
+    (value, flags(value == 0, true, h, difference < 0))
+}
+
+// INC and DEC leave the carry alone.
+fn inc8(value: u8, old: FlagRegister) -> (u8, FlagRegister) {
+    let (value, new) = add8(value, 1, false);
+    (value, FlagRegister { c: old.c, ..new })
+}
+
+fn dec8(value: u8, old: FlagRegister) -> (u8, FlagRegister) {
+    let (value, new) = sub8(value, 1, false);
+    (value, FlagRegister { c: old.c, ..new })
+}
+
+// ADD HL,r16 leaves Z alone. H is the carry out of bit 11, as the high bytes are added after the low ones.
+fn add16(a: u16, b: u16, old: FlagRegister) -> (u16, FlagRegister) {
+    let (value, c) = a.overflowing_add(b);
+    let h = (a & 0x0FFF) + (b & 0x0FFF) > 0x0FFF;
+    (value, flags(old.z, false, h, c))
+}
+
+// ADD SP,i8 and LD HL,SP+i8 take their flags from an 8-bit add of the offset to SP's low byte.
+fn add_sp(sp: u16, n: i8) -> (u16, FlagRegister) {
+    let n = n as i16 as u16;
+    let h = (sp & 0x000F) + (n & 0x000F) > 0x000F;
+    let c = (sp & 0x00FF) + (n & 0x00FF) > 0x00FF;
+    (sp.wrapping_add(n), flags(false, false, h, c))
+}
+
+// Turns the result of adding or subtracting two BCD numbers back into BCD, going by N and the carries the
+// operation left. C is only ever set, never cleared: a carry out of the operation is a carry out of the
+// BCD result too.
+fn daa(a: u8, old: FlagRegister) -> (u8, FlagRegister) {
+    let mut value = a;
+    let mut c = old.c;
+    if !old.n {
+        if old.c || value > 0x99 {
+            value = value.wrapping_add(0x60);
+            c = true;
+        }
+        if old.h || value & 0x0F > 0x09 {
+            value = value.wrapping_add(0x06);
+        }
+    } else {
+        if old.c {
+            value = value.wrapping_sub(0x60);
+        }
+        if old.h {
+            value = value.wrapping_sub(0x06);
         }
     }
-    (acc, c)
+    (value, flags(value == 0, old.n, false, c))
 }
 
-fn half_carry_8_add(a: u8, b: u8, c: u8) -> bool {
-    (a & 0xF) + (b & 0xF) + c > 0xF
+fn flags(z: bool, n: bool, h: bool, c: bool) -> FlagRegister {
+    FlagRegister { z, n, h, c }
 }
 
-fn half_carry_8_sub(a: u8, b: u8, c: u8) -> bool {
-    (a & 0x0F) < (b & 0x0F) + c
+pub fn step(gameboy: &mut Gameboy) -> Result<i64, FeboyError> {
+    let previously_halted = gameboy.halted;
+    let cycles = gameboy.cycle()? as u16;
+    let mem_cycles = cycles - gameboy.mem.cycles;
+    if mem_cycles != 0 && !previously_halted && !gameboy.halted {
+        panic!("Cycle count after considering reads/writes: mem_cycles {} | cycles: {} | micro_ops: {}", mem_cycles, cycles, gameboy.mem.cycles)
+    } else if mem_cycles != 0 {
+        for _ in 0..mem_cycles {
+            gameboy.mem.cycle();
+        }
+    }
+    gameboy.mem.cycles = 0;
+    Ok(cycles as i64 * 4)
 }
 
-fn half_carry_16_add(a: u16, b: u16, c: u16) -> bool {
-    (a & 0x07FF) + (b & 0x07FF) + c > 0x07FF
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::LoadOptions;
+    use crate::gameboy::{add16, add8, add_sp, daa, dec8, flags, sub8, Gameboy};
+    use crate::instruction::Command::{ADD_SP_I8, LD_HL_SP_I8, POP_R16, PUSH_AF};
+    use crate::memory_map::{FlatBus, MemoryMap};
+    use crate::register::RegisterId::A;
+    use crate::register::WordRegister::{ProgramCounter, StackPointer};
+
+    #[test]
+    fn swapping_cartridges_keeps_the_console_running() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4000] = 0x11;
+        let mem = MemoryMap::new(&rom, &"swap".to_owned(), &LoadOptions::default()).unwrap();
+        let mut gameboy = Gameboy::new(mem);
+        gameboy.mem.wram[0] = 0x42;
+        gameboy.reg.pc = ProgramCounter(0xC000);
+
+        rom[0x4000] = 0x22;
+        gameboy
+            .swap_cartridge(&rom, &LoadOptions::default())
+            .unwrap();
+        assert_eq!(gameboy.mem.peek(0x4000), 0x22);
+        assert_eq!(gameboy.mem.peek(0xC000), 0x42);
+        assert_eq!(gameboy.reg.pc.value(), 0xC000);
+
+        // The header promises 128KB the ROM doesn't have.
+        rom[0x0148] = 0x02;
+        rom[0x4000] = 0x33;
+        assert!(gameboy
+            .swap_cartridge(&rom, &LoadOptions::default())
+            .is_err());
+        assert_eq!(gameboy.mem.peek(0x4000), 0x22);
+    }
+
+    #[test]
+    fn add8_sets_half_carry_and_carry() {
+        assert_eq!(
+            add8(0x0F, 0x01, false),
+            (0x10, flags(false, false, true, false))
+        );
+        assert_eq!(
+            add8(0xFF, 0x00, true),
+            (0x00, flags(true, false, true, true))
+        );
+    }
+
+    #[test]
+    fn sub8_sets_borrows() {
+        assert_eq!(
+            sub8(0x10, 0x01, false),
+            (0x0F, flags(false, true, true, false))
+        );
+        assert_eq!(
+            sub8(0x00, 0x00, true),
+            (0xFF, flags(false, true, true, true))
+        );
+    }
+
+    // DEC keeps the carry it was given.
+    #[test]
+    fn dec8_keeps_the_carry() {
+        let carry = flags(false, false, false, true);
+        assert_eq!(dec8(0x01, carry), (0x00, flags(true, true, false, true)));
+    }
+
+    // 9 + 1 and 99 + 1 in BCD, then 10 - 1.
+    #[test]
+    fn daa_adjusts_after_adds_and_subtracts() {
+        let none = flags(false, false, false, false);
+        assert_eq!(daa(0x0A, none), (0x10, none));
+        assert_eq!(daa(0x9A, none), (0x00, flags(true, false, false, true)));
+        let (value, borrowed) = sub8(0x10, 0x01, false);
+        assert_eq!(
+            daa(value, borrowed),
+            (0x09, flags(false, true, false, false))
+        );
+    }
+
+    // The offset is added to SP's low byte for the flags, signed or not, and Z is always clear.
+    #[test]
+    fn add_sp_takes_flags_from_the_low_byte() {
+        let none = flags(false, false, false, false);
+        assert_eq!(add_sp(0x00FF, 1), (0x0100, flags(false, false, true, true)));
+        assert_eq!(add_sp(0x0000, -1), (0xFFFF, none));
+        assert_eq!(
+            add_sp(0x000F, -1),
+            (0x000E, flags(false, false, true, true))
+        );
+    }
+
+    #[test]
+    fn sp_offset_commands_set_their_flags() {
+        let mut gameboy = Gameboy::new(MemoryMap::blank());
+        gameboy.mem.flat_bus = Some(FlatBus::new());
+        gameboy.reg.sp = StackPointer(0xFFF8);
+        assert_eq!(gameboy.handle_command(LD_HL_SP_I8(2)), 3);
+        assert_eq!(gameboy.reg.hl().value(), 0xFFFA);
+        assert_eq!(gameboy.reg.flags, flags(false, false, false, false));
+        assert_eq!(gameboy.handle_command(ADD_SP_I8(8)), 4);
+        assert_eq!(gameboy.reg.sp.value(), 0x0000);
+        assert_eq!(gameboy.reg.flags, flags(false, false, true, true));
+    }
+
+    #[test]
+    fn push_and_pop_af_keep_flags_in_the_high_nibble() {
+        let mut gameboy = Gameboy::new(MemoryMap::blank());
+        gameboy.mem.flat_bus = Some(FlatBus::new());
+        gameboy.reg.sp = StackPointer(0xD000);
+        gameboy.reg[A].value = 0x12;
+        gameboy.reg.flags = flags(false, true, false, true);
+        gameboy.handle_command(PUSH_AF);
+        let bus = gameboy.mem.flat_bus.as_ref().unwrap();
+        assert_eq!(bus.memory[0xCFFE..0xD000], [0x50, 0x12]);
+
+        // The low nibble of F doesn't exist, so it pops as 0.
+        gameboy.mem.flat_bus.as_mut().unwrap().memory[0xCFFE] = 0xAF;
+        gameboy.handle_command(POP_R16(gameboy.reg.af()));
+        assert_eq!(gameboy.reg.flags, flags(true, false, true, false));
+        assert_eq!(gameboy.reg.af().value(), 0x12A0);
+        assert_eq!(gameboy.reg.sp.value(), 0xD000);
+    }
+
+    // A carry into a bit shows up as that bit of the sum differing from the operands' XOR.
+    #[test]
+    fn sixteen_bit_adds_carry_from_the_right_bits() {
+        for a in (0..=0xFFFF_u32).step_by(7) {
+            for b in (0..=0xFFFF_u32).step_by(251) {
+                let sum = a + b;
+                let carries = a ^ b ^ sum;
+                let expected = flags(true, false, carries & 0x1000 != 0, sum > 0xFFFF);
+                let old = flags(true, true, false, false);
+                assert_eq!(add16(a as u16, b as u16, old), (sum as u16, expected));
+            }
+        }
+        assert!(add16(0x0800, 0x0800, flags(false, false, false, false)).1.h);
+        assert!(!add16(0x0400, 0x0400, flags(false, false, false, false)).1.h);
+
+        for sp in (0..=0xFFFF_u16).step_by(3) {
+            for n in -128..=127_i8 {
+                let offset = n as i16 as u16;
+                let sum = sp.wrapping_add(offset);
+                let carries = sp ^ offset ^ sum;
+                let expected = flags(false, false, carries & 0x10 != 0, carries & 0x100 != 0);
+                assert_eq!(add_sp(sp, n), (sum, expected));
+            }
+        }
+    }
+
+    // DAA as hardware documentation describes it, working on a 16-bit result so the carry falls out of bit 8.
+    fn reference_daa(a: u8, n: bool, h: bool, c: bool) -> (u8, bool) {
+        let mut result = a as i16;
+        if n {
+            if h {
+                result = (result - 0x06) & 0xFF;
+            }
+            if c {
+                result -= 0x60;
+            }
+        } else {
+            if h || result & 0x0F > 0x09 {
+                result += 0x06;
+            }
+            if c || result > 0x9F {
+                result += 0x60;
+            }
+        }
+        (result as u8, c || result & 0x100 != 0)
+    }
+
+    #[test]
+    fn daa_matches_the_reference_for_every_input() {
+        for a in 0..=0xFF {
+            for bits in 0..8 {
+                let (n, h, c) = (bits & 4 != 0, bits & 2 != 0, bits & 1 != 0);
+                let (value, c) = reference_daa(a, n, h, c);
+                let expected = (value, flags(value == 0, n, false, c));
+                assert_eq!(
+                    daa(a, flags(false, n, h, c)),
+                    expected,
+                    "{:02X} {:03b}",
+                    a,
+                    bits
+                );
+            }
+        }
+        // Z comes from the result alone, whatever it was going in.
+        assert_eq!(
+            daa(0x00, flags(true, true, true, true)),
+            (0x9A, flags(false, true, false, true))
+        );
+    }
 }
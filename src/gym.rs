@@ -0,0 +1,100 @@
+use crate::error::FeboyError;
+use crate::gameboy::{step, Gameboy};
+use crate::prelude::*;
+
+// A frame's worth of cycles, for when the LCD is off and there's no V-blank to stop at.
+const FRAME_CYCLES: u64 = 70224;
+
+// What the agent sees after every step.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Observation {
+    pub pixels: Vec<u32>,
+    // The watched addresses' values, in the order they were given.
+    pub ram: Vec<u8>,
+    pub frame: u64,
+}
+
+// Drives a game the way reinforcement learning loops expect: every step holds the given buttons for exactly
+// one frame and hands back the screen and the RAM the agent watches. Resetting loads a state, so it's
+// about as fast as a step. No host input should be connected, or it would override the buttons.
+pub struct Environment {
+    pub gameboy: Gameboy,
+    watched: Vec<usize>,
+    // The state reset goes back to, the moment the environment was made unless moved since.
+    start: Vec<u8>,
+}
+
+impl Environment {
+    pub fn new(gameboy: Gameboy, watched: Vec<usize>) -> Self {
+        let start = gameboy.save_state();
+        Self {
+            gameboy,
+            watched,
+            start,
+        }
+    }
+
+    // Buttons are in the joypad's bit order: A, B, Select, Start, Right, Left, Up, Down.
+    pub fn step(&mut self, buttons: u8) -> Result<Observation, FeboyError> {
+        self.gameboy.mem.joypad.set_pressed(buttons);
+        let clock = self.gameboy.clock();
+        while self.gameboy.clock().frames == clock.frames
+            && self.gameboy.clock().cycles < clock.cycles + FRAME_CYCLES
+        {
+            step(&mut self.gameboy)?;
+        }
+        Ok(self.observe())
+    }
+
+    pub fn reset(&mut self) -> Result<Observation, FeboyError> {
+        self.gameboy.load_state(&self.start)?;
+        Ok(self.observe())
+    }
+
+    // Makes the current moment the one reset goes back to, e.g. once past the title screen.
+    pub fn set_start(&mut self) {
+        self.start = self.gameboy.save_state();
+    }
+
+    pub fn observe(&mut self) -> Observation {
+        let frame = self.gameboy.clock().frames;
+        let mem = &mut self.gameboy.mem;
+        Observation {
+            pixels: mem
+                .ppu
+                .take_frame()
+                .unwrap_or_else(|| mem.ppu.pixels.to_vec()),
+            ram: self
+                .watched
+                .iter()
+                .map(|address| mem.peek(*address))
+                .collect(),
+            frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gameboy::Gameboy;
+    use crate::gym::Environment;
+    use crate::memory_map::MemoryMap;
+
+    #[test]
+    fn steps_one_frame_at_a_time() {
+        let mem = MemoryMap::blank();
+        let mut environment = Environment::new(Gameboy::new(mem), vec![0xC000, 0xFF80]);
+        environment.gameboy.mem.wram[0] = 0x42;
+
+        let observation = environment.step(0b1001).unwrap();
+        assert_eq!(observation.frame, 1);
+        assert_eq!(observation.pixels.len(), 160 * 144);
+        assert_eq!(observation.ram[0], 0x42);
+        assert_eq!(environment.gameboy.mem.joypad.pressed(), 0b1001);
+        assert_eq!(environment.step(0).unwrap().frame, 2);
+
+        environment.set_start();
+        environment.step(0).unwrap();
+        assert_eq!(environment.reset().unwrap().frame, 2);
+    }
+}
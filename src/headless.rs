@@ -0,0 +1,146 @@
+use crate::cartridge::LoadOptions;
+use crate::error::FeboyError;
+use crate::frame_hash::HashStream;
+use crate::gameboy::{step, Gameboy};
+use crate::memory_map::MemoryMap;
+use crate::video::deliver;
+use std::io::Write;
+
+pub const RUN_FRAMES: u64 = 7200;
+const FRAME_CYCLES: u64 = 70224;
+
+// What the serial output has to contain for the run to end early, Blargg's markers unless changed.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SerialMarkers {
+    pub pass: String,
+    pub fail: String,
+}
+
+impl Default for SerialMarkers {
+    fn default() -> Self {
+        Self {
+            pass: "Passed".to_owned(),
+            fail: "Failed".to_owned(),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RunOutcome {
+    Passed,
+    Failed,
+    // Ran out of frames without seeing either marker, or without looking for them.
+    Finished,
+}
+
+impl RunOutcome {
+    // For CI: anything but a pass is a failure, and running out of time is told apart from failing.
+    pub fn exit_code(&self, markers: bool) -> i32 {
+        match self {
+            RunOutcome::Passed => 0,
+            RunOutcome::Failed => 1,
+            RunOutcome::Finished if markers => 2,
+            RunOutcome::Finished => 0,
+        }
+    }
+}
+
+// Runs the ROM unthrottled and without a window for up to the given frames, counted in cycles like the
+// benchmark. Everything the game sends over the link port is collected, with nothing plugged in to answer.
+// Every rendered frame's hash goes to the stream, if there is one.
+pub fn run_headless(
+    rom: &Vec<u8>,
+    rom_name: &String,
+    frames: u64,
+    markers: Option<&SerialMarkers>,
+    mut hashes: Option<HashStream<&mut dyn Write>>,
+) -> Result<(RunOutcome, String), FeboyError> {
+    let mut gameboy = Gameboy::new(MemoryMap::new(rom, rom_name, &LoadOptions::default())?);
+    gameboy.mem.serial.linked = true;
+    let target = gameboy.clock().cycles + frames * FRAME_CYCLES;
+    let mut output = vec![];
+    while gameboy.clock().cycles < target {
+        step(&mut gameboy)?;
+        if let Some(hashes) = &mut hashes {
+            deliver(&mut gameboy, hashes);
+            if let Some(e) = hashes.take_error() {
+                return Err(e.into());
+            }
+        }
+        let serial = &mut gameboy.mem.serial;
+        let byte = match serial.take_outgoing() {
+            Some(byte) => byte,
+            None => continue,
+        };
+        serial.complete(0xFF);
+        output.push(byte);
+        if let Some(markers) = markers {
+            let text = String::from_utf8_lossy(&output);
+            if text.contains(&markers.fail) {
+                return Ok((RunOutcome::Failed, text.into_owned()));
+            }
+            if text.contains(&markers.pass) {
+                return Ok((RunOutcome::Passed, text.into_owned()));
+            }
+        }
+    }
+    let text = String::from_utf8_lossy(&output).into_owned();
+    Ok((RunOutcome::Finished, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame_hash::HashStream;
+    use crate::headless::{run_headless, RunOutcome, SerialMarkers};
+    use std::io::Write;
+
+    // Sends a zero-terminated string out of the link port a byte at a time, then spins.
+    fn serial_rom(text: &str) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        #[rustfmt::skip]
+        let program = [
+            0x21, 0x00, 0x02, // LD HL, 0x0200
+            0x2A,             // LD A, (HL+)
+            0xA7,             // AND A
+            0x28, 0x0E,       // JR Z, end
+            0xE0, 0x01,       // LDH (SB), A
+            0x3E, 0x81,       // LD A, 0x81
+            0xE0, 0x02,       // LDH (SC), A
+            0xF0, 0x02,       // LDH A, (SC)
+            0xCB, 0x7F,       // BIT 7, A
+            0x20, 0xFA,       // JR NZ, -6
+            0x18, 0xEE,       // JR -18
+            0x18, 0xFE,       // end: JR -2
+        ];
+        rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+        rom[0x200..0x200 + text.len()].copy_from_slice(text.as_bytes());
+        rom
+    }
+
+    #[test]
+    fn ends_on_serial_markers() {
+        let name = "headless".to_owned();
+        let markers = SerialMarkers::default();
+        let rom = serial_rom("cpu_instrs\n\nPassed all tests");
+        let (outcome, output) = run_headless(&rom, &name, 60, Some(&markers), None).unwrap();
+        assert_eq!(outcome, RunOutcome::Passed);
+        assert_eq!(output, "cpu_instrs\n\nPassed");
+        assert_eq!(outcome.exit_code(true), 0);
+
+        let rom = serial_rom("01:ok 02:Failed");
+        let (outcome, _) = run_headless(&rom, &name, 60, Some(&markers), None).unwrap();
+        assert_eq!(outcome.exit_code(true), 1);
+
+        let mut hashes = vec![];
+        let stream = HashStream::new(&mut hashes as &mut dyn Write);
+        let (outcome, output) = run_headless(&rom, &name, 60, None, Some(stream)).unwrap();
+        assert_eq!(outcome, RunOutcome::Finished);
+        assert_eq!(output, "01:ok 02:Failed");
+        assert_eq!(outcome.exit_code(true), 2);
+        assert_eq!(outcome.exit_code(false), 0);
+        let hashes = String::from_utf8(hashes).unwrap();
+        assert_eq!(hashes.lines().count(), 60);
+        assert!(hashes.starts_with("1 "));
+    }
+}
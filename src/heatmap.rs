@@ -0,0 +1,142 @@
+use minifb::{MouseMode, Scale, Window, WindowOptions};
+use std::ops::Range;
+
+const VRAM: Range<usize> = 0x8000..0xA000;
+const WRAM: Range<usize> = 0xC000..0xE000;
+const WIDTH: usize = 128;
+const HEIGHT: usize = 128;
+// Every frame keeps 63/64 of the counts, so accesses fade out over a second or two.
+const DECAY: f32 = 63.0 / 64.0;
+
+// One cell per VRAM and WRAM address, VRAM first.
+#[derive(Clone)]
+pub struct HeatMap {
+    reads: Vec<f32>,
+    writes: Vec<f32>,
+}
+
+impl HeatMap {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![0.0; VRAM.len() + WRAM.len()],
+            writes: vec![0.0; VRAM.len() + WRAM.len()],
+        }
+    }
+
+    fn index(address: usize) -> Option<usize> {
+        if VRAM.contains(&address) {
+            Some(address - VRAM.start)
+        } else if WRAM.contains(&address) {
+            Some(VRAM.len() + address - WRAM.start)
+        } else {
+            None
+        }
+    }
+
+    fn address(index: usize) -> usize {
+        match index.checked_sub(VRAM.len()) {
+            Some(offset) => WRAM.start + offset,
+            None => VRAM.start + index,
+        }
+    }
+
+    pub fn read(&mut self, address: usize) {
+        if let Some(index) = HeatMap::index(address) {
+            self.reads[index] += 1.0;
+        }
+    }
+
+    pub fn write(&mut self, address: usize) {
+        if let Some(index) = HeatMap::index(address) {
+            self.writes[index] += 1.0;
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        for count in self.reads.iter_mut().chain(self.writes.iter_mut()) {
+            *count *= DECAY;
+        }
+    }
+
+    // Writes show up red and reads green, on a log scale relative to the busiest address.
+    fn pixels(&self) -> Vec<u32> {
+        let brightest = |counts: &[f32]| {
+            let max = counts.iter().fold(0.0_f32, |max, count| max.max(*count));
+            max.ln_1p().max(f32::MIN_POSITIVE)
+        };
+        let (max_reads, max_writes) = (brightest(&self.reads), brightest(&self.writes));
+        self.reads
+            .iter()
+            .zip(&self.writes)
+            .map(|(reads, writes)| {
+                let red = (writes.ln_1p() / max_writes * 255.0) as u32;
+                let green = (reads.ln_1p() / max_reads * 255.0) as u32;
+                red << 16 | green << 8
+            })
+            .collect()
+    }
+}
+
+// A debug window drawing the heat map, one pixel per address with VRAM in the top half. Hovering an
+// address shows its counts in the title bar.
+pub struct HeatMapView {
+    window: Window,
+}
+
+impl HeatMapView {
+    pub fn open() -> Option<Self> {
+        let options = WindowOptions {
+            scale: Scale::X4,
+            ..WindowOptions::default()
+        };
+        match Window::new("Heat map", WIDTH, HEIGHT, options) {
+            Ok(window) => Some(Self { window }),
+            Err(e) => {
+                println!("Failed to open heat map: {}", e);
+                None
+            }
+        }
+    }
+
+    // Returns false once the window has been closed.
+    pub fn draw(&mut self, heat_map: &HeatMap) -> bool {
+        if !self.window.is_open() {
+            return false;
+        }
+        if let Some((x, y)) = self.window.get_mouse_pos(MouseMode::Discard) {
+            let index = (y as usize).min(HEIGHT - 1) * WIDTH + (x as usize).min(WIDTH - 1);
+            self.window.set_title(&format!(
+                "Heat map - {:04X}: {:.0} reads, {:.0} writes",
+                HeatMap::address(index),
+                heat_map.reads[index],
+                heat_map.writes[index]
+            ));
+        }
+        let _ = self
+            .window
+            .update_with_buffer(&heat_map.pixels(), WIDTH, HEIGHT);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heatmap::HeatMap;
+
+    #[test]
+    fn counts_decay_and_color_accesses() {
+        let mut heat_map = HeatMap::new();
+        heat_map.write(0x8000);
+        heat_map.read(0xC001);
+        heat_map.read(0xC001);
+        heat_map.read(0xFF80);
+        heat_map.end_frame();
+
+        let pixels = heat_map.pixels();
+        assert_eq!(pixels[0], 0xFF0000);
+        assert_eq!(pixels[0x2001], 0x00FF00);
+        assert_eq!(pixels[0x2002], 0);
+        assert!(heat_map.reads[0x2001] < 2.0);
+        assert_eq!(HeatMap::address(0x2001), 0xC001);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::input::parse_key;
+use crate::picture::Control;
+use minifb::{Key, KeyRepeat, Window};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    StatePicker,
+    Rewind,
+    FastForward,
+    SpeedUp,
+    SlowDown,
+    Screenshot,
+    Pause,
+    Reset,
+    PowerCycle,
+    PaletteCycle,
+    ScanBarcode,
+    StepInstruction,
+    StepBack,
+    HeatMap,
+    SramEditor,
+    InputDisplay,
+    SpriteOutlines,
+    WindowOutline,
+    TileGrid,
+    BackgroundLayer,
+    WindowLayer,
+    SpriteLayer,
+    BrightnessUp,
+    BrightnessDown,
+    GammaUp,
+    GammaDown,
+    SaturationUp,
+    SaturationDown,
+    BatterySaver,
+    HighPriority,
+    DumpTrace,
+}
+
+impl Hotkey {
+    pub fn parse(name: &str) -> Option<Hotkey> {
+        match name {
+            "save_state" => Some(Hotkey::SaveState),
+            "load_state" => Some(Hotkey::LoadState),
+            "state_picker" => Some(Hotkey::StatePicker),
+            "rewind" => Some(Hotkey::Rewind),
+            "fast_forward" => Some(Hotkey::FastForward),
+            "speed_up" => Some(Hotkey::SpeedUp),
+            "slow_down" => Some(Hotkey::SlowDown),
+            "screenshot" => Some(Hotkey::Screenshot),
+            "pause" => Some(Hotkey::Pause),
+            "reset" => Some(Hotkey::Reset),
+            "power_cycle" => Some(Hotkey::PowerCycle),
+            "palette_cycle" => Some(Hotkey::PaletteCycle),
+            "scan_barcode" => Some(Hotkey::ScanBarcode),
+            "step_instruction" => Some(Hotkey::StepInstruction),
+            "step_back" => Some(Hotkey::StepBack),
+            "heat_map" => Some(Hotkey::HeatMap),
+            "sram_editor" => Some(Hotkey::SramEditor),
+            "input_display" => Some(Hotkey::InputDisplay),
+            "sprite_outlines" => Some(Hotkey::SpriteOutlines),
+            "window_outline" => Some(Hotkey::WindowOutline),
+            "tile_grid" => Some(Hotkey::TileGrid),
+            "background_layer" => Some(Hotkey::BackgroundLayer),
+            "window_layer" => Some(Hotkey::WindowLayer),
+            "sprite_layer" => Some(Hotkey::SpriteLayer),
+            "brightness_up" => Some(Hotkey::BrightnessUp),
+            "brightness_down" => Some(Hotkey::BrightnessDown),
+            "gamma_up" => Some(Hotkey::GammaUp),
+            "gamma_down" => Some(Hotkey::GammaDown),
+            "saturation_up" => Some(Hotkey::SaturationUp),
+            "saturation_down" => Some(Hotkey::SaturationDown),
+            "battery_saver" => Some(Hotkey::BatterySaver),
+            "high_priority" => Some(Hotkey::HighPriority),
+            "dump_trace" => Some(Hotkey::DumpTrace),
+            _ => None,
+        }
+    }
+
+    // Rewind and fast-forward last as long as the keys are held, everything else fires once per press.
+    pub fn held(&self) -> bool {
+        matches!(self, Hotkey::Rewind | Hotkey::FastForward)
+    }
+
+    // The picture control a hotkey steps, and whether up.
+    pub fn picture_control(&self) -> Option<(Control, bool)> {
+        match self {
+            Hotkey::BrightnessUp => Some((Control::Brightness, true)),
+            Hotkey::BrightnessDown => Some((Control::Brightness, false)),
+            Hotkey::GammaUp => Some((Control::Gamma, true)),
+            Hotkey::GammaDown => Some((Control::Gamma, false)),
+            Hotkey::SaturationUp => Some((Control::Saturation, true)),
+            Hotkey::SaturationDown => Some((Control::Saturation, false)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Binding {
+    key: Key,
+    ctrl: bool,
+    shift: bool,
+}
+
+impl Binding {
+    // Bindings are a key name with optional modifiers, e.g. F5 or Ctrl+Shift+R.
+    pub fn parse(value: &str) -> Option<Binding> {
+        let mut parts = value.split('+').map(str::trim).collect::<Vec<&str>>();
+        let key = parse_key(parts.pop()?)?;
+        let mut binding = Binding {
+            key,
+            ctrl: false,
+            shift: false,
+        };
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" => binding.ctrl = true,
+                "shift" => binding.shift = true,
+                _ => return None,
+            }
+        }
+        Some(binding)
+    }
+
+    fn modifiers_down(&self, window: &Window) -> bool {
+        let ctrl = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        ctrl == self.ctrl && shift == self.shift
+    }
+
+    fn name(&self) -> String {
+        let ctrl = if self.ctrl { "Ctrl+" } else { "" };
+        let shift = if self.shift { "Shift+" } else { "" };
+        format!("{}{}{:?}", ctrl, shift, self.key)
+    }
+}
+
+pub struct Hotkeys {
+    bindings: Vec<(Hotkey, Option<Binding>)>,
+}
+
+impl Hotkeys {
+    pub fn new() -> Self {
+        let binding = |key, ctrl, shift| Some(Binding { key, ctrl, shift });
+        Self {
+            bindings: vec![
+                (Hotkey::SaveState, binding(Key::F5, false, false)),
+                (Hotkey::LoadState, binding(Key::F8, false, false)),
+                (Hotkey::StatePicker, binding(Key::F8, false, true)),
+                (Hotkey::Rewind, binding(Key::Backquote, false, false)),
+                (Hotkey::FastForward, binding(Key::Tab, false, false)),
+                (Hotkey::SpeedUp, binding(Key::Equal, false, false)),
+                (Hotkey::SlowDown, binding(Key::Minus, false, false)),
+                (Hotkey::Screenshot, binding(Key::F12, false, false)),
+                (Hotkey::Pause, binding(Key::P, false, false)),
+                (Hotkey::Reset, binding(Key::R, true, false)),
+                (Hotkey::PowerCycle, binding(Key::R, true, true)),
+                (Hotkey::PaletteCycle, binding(Key::F9, false, false)),
+                (Hotkey::ScanBarcode, binding(Key::F10, false, false)),
+                (Hotkey::StepInstruction, binding(Key::F7, false, false)),
+                (Hotkey::StepBack, binding(Key::F7, false, true)),
+                (Hotkey::HeatMap, binding(Key::F11, false, false)),
+                (Hotkey::SramEditor, binding(Key::F6, false, false)),
+                (Hotkey::InputDisplay, binding(Key::F4, false, false)),
+                (Hotkey::SpriteOutlines, binding(Key::F1, true, false)),
+                (Hotkey::WindowOutline, binding(Key::F2, true, false)),
+                (Hotkey::TileGrid, binding(Key::F3, true, false)),
+                (Hotkey::BackgroundLayer, binding(Key::F1, false, true)),
+                (Hotkey::WindowLayer, binding(Key::F2, false, true)),
+                (Hotkey::SpriteLayer, binding(Key::F3, false, true)),
+                (Hotkey::BrightnessUp, binding(Key::Equal, true, false)),
+                (Hotkey::BrightnessDown, binding(Key::Minus, true, false)),
+                (Hotkey::GammaUp, binding(Key::Equal, false, true)),
+                (Hotkey::GammaDown, binding(Key::Minus, false, true)),
+                (Hotkey::SaturationUp, binding(Key::Equal, true, true)),
+                (Hotkey::SaturationDown, binding(Key::Minus, true, true)),
+                (Hotkey::BatterySaver, binding(Key::F9, false, true)),
+                (Hotkey::HighPriority, binding(Key::F10, false, true)),
+                (Hotkey::DumpTrace, binding(Key::F12, false, true)),
+            ],
+        }
+    }
+
+    // "none" leaves the action unbound.
+    pub fn bind(&mut self, hotkey: Hotkey, value: &str) -> Option<()> {
+        let binding = match value.to_lowercase().as_str() {
+            "none" => None,
+            _ => Some(Binding::parse(value)?),
+        };
+        for (bound, current) in self.bindings.iter_mut() {
+            if *bound == hotkey {
+                *current = binding;
+            }
+        }
+        Some(())
+    }
+
+    // A shortcut shared by two actions, or an unmodified key the joypad also uses, would do two things at once.
+    pub fn conflicts(&self, joypad_keys: &[Key]) -> Vec<String> {
+        let mut conflicts = vec![];
+        for (i, (hotkey, binding)) in self.bindings.iter().enumerate() {
+            let binding = match binding {
+                Some(binding) => binding,
+                None => continue,
+            };
+            for (other, _) in self.bindings[i + 1..]
+                .iter()
+                .filter(|(_, other)| other.as_ref() == Some(binding))
+            {
+                conflicts.push(format!(
+                    "{} is bound to both {:?} and {:?}",
+                    binding.name(),
+                    hotkey,
+                    other
+                ));
+            }
+            if !binding.ctrl && !binding.shift && joypad_keys.contains(&binding.key) {
+                conflicts.push(format!(
+                    "{} is bound to both {:?} and a joypad button",
+                    binding.name(),
+                    hotkey
+                ));
+            }
+        }
+        conflicts
+    }
+
+    pub fn active(&self, window: &Window) -> Vec<Hotkey> {
+        self.bindings
+            .iter()
+            .filter_map(|(hotkey, binding)| Some((*hotkey, (*binding)?)))
+            .filter(|(hotkey, binding)| {
+                let triggered = if hotkey.held() {
+                    window.is_key_down(binding.key)
+                } else {
+                    window.is_key_pressed(binding.key, KeyRepeat::No)
+                };
+                triggered && binding.modifiers_down(window)
+            })
+            .map(|(hotkey, _)| hotkey)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hotkeys::{Binding, Hotkey, Hotkeys};
+    use minifb::Key;
+
+    #[test]
+    fn parses_bindings_and_reports_conflicts() {
+        assert_eq!(
+            Binding::parse("ctrl + Shift+r"),
+            Some(Binding {
+                key: Key::R,
+                ctrl: true,
+                shift: true
+            })
+        );
+        assert_eq!(Binding::parse("Alt+R"), None);
+        assert_eq!(Binding::parse("Ctrl+"), None);
+
+        let mut hotkeys = Hotkeys::new();
+        assert!(hotkeys.conflicts(&[Key::Z, Key::X]).is_empty());
+        hotkeys.bind(Hotkey::Pause, "F5").unwrap();
+        hotkeys.bind(Hotkey::Screenshot, "Z").unwrap();
+        hotkeys.bind(Hotkey::Rewind, "none").unwrap();
+        assert_eq!(
+            hotkeys.conflicts(&[Key::Z, Key::X]),
+            vec![
+                "F5 is bound to both SaveState and Pause",
+                "Z is bound to both Screenshot and a joypad button"
+            ]
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use crate::joypad::{Button, BUTTONS};
+use gilrs::{Button as PadButton, Gilrs};
+use minifb::{Key, Window};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use Key::*;
+
+const PAD_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Input {
+    Key(Key),
+    Pad(PadButton),
+}
+
+impl Input {
+    // Keys go by name and gamepad buttons take a Pad: prefix, e.g. Enter or Pad:South.
+    pub fn parse(name: &str) -> Option<Input> {
+        let name = name.trim();
+        match name.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("pad:") => PAD_BUTTONS
+                .iter()
+                .find(|button| format!("{:?}", button).eq_ignore_ascii_case(name[4..].trim()))
+                .map(|button| Input::Pad(*button)),
+            _ => parse_key(name).map(Input::Key),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum InputLatency {
+    Frame,
+    Read,
+}
+
+impl InputLatency {
+    pub fn parse(value: &str) -> Option<InputLatency> {
+        match value.to_lowercase().as_str() {
+            "frame" => Some(InputLatency::Frame),
+            "read" => Some(InputLatency::Read),
+            _ => None,
+        }
+    }
+}
+
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+}
+
+impl Gamepads {
+    // Playing on the keyboard alone still works when no gamepad backend is available.
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("Gamepads unavailable: {}", e);
+                None
+            }
+        };
+        Self { gilrs }
+    }
+
+    // The backend is created on the polling thread itself, which keeps it out of the core. The thread
+    // stops once nothing else holds the buttons.
+    fn spawn(inputs: InputMap, held: Arc<HeldButtons>) {
+        thread::spawn(move || {
+            let mut gamepads = Gamepads::new();
+            while gamepads.gilrs.is_some() && Arc::strong_count(&held) > 1 {
+                gamepads.poll();
+                held.pads
+                    .store(inputs.pads_pressed(&gamepads), Ordering::Relaxed);
+                thread::sleep(PAD_POLL_INTERVAL);
+            }
+        });
+    }
+
+    fn poll(&mut self) {
+        if let Some(gilrs) = &mut self.gilrs {
+            while gilrs.next_event().is_some() {}
+        }
+    }
+
+    fn is_pressed(&self, button: PadButton) -> bool {
+        self.gilrs.iter().any(|gilrs| {
+            gilrs
+                .gamepads()
+                .any(|(_, gamepad)| gamepad.is_pressed(button))
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct InputMap {
+    bindings: [Vec<Input>; 8],
+}
+
+impl InputMap {
+    pub fn new() -> Self {
+        Self {
+            bindings: [
+                vec![Input::Key(Z), Input::Pad(PadButton::East)],
+                vec![Input::Key(C), Input::Pad(PadButton::South)],
+                vec![Input::Key(Backspace), Input::Pad(PadButton::Select)],
+                vec![Input::Key(Enter), Input::Pad(PadButton::Start)],
+                vec![Input::Key(Right), Input::Pad(PadButton::DPadRight)],
+                vec![Input::Key(Left), Input::Pad(PadButton::DPadLeft)],
+                vec![Input::Key(Up), Input::Pad(PadButton::DPadUp)],
+                vec![Input::Key(Down), Input::Pad(PadButton::DPadDown)],
+            ],
+        }
+    }
+
+    fn index(button: Button) -> usize {
+        BUTTONS
+            .iter()
+            .position(|b| *b == button)
+            .unwrap_or_default()
+    }
+
+    // A binding is a comma-separated list, any of which presses the button.
+    pub fn bind(&mut self, button: Button, value: &str) -> Option<()> {
+        let inputs = value
+            .split(',')
+            .map(Input::parse)
+            .collect::<Option<Vec<Input>>>()?;
+        self.bindings[InputMap::index(button)] = inputs;
+        Some(())
+    }
+
+    pub fn keys(&self) -> Vec<Key> {
+        self.bindings
+            .iter()
+            .flatten()
+            .filter_map(|input| match input {
+                Input::Key(key) => Some(*key),
+                Input::Pad(_) => None,
+            })
+            .collect()
+    }
+
+    fn pressed(&self, is_down: impl Fn(&Input) -> bool) -> u8 {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter(|(_, inputs)| inputs.iter().any(&is_down))
+            .map(|(i, _)| 1 << i)
+            .sum()
+    }
+
+    fn keys_pressed(&self, window: &Window) -> u8 {
+        self.pressed(|input| matches!(input, Input::Key(key) if window.is_key_down(*key)))
+    }
+
+    fn pads_pressed(&self, gamepads: &Gamepads) -> u8 {
+        self.pressed(|input| matches!(input, Input::Pad(button) if gamepads.is_pressed(*button)))
+    }
+}
+
+// Buttons held on the host, A/B/Select/Start in the low nibble and Right/Left/Up/Down in the high one.
+#[derive(Default)]
+struct HeldButtons {
+    keys: AtomicU8,
+    pads: AtomicU8,
+}
+
+// The core's end of the input, which it samples right before V-blank every frame and, with read latency,
+// again whenever the game reads P1. It only holds atomics, so the core can run on any thread.
+#[derive(Clone)]
+pub struct InputPort {
+    held: Arc<HeldButtons>,
+    pub latency: InputLatency,
+}
+
+impl InputPort {
+    pub fn pressed(&self) -> u8 {
+        self.held.keys.load(Ordering::Relaxed) | self.held.pads.load(Ordering::Relaxed)
+    }
+}
+
+// Keys can only be read from the window, so the frontend samples them once a frame. Gamepads are polled
+// continuously on their own thread, which keeps read latency meaningful for them.
+pub struct InputSource {
+    inputs: InputMap,
+    port: InputPort,
+}
+
+impl InputSource {
+    pub fn new(inputs: InputMap, latency: InputLatency) -> Self {
+        let held = Arc::new(HeldButtons::default());
+        Gamepads::spawn(inputs.clone(), held.clone());
+        Self {
+            inputs,
+            port: InputPort { held, latency },
+        }
+    }
+
+    pub fn port(&self) -> InputPort {
+        self.port.clone()
+    }
+
+    pub fn update(&self, window: &Window) {
+        let keys = self.inputs.keys_pressed(window);
+        self.port.held.keys.store(keys, Ordering::Relaxed);
+    }
+}
+
+const PAD_BUTTONS: [PadButton; 19] = [
+    PadButton::South,
+    PadButton::East,
+    PadButton::North,
+    PadButton::West,
+    PadButton::C,
+    PadButton::Z,
+    PadButton::LeftTrigger,
+    PadButton::LeftTrigger2,
+    PadButton::RightTrigger,
+    PadButton::RightTrigger2,
+    PadButton::Select,
+    PadButton::Start,
+    PadButton::Mode,
+    PadButton::LeftThumb,
+    PadButton::RightThumb,
+    PadButton::DPadUp,
+    PadButton::DPadDown,
+    PadButton::DPadLeft,
+    PadButton::DPadRight,
+];
+
+const ALL_KEYS: [Key; 106] = [
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    Down,
+    Left,
+    Right,
+    Up,
+    Apostrophe,
+    Backquote,
+    Backslash,
+    Comma,
+    Equal,
+    LeftBracket,
+    Minus,
+    Period,
+    RightBracket,
+    Semicolon,
+    Slash,
+    Backspace,
+    Delete,
+    End,
+    Enter,
+    Escape,
+    Home,
+    Insert,
+    Menu,
+    PageDown,
+    PageUp,
+    Pause,
+    Space,
+    Tab,
+    NumLock,
+    CapsLock,
+    ScrollLock,
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    NumPad0,
+    NumPad1,
+    NumPad2,
+    NumPad3,
+    NumPad4,
+    NumPad5,
+    NumPad6,
+    NumPad7,
+    NumPad8,
+    NumPad9,
+    NumPadDot,
+    NumPadSlash,
+    NumPadAsterisk,
+    NumPadMinus,
+    NumPadPlus,
+    NumPadEnter,
+    LeftAlt,
+    RightAlt,
+    LeftSuper,
+    RightSuper,
+];
+
+pub fn parse_key(name: &str) -> Option<Key> {
+    ALL_KEYS
+        .iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name.trim()))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{Input, InputMap};
+    use crate::joypad::Button;
+    use gilrs::Button as PadButton;
+    use minifb::Key;
+
+    #[test]
+    fn binds_several_inputs_per_button() {
+        let mut inputs = InputMap::new();
+        inputs.bind(Button::A, "X, pad:north, Enter").unwrap();
+        assert_eq!(
+            inputs.bindings[0],
+            [
+                Input::Key(Key::X),
+                Input::Pad(PadButton::North),
+                Input::Key(Key::Enter)
+            ]
+        );
+        assert_eq!(inputs.bind(Button::B, "X, Pad:Nope"), None);
+        assert_eq!(
+            inputs.bindings[1],
+            [Input::Key(Key::C), Input::Pad(PadButton::South)]
+        );
+        assert!(inputs.keys().contains(&Key::X));
+        assert_eq!(Button::parse("select"), Some(Button::Select));
+    }
+}
@@ -33,6 +33,7 @@ pub enum Command {
     DI,
     EI,
     HALT,
+    ILLEGAL,
     INCH_HL,
     INC_R16(WordRegister),
     INC_R8(RegisterId),
@@ -134,7 +135,7 @@ impl Command {
                 OpByte(n) => panic!("Invalid operand for BIT_U3 instruction: {}", n),
             },
 
-            DAA | CPL | SCF | CCF | HALT | DI | EI | JP_HL | INC_R8(..) | DEC_R8(..)
+            DAA | CPL | SCF | CCF | HALT | ILLEGAL | DI | EI | JP_HL | INC_R8(..) | DEC_R8(..)
             | LD_R8_R8(..) | NOP | STOP => 1,
 
             SLA(op) | SRA(op) | SRL(op) => match op {
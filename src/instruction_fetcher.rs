@@ -1,10 +1,12 @@
-use std::cmp::max;
-use std::iter::FromIterator;
+use core::cmp::max;
+use core::iter::FromIterator;
 
+use crate::error::FeboyError;
 use crate::instruction::Command::*;
 use crate::instruction::InstructionOperand::{OpByte, OpHL, OpRegister};
 use crate::instruction::{Instruction, RstVec};
 use crate::memory_map::MemoryMap;
+use crate::prelude::*;
 use crate::register::RegisterId::*;
 use crate::register::{Bit, ConditionCode, Register, RegisterId};
 
@@ -17,8 +19,15 @@ pub struct InstructionFetcher;
 
 impl InstructionFetcher {
     #[deny(unreachable_patterns)]
-    pub fn fetch_instruction(pc: u16, reg: &Register, ram: &mut MemoryMap) -> Instruction {
+    pub fn fetch_instruction(
+        pc: u16,
+        reg: &Register,
+        ram: &mut MemoryMap,
+        halt_bug: bool,
+    ) -> Result<Instruction, FeboyError> {
         let opcode = ram.read(pc);
+        // The HALT bug skips the PC increment after the opcode fetch, so operands start at the opcode itself.
+        let pc = if halt_bug { pc.wrapping_sub(1) } else { pc };
         let register_ids = [B, C, D, E, H, L, A];
 
         let mut operands =
@@ -27,7 +36,7 @@ impl InstructionFetcher {
         let operand_idx = ((opcode & 0x0F) % 8) as usize;
         let register_idx = (max(0x40, opcode) as usize - 0x40) / 8;
 
-        Instruction(
+        Ok(Instruction(
             opcode,
             match opcode {
                 0xCB => {
@@ -273,10 +282,10 @@ impl InstructionFetcher {
                 0x1F => RR(OpRegister(A), true),
 
                 0x10 => {
-                    let opcode = ram.memory[pc as usize + 1];
+                    let opcode = ram.read_without_cycle(pc + 1);
                     match opcode {
                         0x00 => STOP,
-                        _ => panic!("Invalid opcode after STOP: {}", opcode),
+                        _ => return Err(FeboyError::InvalidOpcode { pc, opcode: 0x10 }),
                     }
                 }
 
@@ -356,14 +365,9 @@ impl InstructionFetcher {
                 0xD9 => RETI,
 
                 0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
-                    panic!(
-                        "P: {}, C: {}, N: {}",
-                        ram.read(pc - 1),
-                        opcode,
-                        ram.read(pc + 1)
-                    )
+                    ILLEGAL
                 }
             },
-        )
+        ))
     }
 }
@@ -1,7 +1,10 @@
+use crate::error::FeboyError;
 use crate::interrupt::InterruptId::{JoypadInt, SerialInt, StatInt, TimerInt, VBlankInt};
-use crate::interrupt::InterruptState::{Active, Enabled, Inactive, Priority, Requested};
-use std::collections::HashMap;
-use std::ops::Index;
+use crate::interrupt::InterruptState::{Active, Enabled, Inactive, Requested};
+use crate::prelude::*;
+use crate::state::{StateReader, StateWriter};
+use alloc::collections::BTreeMap;
+use core::ops::Index;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum InterruptId {
@@ -18,11 +21,10 @@ pub enum InterruptState {
     Inactive,
     Enabled,
     Requested,
-    Priority(InterruptId),
 }
 
 pub struct InterruptHandler {
-    registers: HashMap<usize, u8>,
+    registers: BTreeMap<usize, u8>,
     vblank: InterruptMask,
     stat: InterruptMask,
     serial: InterruptMask,
@@ -35,7 +37,7 @@ pub const IF_ADDRESS: usize = 0xFF0F;
 
 impl InterruptHandler {
     pub fn new() -> Self {
-        let mut registers = HashMap::new();
+        let mut registers = BTreeMap::new();
         registers.insert(IF_ADDRESS, 0x00);
         registers.insert(IE_ADDRESS, 0x00);
         let vblank = InterruptMask(0x01);
@@ -59,7 +61,7 @@ impl InterruptHandler {
         let enabled = ie_flag & self[interrupt].0 != 0;
         let requested = if_flag & self[interrupt].0 != 0;
         let active = requested && enabled;
-        let state = if active {
+        if active {
             Active
         } else if enabled {
             Enabled
@@ -67,40 +69,17 @@ impl InterruptHandler {
             Requested
         } else {
             Inactive
-        };
-        match interrupt {
-            VBlankInt => state,
-            StatInt => {
-                if self.get_state(VBlankInt) != Active {
-                    state
-                } else {
-                    Priority(VBlankInt)
-                }
-            }
-            TimerInt => {
-                if self.get_state(StatInt) != Active {
-                    state
-                } else {
-                    Priority(StatInt)
-                }
-            }
-            SerialInt => {
-                if self.get_state(TimerInt) != Active {
-                    state
-                } else {
-                    Priority(TimerInt)
-                }
-            }
-            JoypadInt => {
-                if self.get_state(SerialInt) != Active {
-                    state
-                } else {
-                    Priority(SerialInt)
-                }
-            }
         }
     }
 
+    // Pending interrupts are always serviced from the lowest vector up, whatever order they were requested in.
+    pub fn highest_priority(&self) -> Option<InterruptId> {
+        [VBlankInt, StatInt, TimerInt, SerialInt, JoypadInt]
+            .iter()
+            .copied()
+            .find(|&interrupt| self.get_state(interrupt) == Active)
+    }
+
     pub fn set(&mut self, interrupts: Vec<InterruptId>, set: bool) {
         if set {
             interrupts
@@ -113,15 +92,31 @@ impl InterruptHandler {
         }
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8("IF", self.registers[&IF_ADDRESS]);
+        state.u8("IE", self.registers[&IE_ADDRESS]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        self.registers.insert(IF_ADDRESS, state.u8()?);
+        self.registers.insert(IE_ADDRESS, state.u8()?);
+        Ok(())
+    }
+
+    // Only the low 5 bits of IF exist, the rest read back as 1. IE keeps all 8 bits.
     pub fn read(&self, address: usize) -> Option<u8> {
-        self.registers.get(&address).copied()
+        match address {
+            IF_ADDRESS => Some(self.registers[&IF_ADDRESS] | 0xE0),
+            _ => self.registers.get(&address).copied(),
+        }
     }
 
     pub fn write(&mut self, address: usize, value: u8) -> bool {
-        if !self.registers.contains_key(&address) {
-            return false;
-        }
-        self.registers.insert(address, value | 0xE0);
+        match address {
+            IF_ADDRESS => self.registers.insert(address, value & 0x1F),
+            IE_ADDRESS => self.registers.insert(address, value),
+            _ => return false,
+        };
         true
     }
 }
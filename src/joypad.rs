@@ -1,7 +1,41 @@
+use crate::error::FeboyError;
 use crate::joypad::SelectedButtons::{Action, Direction};
-use minifb::{Key, Window};
-use std::ops::BitXor;
-use Key::*;
+use crate::prelude::*;
+use crate::state::{invalid_state, StateReader, StateWriter};
+use core::ops::BitXor;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+}
+
+// Same order as the joypad bits: the action buttons, then the directions.
+pub const BUTTONS: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+    Button::Right,
+    Button::Left,
+    Button::Up,
+    Button::Down,
+];
+
+impl Button {
+    pub fn parse(name: &str) -> Option<Button> {
+        BUTTONS
+            .iter()
+            .find(|button| format!("{:?}", button).eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
 
 #[derive(PartialEq, Clone, Copy)]
 pub enum SelectedButtons {
@@ -13,6 +47,7 @@ pub struct Joypad {
     selected_buttons: SelectedButtons,
     action_buttons: u8,
     direction_buttons: u8,
+    pressed: u8,
 }
 
 #[derive(Copy, Clone)]
@@ -24,33 +59,52 @@ impl Joypad {
             action_buttons: 0x0F,
             direction_buttons: 0x0F,
             selected_buttons: Action,
+            pressed: 0x00,
         }
     }
 
-    pub fn machine_cycle(&mut self, window: &Window) -> Vec<InputInterrupt> {
-        if window.is_key_down(Escape) {
-            std::process::exit(0)
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8("joypad selection", self.selected_buttons as u8);
+        state.u8("action buttons", self.action_buttons);
+        state.u8("direction buttons", self.direction_buttons);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        self.selected_buttons = match state.u8()? {
+            0x10 => Action,
+            0x20 => Direction,
+            _ => return Err(invalid_state("joypad selection")),
+        };
+        self.action_buttons = state.u8()?;
+        self.direction_buttons = state.u8()?;
+        Ok(())
+    }
+
+    // Buttons held on the host, A/B/Select/Start in the low nibble and Right/Left/Up/Down in the high one.
+    pub fn set_pressed(&mut self, pressed: u8) {
+        self.pressed = pressed;
+    }
+
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    // For frontends that get input as press and release events. A connected input port overwrites these
+    // whenever it's sampled, so use one or the other.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let bit = 1 << button as u8;
+        if pressed {
+            self.pressed |= bit;
+        } else {
+            self.pressed &= !bit;
         }
+    }
 
+    pub fn machine_cycle(&mut self) -> Vec<InputInterrupt> {
         let previous_buttons = *self.buttons();
 
-        let map_buttons = |keys: [Key; 4]| {
-            !(keys
-                .iter()
-                .enumerate()
-                .map(|(i, k)| {
-                    if window.is_key_down(*k) {
-                        2_u8.pow(i as u32)
-                    } else {
-                        0x00
-                    }
-                })
-                .sum::<u8>())
-                & 0x0F
-        };
-
-        self.action_buttons = map_buttons([Z, C, Backspace, Enter]);
-        self.direction_buttons = map_buttons([Right, Left, Up, Down]);
+        self.action_buttons = !self.pressed & 0x0F;
+        self.direction_buttons = !(self.pressed >> 4) & 0x0F;
 
         let size = self.buttons().bitxor(previous_buttons);
         vec![InputInterrupt; size as usize]
@@ -0,0 +1,164 @@
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use std::fs::read_to_string;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// How the bytes at a labelled address are meant to be read.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LabelType {
+    U8,
+    // Two bytes, the low one first, as the CPU's own 16-bit loads and stores lay them out.
+    U16Le,
+    // One byte holding two decimal digits, as scores and timers often are.
+    Bcd,
+}
+
+impl LabelType {
+    pub fn parse(value: &str) -> Option<LabelType> {
+        match value.to_lowercase().as_str() {
+            "u8" => Some(LabelType::U8),
+            "u16le" => Some(LabelType::U16Le),
+            "bcd" => Some(LabelType::Bcd),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            LabelType::U16Le => 2,
+            LabelType::U8 | LabelType::Bcd => 1,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Label {
+    pub address: u16,
+    pub name: String,
+    pub kind: LabelType,
+}
+
+impl Label {
+    // An address, an optional type and a name, e.g. "C0A0 u16le score". Without a type it's a u8.
+    pub fn parse(line: &str) -> Option<Label> {
+        let mut words = line.split_whitespace();
+        let address = u16::from_str_radix(words.next()?, 16).ok()?;
+        let (kind, name) = match (words.next()?, words.next()) {
+            (kind, Some(name)) => (LabelType::parse(kind)?, name),
+            (name, None) => (LabelType::U8, name),
+        };
+        match words.next() {
+            Some(_) => None,
+            None => Some(Label {
+                address,
+                name: name.to_owned(),
+                kind,
+            }),
+        }
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        address.wrapping_sub(self.address) < self.kind.size() as u16
+    }
+
+    // The value as the game means it, read through whatever the caller can see of memory.
+    pub fn value(&self, read: impl Fn(u16) -> u8) -> String {
+        match self.kind {
+            LabelType::U8 => format!("{}", read(self.address)),
+            LabelType::U16Le => {
+                let high = read(self.address.wrapping_add(1));
+                format!("{}", u16::from_le_bytes([read(self.address), high]))
+            }
+            // Nibbles past 9 aren't BCD, but show as hex rather than hiding what's there.
+            LabelType::Bcd => format!("{:02X}", read(self.address)),
+        }
+    }
+}
+
+// The names a game's RAM addresses were given, checked in the order they were listed.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Labels {
+    labels: Vec<Label>,
+}
+
+impl Labels {
+    pub fn new(labels: Vec<Label>) -> Self {
+        Self { labels }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Label> {
+        self.labels.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // The label covering an address, including the second byte of a u16.
+    pub fn at(&self, address: u16) -> Option<&Label> {
+        self.labels.iter().find(|label| label.contains(address))
+    }
+}
+
+// Label files hold one label per line, in the per-game labels directory; lines starting with '#' are
+// comments.
+#[cfg(feature = "std")]
+pub fn load_label_file(path: &Path) -> Labels {
+    let contents = match read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return Labels::default(),
+    };
+    let labels = contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let label = Label::parse(line);
+            if label.is_none() {
+                println!("Ignoring invalid label in {}: {}", path.display(), line);
+            }
+            label
+        })
+        .collect();
+    Labels::new(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::{Label, LabelType, Labels};
+
+    #[test]
+    fn labels_read_their_bytes_as_typed() {
+        let score = Label::parse("C0A0 U16LE score").unwrap();
+        assert_eq!(score.kind, LabelType::U16Le);
+        let lives = Label::parse("c0a2 lives").unwrap();
+        assert_eq!(
+            lives,
+            Label {
+                address: 0xC0A2,
+                name: "lives".to_owned(),
+                kind: LabelType::U8,
+            }
+        );
+        let timer = Label::parse("FF90 bcd timer").unwrap();
+        assert_eq!(Label::parse("C0A0 u32 score"), None);
+        assert_eq!(Label::parse("C0A0 u8 high score"), None);
+        assert_eq!(Label::parse("XYZ lives"), None);
+
+        let read = |address: u16| match address {
+            0xC0A0 => 0x34,
+            0xC0A1 => 0x12,
+            0xC0A2 => 3,
+            _ => 0x59,
+        };
+        assert_eq!(score.value(read), "4660");
+        assert_eq!(lives.value(read), "3");
+        assert_eq!(timer.value(read), "59");
+
+        let labels = Labels::new(vec![score, lives, timer]);
+        assert_eq!(labels.at(0xC0A1).unwrap().name, "score");
+        assert_eq!(labels.at(0xC0A2).unwrap().name, "lives");
+        assert_eq!(labels.at(0xC0A3), None);
+    }
+}
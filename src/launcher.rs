@@ -0,0 +1,129 @@
+use crate::font::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::paths::config_dir;
+use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::fs::{canonicalize, create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const RECENT_FILE: &str = "recent.txt";
+const MAX_RECENT: usize = 10;
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+const BACKGROUND: u32 = 0xE0F8D0;
+const HIGHLIGHT: u32 = 0x88C070;
+const TEXT: u32 = 0x081820;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+const ROM_FILTER: [&str; 4] = ["gb", "gbc", "zip", "gz"];
+
+pub struct RecentRoms {
+    path: PathBuf,
+    roms: Vec<String>,
+}
+
+impl RecentRoms {
+    pub fn load(portable: bool) -> Self {
+        let path = config_dir(portable).join(RECENT_FILE);
+        let roms = read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_owned())
+            .collect();
+        Self { path, roms }
+    }
+
+    pub fn add(&mut self, rom_name: &str) {
+        let rom_name = canonicalize(rom_name)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| rom_name.to_owned());
+        self.roms.retain(|rom| *rom != rom_name);
+        self.roms.insert(0, rom_name);
+        self.roms.truncate(MAX_RECENT);
+        if let Some(dir) = self.path.parent() {
+            create_dir_all(dir).ok();
+        }
+        if let Err(e) = write(&self.path, self.roms.join("\n")) {
+            println!("Failed to write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Shown when feboy is started without a ROM: the first entry opens a file dialog, the rest are recent ROMs.
+pub fn pick_rom(recent: &RecentRoms) -> Option<String> {
+    let mut window = Window::new(
+        "feboy - ESC to exit",
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            resize: true,
+            scale: Scale::X4,
+            scale_mode: ScaleMode::Stretch,
+            ..WindowOptions::default()
+        },
+    )
+    .unwrap();
+    window.limit_update_rate(Some(Duration::from_millis(16)));
+
+    let entries = recent.roms.len() + 1;
+    let mut selected = 0;
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        for key in window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default() {
+            match key {
+                Key::Up => selected = (selected + entries - 1) % entries,
+                Key::Down => selected = (selected + 1) % entries,
+                Key::O => return open_dialog(recent),
+                Key::Enter if selected == 0 => return open_dialog(recent),
+                Key::Enter => return Some(recent.roms[selected - 1].clone()),
+                _ => (),
+            }
+        }
+        window
+            .update_with_buffer(&render(recent, selected), WIDTH, HEIGHT)
+            .unwrap();
+    }
+    None
+}
+
+fn open_dialog(recent: &RecentRoms) -> Option<String> {
+    let mut dialog = rfd::FileDialog::new().add_filter("Game Boy ROM", &ROM_FILTER);
+    if let Some(dir) = recent.roms.first().and_then(|rom| Path::new(rom).parent()) {
+        dialog = dialog.set_directory(dir);
+    }
+    dialog
+        .pick_file()
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+pub fn confirm(title: &str, question: &str) -> bool {
+    rfd::MessageDialog::new()
+        .set_title(title)
+        .set_description(question)
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show()
+}
+
+fn render(recent: &RecentRoms, selected: usize) -> Vec<u32> {
+    let mut buffer = vec![BACKGROUND; WIDTH * HEIGHT];
+    draw_text(&mut buffer, WIDTH, 4, 4, "FEBOY - SELECT A ROM", TEXT);
+    let max_chars = (WIDTH - 8) / GLYPH_WIDTH;
+    let names = recent.roms.iter().map(|rom| {
+        Path::new(rom)
+            .file_name()
+            .map_or_else(|| rom.clone(), |name| name.to_string_lossy().into_owned())
+    });
+    for (index, name) in std::iter::once("Open file... (O)".to_owned())
+        .chain(names)
+        .enumerate()
+    {
+        let y = 16 + index * LINE_HEIGHT;
+        if y + LINE_HEIGHT > HEIGHT {
+            break;
+        }
+        if index == selected {
+            buffer[(y - 1) * WIDTH..(y - 1 + LINE_HEIGHT) * WIDTH].fill(HIGHLIGHT);
+        }
+        let name = name.chars().take(max_chars).collect::<String>();
+        draw_text(&mut buffer, WIDTH, 4, y, &name, TEXT);
+    }
+    buffer
+}
@@ -0,0 +1,111 @@
+// The core is a library so fuzz targets can drive it; the types only became public for that, so their
+// constructors are left without Default impls.
+#![allow(clippy::new_without_default)]
+// Without std only the emulation core is built, for embedders that bring their own screen and buttons.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "achievements")]
+pub mod achievements;
+pub mod apu;
+pub mod assembler;
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod boot;
+pub mod cartridge;
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod compat;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod crash;
+#[cfg(feature = "sameboy")]
+pub mod differential;
+#[cfg(feature = "std")]
+pub mod discord;
+#[cfg(feature = "std")]
+pub mod dot_matrix;
+pub mod error;
+pub mod font;
+#[cfg(feature = "std")]
+pub mod frame_hash;
+#[cfg(feature = "std")]
+pub mod frames;
+#[cfg(feature = "std")]
+pub mod game_db;
+pub mod gameboy;
+pub mod gym;
+#[cfg(feature = "std")]
+pub mod headless;
+#[cfg(feature = "std")]
+pub mod heatmap;
+#[cfg(feature = "std")]
+pub mod hotkeys;
+#[cfg(feature = "std")]
+pub mod input;
+pub mod instruction;
+pub mod instruction_fetcher;
+pub mod interrupt;
+pub mod joypad;
+pub mod labels;
+#[cfg(feature = "std")]
+pub mod launcher;
+#[cfg(feature = "std")]
+pub mod link;
+pub mod mbc;
+pub mod memory_map;
+#[cfg(feature = "std")]
+pub mod osd;
+pub mod outlines;
+#[cfg(feature = "std")]
+pub mod pacing;
+#[cfg(feature = "std")]
+pub mod patch;
+#[cfg(feature = "std")]
+pub mod paths;
+#[cfg(feature = "std")]
+pub mod picture;
+pub mod ppu;
+pub mod profiler;
+pub mod regions;
+pub mod register;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "std")]
+pub mod rom_loader;
+pub mod rtc;
+pub mod sav;
+#[cfg(feature = "std")]
+pub mod save;
+#[cfg(feature = "std")]
+pub mod screen;
+#[cfg(feature = "std")]
+pub mod screenshot;
+pub mod serial;
+#[cfg(test)]
+mod sm83_tests;
+#[cfg(feature = "std")]
+pub mod sram_editor;
+pub mod state;
+pub mod state_diff;
+#[cfg(feature = "std")]
+pub mod state_picker;
+pub mod timer;
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod vgm;
+pub mod video;
+#[cfg(feature = "std")]
+pub mod viewport;
+pub mod watchdog;
+
+// What std's prelude brings in, for the modules that also build without it.
+mod prelude {
+    pub use alloc::borrow::ToOwned;
+    pub use alloc::boxed::Box;
+    pub use alloc::string::String;
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
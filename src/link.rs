@@ -0,0 +1,651 @@
+use crate::serial::{Serial, SerialDevice};
+use std::collections::VecDeque;
+use std::fs::write;
+use std::io::{stdin, BufRead};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+// The adapter clocks a byte out to every player at a fixed pace; the RATE byte the games send is ignored.
+const BYTE_INTERVAL: i64 = 4096;
+const PING_HEADER: u8 = 0xFE;
+const PING_ACK: u8 = 0x88;
+const START_REQUEST: u8 = 0xAA;
+const START_REPLY: u8 = 0xCC;
+const RESTART_REQUEST: u8 = 0xFF;
+const BARCODE_HANDSHAKE: [u8; 4] = [0x10, 0x07, 0x10, 0x07];
+const BARCODE_REPLIES: [u8; 4] = [0xFF, 0xFF, 0x10, 0x07];
+const BARCODE_START: u8 = 0x02;
+const BARCODE_END: u8 = 0x03;
+
+pub enum Cable {
+    // Two Game Boys, optionally with every byte they swap recorded.
+    Direct(Option<LinkRecording>),
+    FourPlayer(FourPlayerAdapter),
+    BarcodeBoy(Port<BarcodeBoy>),
+    Replay(Port<LinkReplay>),
+    // Any other peripheral on a single console's port, e.g. a Loopback.
+    Device(Port<Box<dyn SerialDevice + Send>>),
+}
+
+impl Cable {
+    // `now` is the point in the frame, in T-cycles, that every core has reached.
+    pub fn update(&mut self, serials: &mut [&mut Serial], now: i64) {
+        match self {
+            Cable::Direct(recording) => {
+                if let [first, second] = serials {
+                    for master in 0..2 {
+                        let exchanged = match master {
+                            0 => connect(first, second),
+                            _ => connect(second, first),
+                        };
+                        if let (Some(recording), Some(bytes)) = (recording.as_mut(), exchanged) {
+                            recording.add(Exchange {
+                                cycle: recording.clock + now,
+                                master,
+                                bytes: if master == 0 {
+                                    bytes
+                                } else {
+                                    [bytes[1], bytes[0]]
+                                },
+                            });
+                        }
+                    }
+                }
+            }
+            Cable::FourPlayer(adapter) => adapter.update(serials, now),
+            Cable::BarcodeBoy(port) => {
+                if let [serial] = serials {
+                    port.update(serial, now);
+                }
+            }
+            Cable::Replay(port) => {
+                if let [serial] = serials {
+                    port.update(serial, now);
+                }
+            }
+            Cable::Device(port) => {
+                if let [serial] = serials {
+                    port.update(serial, now);
+                }
+            }
+        }
+    }
+
+    pub fn end_frame(&mut self, frame_cycles: i64) {
+        match self {
+            Cable::Direct(recording) => {
+                if let Some(recording) = recording {
+                    recording.clock += frame_cycles;
+                }
+            }
+            Cable::FourPlayer(adapter) => adapter.clock -= frame_cycles,
+            Cable::BarcodeBoy(port) => port.clock += frame_cycles,
+            Cable::Replay(port) => port.clock += frame_cycles,
+            Cable::Device(port) => port.clock += frame_cycles,
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Cable::Direct(Some(recording)) = self {
+            recording.finish();
+        }
+    }
+}
+
+// A device on a single console's port, along with how long it's been plugged in for.
+pub struct Port<D> {
+    clock: i64,
+    pub device: D,
+}
+
+impl<D: SerialDevice> Port<D> {
+    pub fn new(device: D) -> Self {
+        Self { clock: 0, device }
+    }
+
+    fn update(&mut self, serial: &mut Serial, now: i64) {
+        let cycle = self.clock + now;
+        if let Some(outgoing) = serial.take_outgoing() {
+            serial.complete(self.device.exchange(outgoing, cycle));
+        }
+        let armed = serial.armed();
+        if let Some(incoming) = self.device.drive(cycle, armed).filter(|_| armed) {
+            let outgoing = serial.exchange(incoming);
+            self.device.received(outgoing, cycle);
+        }
+    }
+}
+
+// Returns what the master sent and what the slave sent back.
+fn connect(master: &mut Serial, slave: &mut Serial) -> Option<[u8; 2]> {
+    let outgoing = master.take_outgoing()?;
+    let incoming = slave.exchange(outgoing);
+    master.complete(incoming);
+    Some([outgoing, incoming])
+}
+
+// One byte each way between players 1 and 2, stored as a line of text: the cycle since power on, the player
+// driving the clock and what each player sent, in hex, e.g. "70224 1 FE 88".
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Exchange {
+    pub cycle: i64,
+    // 0 for player 1, 1 for player 2.
+    pub master: usize,
+    pub bytes: [u8; 2],
+}
+
+impl Exchange {
+    fn parse(line: &str) -> Option<Exchange> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if let [cycle, master, first, second] = fields.as_slice() {
+            let master = master
+                .parse::<usize>()
+                .ok()?
+                .checked_sub(1)
+                .filter(|m| *m < 2)?;
+            let byte = |value: &str| u8::from_str_radix(value, 16).ok();
+            Some(Exchange {
+                cycle: cycle.parse().ok()?,
+                master,
+                bytes: [byte(first)?, byte(second)?],
+            })
+        } else {
+            None
+        }
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "{} {} {:02X} {:02X}",
+            self.cycle,
+            self.master + 1,
+            self.bytes[0],
+            self.bytes[1]
+        )
+    }
+}
+
+// Everything that went over a two player cable, written out when the session ends.
+pub struct LinkRecording {
+    path: PathBuf,
+    clock: i64,
+    exchanges: Vec<Exchange>,
+}
+
+impl LinkRecording {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            clock: 0,
+            exchanges: vec![],
+        }
+    }
+
+    fn add(&mut self, exchange: Exchange) {
+        self.exchanges.push(exchange);
+    }
+
+    fn encode(&self) -> String {
+        self.exchanges
+            .iter()
+            .map(|exchange| exchange.line() + "\n")
+            .collect()
+    }
+
+    pub fn finish(&self) {
+        match write(&self.path, self.encode()) {
+            Ok(_) => println!("Wrote link recording {}", self.path.display()),
+            Err(e) => println!(
+                "Failed to write link recording {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+// Plays one side of a recording against a single Game Boy in place of the other. Whenever the game drives
+// the clock it gets the recorded partner's next byte back, whichever side drove that exchange, so a game
+// that strays a little from the recording's timing stays in step. The partner's own transfers are sent once
+// the game reaches the cycle they were recorded at and has one armed.
+pub struct LinkReplay {
+    // The recorded player the game takes the place of.
+    player: usize,
+    exchanges: VecDeque<Exchange>,
+}
+
+impl LinkReplay {
+    // `player` is 0 or 1, for players 1 and 2. Lines that aren't exchanges are skipped.
+    pub fn parse(contents: &str, player: usize) -> Self {
+        Self {
+            player,
+            exchanges: contents.lines().filter_map(Exchange::parse).collect(),
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.exchanges.len()
+    }
+}
+
+impl SerialDevice for LinkReplay {
+    fn exchange(&mut self, _outgoing: u8, _cycle: i64) -> u8 {
+        let reply = self.exchanges.pop_front();
+        reply.map_or(0xFF, |exchange| exchange.bytes[1 - self.player])
+    }
+
+    fn drive(&mut self, cycle: i64, armed: bool) -> Option<u8> {
+        let partner = 1 - self.player;
+        self.exchanges
+            .front()
+            .filter(|exchange| armed && exchange.master == partner && exchange.cycle <= cycle)?;
+        self.exchanges
+            .pop_front()
+            .map(|exchange| exchange.bytes[partner])
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Phase {
+    Ping,
+    Starting,
+    Transmission,
+}
+
+// DMG-07: pings every player until player 1 asks to start, then each round collects a SIZE byte packet
+// from every player and sends all four packets back to everyone during the next round.
+pub struct FourPlayerAdapter {
+    clock: i64,
+    phase: Phase,
+    position: usize,
+    connected: u8,
+    size: usize,
+    requests: usize,
+    packets: [Vec<u8>; 4],
+    outgoing: Vec<u8>,
+}
+
+impl FourPlayerAdapter {
+    pub fn new() -> Self {
+        Self {
+            clock: 0,
+            phase: Phase::Ping,
+            position: 0,
+            connected: 0,
+            size: 1,
+            requests: 0,
+            packets: Default::default(),
+            outgoing: vec![],
+        }
+    }
+
+    fn update(&mut self, serials: &mut [&mut Serial], now: i64) {
+        while self.clock + BYTE_INTERVAL <= now {
+            self.clock += BYTE_INTERVAL;
+            self.transfer(serials);
+        }
+    }
+
+    fn transfer(&mut self, serials: &mut [&mut Serial]) {
+        for (player, serial) in serials.iter_mut().enumerate().take(4) {
+            // The adapter owns the clock, so a player trying to drive it just reads back an idle line.
+            if serial.take_outgoing().is_some() {
+                serial.complete(0xFF);
+            }
+            let incoming = serial.exchange(self.outgoing_byte(player));
+            self.receive(player, incoming);
+        }
+        self.advance();
+    }
+
+    fn outgoing_byte(&self, player: usize) -> u8 {
+        match self.phase {
+            Phase::Ping if self.position == 0 => PING_HEADER,
+            Phase::Ping => self.connected << 4 | (player as u8 + 1),
+            Phase::Starting => START_REPLY,
+            Phase::Transmission => self.outgoing.get(self.position).copied().unwrap_or(0),
+        }
+    }
+
+    fn receive(&mut self, player: usize, incoming: u8) {
+        match self.phase {
+            Phase::Ping => {
+                if self.position == 0 && incoming == PING_ACK {
+                    self.connected |= 1 << player;
+                }
+                if player == 0 {
+                    if self.position == 3 && incoming != START_REQUEST {
+                        self.size = (incoming as usize).max(1);
+                    }
+                    self.count_request(incoming == START_REQUEST);
+                }
+            }
+            Phase::Starting => (),
+            Phase::Transmission => {
+                if self.position < self.size && self.connected & (1 << player) != 0 {
+                    self.packets[player].push(incoming);
+                }
+                if player == 0 && self.position < 4 {
+                    self.count_request(incoming == RESTART_REQUEST);
+                }
+            }
+        }
+    }
+
+    fn count_request(&mut self, request: bool) {
+        self.requests = if request { self.requests + 1 } else { 0 };
+    }
+
+    fn advance(&mut self) {
+        self.position += 1;
+        match self.phase {
+            Phase::Ping if self.position == 4 => {
+                self.position = 0;
+                if self.requests >= 4 {
+                    self.phase = Phase::Starting;
+                }
+            }
+            Phase::Starting if self.position == 4 => {
+                self.position = 0;
+                self.phase = Phase::Transmission;
+                self.requests = 0;
+                self.outgoing = vec![0; 4 * self.size];
+            }
+            Phase::Transmission if self.requests >= 4 => {
+                *self = FourPlayerAdapter {
+                    clock: self.clock,
+                    ..FourPlayerAdapter::new()
+                }
+            }
+            Phase::Transmission if self.position == 4 * self.size => {
+                let size = self.size;
+                self.position = 0;
+                self.outgoing = self
+                    .packets
+                    .iter_mut()
+                    .flat_map(|packet| {
+                        packet.resize(size, 0);
+                        packet.drain(..)
+                    })
+                    .collect();
+            }
+            _ => (),
+        }
+    }
+}
+
+// Barcode Boy codes are EAN-13, sent as ASCII digits.
+pub fn valid_barcode(code: &str) -> bool {
+    code.len() == 13 && code.bytes().all(|digit| digit.is_ascii_digit())
+}
+
+// Answers the game's handshake while it drives the clock, then takes over the clock to send every scanned
+// code framed by STX and ETX, one byte whenever the game has a transfer armed on the external clock.
+pub struct BarcodeBoy {
+    clock: i64,
+    handshake: usize,
+    codes: Vec<String>,
+    next_code: usize,
+    outgoing: VecDeque<u8>,
+    typed: Option<Receiver<String>>,
+}
+
+impl BarcodeBoy {
+    pub fn new(codes: Vec<String>) -> Self {
+        Self {
+            clock: 0,
+            handshake: 0,
+            codes,
+            next_code: 0,
+            outgoing: VecDeque::new(),
+            typed: None,
+        }
+    }
+
+    // Codes typed into the terminal, one per line, are scanned as soon as the next frame starts.
+    pub fn read_stdin(&mut self) {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for line in stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        self.typed = Some(receiver);
+    }
+
+    pub fn scan(&mut self, code: &str) -> bool {
+        if !valid_barcode(code) {
+            return false;
+        }
+        self.outgoing.push_back(BARCODE_START);
+        self.outgoing.extend(code.bytes());
+        self.outgoing.push_back(BARCODE_END);
+        true
+    }
+
+    // Swipes the configured codes in turn.
+    pub fn scan_next(&mut self) -> Option<String> {
+        let code = self.codes.get(self.next_code)?.clone();
+        self.next_code = (self.next_code + 1) % self.codes.len();
+        self.scan(&code);
+        Some(code)
+    }
+
+    fn handshake_reply(&mut self, byte: u8) -> u8 {
+        if self.handshake == BARCODE_HANDSHAKE.len() || byte != BARCODE_HANDSHAKE[self.handshake] {
+            self.handshake = 0;
+        }
+        if byte != BARCODE_HANDSHAKE[self.handshake] {
+            return 0xFF;
+        }
+        self.handshake += 1;
+        BARCODE_REPLIES[self.handshake - 1]
+    }
+}
+
+impl SerialDevice for BarcodeBoy {
+    fn exchange(&mut self, outgoing: u8, _cycle: i64) -> u8 {
+        self.handshake_reply(outgoing)
+    }
+
+    fn drive(&mut self, cycle: i64, armed: bool) -> Option<u8> {
+        let typed = self
+            .typed
+            .as_ref()
+            .map(|typed| typed.try_iter().collect::<Vec<String>>())
+            .unwrap_or_default();
+        for code in typed {
+            match self.scan(code.trim()) {
+                true => println!("Scanned barcode {}", code.trim()),
+                false => println!("Ignoring invalid barcode: {}", code.trim()),
+            }
+        }
+        // One byte goes out per interval the console spends armed.
+        let mut sent = None;
+        while self.clock + BYTE_INTERVAL <= cycle {
+            self.clock += BYTE_INTERVAL;
+            if armed && sent.is_none() {
+                sent = self.outgoing.pop_front();
+            }
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::{
+        BarcodeBoy, Cable, Exchange, FourPlayerAdapter, LinkRecording, LinkReplay, Port,
+        BYTE_INTERVAL,
+    };
+    use crate::serial::{Loopback, Serial};
+
+    // Arms every player's next reply, clocks one byte and returns what each of them received.
+    fn transfer(
+        cable: &mut Cable,
+        players: &mut [Serial],
+        replies: &[u8],
+        now: &mut i64,
+    ) -> Vec<u8> {
+        for (serial, reply) in players.iter_mut().zip(replies) {
+            serial.write(0xFF01, *reply);
+            serial.write(0xFF02, 0x80);
+        }
+        *now += BYTE_INTERVAL;
+        cable.update(&mut players.iter_mut().collect::<Vec<_>>(), *now);
+        players
+            .iter()
+            .map(|serial| serial.read(0xFF01).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn four_player_ping_and_transmission() {
+        let mut cable = Cable::FourPlayer(FourPlayerAdapter::new());
+        let mut players = [Serial::new(), Serial::new()];
+        let mut now = 0;
+
+        let ping = [[0x88, 0x88], [0x88, 0x88], [0x10, 0x10], [0x02, 0x02]];
+        let received = ping
+            .iter()
+            .map(|replies| transfer(&mut cable, &mut players, replies, &mut now))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            [[0xFE, 0xFE], [0x31, 0x32], [0x31, 0x32], [0x31, 0x32]]
+        );
+
+        for _ in 0..4 {
+            transfer(&mut cable, &mut players, &[0xAA, 0x88], &mut now);
+        }
+        for _ in 0..4 {
+            assert_eq!(
+                transfer(&mut cable, &mut players, &[0, 0], &mut now),
+                [0xCC, 0xCC]
+            );
+        }
+
+        let packets = [
+            [1, 3],
+            [2, 4],
+            [0, 0],
+            [0, 0],
+            [0, 0],
+            [0, 0],
+            [0, 0],
+            [0, 0],
+        ];
+        for replies in packets.iter() {
+            transfer(&mut cable, &mut players, replies, &mut now);
+        }
+        let round = packets
+            .iter()
+            .map(|_| transfer(&mut cable, &mut players, &[0, 0], &mut now)[1])
+            .collect::<Vec<_>>();
+        assert_eq!(round, [1, 2, 3, 4, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn barcode_boy_handshake_and_scan() {
+        let reader = BarcodeBoy::new(vec!["4902370501315".to_owned()]);
+        let mut cable = Cable::BarcodeBoy(Port::new(reader));
+        let mut players = [Serial::new()];
+        players[0].linked = true;
+        let mut now = 0;
+
+        let mut replies = vec![];
+        for byte in [0x10, 0x07, 0x10, 0x07] {
+            players[0].write(0xFF01, byte);
+            players[0].write(0xFF02, 0x81);
+            for _ in 0..8 * 128 {
+                players[0].machine_cycle();
+            }
+            cable.update(&mut [&mut players[0]], now);
+            replies.push(players[0].read(0xFF01).unwrap());
+        }
+        assert_eq!(replies, [0xFF, 0xFF, 0x10, 0x07]);
+
+        if let Cable::BarcodeBoy(port) = &mut cable {
+            assert!(!port.device.scan("12345"));
+            assert_eq!(port.device.scan_next().as_deref(), Some("4902370501315"));
+        }
+        let received = (0..15)
+            .map(|_| transfer(&mut cable, &mut players, &[0], &mut now)[0])
+            .collect::<Vec<_>>();
+        assert_eq!(received, b"\x024902370501315\x03");
+    }
+
+    #[test]
+    fn replays_a_recorded_partner() {
+        let mut cable = Cable::Direct(Some(LinkRecording::new("link.txt".into())));
+        let mut players = [Serial::new(), Serial::new()];
+        players.iter_mut().for_each(|serial| serial.linked = true);
+        // Player 1 drives the first byte, player 2 the second.
+        for (master, bytes) in [(0, [0x12, 0x34]), (1, [0x56, 0x78])] {
+            for (serial, byte) in players.iter_mut().zip(bytes) {
+                serial.write(0xFF01, byte);
+                serial.write(0xFF02, 0x80);
+            }
+            players[master].write(0xFF02, 0x81);
+            for _ in 0..8 * 128 {
+                players[master].machine_cycle();
+            }
+            cable.update(&mut players.iter_mut().collect::<Vec<_>>(), 100);
+            cable.end_frame(1000);
+        }
+        let recorded = match &cable {
+            Cable::Direct(Some(recording)) => recording.encode(),
+            _ => unreachable!(),
+        };
+        assert_eq!(recorded, "100 1 12 34\n1100 2 56 78\n");
+        assert_eq!(
+            Exchange::parse("1100 2 56 78"),
+            Some(Exchange {
+                cycle: 1100,
+                master: 1,
+                bytes: [0x56, 0x78]
+            })
+        );
+
+        // Player 1's game against the recorded player 2.
+        let mut cable = Cable::Replay(Port::new(LinkReplay::parse(&recorded, 0)));
+        let mut serial = Serial::new();
+        serial.linked = true;
+        serial.write(0xFF01, 0x12);
+        serial.write(0xFF02, 0x81);
+        for _ in 0..8 * 128 {
+            serial.machine_cycle();
+        }
+        cable.update(&mut [&mut serial], 0);
+        assert_eq!(serial.read(0xFF01), Some(0x34));
+
+        // The partner's transfer waits for the cycle it was recorded at.
+        serial.write(0xFF02, 0x80);
+        cable.update(&mut [&mut serial], 1000);
+        assert_eq!(serial.read(0xFF01), Some(0x34));
+        cable.update(&mut [&mut serial], 1100);
+        assert_eq!(serial.read(0xFF01), Some(0x78));
+        match &cable {
+            Cable::Replay(port) => assert_eq!(port.device.remaining(), 0),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn loopback_sends_bytes_back() {
+        let mut cable = Cable::Device(Port::new(Box::new(Loopback)));
+        let mut serial = Serial::new();
+        serial.linked = true;
+        serial.write(0xFF01, 0x5A);
+        serial.write(0xFF02, 0x81);
+        for _ in 0..8 * 128 {
+            serial.machine_cycle();
+        }
+        cable.update(&mut [&mut serial], 0);
+        assert_eq!(serial.read(0xFF01), Some(0x5A));
+        assert!(serial.machine_cycle().is_some());
+    }
+}
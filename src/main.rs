@@ -1,60 +1,1571 @@
-use std::{env, thread};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::ScopedJoinHandle;
+use std::{env, process, thread};
 
-use gameboy::Gameboy;
+#[cfg(feature = "achievements")]
+use feboy::achievements::Achievements;
+use feboy::assembler::{parse_number, patch_rom};
+use feboy::bench::{bench, BENCH_FRAMES};
+use feboy::boot::BootAnimation;
+use feboy::cartridge::LoadOptions;
+use feboy::cartridge::{Cartridge, CartridgeHeader};
+use feboy::cheats::{load_cheat_file, Freeze};
+use feboy::compat::{check_dir, to_csv, to_markdown, COMPAT_FRAMES};
+use feboy::config::{parse_frame_skip, AutoState, Background, Config, Settings, PALETTES};
+use feboy::crash::{install_panic_hook, write_crash_report};
+#[cfg(feature = "sameboy")]
+use feboy::differential::run_differential;
+use feboy::discord::Presence;
+use feboy::error::FeboyError;
+use feboy::frame_hash::HashStream;
+use feboy::frames::{frame_channel, FrameSender};
+use feboy::game_db::GameDb;
+use feboy::gameboy::{step, Gameboy};
+use feboy::headless::{run_headless, SerialMarkers, RUN_FRAMES};
+use feboy::heatmap::{HeatMap, HeatMapView};
+use feboy::hotkeys::{Hotkey, Hotkeys};
+use feboy::input::InputSource;
+use feboy::interrupt::InterruptId;
+use feboy::labels::load_label_file;
+use feboy::launcher::{confirm, pick_rom, RecentRoms};
+use feboy::link::{BarcodeBoy, Cable, FourPlayerAdapter, LinkRecording, LinkReplay, Port};
+use feboy::memory_map::{MemoryMap, RamFill, FIXED_RAM_SEED};
+use feboy::outlines::Outlines;
+use feboy::pacing::{
+    frame_skip, parse_speed, set_thread_priority, FrameScheduler, FRAME_CYCLES, MAX_SPEED,
+    MIN_SPEED,
+};
+use feboy::paths::{is_portable, DataPaths, SaveDir};
+use feboy::picture::Picture;
+use feboy::ppu::PpuMode;
+#[cfg(feature = "remote")]
+use feboy::remote::{Command, Remote, Reply};
+use feboy::rom_loader::load_rom;
+use feboy::rtc::RtcClock;
+use feboy::sav::{export_sav, import_sav, SavFormat};
+use feboy::save::BatterySave;
+use feboy::screen::Screen;
+#[cfg(feature = "remote")]
+use feboy::screenshot::encode_png;
+use feboy::screenshot::save_screenshot;
+use feboy::serial::{Loopback, Serial};
+use feboy::sram_editor::SramEditor;
+use feboy::state::Rewind;
+use feboy::state_diff::diff_states;
+use feboy::state_picker::{Pick, StatePicker, SLOTS};
+use feboy::vgm::VgmLog;
+use feboy::video::{VideoFrame, VideoSink};
+use feboy::watchdog::Watchdog;
+use std::fs::{read, read_to_string, write, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::memory_map::MemoryMap;
-use std::time::{Duration, Instant};
+const FRAME_DURATION: Duration = Duration::from_micros(16_742);
+const FAST_FORWARD_SPEED: f64 = 4.0;
+const BACKGROUND_SPEED: f64 = 0.25;
 
-use std::fs::read;
+struct Args {
+    rom_name: Option<String>,
+    patch_name: Option<String>,
+    save_dir: Option<SaveDir>,
+    portable: bool,
+    vgm_path: Option<PathBuf>,
+    linked_roms: Vec<String>,
+    four_player: bool,
+    barcode_boy: bool,
+    loopback: bool,
+    record_link: Option<PathBuf>,
+    replay_link: Option<(PathBuf, usize)>,
+    diff_states: Option<(String, String)>,
+    // Export or import, then the file to convert and the file to write.
+    sav: Option<(String, String, String)>,
+    sav_format: Option<SavFormat>,
+    asm_patches: Vec<(usize, String)>,
+    strict: bool,
+    deterministic: bool,
+    // Keeps the last instructions executed, for the dump trace hotkey and crash reports.
+    trace: bool,
+    // Reports V-blank and STAT handlers that run past the blanking period they started in.
+    profile_interrupts: bool,
+    bench: bool,
+    run: bool,
+    // Ends a headless run on the serial markers, with an exit code saying which was seen.
+    exit_code_from_serial: bool,
+    pass_marker: Option<String>,
+    fail_marker: Option<String>,
+    // Where a headless run writes every frame's hash, - for standard output.
+    frame_hashes: Option<PathBuf>,
+    compat: bool,
+    csv: bool,
+    frames: Option<u64>,
+    speed: Option<f64>,
+    frame_skip: Option<usize>,
+    ram_fill: Option<RamFill>,
+    ram_seed: Option<u32>,
+    freezes: Vec<Freeze>,
+    #[cfg(feature = "sameboy")]
+    differential: Option<String>,
+    #[cfg(feature = "remote")]
+    remote: Option<String>,
+}
 
-mod gameboy;
-mod instruction;
-mod instruction_fetcher;
-mod interrupt;
-mod joypad;
-mod memory_map;
-mod ppu;
-mod register;
-mod timer;
+impl Args {
+    fn parse() -> Self {
+        Args::parse_from(env::args().skip(1))
+    }
 
-const FREQUENCY: u32 = 4194304;
+    fn parse_from(args: impl Iterator<Item = String>) -> Self {
+        let mut args = args.peekable();
+        let mut rom_name = None;
+        let mut patch_name = None;
+        let mut save_dir = None;
+        let mut portable = false;
+        let mut vgm_path = None;
+        let mut linked_roms = vec![];
+        let mut four_player = false;
+        let mut barcode_boy = false;
+        let mut loopback = false;
+        let mut record_link = None;
+        let mut replay_link = None;
+        let mut diff_states = None;
+        let mut sav = None;
+        let mut sav_format = None;
+        let mut asm_patches = vec![];
+        let mut strict = false;
+        let mut deterministic = false;
+        let mut trace = false;
+        let mut profile_interrupts = false;
+        let mut bench = false;
+        let mut run = false;
+        let mut exit_code_from_serial = false;
+        let mut pass_marker = None;
+        let mut fail_marker = None;
+        let mut frame_hashes = None;
+        let mut compat = false;
+        let mut csv = false;
+        let mut frames = None;
+        let mut speed = None;
+        let mut frame_skip = None;
+        let mut ram_fill = None;
+        let mut ram_seed = None;
+        let mut freezes = vec![];
+        #[cfg(feature = "sameboy")]
+        let mut differential = None;
+        #[cfg(feature = "remote")]
+        let mut remote = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--patch" => patch_name = args.next(),
+                "--save-dir" => save_dir = args.next().and_then(|dir| SaveDir::parse(&dir)),
+                "--portable" => portable = true,
+                "--log-vgm" => vgm_path = args.next().map(PathBuf::from),
+                "--dual" => {
+                    rom_name = args.next();
+                    linked_roms.extend(args.next());
+                }
+                // Player 1's ROM followed by up to three more, e.g. --four-player f1.gb f1.gb f1.gb
+                "--four-player" => {
+                    four_player = true;
+                    rom_name = args.next();
+                    while let Some(rom) = args.next_if(|arg| !arg.starts_with("--")) {
+                        linked_roms.push(rom);
+                    }
+                    linked_roms.truncate(3);
+                }
+                "--barcode-boy" => barcode_boy = true,
+                // Wires the link port back into itself, for link cable test ROMs
+                "--loopback" => loopback = true,
+                "--record-link" => record_link = args.next().map(PathBuf::from),
+                // A recording and the player the game takes the place of, 1 by default, e.g.
+                // --replay-link trade.txt 2
+                "--replay-link" => {
+                    let path = args.next().map(PathBuf::from);
+                    let player = args.next_if(|arg| arg == "1" || arg == "2");
+                    let player = player.map_or(0, |player| if player == "2" { 1 } else { 0 });
+                    replay_link = path.map(|path| (path, player));
+                }
+                "--strict" => strict = true,
+                // Cuts the core off from the host, so the same ROM always draws the same frames
+                "--deterministic" => deterministic = true,
+                "--trace" => trace = true,
+                "--profile-interrupts" => profile_interrupts = true,
+                // Runs the ROM headlessly and as fast as possible, e.g. feboy bench game.gb --frames 600
+                "bench" => bench = true,
+                // Runs a test ROM without a window for CI, e.g. feboy run test.gb --frames 3600
+                // --exit-code-from-serial --pass "Passed" --fail "Failed"
+                "run" => run = true,
+                "--exit-code-from-serial" => exit_code_from_serial = true,
+                "--pass" => pass_marker = args.next(),
+                "--fail" => fail_marker = args.next(),
+                // e.g. feboy run game.gb --frames 600 --frame-hashes golden.txt, then diff two builds' files
+                "--frame-hashes" => frame_hashes = args.next().map(PathBuf::from),
+                // Boots every ROM in a directory and prints a Markdown table, e.g. feboy compat roms/ --csv
+                "compat" => compat = true,
+                "--csv" => csv = true,
+                "--frames" => frames = args.next().and_then(|frames| frames.parse().ok()),
+                "--frame-skip" => frame_skip = args.next().as_deref().and_then(parse_frame_skip),
+                // What WRAM and HRAM power up with, e.g. --ram-init random --seed 1234
+                "--ram-init" => ram_fill = args.next().as_deref().and_then(RamFill::parse),
+                "--seed" => ram_seed = args.next().and_then(|seed| seed.parse().ok()),
+                // Holds a RAM address at a value, e.g. --freeze C0A0=99
+                "--freeze" => freezes.extend(args.next().as_deref().and_then(Freeze::parse)),
+                "--speed" => {
+                    speed = args.next().as_deref().and_then(parse_speed);
+                    if speed.is_none() {
+                        println!("--speed takes a value from {} to {}", MIN_SPEED, MAX_SPEED);
+                    }
+                }
+                // Runs the ROM against SameBoy, which needs a DMG boot ROM, e.g. --differential dmg_boot.bin
+                #[cfg(feature = "sameboy")]
+                "--differential" => differential = args.next(),
+                // The address to take remote control commands on, e.g. --remote 127.0.0.1:7777
+                #[cfg(feature = "remote")]
+                "--remote" => remote = args.next(),
+                // The states are loaded into the ROM so its mapper names its own registers, e.g.
+                // feboy game.gb --diff-states before.state after.state
+                "--diff-states" => diff_states = args.next().zip(args.next()),
+                // Converts battery saves from and to other emulators', e.g.
+                // feboy sav import game.srm game.sav --format raw
+                "sav" => {
+                    let files = args.next().zip(args.next()).zip(args.next());
+                    sav = files.map(|((direction, from), to)| (direction, from, to));
+                }
+                "--format" => sav_format = args.next().as_deref().and_then(SavFormat::parse),
+                // A ROM file offset and the code to write there, e.g. --asm 0x0150 "ld a, 5; nop"
+                "--asm" => asm_patches.extend(
+                    args.next()
+                        .and_then(|offset| parse_number(&offset))
+                        .map(|offset| offset as usize)
+                        .zip(args.next()),
+                ),
+                _ => rom_name = Some(arg),
+            }
+        }
+        Self {
+            rom_name,
+            patch_name,
+            save_dir,
+            portable: is_portable(portable),
+            vgm_path,
+            linked_roms,
+            four_player,
+            barcode_boy,
+            loopback,
+            record_link,
+            replay_link,
+            diff_states,
+            sav,
+            sav_format,
+            asm_patches,
+            strict,
+            deterministic,
+            trace,
+            profile_interrupts,
+            bench,
+            run,
+            exit_code_from_serial,
+            pass_marker,
+            fail_marker,
+            frame_hashes,
+            compat,
+            csv,
+            frames,
+            speed,
+            frame_skip,
+            ram_fill,
+            ram_seed,
+            freezes,
+            #[cfg(feature = "sameboy")]
+            differential,
+            #[cfg(feature = "remote")]
+            remote,
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let rom_name = args.get(1).unwrap();
-    let rom = read(rom_name).unwrap();
-    let mem = MemoryMap::new(&rom, rom_name);
+    install_panic_hook();
+    let args = Args::parse();
+    if let Some((a, b)) = &args.diff_states {
+        let rom_name = match &args.rom_name {
+            Some(rom_name) => rom_name,
+            None => {
+                println!("Usage: feboy <rom> --diff-states <state> <state>");
+                return;
+            }
+        };
+        let options = LoadOptions {
+            game_db: GameDb::load(args.portable),
+            strict: false,
+        };
+        let diff = load_rom(rom_name, args.patch_name.as_deref())
+            .and_then(|rom| MemoryMap::new(&rom, rom_name, &options))
+            .and_then(|mem| Ok((Gameboy::new(mem), read(a)?, read(b)?)))
+            .and_then(|(mut gameboy, a, b)| diff_states(&mut gameboy, &a, &b));
+        match diff {
+            Ok(report) => print!("{}", report),
+            Err(e) => println!("Failed to diff {} and {}: {}", a, b, e),
+        }
+        return;
+    }
+    if let Some((direction, from, to)) = &args.sav {
+        let (convert, format) = match (direction.as_str(), args.sav_format) {
+            ("export", Some(format)) => (export_sav as fn(&[u8], _) -> _, format),
+            ("import", Some(format)) => (import_sav as fn(&[u8], _) -> _, format),
+            _ => {
+                println!("Usage: feboy sav export|import <from> <to> --format raw|vba|mbc2");
+                return;
+            }
+        };
+        let result = read(from)
+            .map_err(FeboyError::from)
+            .and_then(|data| convert(&data, format))
+            .and_then(|data| Ok(write(to, data)?));
+        match result {
+            Ok(_) => println!("Wrote {}", to),
+            Err(e) => println!("Failed to convert {}: {}", from, e),
+        }
+        return;
+    }
+    if args.bench {
+        let rom_name = match &args.rom_name {
+            Some(rom_name) => rom_name,
+            None => {
+                println!("Usage: feboy bench <rom> [--frames <count>] [--frame-skip <n>]");
+                return;
+            }
+        };
+        let frames = args.frames.unwrap_or(BENCH_FRAMES);
+        let frame_skip = args.frame_skip.unwrap_or(1);
+        let result = load_rom(rom_name, args.patch_name.as_deref())
+            .and_then(|rom| bench(&rom, rom_name, frames, frame_skip));
+        match result {
+            Ok(result) => print!("{}", result),
+            Err(e) => println!("Benchmark of {} failed: {}", rom_name, e),
+        }
+        return;
+    }
+    if args.run {
+        let rom_name = match &args.rom_name {
+            Some(rom_name) => rom_name,
+            None => {
+                println!("Usage: feboy run <rom> [--frames <count>] [--exit-code-from-serial [--pass <text>] [--fail <text>]]");
+                process::exit(2);
+            }
+        };
+        let frames = args.frames.unwrap_or(RUN_FRAMES);
+        let markers = args.exit_code_from_serial.then(|| {
+            let defaults = SerialMarkers::default();
+            SerialMarkers {
+                pass: args.pass_marker.clone().unwrap_or(defaults.pass),
+                fail: args.fail_marker.clone().unwrap_or(defaults.fail),
+            }
+        });
+        let mut out: Box<dyn Write> = match args.frame_hashes.as_deref() {
+            Some(path) if path == Path::new("-") => Box::new(io::stdout().lock()),
+            Some(path) => match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(e) => {
+                    println!("Failed to create {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            },
+            None => Box::new(io::sink()),
+        };
+        let hashes = args
+            .frame_hashes
+            .is_some()
+            .then(|| HashStream::new(&mut *out as &mut dyn Write));
+        let result = load_rom(rom_name, args.patch_name.as_deref())
+            .and_then(|rom| run_headless(&rom, rom_name, frames, markers.as_ref(), hashes));
+        // Exiting skips destructors, so the file is flushed here.
+        drop(out);
+        match result {
+            Ok((outcome, output)) => {
+                println!("{}", output);
+                process::exit(outcome.exit_code(markers.is_some()));
+            }
+            Err(e) => {
+                println!("Running {} failed: {}", rom_name, e);
+                process::exit(1);
+            }
+        }
+    }
+    if args.compat {
+        let dir = args.rom_name.as_deref().unwrap_or(".");
+        let frames = args.frames.unwrap_or(COMPAT_FRAMES);
+        match check_dir(Path::new(dir), frames) {
+            Ok(entries) if args.csv => print!("{}", to_csv(&entries)),
+            Ok(entries) => print!("{}", to_markdown(&entries)),
+            Err(e) => println!("Failed to read {}: {}", dir, e),
+        }
+        return;
+    }
+    let mut recent = RecentRoms::load(args.portable);
+    let rom_name = match args.rom_name.clone().or_else(|| pick_rom(&recent)) {
+        Some(rom_name) => rom_name,
+        None => return,
+    };
+    let mut games = vec![load_game(
+        &rom_name,
+        args.patch_name.as_deref(),
+        &args.asm_patches,
+        &args,
+        &mut recent,
+    )];
+    for linked_rom in &args.linked_roms {
+        games.push(load_game(linked_rom, None, &[], &args, &mut recent));
+    }
+    if let Some(vgm_path) = &args.vgm_path {
+        games[0]
+            .gameboy
+            .mem
+            .start_vgm_log(VgmLog::new(vgm_path.clone()));
+    }
+
+    let settings = &games[0].settings;
+    let presence = match (settings.discord, settings.discord_app_id.as_str()) {
+        (true, "") => {
+            println!("Discord presence needs discord_app_id to be set");
+            None
+        }
+        (true, app_id) => Some(Presence::start(app_id.to_owned())),
+        (false, _) => None,
+    };
+    // Kept with the game that takes commands, which is the only one that can switch ROMs.
+    games[0].session.presence = presence;
+    if let Some(presence) = &games[0].session.presence {
+        presence.set_game(&games[0].gameboy.mem.cartridge.header.title);
+    }
+    // Linked cores share one frame loop without hotkeys, so only a single game takes commands.
+    #[cfg(feature = "remote")]
+    if let Some(address) = &args.remote {
+        match Remote::listen(address) {
+            Ok(remote) => {
+                println!("Listening for remote commands on {}", address);
+                games[0].session.remote = Some(remote);
+            }
+            Err(e) => println!("Failed to listen on {}: {}", address, e),
+        }
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+        .expect("Failed to install SIGINT handler");
+
+    #[cfg(feature = "sameboy")]
+    if let Some(boot_rom) = &args.differential {
+        match run_differential(&mut games[0].gameboy, &rom_name, boot_rom, &running) {
+            Ok(Some(report)) => print!("{}", report),
+            Ok(None) => (),
+            Err(e) => println!("Differential run failed: {}", e),
+        }
+        return;
+    }
+
+    let result = catch_unwind(AssertUnwindSafe(|| match games.as_mut_slice() {
+        _ if args.four_player => run_linked(
+            &mut games,
+            Cable::FourPlayer(FourPlayerAdapter::new()),
+            &running,
+        ),
+        [game] if args.barcode_boy => {
+            let mut reader = BarcodeBoy::new(game.settings.barcodes.clone());
+            reader.read_stdin();
+            run(game, Some(Cable::BarcodeBoy(Port::new(reader))), &running)
+        }
+        [game] if args.loopback => {
+            let cable = Cable::Device(Port::new(Box::new(Loopback)));
+            run(game, Some(cable), &running)
+        }
+        [game] => match &args.replay_link {
+            Some((path, player)) => {
+                let contents = read_to_string(path).unwrap_or_else(|e| {
+                    println!("Failed to read link recording {}: {}", path.display(), e);
+                    process::exit(1);
+                });
+                let replay = LinkReplay::parse(&contents, *player);
+                run(game, Some(Cable::Replay(Port::new(replay))), &running)
+            }
+            None => run(game, None, &running),
+        },
+        _ => {
+            let recording = args.record_link.clone().map(LinkRecording::new);
+            run_linked(&mut games, Cable::Direct(recording), &running)
+        }
+    }));
+    if result.is_err() {
+        for game in games.iter_mut() {
+            let labels = load_label_file(&game.session.paths.labels());
+            write_crash_report(
+                &mut game.gameboy,
+                &labels,
+                &game.session.paths.crash_report(),
+            );
+        }
+    }
+    for game in games.iter_mut() {
+        game.session.save.flush(&mut game.gameboy.mem.cartridge);
+        game.gameboy.mem.finish_vgm_log();
+        // A game that crashed is better started over than resumed.
+        if result.is_ok() {
+            save_auto_state(&game.gameboy, &game.session);
+        }
+    }
+    if let Err(panic) = result {
+        resume_unwind(panic);
+    }
+}
 
+struct Game {
+    gameboy: Gameboy,
+    settings: Settings,
+    session: Session,
+    frontend: Frontend,
+}
+
+// The config's settings for the game with the command line's on top. Deterministic runs start from the
+// defaults instead, so nothing the player has configured can change what the core does.
+fn game_settings(args: &Args, header: &CartridgeHeader) -> Settings {
+    let mut settings = match args.deterministic {
+        true => deterministic_settings(),
+        false => Config::load(args.portable).settings(header),
+    };
+    if let Some(save_dir) = &args.save_dir {
+        settings.save_dir = save_dir.clone();
+    }
+    if let Some(speed) = args.speed {
+        settings.speed = speed;
+    }
+    if let Some(frame_skip) = args.frame_skip {
+        settings.frame_skip = frame_skip;
+    }
+    if let Some(ram_fill) = args.ram_fill {
+        settings.ram_fill = ram_fill;
+    }
+    if args.ram_seed.is_some() {
+        settings.ram_seed = args.ram_seed;
+    }
+    settings.freezes.extend(&args.freezes);
+    settings
+}
+
+// RAM is filled from a fixed seed unless one was given, the cartridge clock follows emulated time, the
+// window's focus is ignored and no input is connected. There's no audio yet, and no movie files, so nothing
+// else reaches the core from the host.
+fn deterministic_settings() -> Settings {
+    let mut settings = Settings::new();
+    settings.rtc = RtcClock::Emulated;
+    settings.ram_seed = Some(FIXED_RAM_SEED);
+    settings.background = Background::Run;
+    settings
+}
+
+fn load_game(
+    rom_name: &str,
+    patch_name: Option<&str>,
+    asm_patches: &[(usize, String)],
+    args: &Args,
+    recent: &mut RecentRoms,
+) -> Game {
+    let mem = load_rom(rom_name, patch_name).and_then(|mut rom| {
+        for (offset, source) in asm_patches {
+            patch_rom(&mut rom, *offset, source)?;
+            println!("Assembled {} at 0x{:X}", source, offset);
+        }
+        let options = LoadOptions {
+            game_db: GameDb::load(args.portable),
+            strict: args.strict,
+        };
+        MemoryMap::new(&rom, &rom_name.to_owned(), &options)
+    });
+    let mut mem = match mem {
+        Ok(mem) => mem,
+        Err(e) => {
+            println!("Failed to load {}: {}", rom_name, e);
+            process::exit(1);
+        }
+    };
+    recent.add(rom_name);
+    let mut settings = game_settings(args, &mem.cartridge.header);
+    let paths = DataPaths::new(rom_name, &settings.save_dir, args.portable);
+    if !args.deterministic {
+        settings.cheats.extend(load_cheat_file(&paths.cheats()));
+    }
+    mem.apply_settings(&settings);
+    report_ram_seed(&mem);
+
+    // Neither is battery RAM loaded or written back, or each run would start from where the last left off.
+    let save = match args.deterministic {
+        true => BatterySave::disabled(),
+        false => BatterySave::new(paths.battery_save()),
+    };
+    save.load(&mut mem.cartridge);
+    for conflict in settings.hotkeys.conflicts(&settings.inputs.keys()) {
+        println!("Hotkey conflict: {}", conflict);
+    }
+    let input = InputSource::new(settings.inputs.clone(), settings.input_latency);
+    if !args.deterministic {
+        mem.connect_input(input.port());
+    }
+
+    let (frames, frame_receiver) = frame_channel();
+    let (messages, message_receiver) = channel();
+    let (hotkeys, hotkey_receiver) = channel();
+    let (heat_maps, heat_map_receiver) = channel();
+    let (sram, sram_receiver) = channel();
+    let (sram_edits, sram_edit_receiver) = channel();
+    let (focus, focus_receiver) = channel();
+    let mut screen = Screen::open(rom_name, frame_receiver);
+    screen.set_dot_matrix(settings.dot_matrix);
+    screen.set_scaling(settings.scaling);
+    screen.set_osd(settings.osd);
+    let frontend = Frontend {
+        screen,
+        input,
+        messages: message_receiver,
+        hotkeys,
+        heat_map: None,
+        heat_maps: heat_map_receiver,
+        sram_editor: None,
+        sram: sram_receiver,
+        sram_edits,
+        paths: paths.clone(),
+        focus,
+        focused: true,
+    };
+    let mut session = Session {
+        frames,
+        messages,
+        hotkeys: hotkey_receiver,
+        held_hotkeys: vec![],
+        heat_maps,
+        sram,
+        sram_edits: sram_edit_receiver,
+        sram_editor: false,
+        focus: focus_receiver,
+        focused: true,
+        background: settings.background,
+        scheduler: FrameScheduler::new(settings.speed, settings.refresh_rate),
+        boot_animation: Some(BootAnimation::new(&mem.cartridge.header.logo))
+            .filter(|_| settings.boot_animation),
+        save,
+        paths,
+        rewind: Rewind::new(),
+        palette: PALETTES.iter().position(|(_, p)| *p == settings.palette),
+        base_palette: settings.palette,
+        picture: settings.picture,
+        config_path: Config::path(args.portable),
+        paused: false,
+        outlines: Outlines::default(),
+        locked: false,
+        watchdog: Watchdog::new(),
+        #[cfg(feature = "achievements")]
+        achievements: Achievements::connect(&settings.achievements, mem.cartridge.rom()),
+        #[cfg(feature = "remote")]
+        remote: None,
+        #[cfg(feature = "remote")]
+        portable: args.portable,
+        auto_state: settings.auto_state,
+        slot: 1,
+        picker: None,
+        frame_skip: settings.frame_skip,
+        battery_saver: settings.battery_saver,
+        high_priority: settings.high_priority,
+        deterministic: args.deterministic,
+        presence: None,
+    };
+    session.scheduler.spin = !settings.battery_saver;
     let mut gameboy = Gameboy::new(mem);
+    gameboy.set_trace(args.trace);
+    gameboy.set_interrupt_profiling(args.profile_interrupts);
+    if !args.deterministic && resume_auto_state(&mut gameboy, &session, true) {
+        session.boot_animation = None;
+    }
+    Game {
+        gameboy,
+        settings,
+        session,
+        frontend,
+    }
+}
 
-    loop {
-        run_frame(&mut gameboy);
+// A single core can still have a peripheral plugged into its serial port.
+fn run(game: &mut Game, cable: Option<Cable>, running: &AtomicBool) {
+    let Game {
+        gameboy,
+        settings,
+        session,
+        frontend,
+    } = game;
+    let settings = &*settings;
+    frontend.restore_layout(settings);
+    session.sram_editor = frontend.sram_editor.is_some();
+    if frontend.heat_map.is_some() {
+        gameboy.mem.set_heat_map(true);
+    }
+    thread::scope(|scope| {
+        let emulation = scope.spawn(|| emulate(gameboy, session, cable, running));
+        present(
+            &mut [frontend],
+            Some(&settings.hotkeys),
+            running,
+            &emulation,
+        );
+    });
+    if !session.deterministic {
+        frontend.save_layout(settings, &session.config_path);
     }
 }
 
-fn run_frame(gameboy: &mut Gameboy) {
+fn emulate(
+    gameboy: &mut Gameboy,
+    session: &mut Session,
+    mut cable: Option<Cable>,
+    running: &AtomicBool,
+) {
+    let mut clocks = [0];
+    gameboy.mem.serial.linked = cable.is_some();
+    if session.high_priority {
+        raise_priority();
+    }
+    while running.load(Ordering::SeqCst) {
+        let hotkeys = session.receive_hotkeys();
+        // Hotkeys are dropped until the game itself has started.
+        if let Some(animation) = &mut session.boot_animation {
+            let palette = gameboy.mem.ppu.palette();
+            match animation.next_frame(palette) {
+                Some(frame) => {
+                    session.frames.send(&frame, palette, 0);
+                    session.scheduler.wait(FRAME_CYCLES, 1.0);
+                    continue;
+                }
+                None => session.boot_animation = None,
+            }
+        }
+        handle_hotkeys(gameboy, session, &hotkeys);
+        #[cfg(feature = "remote")]
+        handle_remote(gameboy, session);
+        session.sync_sram(&mut gameboy.mem.cartridge);
+        if let Some(Cable::BarcodeBoy(port)) = &mut cable {
+            if hotkeys.contains(&Hotkey::ScanBarcode) {
+                let message = match port.device.scan_next() {
+                    Some(code) => format!("Scanned {}", code),
+                    None => "No barcodes configured".to_owned(),
+                };
+                session.show_message(message);
+            }
+        }
+        if session.picker.is_some() {
+            handle_picker(gameboy, session);
+            session.scheduler.wait(FRAME_CYCLES, 1.0);
+            continue;
+        }
+        if let Some(focused) = session.focus.try_iter().last() {
+            session.focused = focused;
+        }
+        let background = if session.focused {
+            Background::Run
+        } else {
+            session.background
+        };
+        let halted = session.paused || background == Background::Pause;
+        if halted || hotkeys.contains(&Hotkey::Rewind) {
+            if !halted {
+                session.rewind.step_back(gameboy);
+            }
+            session.present(&mut gameboy.mem);
+            session.scheduler.wait(FRAME_CYCLES, 1.0);
+            continue;
+        }
+        session.rewind.record(gameboy);
+        let boost = if hotkeys.contains(&Hotkey::FastForward) {
+            FAST_FORWARD_SPEED
+        } else if background == Background::Throttle {
+            BACKGROUND_SPEED
+        } else {
+            1.0
+        };
+        let result = match &mut cable {
+            Some(cable) => run_linked_frame(&mut [&mut *gameboy], &mut clocks, cable),
+            None => run_frame(gameboy),
+        };
+        if let Err(e) = result {
+            println!("Emulation stopped: {}", e);
+            break;
+        }
+        session.scheduler.wait(FRAME_CYCLES, boost);
+        session.present(&mut gameboy.mem);
+        report_overruns(gameboy);
+        if let Some(heat_map) = gameboy.mem.heat_map() {
+            heat_map.end_frame();
+            let _ = session.heat_maps.send(heat_map.clone());
+        }
+        if gameboy.locked() && !session.locked {
+            let pc = gameboy.reg.pc.value().wrapping_sub(1);
+            session.show_message(format!("CPU locked at PC={:04X}", pc));
+        }
+        session.locked = gameboy.locked();
+        if let Some(pc) = session.watchdog.update(gameboy) {
+            session.show_message(format!(
+                "CPU stuck at {:04X} with interrupts off, reset to recover",
+                pc
+            ));
+        }
+        #[cfg(feature = "achievements")]
+        if let Some(achievements) = &mut session.achievements {
+            for message in achievements.update(&gameboy.mem) {
+                session.show_message(message);
+            }
+        }
+        session.save.update(&mut gameboy.mem.cartridge);
+    }
+}
+
+// Every core gets its own window, tiled two to a row, with their serial ports wired through the cable.
+// Hotkeys are left out since pausing or rewinding one side would desync the others.
+fn run_linked(games: &mut [Game], mut cable: Cable, running: &AtomicBool) {
+    for (i, game) in games.iter_mut().enumerate() {
+        let window = &mut game.frontend.screen.window;
+        let (width, height) = window.get_size();
+        window.set_position(
+            40 + (i % 2 * (width + 20)) as isize,
+            40 + (i / 2 * (height + 40)) as isize,
+        );
+        game.gameboy.mem.serial.linked = true;
+    }
+    let settings = &games[0].settings;
+    let mut scheduler = FrameScheduler::new(settings.speed, settings.refresh_rate);
+    scheduler.spin = !settings.battery_saver;
+    let high_priority = settings.high_priority;
+    let (mut cores, mut frontends): (Vec<_>, Vec<_>) = games
+        .iter_mut()
+        .map(|game| ((&mut game.gameboy, &mut game.session), &mut game.frontend))
+        .unzip();
+    thread::scope(|scope| {
+        let emulation = scope.spawn(|| {
+            if high_priority {
+                raise_priority();
+            }
+            let mut clocks = vec![0; cores.len()];
+            while running.load(Ordering::SeqCst) {
+                let mut gameboys = cores
+                    .iter_mut()
+                    .map(|(gameboy, _)| &mut **gameboy)
+                    .collect::<Vec<&mut Gameboy>>();
+                if let Err(e) = run_linked_frame(&mut gameboys, &mut clocks, &mut cable) {
+                    println!("Emulation stopped: {}", e);
+                    break;
+                }
+                scheduler.wait(FRAME_CYCLES, 1.0);
+                for (gameboy, session) in cores.iter_mut() {
+                    session.present(&mut gameboy.mem);
+                    session.save.update(&mut gameboy.mem.cartridge);
+                }
+            }
+        });
+        present(&mut frontends, None, running, &emulation);
+    });
+    cable.finish();
+}
+
+// Runs on the main thread, which owns the windows, while the cores run on their own. Each pass shows the
+// newest frames and forwards input and hotkeys, so a slow present or a burst of window events never holds
+// up emulation. Closing any window stops every core.
+fn present(
+    frontends: &mut [&mut Frontend],
+    hotkeys: Option<&Hotkeys>,
+    running: &AtomicBool,
+    emulation: &ScopedJoinHandle<()>,
+) {
+    while running.load(Ordering::SeqCst)
+        && !emulation.is_finished()
+        && frontends.iter().all(|frontend| frontend.screen.is_open())
+    {
+        frontends[0].screen.wait_for_frame(FRAME_DURATION);
+        for frontend in frontends.iter_mut() {
+            for message in frontend.messages.try_iter() {
+                frontend.screen.show_message(message);
+            }
+            frontend.screen.update();
+            frontend.input.update(&frontend.screen.window);
+            frontend.forward_focus();
+            if let Some(hotkeys) = hotkeys {
+                frontend.forward_hotkeys(hotkeys);
+            }
+        }
+    }
+    running.store(false, Ordering::SeqCst);
+}
+
+// The windows and everything read from them, owned by the main thread.
+struct Frontend {
+    screen: Screen,
+    input: InputSource,
+    messages: Receiver<String>,
+    hotkeys: Sender<Vec<Hotkey>>,
+    heat_map: Option<HeatMapView>,
+    heat_maps: Receiver<HeatMap>,
+    sram_editor: Option<SramEditor>,
+    sram: Receiver<Vec<u8>>,
+    sram_edits: Sender<(usize, u8)>,
+    paths: DataPaths,
+    focus: Sender<bool>,
+    focused: bool,
+}
+
+impl Frontend {
+    // Reopens the debug windows that were open when the last game was closed. Linked games have no hotkeys
+    // to close them with, so only a single game does this.
+    fn restore_layout(&mut self, settings: &Settings) {
+        if settings.heat_map_open {
+            self.heat_map = HeatMapView::open();
+        }
+        if settings.sram_editor_open {
+            self.sram_editor = SramEditor::open(self.paths.clone());
+        }
+    }
+
+    // Only windows that were opened or closed are written, so the config is left alone otherwise.
+    fn save_layout(&self, settings: &Settings, config_path: &Path) {
+        let windows = [
+            (
+                "heat_map_open",
+                self.heat_map.is_some(),
+                settings.heat_map_open,
+            ),
+            (
+                "sram_editor_open",
+                self.sram_editor.is_some(),
+                settings.sram_editor_open,
+            ),
+        ];
+        for (name, open, saved) in windows {
+            if open == saved {
+                continue;
+            }
+            let value = if open { "on" } else { "off" };
+            if let Err(e) = Config::store(config_path, name, value) {
+                println!("Failed to save {}: {}", name, e);
+            }
+        }
+    }
+
+    // Only changes are sent, since checking focus is cheap but the core only cares when it flips.
+    fn forward_focus(&mut self) {
+        let focused = self.screen.window.is_active();
+        if focused != self.focused {
+            self.focused = focused;
+            let _ = self.focus.send(focused);
+        }
+    }
+
+    // The debug windows live here too, so toggling or closing them is also passed on to the core.
+    fn forward_hotkeys(&mut self, hotkeys: &Hotkeys) {
+        let mut active = hotkeys.active(&self.screen.window);
+        // The input display is drawn here, so the core never needs to hear about it.
+        if active.contains(&Hotkey::InputDisplay) {
+            active.retain(|hotkey| *hotkey != Hotkey::InputDisplay);
+            let shown = self.screen.toggle_input_display();
+            self.screen.show_message(format!(
+                "Input display {}",
+                if shown { "on" } else { "off" }
+            ));
+        }
+        if active.contains(&Hotkey::HeatMap) {
+            self.heat_map = match self.heat_map.take() {
+                Some(_) => None,
+                None => HeatMapView::open(),
+            };
+            if self.heat_map.is_none() {
+                active.retain(|hotkey| *hotkey != Hotkey::HeatMap);
+            }
+        }
+        if let (Some(view), Some(heat_map)) = (&mut self.heat_map, self.heat_maps.try_iter().last())
+        {
+            if !view.draw(&heat_map) {
+                self.heat_map = None;
+                active.push(Hotkey::HeatMap);
+            }
+        }
+        if active.contains(&Hotkey::SramEditor) {
+            self.sram_editor = match self.sram_editor.take() {
+                Some(_) => None,
+                None => SramEditor::open(self.paths.clone()),
+            };
+            if self.sram_editor.is_none() {
+                active.retain(|hotkey| *hotkey != Hotkey::SramEditor);
+            }
+        }
+        if let Some(editor) = &mut self.sram_editor {
+            match editor.update(self.sram.try_iter().last()) {
+                Some(edits) => edits.into_iter().for_each(|edit| {
+                    let _ = self.sram_edits.send(edit);
+                }),
+                None => {
+                    self.sram_editor = None;
+                    active.push(Hotkey::SramEditor);
+                }
+            }
+        }
+        let _ = self.hotkeys.send(active);
+    }
+}
+
+// What the emulation thread keeps of a game besides the core itself.
+struct Session {
+    frames: FrameSender,
+    messages: Sender<String>,
+    hotkeys: Receiver<Vec<Hotkey>>,
+    held_hotkeys: Vec<Hotkey>,
+    heat_maps: Sender<HeatMap>,
+    sram: Sender<Vec<u8>>,
+    sram_edits: Receiver<(usize, u8)>,
+    // Whether the SRAM editor is open and wants a copy of cartridge RAM every frame.
+    sram_editor: bool,
+    focus: Receiver<bool>,
+    focused: bool,
+    background: Background,
+    scheduler: FrameScheduler,
+    // Played before the first frame of the game, if enabled.
+    boot_animation: Option<BootAnimation>,
+    save: BatterySave,
+    paths: DataPaths,
+    rewind: Rewind,
+    palette: Option<usize>,
+    // The palette before the picture controls are applied.
+    base_palette: [u32; 4],
+    picture: Picture,
+    config_path: PathBuf,
+    paused: bool,
+    outlines: Outlines,
+    // Whether the CPU had already hit an illegal opcode, so the lock-up is only announced once.
+    locked: bool,
+    watchdog: Watchdog,
+    #[cfg(feature = "achievements")]
+    achievements: Option<Achievements>,
+    #[cfg(feature = "remote")]
+    remote: Option<Remote>,
+    // Where a ROM loaded by a remote command looks for its config and saves.
+    #[cfg(feature = "remote")]
+    portable: bool,
+    auto_state: AutoState,
+    // The state slot the save and load hotkeys use, the last one picked.
+    slot: usize,
+    // While it's open, the game stands still behind it.
+    picker: Option<StatePicker>,
+    // The configured frame skip, which battery saver raises while it's on.
+    frame_skip: usize,
+    battery_saver: bool,
+    high_priority: bool,
+    // Nothing is read from or written to disk for the game: no config, cheats, battery RAM or states.
+    deterministic: bool,
+    presence: Option<Presence>,
+}
+
+impl Session {
+    fn show_message(&self, message: String) {
+        let _ = self.messages.send(message);
+    }
+
+    fn set_battery_saver(&mut self, mem: &mut MemoryMap, enabled: bool) {
+        self.battery_saver = enabled;
+        self.scheduler.spin = !enabled;
+        mem.ppu.set_frame_skip(frame_skip(self.frame_skip, enabled));
+    }
+
+    fn present(&mut self, mem: &mut MemoryMap) {
+        if let Some(mut pixels) = mem.ppu.take_frame() {
+            self.outlines.draw(&mem.ppu, &mut pixels);
+            self.frames.frame(&VideoFrame {
+                number: mem.clock().frames,
+                pixels: &pixels,
+                palette: mem.ppu.palette(),
+                buttons: mem.joypad.pressed(),
+            });
+        }
+    }
+
+    // Runs while paused too, since that's when saves are usually edited.
+    fn sync_sram(&mut self, cartridge: &mut Cartridge) {
+        for (offset, value) in self.sram_edits.try_iter() {
+            cartridge.write_ram(offset, value);
+        }
+        if self.sram_editor {
+            let _ = self.sram.send(cartridge.ram().to_vec());
+        }
+    }
+
+    // Hotkeys come in once per window update, which may be more or less often than once per frame. Every
+    // one-shot hotkey is handled, while held ones count as down if they were in the latest update.
+    fn receive_hotkeys(&mut self) -> Vec<Hotkey> {
+        let mut hotkeys = vec![];
+        for active in self.hotkeys.try_iter() {
+            let (held, pressed): (Vec<_>, Vec<_>) =
+                active.into_iter().partition(|hotkey| hotkey.held());
+            hotkeys.extend(pressed);
+            self.held_hotkeys = held;
+        }
+        hotkeys.extend(&self.held_hotkeys);
+        hotkeys
+    }
+}
+
+fn save_state(gameboy: &Gameboy, session: &Session) {
+    let path = session.paths.save_state(session.slot);
+    let message = match write(path, gameboy.save_state_with_thumbnail()) {
+        Ok(_) => format!("Saved slot {}", session.slot),
+        Err(e) => format!("Failed to save state: {}", e),
+    };
+    session.show_message(message);
+}
+
+fn load_state(gameboy: &mut Gameboy, session: &Session) {
+    let result = read(session.paths.save_state(session.slot))
+        .map_err(FeboyError::from)
+        .and_then(|state| gameboy.load_state(&state));
+    let message = match result {
+        Ok(_) => format!("Loaded slot {}", session.slot),
+        Err(e) => format!("Failed to load state: {}", e),
+    };
+    session.show_message(message);
+}
+
+// Runs instead of the game while the state picker is open. Picking a slot makes it the one the hotkeys use.
+fn handle_picker(gameboy: &mut Gameboy, session: &mut Session) {
+    let picker = match &mut session.picker {
+        Some(picker) => picker,
+        None => return,
+    };
+    let pick = picker.update(gameboy.mem.host_buttons());
+    let palette = gameboy.mem.ppu.palette();
+    session.frames.send(&picker.draw(palette), palette, 0);
+    match pick {
+        Some(Pick::Load(slot)) => {
+            session.slot = slot;
+            load_state(gameboy, session);
+        }
+        Some(Pick::Save(slot)) => {
+            session.slot = slot;
+            save_state(gameboy, session);
+        }
+        Some(Pick::Close) | None => (),
+    }
+    if pick.is_some() {
+        session.picker = None;
+        session.frames.send(
+            &gameboy.mem.ppu.pixels,
+            palette,
+            gameboy.mem.joypad.pressed(),
+        );
+    }
+}
+
+// Held hotkeys (rewind and fast-forward) and barcode scans are handled by the emulation loop.
+fn handle_hotkeys(gameboy: &mut Gameboy, session: &mut Session, hotkeys: &[Hotkey]) {
+    for hotkey in hotkeys {
+        match hotkey {
+            Hotkey::SaveState => save_state(gameboy, session),
+            Hotkey::LoadState => load_state(gameboy, session),
+            Hotkey::StatePicker => {
+                session.picker = match session.picker.take() {
+                    Some(_) => None,
+                    None => {
+                        let paths = (1..=SLOTS).map(|slot| session.paths.save_state(slot));
+                        Some(StatePicker::open(&paths.collect::<Vec<_>>(), session.slot))
+                    }
+                };
+            }
+            Hotkey::Screenshot => {
+                save_screenshot(&gameboy.mem.ppu.pixels, &session.paths.screenshot())
+            }
+            Hotkey::DumpTrace => match gameboy.trace() {
+                Some(trace) => {
+                    let path = session.paths.trace();
+                    match write(&path, trace.dump()) {
+                        Ok(_) => session.show_message(format!("Saved trace {}", path.display())),
+                        Err(e) => println!("Failed to save trace {}: {}", path.display(), e),
+                    }
+                }
+                None => session.show_message("Tracing is off, run with --trace".to_owned()),
+            },
+            Hotkey::Pause => {
+                session.paused = !session.paused;
+                let message = if session.paused { "Paused" } else { "Resumed" };
+                session.show_message(message.to_owned());
+            }
+            Hotkey::SpeedUp | Hotkey::SlowDown => {
+                let speed = session.scheduler.change_speed(*hotkey == Hotkey::SpeedUp);
+                session.show_message(format!("Speed: {}x", speed));
+            }
+            Hotkey::Reset => gameboy.soft_reset(),
+            // Power cycling reloads battery RAM from disk, like pulling the cartridge out and back in.
+            Hotkey::PowerCycle => {
+                session.save.flush(&mut gameboy.mem.cartridge);
+                gameboy.power_cycle();
+                report_ram_seed(&gameboy.mem);
+                session.save.load(&mut gameboy.mem.cartridge);
+            }
+            Hotkey::PaletteCycle => {
+                let index = session.palette.map_or(0, |i| (i + 1) % PALETTES.len());
+                let (name, palette) = PALETTES[index];
+                session.palette = Some(index);
+                session.base_palette = palette;
+                gameboy.mem.ppu.set_palette(session.picture.apply(palette));
+                session.show_message(format!("Palette: {}", name));
+            }
+            // Adjustments are saved to the global section of the config straight away.
+            Hotkey::BrightnessUp
+            | Hotkey::BrightnessDown
+            | Hotkey::GammaUp
+            | Hotkey::GammaDown
+            | Hotkey::SaturationUp
+            | Hotkey::SaturationDown => {
+                let (control, up) = hotkey.picture_control().unwrap();
+                let (name, value) = session.picture.adjust(control, up);
+                gameboy
+                    .mem
+                    .ppu
+                    .set_palette(session.picture.apply(session.base_palette));
+                let value = format!("{:.1}", value);
+                if let Err(e) = Config::store(&session.config_path, name, &value) {
+                    println!("Failed to save {}: {}", name, e);
+                }
+                session.show_message(format!("{} = {}", name, value));
+            }
+            // Like the picture controls, these are remembered for next time.
+            Hotkey::BatterySaver | Hotkey::HighPriority => {
+                let (name, enabled) = match hotkey {
+                    Hotkey::BatterySaver => {
+                        let enabled = !session.battery_saver;
+                        session.set_battery_saver(&mut gameboy.mem, enabled);
+                        ("battery_saver", enabled)
+                    }
+                    _ => {
+                        session.high_priority = !session.high_priority;
+                        if !set_thread_priority(session.high_priority) && session.high_priority {
+                            session.show_message("Couldn't raise priority".to_owned());
+                        }
+                        ("high_priority", session.high_priority)
+                    }
+                };
+                let value = if enabled { "on" } else { "off" };
+                if let Err(e) = Config::store(&session.config_path, name, value) {
+                    println!("Failed to save {}: {}", name, e);
+                }
+                session.show_message(format!("{} = {}", name, value));
+            }
+            Hotkey::HeatMap => {
+                let enabled = gameboy.mem.heat_map().is_none();
+                gameboy.mem.set_heat_map(enabled);
+            }
+            Hotkey::SramEditor => session.sram_editor = !session.sram_editor,
+            Hotkey::SpriteOutlines | Hotkey::WindowOutline | Hotkey::TileGrid => {
+                let outlines = &mut session.outlines;
+                let (name, enabled) = match hotkey {
+                    Hotkey::SpriteOutlines => ("Sprite outlines", &mut outlines.sprites),
+                    Hotkey::WindowOutline => ("Window outline", &mut outlines.window),
+                    _ => ("Tile grid", &mut outlines.tiles),
+                };
+                *enabled = !*enabled;
+                let state = if *enabled { "on" } else { "off" };
+                session.show_message(format!("{} {}", name, state));
+            }
+            Hotkey::BackgroundLayer | Hotkey::WindowLayer | Hotkey::SpriteLayer => {
+                let layers = &mut gameboy.mem.ppu.layers;
+                let (name, shown) = match hotkey {
+                    Hotkey::BackgroundLayer => ("Background", &mut layers.background),
+                    Hotkey::WindowLayer => ("Window", &mut layers.window),
+                    _ => ("Sprites", &mut layers.sprites),
+                };
+                *shown = !*shown;
+                let state = if *shown { "shown" } else { "hidden" };
+                session.show_message(format!("{} {}", name, state));
+            }
+            // Stepping only makes sense while paused, where the frame loop isn't running.
+            Hotkey::StepInstruction if session.paused => {
+                let message = match step(gameboy) {
+                    Ok(_) => format!("Stepped to {:04X}", gameboy.reg.pc.value()),
+                    Err(e) => format!("Step failed: {}", e),
+                };
+                session.show_message(message);
+            }
+            Hotkey::StepBack if session.paused => {
+                let message = match step_back(gameboy, &session.rewind) {
+                    Ok(true) => format!("Stepped back to {:04X}", gameboy.reg.pc.value()),
+                    Ok(false) => "No earlier snapshot to step back from".to_owned(),
+                    Err(e) => format!("Step back failed: {}", e),
+                };
+                session.show_message(message);
+            }
+            Hotkey::Rewind
+            | Hotkey::FastForward
+            | Hotkey::ScanBarcode
+            | Hotkey::StepInstruction
+            | Hotkey::StepBack
+            | Hotkey::InputDisplay => (),
+        }
+    }
+}
+
+#[cfg(feature = "remote")]
+fn handle_remote(gameboy: &mut Gameboy, session: &mut Session) {
+    let requests = match &session.remote {
+        Some(remote) => remote.requests(),
+        None => return,
+    };
+    for request in requests {
+        let result = match &request.command {
+            Command::Pause | Command::Resume => {
+                session.paused = request.command == Command::Pause;
+                Ok(Reply::Done)
+            }
+            Command::LoadRom(path) => swap_rom(gameboy, session, path).map(|_| Reply::Done),
+            Command::SaveState(path) => {
+                let path = path
+                    .clone()
+                    .unwrap_or_else(|| session.paths.save_state(session.slot));
+                write(path, gameboy.save_state_with_thumbnail())
+                    .map(|_| Reply::Done)
+                    .map_err(FeboyError::from)
+            }
+            Command::LoadState(path) => {
+                let path = path
+                    .clone()
+                    .unwrap_or_else(|| session.paths.save_state(session.slot));
+                read(path)
+                    .map_err(FeboyError::from)
+                    .and_then(|state| gameboy.load_state(&state))
+                    .map(|_| Reply::Done)
+            }
+            Command::Press(buttons) | Command::Release(buttons) => {
+                let held = match request.command {
+                    Command::Press(_) => gameboy.mem.remote_buttons() | buttons,
+                    _ => gameboy.mem.remote_buttons() & !buttons,
+                };
+                gameboy.mem.set_remote_buttons(held);
+                Ok(Reply::Done)
+            }
+            Command::Read { address, length } => {
+                let data = (0..*length).map(|i| gameboy.mem.peek(address + i));
+                Ok(Reply::Data(data.collect()))
+            }
+            Command::Screenshot => Ok(Reply::Png(encode_png(&gameboy.mem.ppu.pixels, 160, 144))),
+        };
+        request.reply(result.unwrap_or_else(|e| Reply::Failed(e.to_string())));
+    }
+}
+
+// Swaps the cartridge for another game, with that game's own settings and saves, as if the console had been
+// switched off and back on with it. The window keeps its title.
+#[cfg(feature = "remote")]
+fn swap_rom(gameboy: &mut Gameboy, session: &mut Session, path: &str) -> Result<(), FeboyError> {
+    let options = LoadOptions {
+        game_db: GameDb::load(session.portable),
+        strict: false,
+    };
+    let mut mem = MemoryMap::new(&load_rom(path, None)?, &path.to_owned(), &options)?;
+    let mut settings = match session.deterministic {
+        true => deterministic_settings(),
+        false => Config::load(session.portable).settings(&mem.cartridge.header),
+    };
+    let paths = DataPaths::new(path, &settings.save_dir, session.portable);
+    if !session.deterministic {
+        settings.cheats.extend(load_cheat_file(&paths.cheats()));
+    }
+    mem.apply_settings(&settings);
+    report_ram_seed(&mem);
+    session.save.flush(&mut gameboy.mem.cartridge);
+    save_auto_state(gameboy, session);
+    session.save = match session.deterministic {
+        true => BatterySave::disabled(),
+        false => BatterySave::new(paths.battery_save()),
+    };
+    session.save.load(&mut mem.cartridge);
+    session.paths = paths;
+    session.rewind = Rewind::new();
+    if let Some(input) = gameboy.mem.disconnect_input() {
+        mem.connect_input(input);
+    }
+    mem.set_remote_buttons(gameboy.mem.remote_buttons());
+    mem.serial.linked = gameboy.mem.serial.linked;
+    let (trace, profiling) = (gameboy.trace().is_some(), gameboy.profiling_interrupts());
+    *gameboy = Gameboy::new(mem);
+    gameboy.set_trace(trace);
+    gameboy.set_interrupt_profiling(profiling);
+    if let Some(presence) = &session.presence {
+        presence.set_game(&gameboy.mem.cartridge.header.title);
+    }
+    // Battery saver stays as the player last left it.
+    session.frame_skip = settings.frame_skip;
+    session.set_battery_saver(&mut gameboy.mem, session.battery_saver);
+    session.auto_state = settings.auto_state;
+    resume_auto_state(gameboy, session, false);
+    Ok(())
+}
+
+// One line per frame that had a handler overrun, with what its V-blank and STAT handlers took in total.
+fn report_overruns(gameboy: &mut Gameboy) {
+    let runs = match gameboy.take_handler_runs() {
+        Some(runs) if runs.iter().any(|run| run.overran) => runs,
+        _ => return,
+    };
+    let total = |interrupt| {
+        let runs = runs.iter().filter(|run| run.interrupt == interrupt);
+        let (count, cycles) = runs.fold((0, 0), |(count, cycles), run| {
+            (count + 1, cycles + run.cycles)
+        });
+        format!("{} runs, {} cycles", count, cycles)
+    };
+    let overruns = runs.iter().filter(|run| run.overran).map(|run| {
+        let period = if run.started == PpuMode::VBlank {
+            "V-blank"
+        } else {
+            "H-blank"
+        };
+        format!(
+            "{} handler took {} cycles, past the end of {}",
+            run.name(),
+            run.cycles,
+            period
+        )
+    });
+    println!(
+        "Frame {}: {} (V-blank {}, STAT {})",
+        gameboy.clock().frames,
+        overruns.collect::<Vec<_>>().join("; "),
+        total(InterruptId::VBlankInt),
+        total(InterruptId::StatInt)
+    );
+}
+
+// The game still runs at normal priority, so failing is only worth a note.
+fn raise_priority() {
+    if !set_thread_priority(true) {
+        println!("Couldn't raise the emulation thread's priority");
+    }
+}
+
+// Picks up where the game was last closed, if a state was saved then. Asking needs the main thread, so
+// without it the state is only resumed when the config says to do so without asking.
+fn resume_auto_state(gameboy: &mut Gameboy, session: &Session, can_ask: bool) -> bool {
+    let path = session
+        .paths
+        .auto_state(gameboy.mem.cartridge.header.global_checksum);
+    let resume = match session.auto_state {
+        AutoState::Off => false,
+        _ if !path.exists() => false,
+        AutoState::Ask => can_ask && confirm("feboy", "Resume where you left off?"),
+        AutoState::Resume => true,
+    };
+    if !resume {
+        return false;
+    }
+    let result = read(&path)
+        .map_err(FeboyError::from)
+        .and_then(|state| gameboy.load_state(&state));
+    match &result {
+        Ok(_) => session.show_message("Resumed".to_owned()),
+        Err(e) => println!("Failed to resume from {}: {}", path.display(), e),
+    }
+    result.is_ok()
+}
+
+// So a bug that only shows up with some RAM contents can be brought back with --seed.
+fn report_ram_seed(mem: &MemoryMap) {
+    if let Some(seed) = mem.drawn_seed() {
+        println!("RAM seed: {}", seed);
+    }
+}
+
+fn save_auto_state(gameboy: &Gameboy, session: &Session) {
+    if session.auto_state == AutoState::Off || session.deterministic {
+        return;
+    }
+    let path = session
+        .paths
+        .auto_state(gameboy.mem.cartridge.header.global_checksum);
+    if let Err(e) = write(&path, gameboy.save_state_with_thumbnail()) {
+        println!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+fn run_frame(gameboy: &mut Gameboy) -> Result<(), FeboyError> {
     let mut elapsed_cycles = 0;
-    const CYCLE_DURATION: f64 = 1.0_f64 / FREQUENCY as f64;
-    let start = Instant::now();
-    while elapsed_cycles < FREQUENCY / 60 {
-        let previously_halted = gameboy.halted;
-        let cycles = gameboy.cycle() as u16;
-        elapsed_cycles += cycles as u32 * 4;
-        let mem_cycles = cycles - gameboy.mem.cycles;
-        if mem_cycles != 0 && !previously_halted && !gameboy.halted {
-            panic!("Cycle count after considering reads/writes: mem_cycles {} | cycles: {} | micro_ops: {}", mem_cycles, cycles, gameboy.mem.cycles)
-        } else if mem_cycles != 0 {
-            for _ in 0..mem_cycles {
-                gameboy.mem.cycle();
-            }
-        }
-        gameboy.mem.cycles = 0;
-    }
-    let cycles_time: f64 = CYCLE_DURATION * elapsed_cycles as f64;
-    let sleep_time = cycles_time - start.elapsed().as_secs_f64();
-    if sleep_time > 0.0 {
-        thread::sleep(Duration::from_secs_f64(sleep_time));
+    while elapsed_cycles < FRAME_CYCLES {
+        elapsed_cycles += step(gameboy)?;
+    }
+    Ok(())
+}
+
+// The core furthest behind always runs next, so none gets more than an instruction ahead of the rest.
+// Whatever lead is left at the end of a frame carries into the next one.
+fn run_linked_frame(
+    gameboys: &mut [&mut Gameboy],
+    clocks: &mut [i64],
+    cable: &mut Cable,
+) -> Result<(), FeboyError> {
+    loop {
+        let (behind, now) = clocks
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by_key(|(_, clock)| *clock)
+            .unwrap_or_default();
+        let mut serials = gameboys
+            .iter_mut()
+            .map(|gameboy| &mut gameboy.mem.serial)
+            .collect::<Vec<&mut Serial>>();
+        cable.update(&mut serials, now);
+        if now >= FRAME_CYCLES {
+            break;
+        }
+        clocks[behind] += step(gameboys[behind])?;
     }
+    clocks.iter_mut().for_each(|clock| *clock -= FRAME_CYCLES);
+    cable.end_frame(FRAME_CYCLES);
+    Ok(())
+}
+
+// Replays from the latest rewind snapshot taken before the current instruction, counting the steps it takes
+// to get back here, then replays again stopping one short. Input is held as it was in the snapshot since the
+// keys pressed the first time around aren't recorded. The cartridge clock follows emulated time while
+// replaying, or a clock following the host would tick differently on each pass.
+fn step_back(gameboy: &mut Gameboy, rewind: &Rewind) -> Result<bool, FeboyError> {
+    let target = gameboy.clock().cycles;
+    let snapshot = match rewind.snapshot_before(target) {
+        Some(snapshot) => snapshot,
+        None => return Ok(false),
+    };
+    let input = gameboy.mem.disconnect_input();
+    let clock = gameboy.mem.cartridge.rtc_clock();
+    gameboy.mem.cartridge.set_rtc_clock(RtcClock::Emulated);
+    let mut replay = || {
+        gameboy.load_state(snapshot)?;
+        let mut steps = 0;
+        while gameboy.clock().cycles < target {
+            step(gameboy)?;
+            steps += 1;
+        }
+        gameboy.load_state(snapshot)?;
+        for _ in 1..steps {
+            step(gameboy)?;
+        }
+        Ok(true)
+    };
+    let result = replay();
+    if let Some(input) = input {
+        gameboy.mem.connect_input(input);
+    }
+    if let Some(clock) = clock {
+        gameboy.mem.cartridge.set_rtc_clock(clock);
+    }
+    result
 }
 
 #[cfg(test)]
@@ -64,7 +1575,11 @@ mod tests {
 
     use std::io::Error;
 
-    use crate::{run_frame, Gameboy, MemoryMap};
+    use crate::{
+        game_settings, run_frame, step, step_back, Args, Gameboy, LoadOptions, MemoryMap, Rewind,
+        RtcClock,
+    };
+    use feboy::frame_hash::frame_hash;
     use image::io::Reader;
     use image::RgbaImage;
     use std::path::Path;
@@ -101,15 +1616,6 @@ mod tests {
                     println!("Skipping non ROM file: {rom}");
                     return false;
                 }
-                let rom_vec = read(&rom).unwrap();
-                if rom_vec.len() > 32768 {
-                    println!(
-                        "Still need to implement MBC for larger ROM's: {}",
-                        rom.clone()
-                    );
-                    return false;
-                }
-
                 return true;
             })
             .collect();
@@ -125,7 +1631,14 @@ mod tests {
                 println!("Sleeping for {}", 50 * idx);
                 sleep(Duration::from_millis(100 * idx as u64));
                 let rom_vec = read(&rom).unwrap();
-                let mem = MemoryMap::new(&rom_vec, &rom);
+                let mem = match MemoryMap::new(&rom_vec, &rom, &LoadOptions::default()) {
+                    Ok(mem) => mem,
+                    // A ROM that stops loading is a regression, not a ROM to skip.
+                    Err(e) => {
+                        tx_finish.send(Err(format!("{}: {}", rom, e))).unwrap();
+                        return;
+                    }
+                };
                 let mut gameboy = Gameboy::new(mem);
                 println!("Beginning test loop");
                 let mut tests_counter = 0;
@@ -211,15 +1724,16 @@ mod tests {
                         }
                     }
 
-                    run_frame(&mut gameboy);
+                    run_frame(&mut gameboy).unwrap();
                 }
-                tx_finish.send(idx).unwrap();
+                tx_finish.send(Ok(idx)).unwrap();
             });
         }
         let mut count = 0;
         while count != total {
             match test_status_rv.recv() {
-                Ok(_) => {
+                Ok(Err(e)) => return Err(Error::other(e)),
+                Ok(Ok(_)) => {
                     println!("Increased counter {count}/{total}");
                     count += 1
                 }
@@ -297,4 +1811,62 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn deterministic_runs_draw_the_same_frames() {
+        let args = ["--deterministic", "--ram-init", "random", "game.gb"];
+        let args = Args::parse_from(args.iter().map(|arg| arg.to_string()));
+        // Copies the first byte of WRAM to the background palette, then spins.
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x157].copy_from_slice(&[0xFA, 0x00, 0xC0, 0xE0, 0x47, 0x18, 0xFE]);
+        let run = || {
+            let mut mem =
+                MemoryMap::new(&rom, &"game.gb".to_owned(), &LoadOptions::default()).unwrap();
+            mem.apply_settings(&game_settings(&args, &mem.cartridge.header));
+            let mut gameboy = Gameboy::new(mem);
+            let mut hashes = vec![];
+            for _ in 0..10 {
+                run_frame(&mut gameboy).unwrap();
+                hashes.extend(gameboy.mem.ppu.take_frame().map(|frame| frame_hash(&frame)));
+            }
+            hashes
+        };
+        let hashes = run();
+        assert!(!hashes.is_empty());
+        assert_eq!(hashes, run());
+    }
+
+    #[test]
+    fn step_back_undoes_one_instruction() {
+        // An MBC3 cartridge with a clock, counting up in WRAM.
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        rom[0x150..0x156].copy_from_slice(&[0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xFA]);
+        let mut mem = MemoryMap::new(&rom, &"game.gb".to_owned(), &LoadOptions::default()).unwrap();
+        // Replays run on emulated time, so the run being rewound has to as well to match exactly.
+        mem.cartridge.set_rtc_clock(RtcClock::Emulated);
+        let mut gameboy = Gameboy::new(mem);
+        let mut rewind = Rewind::new();
+        while rewind.snapshot_before(u64::MAX).is_none() {
+            run_frame(&mut gameboy).unwrap();
+            rewind.record(&gameboy);
+        }
+
+        let mut states = vec![];
+        for _ in 0..20 {
+            step(&mut gameboy).unwrap();
+            states.push(gameboy.save_state());
+        }
+        for expected in states.iter().rev().skip(1).take(3) {
+            assert!(step_back(&mut gameboy, &rewind).unwrap());
+            assert!(gameboy.save_state() == *expected);
+        }
+
+        gameboy.mem.cartridge.set_rtc_clock(RtcClock::Host);
+        assert!(step_back(&mut gameboy, &rewind).unwrap());
+        assert_eq!(gameboy.mem.cartridge.rtc_clock(), Some(RtcClock::Host));
+    }
 }
@@ -0,0 +1,379 @@
+use crate::error::FeboyError;
+use crate::rtc::Rtc;
+use crate::state::{StateReader, StateWriter};
+use core::cmp::max;
+
+pub enum Mbc {
+    NoMbc,
+    Mbc1(Mbc1),
+    Mbc3(Mbc3),
+    Mmm01(Mmm01),
+}
+
+impl Mbc {
+    pub fn rom_offset(&self, address: usize) -> usize {
+        let bank = match self {
+            Mbc::NoMbc => return address,
+            Mbc::Mbc1(mbc) => mbc.rom_bank(address),
+            Mbc::Mbc3(mbc) => mbc.rom_bank(address),
+            Mbc::Mmm01(mbc) => mbc.rom_bank(address),
+        };
+        bank * 0x4000 + (address & 0x3FFF)
+    }
+
+    pub fn ram_offset(&self, address: usize) -> Option<usize> {
+        let bank = match self {
+            Mbc::NoMbc => 0,
+            Mbc::Mbc1(mbc) if mbc.ram_enabled => mbc.ram_bank(),
+            Mbc::Mbc3(mbc) if mbc.ram_enabled && mbc.ram_select <= 0x03 => mbc.ram_select as usize,
+            Mbc::Mmm01(mbc) if mbc.ram_enabled => mbc.ram_bank(),
+            _ => return None,
+        };
+        Some(bank * 0x2000 + (address & 0x1FFF))
+    }
+
+    pub fn rtc(&mut self) -> Option<&mut Rtc> {
+        match self {
+            Mbc::Mbc3(mbc) => mbc.rtc.as_mut(),
+            _ => None,
+        }
+    }
+
+    // Reads the clock register mapped at A000-BFFF, if one is.
+    pub fn read_rtc(&self) -> Option<u8> {
+        match self {
+            Mbc::Mbc3(Mbc3 {
+                ram_enabled: true,
+                ram_select: register @ 0x08..=0x0C,
+                rtc: Some(rtc),
+                ..
+            }) => Some(rtc.read(*register)),
+            _ => None,
+        }
+    }
+
+    pub fn write_rtc(&mut self, value: u8) -> bool {
+        match self {
+            Mbc::Mbc3(Mbc3 {
+                ram_enabled: true,
+                ram_select: register @ 0x08..=0x0C,
+                rtc: Some(rtc),
+                ..
+            }) => {
+                rtc.write(*register, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = match self {
+            Mbc::NoMbc => Mbc::NoMbc,
+            Mbc::Mbc1(mbc) => Mbc::Mbc1(Mbc1::new(mbc.multicart)),
+            // The clock has its own battery and keeps going through a reset.
+            Mbc::Mbc3(mbc) => Mbc::Mbc3(Mbc3::new(mbc.rtc.take())),
+            Mbc::Mmm01(mbc) => Mbc::Mmm01(Mmm01::new(mbc.rom_banks)),
+        };
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        match self {
+            Mbc::NoMbc => (),
+            Mbc::Mbc1(mbc) => {
+                state.bool("RAM enabled", mbc.ram_enabled);
+                state.u8("BANK1", mbc.bank1);
+                state.u8("BANK2", mbc.bank2);
+                state.bool("advanced banking", mbc.advanced_banking);
+            }
+            Mbc::Mbc3(mbc) => {
+                state.bool("RAM enabled", mbc.ram_enabled);
+                state.u8("ROM bank", mbc.rom_bank);
+                state.u8("RAM or RTC select", mbc.ram_select);
+                if let Some(rtc) = &mbc.rtc {
+                    rtc.save_state(state);
+                }
+            }
+            Mbc::Mmm01(mbc) => {
+                state.bool("RAM enabled", mbc.ram_enabled);
+                for (name, bank) in [
+                    ("ROM bank low", mbc.rom_bank_low),
+                    ("ROM bank mid", mbc.rom_bank_mid),
+                    ("ROM bank high", mbc.rom_bank_high),
+                    ("ROM bank mask", mbc.rom_bank_mask),
+                    ("RAM bank low", mbc.ram_bank_low),
+                    ("RAM bank high", mbc.ram_bank_high),
+                    ("RAM bank mask", mbc.ram_bank_mask),
+                ] {
+                    state.u8(name, bank);
+                }
+                state.bool("MBC1 mode", mbc.mbc1_mode);
+                state.bool("MBC1 mode locked", mbc.mbc1_mode_locked);
+                state.bool("multiplex", mbc.multiplex);
+                state.bool("mapped", mbc.mapped);
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        match self {
+            Mbc::NoMbc => (),
+            Mbc::Mbc1(mbc) => {
+                mbc.ram_enabled = state.bool()?;
+                mbc.bank1 = state.u8()?;
+                mbc.bank2 = state.u8()?;
+                mbc.advanced_banking = state.bool()?;
+            }
+            Mbc::Mbc3(mbc) => {
+                mbc.ram_enabled = state.bool()?;
+                mbc.rom_bank = state.u8()?;
+                mbc.ram_select = state.u8()?;
+                if let Some(rtc) = &mut mbc.rtc {
+                    rtc.load_state(state)?;
+                }
+            }
+            Mbc::Mmm01(mbc) => {
+                mbc.ram_enabled = state.bool()?;
+                for bank in [
+                    &mut mbc.rom_bank_low,
+                    &mut mbc.rom_bank_mid,
+                    &mut mbc.rom_bank_high,
+                    &mut mbc.rom_bank_mask,
+                    &mut mbc.ram_bank_low,
+                    &mut mbc.ram_bank_high,
+                    &mut mbc.ram_bank_mask,
+                ] {
+                    *bank = state.u8()?;
+                }
+                mbc.mbc1_mode = state.bool()?;
+                mbc.mbc1_mode_locked = state.bool()?;
+                mbc.multiplex = state.bool()?;
+                mbc.mapped = state.bool()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match self {
+            Mbc::NoMbc => (),
+            Mbc::Mbc1(mbc) => mbc.write(address, value),
+            Mbc::Mbc3(mbc) => mbc.write(address, value),
+            Mbc::Mmm01(mbc) => mbc.write(address, value),
+        }
+    }
+}
+
+pub struct Mbc1 {
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    advanced_banking: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(multicart: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_banking: false,
+            multicart,
+        }
+    }
+
+    // MBC1M carts leave bit 4 of BANK1 unconnected and wire BANK2 to ROM bank bits 4-5 instead.
+    fn bank2_shift(&self) -> usize {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn rom_bank(&self, address: usize) -> usize {
+        let bank2 = (self.bank2 as usize) << self.bank2_shift();
+        let bank1 = if self.multicart {
+            self.bank1 & 0x0F
+        } else {
+            self.bank1
+        } as usize;
+        match address {
+            0x0000..=0x3FFF if self.advanced_banking => bank2,
+            0x0000..=0x3FFF => 0,
+            _ => bank2 | bank1,
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.bank1 = max(value & 0x1F, 1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.advanced_banking = value & 0x01 != 0,
+        }
+    }
+}
+
+pub struct Mbc3 {
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 00-03 map a RAM bank into A000-BFFF, 08-0C one of the clock's registers.
+    ram_select: u8,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rtc: Option<Rtc>) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rtc,
+        }
+    }
+
+    fn rom_bank(&self, address: usize) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = max(value & 0x7F, 1),
+            0x4000..=0x5FFF => self.ram_select = value,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.latch(value);
+                }
+            }
+        }
+    }
+}
+
+pub struct Mmm01 {
+    ram_enabled: bool,
+    rom_banks: usize,
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    rom_bank_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    ram_bank_mask: u8,
+    mbc1_mode: bool,
+    mbc1_mode_locked: bool,
+    multiplex: bool,
+    mapped: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom_banks: usize) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_banks,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_mask: 0,
+            mbc1_mode: false,
+            mbc1_mode_locked: false,
+            multiplex: false,
+            mapped: false,
+        }
+    }
+
+    // Until the menu maps a game in, the last 32KB of the ROM (where the menu lives) is visible.
+    fn rom_bank(&self, address: usize) -> usize {
+        if !self.mapped {
+            return match address {
+                0x0000..=0x3FFF => self.rom_banks - 2,
+                _ => self.rom_banks - 1,
+            };
+        }
+        let mid = if self.multiplex {
+            self.ram_bank_low
+        } else {
+            self.rom_bank_mid
+        } as usize;
+        let outer = (mid << 5) | ((self.rom_bank_high as usize) << 7);
+        let low_mask = (self.rom_bank_mask << 1) as usize;
+
+        let rom0_mid = if self.multiplex && self.mbc1_mode {
+            0
+        } else {
+            mid << 5
+        };
+        let rom0_bank = (self.rom_bank_low as usize & low_mask)
+            | rom0_mid
+            | ((self.rom_bank_high as usize) << 7);
+        let romx_bank = self.rom_bank_low as usize | outer;
+
+        match address {
+            0x0000..=0x3FFF => rom0_bank,
+            _ if romx_bank == rom0_bank => romx_bank + 1,
+            _ => romx_bank,
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.multiplex {
+            self.rom_bank_mid
+        } else {
+            self.ram_bank_low
+        };
+        (low | (self.ram_bank_high << 2)) as usize
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0x03;
+                }
+                let mask = self.rom_bank_mask << 1;
+                self.rom_bank_low = ((self.rom_bank_low & mask) | (value & !mask)) & 0x1F;
+            }
+            0x4000..=0x5FFF => {
+                let mask = self.ram_bank_mask;
+                self.ram_bank_low = ((self.ram_bank_low & mask) | (value & !mask)) & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (value >> 4) & 0x03;
+                    self.mbc1_mode_locked = value & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mbc1_mode_locked {
+                    self.mbc1_mode = value & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0x0F;
+                    self.multiplex = value & 0x40 != 0;
+                }
+            }
+        }
+    }
+}
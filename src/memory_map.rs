@@ -1,11 +1,33 @@
+use crate::apu::{Apu, SOUND_REGISTERS};
+use crate::cartridge::{Cartridge, LoadOptions};
+use crate::cheats::{Cheat, Freeze};
+#[cfg(feature = "std")]
+use crate::config::Settings;
+use crate::error::FeboyError;
+#[cfg(feature = "std")]
+use crate::heatmap::HeatMap;
+#[cfg(feature = "std")]
+use crate::input::{InputLatency, InputPort};
 use crate::interrupt::InterruptHandler;
-use crate::interrupt::InterruptId::{JoypadInt, StatInt, TimerInt, VBlankInt};
+use crate::interrupt::InterruptId::{JoypadInt, SerialInt, StatInt, TimerInt, VBlankInt};
 use crate::joypad::Joypad;
+#[cfg(feature = "std")]
+use crate::pacing::frame_skip;
 use crate::ppu::PpuState::ModeChange;
 use crate::ppu::RenderCycle::{Normal, StatTrigger};
 use crate::ppu::{DmaState, PpuMode, PPU};
+use crate::prelude::*;
+use crate::regions::{region, Region};
+use crate::serial::Serial;
+use crate::state::{StateReader, StateWriter};
 use crate::timer::Timer;
-use std::any::{Any, TypeId};
+#[cfg(feature = "std")]
+use crate::vgm::VgmLog;
+use core::any::{Any, TypeId};
+#[cfg(feature = "std")]
+use std::iter;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
 use DmaState::{Inactive, Starting};
 use OamCorruptionCause::IncDec;
 use PpuMode::VBlank;
@@ -20,44 +42,331 @@ pub enum OamCorruptionCause {
     ReadWrite,
 }
 
+// What WRAM and HRAM hold at power on.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RamFill {
+    Zero,
+    Ones,
+    // Seeded from the host clock, unless a seed is given to fill it the same way every boot.
+    Random,
+    // Runs of 00 and FF, roughly what many DMG units power up with.
+    Pattern,
+}
+
+impl RamFill {
+    pub fn parse(value: &str) -> Option<RamFill> {
+        match value.to_lowercase().as_str() {
+            "zero" | "00" => Some(RamFill::Zero),
+            "ff" => Some(RamFill::Ones),
+            "random" => Some(RamFill::Random),
+            "pattern" => Some(RamFill::Pattern),
+            _ => None,
+        }
+    }
+}
+
+pub const FIXED_RAM_SEED: u32 = 0x2545_F491;
+
+#[cfg(feature = "std")]
+fn clock_seed() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |time| time.as_nanos() as u32 | 1)
+}
+
+// There's no clock to seed from without std, so every boot fills RAM the same way.
+#[cfg(not(feature = "std"))]
+fn clock_seed() -> u32 {
+    FIXED_RAM_SEED
+}
+
+// Counters that keep running across resets; only loading a state moves them back.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Clock {
+    pub cycles: u64,
+    pub frames: u64,
+    pub scanlines: u64,
+}
+
+const WRAM_SIZE: usize = 0x2000;
+const IO_SIZE: usize = 0x80;
+const HRAM_SIZE: usize = 0x7F;
+
 pub struct MemoryMap {
-    pub memory: Vec<u8>,
+    pub wram: Vec<u8>,
+    // Backs the IO registers that no device handles yet.
+    io: Vec<u8>,
+    pub hram: Vec<u8>,
     pub interrupt_handler: InterruptHandler,
     pub ppu: PPU,
+    pub cartridge: Cartridge,
+    pub serial: Serial,
     timer: Timer,
-    joypad: Joypad,
-    rom_size: usize,
+    pub joypad: Joypad,
+    pub apu: Apu,
     rom_name: String,
     pub cycles: u16,
     dma_progress: usize,
     oam_corruption: Option<OamCorruptionCause>,
+    cheats: Vec<Cheat>,
+    freezes: Vec<Freeze>,
+    ram_fill: RamFill,
+    ram_seed: Option<u32>,
+    // The seed the last random fill drew when none was given.
+    drawn_seed: Option<u32>,
+    #[cfg(feature = "std")]
+    vgm_log: Option<VgmLog>,
+    #[cfg(feature = "std")]
+    input: Option<InputPort>,
+    // Buttons held by something other than the host's keys and pads, like a remote control client.
+    #[cfg(feature = "std")]
+    remote_buttons: u8,
+    clock: Clock,
+    last_ly: u8,
+    #[cfg(feature = "std")]
+    heat_map: Option<HeatMap>,
+    #[cfg(test)]
+    pub flat_bus: Option<FlatBus>,
+}
+
+// Stands in for the whole address space in single instruction tests. Instead of ticking the rest of the
+// hardware, every machine cycle records the access made during it, if any.
+#[cfg(test)]
+pub struct FlatBus {
+    pub memory: Vec<u8>,
+    pub cycles: Vec<Option<(u16, u8, bool)>>,
+    access: Option<(u16, u8, bool)>,
+}
+
+#[cfg(test)]
+impl FlatBus {
+    pub fn new() -> Self {
+        Self {
+            memory: vec![0; 0x10000],
+            cycles: vec![],
+            access: None,
+        }
+    }
 }
 
 impl MemoryMap {
-    pub fn new(rom: &Vec<u8>, rom_name: &String) -> MemoryMap {
-        let ppu = PPU::new(rom_name);
+    pub fn new(
+        rom: &Vec<u8>,
+        rom_name: &String,
+        options: &LoadOptions,
+    ) -> Result<MemoryMap, FeboyError> {
+        let cartridge = Cartridge::new(rom.to_vec(), options)?;
+        let ppu = PPU::new();
         let joypad = Joypad::new();
+        let apu = Apu::new();
         let interrupt_handler = InterruptHandler::new();
         let timer = Timer::new();
-        let rom_size = rom.len() as usize;
+        let serial = Serial::new();
         let rom_name = rom_name.to_owned();
-        let memory = vec![0; 0x10000];
         let micro_ops = 0;
         let dma_progress = 0;
         let oam_corruption = None;
-        let mem = MemoryMap {
+        let mut mem = MemoryMap {
             joypad,
+            apu,
             ppu,
+            cartridge,
             interrupt_handler,
             timer,
-            memory,
+            serial,
+            wram: vec![0; WRAM_SIZE],
+            io: vec![0; IO_SIZE],
+            hram: vec![0; HRAM_SIZE],
             rom_name,
-            rom_size,
             cycles: micro_ops,
             dma_progress,
             oam_corruption,
+            cheats: vec![],
+            freezes: vec![],
+            ram_fill: RamFill::Zero,
+            ram_seed: None,
+            drawn_seed: None,
+            #[cfg(feature = "std")]
+            vgm_log: None,
+            #[cfg(feature = "std")]
+            input: None,
+            #[cfg(feature = "std")]
+            remote_buttons: 0,
+            clock: Clock::default(),
+            last_ly: 0,
+            #[cfg(feature = "std")]
+            heat_map: None,
+            #[cfg(test)]
+            flat_bus: None,
+        };
+        mem.init_memory();
+        Ok(mem)
+    }
+
+    // An empty 32KB ROM with no mapper, for tests that only need a console to run on.
+    #[cfg(test)]
+    pub fn blank() -> Self {
+        let rom = vec![0; 0x8000];
+        Self::new(&rom, &"blank".to_owned(), &LoadOptions::default()).unwrap()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.ppu
+            .set_palette(settings.picture.apply(settings.palette));
+        self.ppu
+            .set_frame_skip(frame_skip(settings.frame_skip, settings.battery_saver));
+        self.cheats = settings.cheats.clone();
+        self.ram_fill = settings.ram_fill;
+        self.ram_seed = settings.ram_seed;
+        self.fill_ram();
+        self.freezes.clear();
+        for freeze in &settings.freezes {
+            self.freeze(*freeze);
+        }
+        self.cartridge.set_rtc_clock(settings.rtc);
+    }
+
+    // Freezing an address that's already frozen changes the value it's held at.
+    pub fn freeze(&mut self, freeze: Freeze) {
+        self.unfreeze(freeze.address);
+        self.freezes.push(freeze);
+        self.write_without_cycle(freeze.address, freeze.value);
+    }
+
+    pub fn unfreeze(&mut self, address: u16) {
+        self.freezes.retain(|freeze| freeze.address != address);
+    }
+
+    // The seed RAM was last filled from, if it was drawn rather than given, for the frontend to print.
+    pub fn drawn_seed(&self) -> Option<u32> {
+        self.drawn_seed
+    }
+
+    #[cfg(feature = "std")]
+    pub fn connect_input(&mut self, input: InputPort) {
+        self.input = Some(input);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn disconnect_input(&mut self) -> Option<InputPort> {
+        self.input.take()
+    }
+
+    // Without a host input connected, nothing else sets the joypad, so they go straight to it.
+    #[cfg(feature = "std")]
+    pub fn set_remote_buttons(&mut self, buttons: u8) {
+        self.remote_buttons = buttons;
+        if self.input.is_none() {
+            self.joypad.set_pressed(buttons);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn remote_buttons(&self) -> u8 {
+        self.remote_buttons
+    }
+
+    // What the player is holding right now, whether or not the game is running to see it.
+    #[cfg(feature = "std")]
+    pub fn host_buttons(&self) -> u8 {
+        self.input.as_ref().map_or(0, InputPort::pressed)
+    }
+
+    fn sample_input(&mut self) {
+        #[cfg(feature = "std")]
+        if let Some(input) = &self.input {
+            self.joypad
+                .set_pressed(input.pressed() | self.remote_buttons);
+        }
+    }
+
+    pub fn soft_reset(&mut self) {
+        self.reset(true);
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.reset(false);
+    }
+
+    fn reset(&mut self, preserve_ram: bool) {
+        self.joypad = Joypad::new();
+        self.apu = Apu::new();
+        self.ppu.reset(preserve_ram);
+        self.interrupt_handler = InterruptHandler::new();
+        self.timer = Timer::new();
+        let linked = self.serial.linked;
+        self.serial = Serial::new();
+        self.serial.linked = linked;
+        self.cartridge.reset();
+        self.cycles = 0;
+        self.dma_progress = 0;
+        self.oam_corruption = None;
+        if !preserve_ram {
+            self.io.iter_mut().for_each(|byte| *byte = 0);
+            self.fill_ram();
+        }
+        self.init_memory();
+    }
+
+    // Real hardware powers up with WRAM and HRAM in an unpredictable state, which some games accidentally
+    // rely on.
+    fn fill_ram(&mut self) {
+        let mut seed = match (self.ram_fill, self.ram_seed) {
+            // Xorshift never leaves 0, so that seed alone is swapped out.
+            (RamFill::Random, Some(0)) => FIXED_RAM_SEED,
+            (RamFill::Random, Some(seed)) => seed,
+            (RamFill::Random, None) => clock_seed(),
+            _ => 1,
+        };
+        self.drawn_seed =
+            Some(seed).filter(|_| self.ram_fill == RamFill::Random && self.ram_seed.is_none());
+        let fill = self.ram_fill;
+        for (i, byte) in self.wram.iter_mut().chain(self.hram.iter_mut()).enumerate() {
+            *byte = match fill {
+                RamFill::Zero => 0x00,
+                RamFill::Ones => 0xFF,
+                RamFill::Random => {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                }
+                RamFill::Pattern if i & 0x08 == 0 => 0x00,
+                RamFill::Pattern => 0xFF,
+            };
+        }
+    }
+
+    fn apply_ram_cheats(&mut self) {
+        for cheat in self.cheats.clone() {
+            if let Cheat::GameShark { address, value } = cheat {
+                self.write_without_cycle(address, value);
+            }
+        }
+        for freeze in self.freezes.clone() {
+            self.write_without_cycle(freeze.address, freeze.value);
+        }
+    }
+
+    // Echo RAM writes land in WRAM, so a freeze on either address holds both.
+    fn frozen<T: 'static + Into<usize> + Copy>(&self, address: T) -> Option<Freeze> {
+        if self.freezes.is_empty() {
+            return None;
+        }
+        let address = if address.type_id() == TypeId::of::<u8>() {
+            address.into() + 0xFF00
+        } else {
+            address.into()
+        };
+        let unmirrored = |address: usize| match region(address) {
+            Region::Wram => Region::Wram.start() + address % WRAM_SIZE,
+            _ => address,
         };
-        MemoryMap::init_memory(mem, rom)
+        self.freezes
+            .iter()
+            .copied()
+            .find(|freeze| unmirrored(freeze.address as usize) == unmirrored(address))
     }
 
     fn in_oam<T: 'static + Into<usize> + Copy>(&self, address: T) -> bool {
@@ -77,10 +386,7 @@ impl MemoryMap {
         if !self.in_oam(address) {
             return;
         }
-        self.ppu.oam_corruption = match self.ppu.oam_corruption {
-            None => Some(IncDec),
-            _ => panic!(),
-        }
+        self.ppu.trigger_oam_corruption(IncDec);
     }
 
     pub fn read<T: 'static + Into<usize> + Copy>(&mut self, address: T) -> u8 {
@@ -95,6 +401,9 @@ impl MemoryMap {
         value: Value,
     ) {
         self.write_without_cycle(address, value.into());
+        if let Some(freeze) = self.frozen(address) {
+            self.write_without_cycle(freeze.address, freeze.value);
+        }
         self.cycle();
     }
 
@@ -105,14 +414,59 @@ impl MemoryMap {
         } else {
             address.into()
         };
-        let read = self
-            .ppu
-            .read(translated_address)
-            .or(self.interrupt_handler.read(translated_address))
-            .or(self.timer.read(translated_address))
-            .or(self.joypad.read(translated_address))
-            .unwrap_or(self.memory[translated_address]);
-        read
+        #[cfg(feature = "std")]
+        if translated_address == 0xFF00
+            && matches!(&self.input, Some(input) if input.latency == InputLatency::Read)
+        {
+            self.sample_input();
+        }
+        #[cfg(feature = "std")]
+        if let Some(heat_map) = &mut self.heat_map {
+            heat_map.read(translated_address);
+        }
+        #[cfg(test)]
+        if let Some(bus) = &mut self.flat_bus {
+            let value = bus.memory[translated_address];
+            bus.access = Some((translated_address as u16, value, false));
+            return value;
+        }
+        match region(translated_address) {
+            Region::Rom | Region::CartridgeRam => {
+                let value = self.cartridge.read(translated_address).unwrap_or(0xFF);
+                Cheat::patch_rom_read(&self.cheats, translated_address, value)
+            }
+            Region::Vram | Region::Oam => self.ppu.read(translated_address).unwrap_or(0xFF),
+            Region::Wram => self.wram[translated_address % WRAM_SIZE],
+            Region::Io => self
+                .ppu
+                .read(translated_address)
+                .or(self.interrupt_handler.read(translated_address))
+                .or(self.timer.read(translated_address))
+                .or(self.serial.read(translated_address))
+                .or(self.joypad.read(translated_address))
+                .or(self.apu.read(translated_address))
+                .unwrap_or(self.io[translated_address - Region::Io.start()]),
+            Region::Hram => self.hram[translated_address - Region::Hram.start()],
+            Region::InterruptEnable => self
+                .interrupt_handler
+                .read(translated_address)
+                .unwrap_or(0xFF),
+        }
+    }
+
+    // Reads RAM without the side effects a CPU read could have, for tools watching the game from outside.
+    // VRAM and OAM belong to the PPU and read as open bus.
+    pub fn peek(&self, address: usize) -> u8 {
+        let address = address & 0xFFFF;
+        match region(address) {
+            Region::Rom | Region::CartridgeRam => self.cartridge.read(address).unwrap_or(0xFF),
+            Region::Vram | Region::Oam => 0xFF,
+            Region::Wram => self.wram[address % WRAM_SIZE],
+            Region::Io if SOUND_REGISTERS.contains(&address) => self.apu.register(address),
+            Region::Io => self.io[address - Region::Io.start()],
+            Region::Hram => self.hram[address - Region::Hram.start()],
+            Region::InterruptEnable => self.interrupt_handler.read(address).unwrap_or(0xFF),
+        }
     }
 
     fn write_without_cycle<T: 'static + Into<usize> + Copy>(&mut self, address: T, value: u8) {
@@ -122,18 +476,55 @@ impl MemoryMap {
         } else {
             address.into()
         };
-        if !(self.ppu.write(translated_address, value)
-            || self.timer.write(translated_address, value)
-            || self.interrupt_handler.write(translated_address, value)
-            || self.joypad.write(translated_address, value))
-            && (translated_address >= self.rom_size)
-        {
-            self.memory[translated_address] = value
+        #[cfg(test)]
+        if let Some(bus) = &mut self.flat_bus {
+            bus.memory[translated_address] = value;
+            bus.access = Some((translated_address as u16, value, true));
+            return;
+        }
+        match region(translated_address) {
+            // ROM writes only ever reach the mapper registers.
+            Region::Rom | Region::CartridgeRam => self.cartridge.write(translated_address, value),
+            Region::Vram | Region::Oam => {
+                self.ppu.write(translated_address, value);
+            }
+            Region::Wram => self.wram[translated_address % WRAM_SIZE] = value,
+            Region::Io => {
+                if !(self.ppu.write(translated_address, value)
+                    || self.timer.write(translated_address, value)
+                    || self.serial.write(translated_address, value)
+                    || self.interrupt_handler.write(translated_address, value)
+                    || self.joypad.write(translated_address, value)
+                    || self.apu.write(translated_address, value))
+                {
+                    self.io[translated_address - Region::Io.start()] = value
+                }
+            }
+            Region::Hram => self.hram[translated_address - Region::Hram.start()] = value,
+            Region::InterruptEnable => {
+                self.interrupt_handler.write(translated_address, value);
+            }
+        }
+        #[cfg(feature = "std")]
+        if let Some(heat_map) = &mut self.heat_map {
+            heat_map.write(translated_address);
+        }
+        #[cfg(feature = "std")]
+        if let Some(vgm_log) = &mut self.vgm_log {
+            if SOUND_REGISTERS.contains(&translated_address) {
+                vgm_log.write(translated_address, value);
+            }
         }
     }
 
     pub fn cycle(&mut self) {
         self.cycles += 1;
+        #[cfg(test)]
+        if let Some(bus) = &mut self.flat_bus {
+            let access = bus.access.take();
+            bus.cycles.push(access);
+            return;
+        }
         self.dma_transfer();
         self.machine_cycle();
     }
@@ -152,7 +543,14 @@ impl MemoryMap {
         }
     }
 
+    // Cycles are counted in T-cycles, frames as V-blank entries and scanlines as LY changes.
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
     fn machine_cycle(&mut self) {
+        self.clock.cycles += 4;
+        self.cartridge.machine_cycle();
         let mut interrupts = vec![];
         interrupts.append(&mut match self.ppu.machine_cycle() {
             StatTrigger(ModeChange(_, VBlank)) => vec![VBlankInt, StatInt],
@@ -160,60 +558,310 @@ impl MemoryMap {
             StatTrigger(_) => vec![StatInt],
             _ => vec![],
         });
+        let ly = self.ppu.ly();
+        if ly != self.last_ly {
+            self.last_ly = ly;
+            self.clock.scanlines += 1;
+        }
+        if interrupts.contains(&VBlankInt) {
+            self.clock.frames += 1;
+            self.apply_ram_cheats();
+            self.sample_input();
+        }
         interrupts.append(&mut match self.timer.machine_cycle() {
             Some(_) => vec![TimerInt],
             None => vec![],
         });
+        interrupts.append(&mut match self.serial.machine_cycle() {
+            Some(_) => vec![SerialInt],
+            None => vec![],
+        });
 
         interrupts.append(
             &mut self
                 .joypad
-                .machine_cycle(&self.ppu.window)
+                .machine_cycle()
                 .iter()
                 .map(|_| JoypadInt)
                 .collect(),
         );
 
+        #[cfg(feature = "std")]
+        if let Some(vgm_log) = &mut self.vgm_log {
+            vgm_log.machine_cycle();
+        }
+
         self.oam_corruption = None;
         self.interrupt_handler.set(interrupts, true);
     }
 
-    fn init_memory(mut mem: MemoryMap, rom: &Vec<u8>) -> MemoryMap {
-        for (index, value) in rom.iter().enumerate() {
-            mem.memory[index] = *value
-        }
-        mem.write_without_cycle(0xFF05_u16, 0);
-        mem.write_without_cycle(0xFF06_u16, 0);
-        mem.write_without_cycle(0xFF07_u16, 0);
-        mem.write_without_cycle(0xFF10_u16, 0x80);
-        mem.write_without_cycle(0xFF11_u16, 0xBF);
-        mem.write_without_cycle(0xFF12_u16, 0xF3);
-        mem.write_without_cycle(0xFF14_u16, 0xBF);
-        mem.write_without_cycle(0xFF16_u16, 0x3F);
-        mem.write_without_cycle(0xFF16_u16, 0x3F);
-        mem.write_without_cycle(0xFF17_u16, 0);
-        mem.write_without_cycle(0xFF19_u16, 0xBF);
-        mem.write_without_cycle(0xFF1A_u16, 0x7F);
-        mem.write_without_cycle(0xFF1B_u16, 0xFF);
-        mem.write_without_cycle(0xFF1C_u16, 0x9F);
-        mem.write_without_cycle(0xFF1E_u16, 0xFF);
-        mem.write_without_cycle(0xFF20_u16, 0xFF);
-        mem.write_without_cycle(0xFF21_u16, 0);
-        mem.write_without_cycle(0xFF22_u16, 0);
-        mem.write_without_cycle(0xFF23_u16, 0xBF);
-        mem.write_without_cycle(0xFF24_u16, 0x77);
-        mem.write_without_cycle(0xFF25_u16, 0xF3);
-        mem.write_without_cycle(0xFF26_u16, 0xF1);
-        mem.write_without_cycle(0xFF40_u16, 0x91);
-        mem.write_without_cycle(0xFF42_u16, 0);
-        mem.write_without_cycle(0xFF43_u16, 0);
-        mem.write_without_cycle(0xFF45_u16, 0);
-        mem.write_without_cycle(0xFF47_u16, 0xFC);
-        mem.write_without_cycle(0xFF48_u16, 0xFF);
-        mem.write_without_cycle(0xFF49_u16, 0xFF);
-        mem.write_without_cycle(0xFF4A_u16, 0);
-        mem.write_without_cycle(0xFF4B_u16, 0);
-        mem.write_without_cycle(0xFF00_u16, 0xFF);
-        mem
+    // The log opens with the current register state, NR52 first so the sound hardware is powered before the rest.
+    #[cfg(feature = "std")]
+    pub fn start_vgm_log(&mut self, mut vgm_log: VgmLog) {
+        let registers =
+            iter::once(0xFF26).chain(SOUND_REGISTERS.filter(|&address| address != 0xFF26));
+        for address in registers {
+            vgm_log.write(address, self.apu.register(address));
+        }
+        self.vgm_log = Some(vgm_log);
+    }
+
+    // Access counting only runs while the heat map is open.
+    #[cfg(feature = "std")]
+    pub fn set_heat_map(&mut self, enabled: bool) {
+        self.heat_map = if enabled { Some(HeatMap::new()) } else { None };
+    }
+
+    #[cfg(feature = "std")]
+    pub fn heat_map(&mut self) -> Option<&mut HeatMap> {
+        self.heat_map.as_mut()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn finish_vgm_log(&mut self) {
+        if let Some(vgm_log) = &mut self.vgm_log {
+            vgm_log.finish();
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.memory("WRAM", 0xC000, &self.wram);
+        state.memory("IO (memory backed)", 0xFF00, &self.io);
+        state.memory("HRAM", 0xFF80, &self.hram);
+        state.u16("instruction cycles", self.cycles);
+        state.usize("DMA progress", self.dma_progress);
+        state.u64("clock cycles", self.clock.cycles);
+        state.u64("clock frames", self.clock.frames);
+        state.u64("clock scanlines", self.clock.scanlines);
+        state.u8("last LY", self.last_ly);
+        self.interrupt_handler.save_state(state);
+        self.ppu.save_state(state);
+        self.cartridge.save_state(state);
+        self.timer.save_state(state);
+        self.serial.save_state(state);
+        self.joypad.save_state(state);
+        self.apu.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        state.bytes(&mut self.wram)?;
+        state.bytes(&mut self.io)?;
+        state.bytes(&mut self.hram)?;
+        self.cycles = state.u16()?;
+        self.dma_progress = state.usize()?;
+        self.clock = Clock {
+            cycles: state.u64()?,
+            frames: state.u64()?,
+            scanlines: state.u64()?,
+        };
+        self.last_ly = state.u8()?;
+        self.oam_corruption = None;
+        self.interrupt_handler.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.cartridge.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.apu.load_state(state)?;
+        // The sound log picks up from the loaded register state.
+        #[cfg(feature = "std")]
+        if let Some(vgm_log) = self.vgm_log.take() {
+            self.start_vgm_log(vgm_log);
+        }
+        Ok(())
+    }
+
+    fn init_memory(&mut self) {
+        self.write_without_cycle(0xFF05_u16, 0);
+        self.write_without_cycle(0xFF06_u16, 0);
+        self.write_without_cycle(0xFF07_u16, 0);
+        self.write_without_cycle(0xFF10_u16, 0x80);
+        self.write_without_cycle(0xFF11_u16, 0xBF);
+        self.write_without_cycle(0xFF12_u16, 0xF3);
+        self.write_without_cycle(0xFF14_u16, 0xBF);
+        self.write_without_cycle(0xFF16_u16, 0x3F);
+        self.write_without_cycle(0xFF16_u16, 0x3F);
+        self.write_without_cycle(0xFF17_u16, 0);
+        self.write_without_cycle(0xFF19_u16, 0xBF);
+        self.write_without_cycle(0xFF1A_u16, 0x7F);
+        self.write_without_cycle(0xFF1B_u16, 0xFF);
+        self.write_without_cycle(0xFF1C_u16, 0x9F);
+        self.write_without_cycle(0xFF1E_u16, 0xFF);
+        self.write_without_cycle(0xFF20_u16, 0xFF);
+        self.write_without_cycle(0xFF21_u16, 0);
+        self.write_without_cycle(0xFF22_u16, 0);
+        self.write_without_cycle(0xFF23_u16, 0xBF);
+        self.write_without_cycle(0xFF24_u16, 0x77);
+        self.write_without_cycle(0xFF25_u16, 0xF3);
+        self.write_without_cycle(0xFF26_u16, 0xF1);
+        self.write_without_cycle(0xFF40_u16, 0x91);
+        self.write_without_cycle(0xFF42_u16, 0);
+        self.write_without_cycle(0xFF43_u16, 0);
+        self.write_without_cycle(0xFF45_u16, 0);
+        self.write_without_cycle(0xFF47_u16, 0xFC);
+        self.write_without_cycle(0xFF48_u16, 0xFF);
+        self.write_without_cycle(0xFF49_u16, 0xFF);
+        self.write_without_cycle(0xFF4A_u16, 0);
+        self.write_without_cycle(0xFF4B_u16, 0);
+        self.write_without_cycle(0xFF00_u16, 0xFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::LoadOptions;
+    use crate::cheats::Freeze;
+    use crate::config::Settings;
+    use crate::gameboy::{step, Gameboy};
+    use crate::memory_map::{MemoryMap, RamFill, FIXED_RAM_SEED};
+    use std::thread;
+
+    #[test]
+    fn clock_counts_a_full_frame() {
+        // JR -2
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        let mut gameboy = Gameboy::new(
+            MemoryMap::new(&rom, &"clock".to_owned(), &LoadOptions::default()).unwrap(),
+        );
+        let mut run_to_frame = |frame| {
+            while gameboy.clock().frames < frame {
+                step(&mut gameboy).unwrap();
+            }
+            gameboy.clock()
+        };
+        let (first, second) = (run_to_frame(1), run_to_frame(2));
+        assert_eq!(second.cycles - first.cycles, 70224);
+        assert_eq!(second.scanlines - first.scanlines, 154);
+    }
+
+    // Embedders can run the core on a worker thread and hand the frames to their own frontend.
+    #[test]
+    fn core_runs_on_another_thread() {
+        let mut gameboy = Gameboy::new(MemoryMap::blank());
+        let worker = thread::spawn(move || {
+            while gameboy.clock().frames < 1 {
+                step(&mut gameboy).unwrap();
+            }
+            gameboy
+        });
+        assert_eq!(worker.join().unwrap().clock().frames, 1);
+    }
+
+    #[test]
+    fn frame_skip_only_changes_what_is_presented() {
+        let run = |frame_skip| {
+            let mut gameboy = Gameboy::new(MemoryMap::blank());
+            gameboy.mem.ppu.set_frame_skip(frame_skip);
+            let mut presented = 0;
+            while gameboy.clock().frames < 9 {
+                step(&mut gameboy).unwrap();
+                presented += gameboy.mem.ppu.take_frame().is_some() as usize;
+            }
+            let state = (
+                gameboy.clock(),
+                gameboy.reg.pc.value(),
+                gameboy.mem.ppu.ly(),
+            );
+            (presented, state)
+        };
+        let (all, skipping) = (run(1), run(3));
+        assert!(all.0 >= 8);
+        assert_eq!(skipping.0, all.0.div_ceil(3));
+        assert_eq!(skipping.1, all.1);
+    }
+
+    #[test]
+    fn rom_writes_only_reach_the_mapper() {
+        // MBC1 with 4 banks, each starting with its number.
+        let mut rom = vec![0; 0x10000];
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x01;
+        for bank in 0..4 {
+            rom[bank * 0x4000 + 0x1000] = bank as u8;
+        }
+        let mut mem = MemoryMap::new(&rom, &"rom".to_owned(), &LoadOptions::default()).unwrap();
+        mem.write(0x1000_u16, 0x55_u8);
+        assert_eq!(mem.read(0x1000_u16), 0x00);
+        mem.write(0x2000_u16, 0x03_u8);
+        assert_eq!(mem.read(0x5000_u16), 0x03);
+        mem.write(0x5000_u16, 0x55_u8);
+        assert_eq!(mem.read(0x5000_u16), 0x03);
+        assert_eq!(mem.read(0xA000_u16), 0xFF);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut mem = MemoryMap::blank();
+        mem.write(0xC123_u16, 0x12_u8);
+        assert_eq!(mem.read(0xE123_u16), 0x12);
+        mem.write(0xFDFF_u16, 0x34_u8);
+        assert_eq!(mem.wram[0x1DFF], 0x34);
+        mem.write(0xFF80_u16, 0x56_u8);
+        assert_eq!(mem.peek(0xFF80), 0x56);
+        assert_eq!(mem.peek(0xC123), 0x12);
+    }
+
+    #[test]
+    fn frozen_addresses_keep_their_value() {
+        let mut mem = MemoryMap::blank();
+        let mut settings = Settings::new();
+        settings.freezes = vec![Freeze {
+            address: 0xC0A0,
+            value: 0x99,
+        }];
+        mem.apply_settings(&settings);
+        assert_eq!(mem.peek(0xC0A0), 0x99);
+        mem.write(0xC0A0_u16, 0x01_u8);
+        mem.write(0xE0A0_u16, 0x02_u8);
+        assert_eq!(mem.read(0xC0A0_u16), 0x99);
+        mem.write(0xC0A1_u16, 0x03_u8);
+        assert_eq!(mem.peek(0xC0A1), 0x03);
+
+        mem.unfreeze(0xC0A0);
+        mem.write(0xC0A0_u16, 0x01_u8);
+        assert_eq!(mem.peek(0xC0A0), 0x01);
+    }
+
+    #[test]
+    fn seeded_ram_fill_repeats() {
+        let fill = |ram_fill, ram_seed| {
+            let mut mem = MemoryMap::blank();
+            let mut settings = Settings::new();
+            settings.ram_fill = ram_fill;
+            settings.ram_seed = ram_seed;
+            mem.apply_settings(&settings);
+            let ram = [mem.wram.clone(), mem.hram.clone()];
+            // A power cycle fills it the same way again.
+            mem.write(0xFF90_u16, 0x5A_u8);
+            mem.power_cycle();
+            assert_eq!([mem.wram.clone(), mem.hram.clone()], ram);
+            ram
+        };
+        let seeded = fill(RamFill::Random, Some(FIXED_RAM_SEED));
+        assert_eq!(seeded, fill(RamFill::Random, Some(FIXED_RAM_SEED)));
+        assert_ne!(seeded, fill(RamFill::Random, Some(1)));
+        // Neighbouring seeds fill it differently, and 0 still fills it with something.
+        assert_ne!(
+            fill(RamFill::Random, Some(2)),
+            fill(RamFill::Random, Some(3))
+        );
+        assert_eq!(fill(RamFill::Random, Some(0)), seeded);
+        assert!(fill(RamFill::Ones, None)[1]
+            .iter()
+            .all(|byte| *byte == 0xFF));
+        let [wram, _] = fill(RamFill::Pattern, None);
+        assert_eq!((wram[0x07], wram[0x08], wram[0x10]), (0x00, 0xFF, 0x00));
+        assert_eq!(RamFill::parse("Pattern"), Some(RamFill::Pattern));
+
+        // Only a seed drawn from the clock is handed back, for the frontend to print.
+        let mut mem = MemoryMap::blank();
+        let mut settings = Settings::new();
+        settings.ram_fill = RamFill::Random;
+        mem.apply_settings(&settings);
+        assert!(mem.drawn_seed().is_some());
+        settings.ram_seed = Some(FIXED_RAM_SEED);
+        mem.apply_settings(&settings);
+        assert_eq!(mem.drawn_seed(), None);
     }
 }
@@ -0,0 +1,224 @@
+use crate::font::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+// Past this many the oldest message goes, so a burst of them never covers the game.
+const MAX_MESSAGES: usize = 3;
+const FPS_INTERVAL: Duration = Duration::from_secs(1);
+// The input display's size and where each button sits in it, as x, y, width and height, in the joypad's
+// bit order: A, B, Select, Start, Right, Left, Up, Down.
+const PAD_WIDTH: usize = 38;
+const PAD_HEIGHT: usize = 20;
+const PAD_BUTTONS: [(usize, usize, usize, usize); 8] = [
+    (30, 4, 5, 5),
+    (23, 8, 5, 5),
+    (12, 15, 5, 2),
+    (19, 15, 5, 2),
+    (10, 6, 4, 4),
+    (2, 6, 4, 4),
+    (6, 2, 4, 4),
+    (6, 10, 4, 4),
+];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub fn parse(value: &str) -> Option<Corner> {
+        match value.to_lowercase().replace(' ', "_").as_str() {
+            "top_left" => Some(Corner::TopLeft),
+            "top_right" => Some(Corner::TopRight),
+            "bottom_left" => Some(Corner::BottomLeft),
+            "bottom_right" => Some(Corner::BottomRight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct OsdSettings {
+    pub corner: Corner,
+    // How long a message stays up.
+    pub timeout: Duration,
+    pub fps: bool,
+    // A diagram of the Game Boy's buttons with the held ones lit up.
+    pub input: bool,
+}
+
+impl OsdSettings {
+    pub fn new() -> Self {
+        Self {
+            corner: Corner::BottomLeft,
+            timeout: Duration::from_secs(3),
+            fps: false,
+            input: false,
+        }
+    }
+}
+
+// Everything drawn over the game: the FPS counter if it's on, then any recent messages, stacked from one
+// corner with the newest nearest to the edge. The input display goes in the other corner along the same
+// edge.
+pub struct Osd {
+    settings: OsdSettings,
+    messages: VecDeque<(String, Instant)>,
+    // The frame count and time the current FPS measurement started at, and the last rate measured.
+    counted: (usize, Instant),
+    fps: f64,
+}
+
+impl Osd {
+    pub fn new(settings: OsdSettings) -> Self {
+        Self {
+            settings,
+            messages: VecDeque::new(),
+            counted: (0, Instant::now()),
+            fps: 0.0,
+        }
+    }
+
+    pub fn toggle_input(&mut self) -> bool {
+        self.settings.input = !self.settings.input;
+        self.settings.input
+    }
+
+    pub fn show(&mut self, message: String) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((message, Instant::now()));
+    }
+
+    // Takes the number of frames received from the core so far.
+    pub fn lines(&mut self, frames: usize) -> Vec<String> {
+        let timeout = self.settings.timeout;
+        self.messages.retain(|(_, shown)| shown.elapsed() < timeout);
+        let elapsed = self.counted.1.elapsed();
+        if elapsed >= FPS_INTERVAL {
+            self.fps = (frames - self.counted.0) as f64 / elapsed.as_secs_f64();
+            self.counted = (frames, Instant::now());
+        }
+        let mut lines = vec![];
+        if self.settings.fps {
+            lines.push(format!("{:.0} FPS", self.fps));
+        }
+        lines.extend(self.messages.iter().map(|(message, _)| message.clone()));
+        lines
+    }
+
+    // Only copies the frame when there's something to draw on it. The buttons are the ones the game saw
+    // during the frame, so a movie or netplay input shows up the same as the local player's.
+    pub fn draw(&mut self, pixels: &mut Cow<[u32]>, palette: [u32; 4], frames: usize, buttons: u8) {
+        let lines = self.lines(frames);
+        if lines.is_empty() && !self.settings.input {
+            return;
+        }
+        let [light, _, mid, dark] = palette;
+        let pixels = pixels.to_mut();
+        if self.settings.input {
+            let x = match self.settings.corner {
+                Corner::TopLeft | Corner::BottomLeft => WIDTH - PAD_WIDTH,
+                Corner::TopRight | Corner::BottomRight => 0,
+            };
+            let y = match self.settings.corner {
+                Corner::TopLeft | Corner::TopRight => 0,
+                Corner::BottomLeft | Corner::BottomRight => HEIGHT - PAD_HEIGHT,
+            };
+            fill(pixels, (x, y, PAD_WIDTH, PAD_HEIGHT), dark);
+            for (i, (dx, dy, width, height)) in PAD_BUTTONS.iter().enumerate() {
+                let color = if buttons & (1 << i) != 0 { light } else { mid };
+                fill(pixels, (x + dx, y + dy, *width, *height), color);
+            }
+        }
+        let count = lines.len();
+        for (i, line) in lines.iter().enumerate() {
+            let width = (line.chars().count() * GLYPH_WIDTH + 3).min(WIDTH);
+            let x = match self.settings.corner {
+                Corner::TopLeft | Corner::BottomLeft => 0,
+                Corner::TopRight | Corner::BottomRight => WIDTH - width,
+            };
+            let y = match self.settings.corner {
+                Corner::TopLeft | Corner::TopRight => (count - 1 - i) * LINE_HEIGHT,
+                Corner::BottomLeft | Corner::BottomRight => HEIGHT - (i + 1) * LINE_HEIGHT,
+            };
+            if y + LINE_HEIGHT > HEIGHT {
+                continue;
+            }
+            fill(pixels, (x, y, width, LINE_HEIGHT), dark);
+            draw_text(pixels, WIDTH, x + 2, y + 2, line, light);
+        }
+    }
+}
+
+fn fill(pixels: &mut [u32], (x, y, width, height): (usize, usize, usize, usize), color: u32) {
+    for row in y..y + height {
+        pixels[row * WIDTH + x..row * WIDTH + x + width].fill(color);
+    }
+}
+
+pub fn parse_timeout(value: &str) -> Option<Duration> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds > 0.0 && seconds.is_finite())
+        .map(Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::osd::{parse_timeout, Corner, Osd, OsdSettings};
+    use std::borrow::Cow;
+    use std::time::Duration;
+
+    #[test]
+    fn stacks_messages_in_a_corner() {
+        let mut settings = OsdSettings::new();
+        settings.corner = Corner::TopRight;
+        let mut osd = Osd::new(settings);
+        assert!(osd.lines(0).is_empty());
+
+        for slot in 1..=4 {
+            osd.show(format!("Saved slot {}", slot));
+        }
+        assert_eq!(
+            osd.lines(0),
+            ["Saved slot 2", "Saved slot 3", "Saved slot 4"]
+        );
+
+        let mut pixels = Cow::Owned(vec![1; 160 * 144]);
+        assert!(osd.toggle_input());
+        osd.draw(&mut pixels, [2, 0, 4, 3], 0, 0b1001_0001);
+        // The newest message sits in the corner, the older ones below it.
+        let at = |x: usize, y: usize| pixels[y * 160 + x];
+        assert_eq!(at(159, 0), 3);
+        assert!(pixels[2 * 160..3 * 160].contains(&2));
+        assert_eq!(at(159, 2 * 8), 3);
+        assert_eq!(at(159, 3 * 8), 1);
+        // The input display takes the other corner, with A, Right and Down held.
+        assert_eq!(at(0, 0), 3);
+        assert_eq!((at(30, 4), at(23, 8)), (2, 4));
+        assert_eq!((at(10, 6), at(2, 6)), (2, 4));
+        assert_eq!((at(6, 10), at(6, 2)), (2, 4));
+        assert_eq!(at(38, 0), 1);
+
+        let mut osd = Osd::new(OsdSettings {
+            timeout: Duration::ZERO,
+            ..settings
+        });
+        osd.show("Gone".to_owned());
+        assert!(osd.lines(0).is_empty());
+
+        assert_eq!(Corner::parse("Bottom right"), Some(Corner::BottomRight));
+        assert_eq!(parse_timeout("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_timeout("0"), None);
+    }
+}
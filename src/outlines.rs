@@ -0,0 +1,110 @@
+use crate::ppu::PPU;
+
+const WIDTH: i32 = 160;
+const HEIGHT: i32 = 144;
+const SPRITE_COLOR: u32 = 0xFF2020;
+const WINDOW_COLOR: u32 = 0x2080FF;
+const TILE_COLOR: u32 = 0x20C020;
+
+// Debug outlines drawn over a finished frame: a box around every sprite in OAM, one around the window and
+// the background's tile grid. They come from the PPU's registers as the frame ends, so a game that changes
+// them mid-frame, e.g. for a status bar, only has its last values outlined.
+#[derive(Default, Clone, Copy)]
+pub struct Outlines {
+    pub sprites: bool,
+    pub window: bool,
+    pub tiles: bool,
+}
+
+impl Outlines {
+    pub fn draw(&self, ppu: &PPU, pixels: &mut [u32]) {
+        // Dotted, so the picture still shows through the grid.
+        if self.tiles {
+            let (scx, scy) = ppu.scroll();
+            let (dx, dy) = ((8 - scx as i32 % 8) % 8, (8 - scy as i32 % 8) % 8);
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let on_line = (x - dx) % 8 == 0 || (y - dy) % 8 == 0;
+                    if on_line && (x + y) % 2 == 0 {
+                        pixels[(y * WIDTH + x) as usize] = TILE_COLOR;
+                    }
+                }
+            }
+        }
+        if let (true, Some((x, y))) = (self.window, ppu.window_position()) {
+            let (width, height) = ((WIDTH - x) as usize, (HEIGHT - y) as usize);
+            rectangle(pixels, (x, y, width, height), WINDOW_COLOR);
+        }
+        if self.sprites {
+            for bounds in ppu.sprite_bounds() {
+                rectangle(pixels, bounds, SPRITE_COLOR);
+            }
+        }
+    }
+}
+
+// Only the part of the outline that's on screen is drawn.
+fn rectangle(pixels: &mut [u32], (x, y, width, height): (i32, i32, usize, usize), color: u32) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (right, bottom) = (x + width as i32 - 1, y + height as i32 - 1);
+    let mut plot = |px: i32, py: i32| {
+        if (0..WIDTH).contains(&px) && (0..HEIGHT).contains(&py) {
+            pixels[(py * WIDTH + px) as usize] = color;
+        }
+    };
+    for px in x..=right {
+        plot(px, y);
+        plot(px, bottom);
+    }
+    for py in y..=bottom {
+        plot(x, py);
+        plot(right, py);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outlines::{Outlines, SPRITE_COLOR, TILE_COLOR, WINDOW_COLOR};
+    use crate::ppu::PPU;
+
+    #[test]
+    fn outlines_sprites_window_and_tiles() {
+        let mut ppu = PPU::new();
+        // A sprite at the top left corner of the screen, half of it hanging off the left edge.
+        ppu.write(0xFE00, 16);
+        ppu.write(0xFE01, 4);
+        ppu.write(0xFF40, 0xA1);
+        ppu.write(0xFF4A, 100);
+        ppu.write(0xFF4B, 87);
+        ppu.write(0xFF42, 3);
+        let mut pixels = vec![0; 160 * 144];
+        let outlines = Outlines {
+            sprites: true,
+            window: true,
+            tiles: false,
+        };
+        outlines.draw(&ppu, &mut pixels);
+        let at = |x: usize, y: usize| pixels[y * 160 + x];
+        assert_eq!(
+            (at(0, 0), at(3, 0), at(3, 7), at(4, 0)),
+            (SPRITE_COLOR, SPRITE_COLOR, SPRITE_COLOR, 0)
+        );
+        assert_eq!((at(2, 3), at(3, 3)), (0, SPRITE_COLOR));
+        assert_eq!(
+            (at(80, 100), at(159, 143), at(81, 101)),
+            (WINDOW_COLOR, WINDOW_COLOR, 0)
+        );
+
+        let mut pixels = vec![0; 160 * 144];
+        Outlines {
+            tiles: true,
+            ..Outlines::default()
+        }
+        .draw(&ppu, &mut pixels);
+        // SCY is 3, so the first tile row ends 5 lines down.
+        assert_eq!(pixels[5 * 160 + 1], TILE_COLOR);
+        assert_eq!(pixels[4 * 160 + 2], 0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const CPU_FREQUENCY: u32 = 4_194_304;
+pub const FRAME_CYCLES: i64 = 70224;
+// About 59.7275 Hz.
+pub const FRAME_RATE: f64 = CPU_FREQUENCY as f64 / FRAME_CYCLES as f64;
+// Sleeping can overshoot by a millisecond or more, so the last stretch before a deadline is spun instead.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 8.0;
+// Battery saver draws at most every other frame, so the screen updates at half rate.
+pub const BATTERY_SAVER_FRAME_SKIP: usize = 2;
+// Further behind than this (a breakpoint, a stalled host) and the schedule restarts from now rather than
+// running flat out to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Paces emulation against a fixed timeline: every frame's deadline is the previous deadline plus the frame's
+// length, not the time it finished plus its length, so waking up late for one frame shortens the wait for
+// the next and the error never accumulates over a session.
+// Speed changes only stretch or shrink the wait, the core always runs whole frames, so slow motion and
+// speed-ups never change what the game sees.
+pub struct FrameScheduler {
+    deadline: Instant,
+    // Stretches emulated time to match the display's refresh rate, if one was set.
+    time_scale: f64,
+    pub speed: f64,
+    // Spinning out the last stretch keeps frames on time but keeps a core busy; without it the thread
+    // sleeps through the whole wait and wakes up when the OS gets to it.
+    pub spin: bool,
+}
+
+impl FrameScheduler {
+    pub fn new(speed: f64, refresh_rate: Option<f64>) -> Self {
+        Self {
+            deadline: Instant::now(),
+            time_scale: refresh_rate.map_or(1.0, |rate| FRAME_RATE / rate),
+            speed,
+            spin: true,
+        }
+    }
+
+    // Doubles or halves the speed, staying within MIN_SPEED and MAX_SPEED.
+    pub fn change_speed(&mut self, faster: bool) -> f64 {
+        let speed = if faster {
+            self.speed * 2.0
+        } else {
+            self.speed / 2.0
+        };
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.speed
+    }
+
+    // Waits until the emulated time of `cycles` has passed since the last deadline. `boost` multiplies the
+    // speed on top, for fast-forward.
+    pub fn wait(&mut self, cycles: i64, boost: f64) {
+        let speed = self.speed * boost;
+        let seconds = cycles as f64 / CPU_FREQUENCY as f64 * self.time_scale / speed;
+        self.deadline += Duration::from_secs_f64(seconds);
+        let now = Instant::now();
+        if now > self.deadline + MAX_LAG {
+            self.deadline = now;
+            return;
+        }
+        let margin = if self.spin {
+            SPIN_MARGIN
+        } else {
+            Duration::ZERO
+        };
+        if let Some(sleep) = self.deadline.checked_duration_since(now + margin) {
+            thread::sleep(sleep);
+        }
+        while Instant::now() < self.deadline {
+            thread::yield_now();
+        }
+    }
+}
+
+pub fn frame_skip(frame_skip: usize, battery_saver: bool) -> usize {
+    match battery_saver {
+        true => frame_skip.max(BATTERY_SAVER_FRAME_SKIP),
+        false => frame_skip,
+    }
+}
+
+// Raises or restores the priority of the calling thread, returning whether the OS allowed it. Raising it
+// usually needs extra privileges outside Windows.
+#[cfg(target_os = "linux")]
+pub fn set_thread_priority(high: bool) -> bool {
+    // Linux keeps a nice value per thread, addressed by the thread's ID. Going below 0 needs CAP_SYS_NICE.
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        libc::setpriority(libc::PRIO_PROCESS, tid, if high { -10 } else { 0 }) == 0
+    }
+}
+
+#[cfg(windows)]
+pub fn set_thread_priority(high: bool) -> bool {
+    use std::ffi::c_void;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadPriority(thread: *mut c_void, priority: i32) -> i32;
+    }
+    const THREAD_PRIORITY_NORMAL: i32 = 0;
+    const THREAD_PRIORITY_HIGHEST: i32 = 2;
+    let priority = if high {
+        THREAD_PRIORITY_HIGHEST
+    } else {
+        THREAD_PRIORITY_NORMAL
+    };
+    unsafe { SetThreadPriority(GetCurrentThread(), priority) != 0 }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn set_thread_priority(_high: bool) -> bool {
+    false
+}
+
+pub fn parse_speed(value: &str) -> Option<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|speed| (MIN_SPEED..=MAX_SPEED).contains(speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pacing::{frame_skip, parse_speed, FrameScheduler, FRAME_CYCLES, FRAME_RATE};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn keeps_to_the_schedule_without_drifting() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(2.0, None);
+        for _ in 0..60 {
+            scheduler.wait(FRAME_CYCLES, 4.0);
+        }
+        let expected = Duration::from_secs_f64(60.0 / FRAME_RATE / 8.0);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= expected);
+
+        // A stall resynchronizes instead of rushing through the missed frames.
+        sleep(Duration::from_millis(200));
+        let start = Instant::now();
+        scheduler.wait(FRAME_CYCLES, 4.0);
+        scheduler.wait(FRAME_CYCLES, 4.0);
+        assert!(start.elapsed() >= Duration::from_secs_f64(1.0 / FRAME_RATE / 8.0));
+
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(4.0, Some(60.0));
+        assert_eq!(scheduler.change_speed(true), 8.0);
+        assert_eq!(scheduler.change_speed(true), 8.0);
+        for _ in 0..8 {
+            scheduler.wait(FRAME_CYCLES, 1.0);
+        }
+        assert!(start.elapsed() >= Duration::from_secs_f64(1.0 / 60.0));
+    }
+
+    #[test]
+    fn slows_down_without_changing_emulation() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(0.5, None);
+        assert_eq!(scheduler.change_speed(false), 0.25);
+        assert_eq!(scheduler.change_speed(false), 0.25);
+        scheduler.wait(FRAME_CYCLES / 16, 1.0);
+        assert!(start.elapsed() >= Duration::from_secs_f64(1.0 / FRAME_RATE / 4.0));
+
+        assert_eq!(parse_speed("0.25"), Some(0.25));
+        assert_eq!(parse_speed("8"), Some(8.0));
+        assert_eq!(parse_speed("0.1"), None);
+        assert_eq!(parse_speed("fast"), None);
+    }
+
+    #[test]
+    fn battery_saver_sleeps_and_skips_frames() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(8.0, None);
+        scheduler.spin = false;
+        for _ in 0..4 {
+            scheduler.wait(FRAME_CYCLES, 1.0);
+        }
+        assert!(start.elapsed() >= Duration::from_secs_f64(4.0 / FRAME_RATE / 8.0));
+
+        assert_eq!(frame_skip(1, true), 2);
+        assert_eq!(frame_skip(3, true), 3);
+        assert_eq!(frame_skip(1, false), 1);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::error::FeboyError;
+use std::convert::TryInto;
+
+const IPS_HEADER: &[u8] = b"PATCH";
+const IPS_FOOTER: &[u8] = b"EOF";
+const BPS_HEADER: &[u8] = b"BPS1";
+
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, FeboyError> {
+    if patch.starts_with(IPS_HEADER) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_HEADER) {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid_patch("Unknown patch format"))
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, FeboyError> {
+    let mut target = rom.to_vec();
+    let mut reader = PatchReader::new(&patch[IPS_HEADER.len()..]);
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == IPS_FOOTER {
+            break;
+        }
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]) as usize;
+        let size = u16::from_be_bytes(reader.array()?) as usize;
+        let (size, data) = if size == 0 {
+            let run_length = u16::from_be_bytes(reader.array()?) as usize;
+            let value = reader.byte()?;
+            (run_length, vec![value; run_length])
+        } else {
+            (size, reader.bytes(size)?.to_vec())
+        };
+        if target.len() < offset + size {
+            target.resize(offset + size, 0);
+        }
+        target[offset..offset + size].copy_from_slice(&data);
+    }
+    if let Ok(truncate) = reader.bytes(3) {
+        let length = u32::from_be_bytes([0, truncate[0], truncate[1], truncate[2]]) as usize;
+        target.truncate(length);
+    }
+    Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, FeboyError> {
+    if patch.len() < BPS_HEADER.len() + 12 {
+        return Err(invalid_patch("BPS patch is truncated"));
+    }
+    let footer = &patch[patch.len() - 12..];
+    let checksum = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (checksum(0), checksum(4), checksum(8));
+
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(invalid_patch("BPS patch checksum mismatch"));
+    }
+    if crc32(rom) != source_crc {
+        return Err(invalid_patch(
+            "ROM does not match the BPS patch source checksum",
+        ));
+    }
+
+    let mut reader = PatchReader::new(&patch[BPS_HEADER.len()..patch.len() - 12]);
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(invalid_patch(
+            "ROM size does not match the BPS patch source size",
+        ));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0_isize;
+    let mut target_offset = 0_isize;
+    while !reader.is_empty() {
+        let action = reader.varint()?;
+        let length = (action >> 2) + 1;
+        match action & 0x03 {
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(read_range(rom, start as isize, length)?);
+            }
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            2 => {
+                source_offset += reader.signed_varint()?;
+                target.extend_from_slice(read_range(rom, source_offset, length)?);
+                source_offset += length as isize;
+            }
+            _ => {
+                target_offset += reader.signed_varint()?;
+                if target_offset < 0 || target_offset as usize >= target.len() {
+                    return Err(invalid_patch("BPS target copy out of range"));
+                }
+                // Target copies may overlap the bytes they produce, so copy one at a time.
+                for _ in 0..length {
+                    target.push(target[target_offset as usize]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size || crc32(&target) != target_crc {
+        return Err(invalid_patch(
+            "Patched ROM does not match the BPS target checksum",
+        ));
+    }
+    Ok(target)
+}
+
+fn read_range(data: &[u8], offset: isize, length: usize) -> Result<&[u8], FeboyError> {
+    if offset < 0 || offset as usize + length > data.len() {
+        return Err(invalid_patch("BPS source read out of range"));
+    }
+    Ok(&data[offset as usize..offset as usize + length])
+}
+
+fn invalid_patch(message: &str) -> FeboyError {
+    FeboyError::InvalidPatch(message.to_owned())
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xFFFFFFFF_u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], FeboyError> {
+        if self.position + length > self.data.len() {
+            return Err(invalid_patch("Patch ended unexpectedly"));
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FeboyError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, FeboyError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<usize, FeboyError> {
+        let mut value = 0_usize;
+        let mut shift = 1_usize;
+        loop {
+            let byte = self.byte()?;
+            value += (byte & 0x7F) as usize * shift;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+
+    fn signed_varint(&mut self) -> Result<isize, FeboyError> {
+        let value = self.varint()?;
+        let magnitude = (value >> 1) as isize;
+        Ok(if value & 1 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::patch::{apply_patch, crc32};
+
+    #[test]
+    fn ips_records_and_rle() {
+        let rom = vec![0_u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+
+        let patched = apply_patch(&rom, &patch).unwrap();
+        assert_eq!(
+            patched,
+            vec![0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+    }
+
+    #[test]
+    fn bps_source_and_target_reads() {
+        let rom = vec![1_u8, 2, 3, 4];
+        let mut patch = b"BPS1".to_vec();
+        // Source size 4, target size 6, no metadata.
+        patch.extend_from_slice(&[0x84, 0x86, 0x80]);
+        // SourceRead of 2 bytes, then TargetRead of 4 bytes.
+        patch.extend_from_slice(&[0x84, 0x8D, 9, 8, 7, 6]);
+        let target = vec![1_u8, 2, 9, 8, 7, 6];
+        patch.extend_from_slice(&crc32(&rom).to_le_bytes());
+        patch.extend_from_slice(&crc32(&target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), target);
+        assert!(apply_patch(&[0, 0, 0, 0], &patch).is_err());
+    }
+}
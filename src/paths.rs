@@ -0,0 +1,175 @@
+use std::env;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const CONFIG_FILE: &str = "feboy.ini";
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum SaveDir {
+    NextToRom,
+    Data,
+    Custom(PathBuf),
+}
+
+impl SaveDir {
+    pub fn parse(value: &str) -> Option<SaveDir> {
+        match value.to_lowercase().as_str() {
+            "" => None,
+            "rom" => Some(SaveDir::NextToRom),
+            "data" => Some(SaveDir::Data),
+            _ => Some(SaveDir::Custom(PathBuf::from(value))),
+        }
+    }
+}
+
+fn executable_dir() -> Option<PathBuf> {
+    env::current_exe().ok()?.parent().map(Path::to_path_buf)
+}
+
+// Dropping a feboy.ini beside the executable turns on portable mode without needing the flag.
+pub fn is_portable(requested: bool) -> bool {
+    requested || executable_dir().is_some_and(|dir| dir.join(CONFIG_FILE).exists())
+}
+
+pub fn config_dir(portable: bool) -> PathBuf {
+    if portable {
+        return executable_dir().unwrap_or_default();
+    }
+    env::var("APPDATA")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("XDG_CONFIG_HOME").map(PathBuf::from))
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default()
+        .join("feboy")
+}
+
+pub fn data_dir(portable: bool) -> PathBuf {
+    if portable {
+        return executable_dir().unwrap_or_default();
+    }
+    env::var("APPDATA")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("XDG_DATA_HOME").map(PathBuf::from))
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_default()
+        .join("feboy")
+}
+
+#[derive(Clone)]
+pub struct DataPaths {
+    rom_path: PathBuf,
+    root: Option<PathBuf>,
+}
+
+impl DataPaths {
+    pub fn new(rom_name: &str, save_dir: &SaveDir, portable: bool) -> Self {
+        let root = match save_dir {
+            SaveDir::NextToRom if !portable => None,
+            SaveDir::Custom(dir) => Some(dir.clone()),
+            _ => Some(data_dir(portable)),
+        };
+        Self {
+            rom_path: PathBuf::from(rom_name),
+            root,
+        }
+    }
+
+    // Without a central directory every file sits next to the ROM; otherwise each kind gets its own subdirectory.
+    fn file(&self, kind: &str, extension: &str) -> PathBuf {
+        let path = self.rom_path.with_extension(extension);
+        let root = match &self.root {
+            Some(root) => root.join(kind),
+            None => return path,
+        };
+        if let Err(e) = create_dir_all(&root) {
+            println!("Failed to create directory {}: {}", root.display(), e);
+        }
+        root.join(path.file_name().unwrap_or_default())
+    }
+
+    pub fn battery_save(&self) -> PathBuf {
+        self.file("saves", "sav")
+    }
+
+    pub fn cheats(&self) -> PathBuf {
+        self.file("cheats", "cht")
+    }
+
+    pub fn labels(&self) -> PathBuf {
+        self.file("labels", "labels")
+    }
+
+    pub fn sram_bank(&self, bank: usize) -> PathBuf {
+        self.file("saves", &format!("bank{}.sram", bank))
+    }
+
+    // Slot 1 keeps the name states had before there were slots.
+    pub fn save_state(&self, slot: usize) -> PathBuf {
+        match slot {
+            1 => self.file("states", "state"),
+            _ => self.file("states", &format!("{}.state", slot)),
+        }
+    }
+
+    // Named after the header checksum too, so a different game that happens to share the file name never
+    // offers to resume it.
+    pub fn auto_state(&self, checksum: u16) -> PathBuf {
+        self.file("states", &format!("{:04X}.auto.state", checksum))
+    }
+
+    pub fn screenshot(&self) -> PathBuf {
+        self.timestamped("screenshots", "png")
+    }
+
+    pub fn crash_report(&self) -> PathBuf {
+        self.timestamped("crashes", "txt")
+    }
+
+    pub fn trace(&self) -> PathBuf {
+        self.timestamped("traces", "trace")
+    }
+
+    // Screenshots and crash reports are stamped with the time they were taken so they never overwrite each other.
+    fn timestamped(&self, kind: &str, extension: &str) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        self.file(kind, &format!("{}.{}", timestamp, extension))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paths::{DataPaths, SaveDir};
+    use std::path::PathBuf;
+
+    #[test]
+    fn files_go_next_to_rom_or_into_save_dir() {
+        let next_to_rom = DataPaths::new("roms/tetris.gb", &SaveDir::NextToRom, false);
+        assert_eq!(next_to_rom.battery_save(), PathBuf::from("roms/tetris.sav"));
+
+        let dir = std::env::temp_dir().join("feboy_paths_test");
+        let custom = DataPaths::new("roms/tetris.gb", &SaveDir::Custom(dir.clone()), false);
+        assert_eq!(custom.battery_save(), dir.join("saves").join("tetris.sav"));
+        assert_eq!(custom.cheats(), dir.join("cheats").join("tetris.cht"));
+        assert_eq!(custom.labels(), dir.join("labels").join("tetris.labels"));
+        assert_eq!(
+            custom.save_state(1),
+            dir.join("states").join("tetris.state")
+        );
+        assert_eq!(
+            custom.save_state(2),
+            dir.join("states").join("tetris.2.state")
+        );
+        assert_eq!(
+            custom.auto_state(0x0A6B),
+            dir.join("states").join("tetris.0A6B.auto.state")
+        );
+        assert_eq!(
+            custom.sram_bank(2),
+            dir.join("saves").join("tetris.bank2.sram")
+        );
+        assert!(dir.join("saves").is_dir());
+    }
+}
@@ -0,0 +1,99 @@
+pub const BRIGHTNESS: (f64, f64) = (0.5, 1.5);
+pub const GAMMA: (f64, f64) = (0.5, 2.5);
+pub const SATURATION: (f64, f64) = (0.0, 2.0);
+const STEP: f64 = 0.1;
+
+// Picture controls for screens the default palettes look washed out or too dark on. They're applied when the
+// palette's four colors are picked, so they cost nothing per pixel.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Picture {
+    pub brightness: f64,
+    pub gamma: f64,
+    pub saturation: f64,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Control {
+    Brightness,
+    Gamma,
+    Saturation,
+}
+
+impl Picture {
+    pub fn new() -> Self {
+        Self {
+            brightness: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+        }
+    }
+
+    pub fn apply(&self, palette: [u32; 4]) -> [u32; 4] {
+        palette.map(|color| {
+            let [_, r, g, b] = color.to_be_bytes();
+            let channels = [r, g, b].map(|c| c as f64 / 255.0);
+            let luma = 0.299 * channels[0] + 0.587 * channels[1] + 0.114 * channels[2];
+            let [r, g, b] = channels.map(|c| {
+                let saturated = (luma + (c - luma) * self.saturation).clamp(0.0, 1.0);
+                let c = saturated.powf(1.0 / self.gamma) * self.brightness;
+                (c.clamp(0.0, 1.0) * 255.0).round() as u8
+            });
+            u32::from_be_bytes([0, r, g, b])
+        })
+    }
+
+    // Steps a control up or down, returning its config key and new value.
+    pub fn adjust(&mut self, control: Control, up: bool) -> (&'static str, f64) {
+        let (name, value, (min, max)) = match control {
+            Control::Brightness => ("brightness", &mut self.brightness, BRIGHTNESS),
+            Control::Gamma => ("gamma", &mut self.gamma, GAMMA),
+            Control::Saturation => ("saturation", &mut self.saturation, SATURATION),
+        };
+        let step = if up { STEP } else { -STEP };
+        // Rounded so repeated steps don't drift away from the values a config file would hold.
+        *value = ((*value + step) * 10.0)
+            .round()
+            .clamp(min * 10.0, max * 10.0)
+            / 10.0;
+        (name, *value)
+    }
+}
+
+pub fn parse_control(value: &str, (min, max): (f64, f64)) -> Option<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|value| (min..=max).contains(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::picture::{parse_control, Control, Picture, GAMMA};
+
+    #[test]
+    fn adjusts_the_palette_colors() {
+        let palette = [0xFFFFFF, 0x88C070, 0x404040, 0x000000];
+        let mut picture = Picture::new();
+        assert_eq!(picture.apply(palette), palette);
+
+        picture.saturation = 0.0;
+        assert_eq!(picture.apply(palette)[1], 0xA6A6A6);
+        picture.saturation = 1.0;
+        picture.gamma = 2.0;
+        assert_eq!(picture.apply(palette)[2], 0x808080);
+        picture.gamma = 1.0;
+        picture.brightness = 0.5;
+        assert_eq!(picture.apply(palette), [0x808080, 0x446038, 0x202020, 0]);
+
+        assert_eq!(
+            picture.adjust(Control::Brightness, false),
+            ("brightness", 0.5)
+        );
+        for _ in 0..30 {
+            picture.adjust(Control::Gamma, true);
+        }
+        assert_eq!(picture.gamma, 2.5);
+        assert_eq!(parse_control("2.5", GAMMA), Some(2.5));
+        assert_eq!(parse_control("3", GAMMA), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::FeboyError;
 use crate::memory_map::OamCorruptionCause;
 use crate::ppu::AddressingMode::{H8000, H8800};
 use crate::ppu::DmaState::Inactive;
@@ -7,9 +8,10 @@ use crate::ppu::PpuState::{LcdOff, ModeChange, ProcessingMode};
 use crate::ppu::RenderCycle::{Normal, StatTrigger};
 use crate::ppu::StatInterrupt::{Low, LycInt, ModeInt};
 use crate::ppu::TileMapArea::{H9800, H9C00};
-use minifb::{Scale, ScaleMode, Window, WindowOptions};
-use std::cmp::min;
-use std::convert::TryInto;
+use crate::prelude::*;
+use crate::state::{invalid_state, StateReader, StateWriter};
+use core::cmp::min;
+use core::convert::TryInto;
 use DmaState::{Executing, Finished, Starting};
 use OamCorruptionCause::{IncDec, Read, ReadWrite, Write};
 
@@ -46,14 +48,48 @@ pub struct PPU {
     stat_line: StatInterrupt,
     force_irq: bool,
     lcdc: LcdControl,
-    pub(crate) pixels: Box<[u32]>,
-    pub window: Window,
+    pub pixels: Box<[u32]>,
+    palette: [Color; 4],
+    frame: Option<Vec<u32>>,
+    // The line drawn since the last call to take_scanline, if any.
+    drawn_line: Option<u8>,
     pub last_ticks: usize,
     pub old_mode: PpuMode,
     pub last_lyc_check: bool,
     pub oam_corruption: Option<OamCorruptionCause>,
+    frame_visible: bool,
+    off_ticks: usize,
+    first_line: bool,
+    pixel_transfer_ticks: usize,
+    // Only one frame in frame_skip is drawn and presented; the others still run every mode, interrupt and
+    // DMA, they just never touch the pixels.
+    frame_skip: usize,
+    skipped: usize,
+    pub layers: Layers,
 }
 
+// Debug switches for each layer the PPU composites. A hidden background or window draws color 0 in its
+// place, so sprites behind it still show.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub sprites: bool,
+}
+
+impl Layers {
+    pub fn new() -> Self {
+        Self {
+            background: true,
+            window: true,
+            sprites: true,
+        }
+    }
+}
+
+const FRAME_TICKS: usize = 70224;
+const DOTS_PER_CYCLE: usize = 4;
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum PpuState {
     ModeChange(PpuMode, PpuMode),
@@ -75,25 +111,9 @@ enum StatInterrupt {
 
 #[deny(unreachable_patterns)]
 impl PPU {
-    pub fn new(rom_name: &String) -> Self {
+    pub fn new() -> Self {
         let lcdc = LcdControl::new(0);
         let fb = [0_u32; 160 * 144];
-        let window = Window::new(
-            format!("{} - ESC to exit", rom_name).as_str(),
-            160,
-            144,
-            WindowOptions {
-                borderless: false,
-                transparency: false,
-                title: true,
-                resize: true,
-                scale: Scale::X1,
-                scale_mode: ScaleMode::Stretch,
-                topmost: false,
-                none: false,
-            },
-        )
-        .unwrap();
         PPU {
             mode: HBlank,
             tile_block_a: [0; 2048],
@@ -113,35 +133,186 @@ impl PPU {
             dma_progress: 0,
             dma_offset: 0,
             pixels: Box::new(fb),
+            palette: [WHITE, LIGHT_GRAY, DARK_GRAY, BLACK],
             old_mode: HBlank,
             dma: Inactive,
             last_lyc_check: false,
-            window,
+            frame: None,
+            drawn_line: None,
+            frame_visible: false,
+            off_ticks: 0,
+            first_line: false,
+            pixel_transfer_ticks: 175,
+            frame_skip: 1,
+            skipped: 0,
+            layers: Layers::new(),
         }
     }
 
+    // A soft reset leaves VRAM and OAM untouched, a power cycle clears them.
+    pub fn reset(&mut self, preserve_ram: bool) {
+        if !preserve_ram {
+            self.tile_block_a = [0; 2048];
+            self.tile_block_b = [0; 2048];
+            self.tile_block_c = [0; 2048];
+            self.tile_map_a = [0; 1024];
+            self.tile_map_b = [0; 1024];
+            self.oam = [0; 160];
+        }
+        self.mode = HBlank;
+        self.old_mode = HBlank;
+        self.registers = [0; 11];
+        self.lcdc = LcdControl::new(0);
+        self.oam_corruption = None;
+        self.ticks = 0;
+        self.stat_line = Low;
+        self.state = LcdOff;
+        self.force_irq = true;
+        self.last_ticks = 0;
+        self.dma_progress = 0;
+        self.dma_offset = 0;
+        self.dma = Inactive;
+        self.last_lyc_check = false;
+        self.frame_visible = false;
+    }
+
+    // The palette, on-screen message and finished frame belong to the frontend and are left alone.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8("PPU mode", self.mode as u8);
+        state.u8("PPU previous mode", self.old_mode as u8);
+        state.u8("DMA state", self.dma as u8);
+        state.usize("PPU DMA progress", self.dma_progress);
+        state.usize("DMA source", self.dma_offset);
+        for (name, base, memory) in [
+            ("tile block 0", 0x8000, &self.tile_block_a[..]),
+            ("tile block 1", 0x8800, &self.tile_block_b),
+            ("tile block 2", 0x9000, &self.tile_block_c),
+            ("tile map 0", 0x9800, &self.tile_map_a),
+            ("tile map 1", 0x9C00, &self.tile_map_b),
+            ("OAM", 0xFE00, &self.oam),
+            ("PPU registers", 0xFF41, &self.registers),
+        ] {
+            state.memory(name, base, memory);
+        }
+        state.u8("LCDC", self.lcdc.get());
+        state.usize("PPU ticks", self.ticks);
+        state.usize("PPU last ticks", self.last_ticks);
+        match self.state {
+            LcdOff => state.u8("PPU state", 0),
+            ProcessingMode(mode) => {
+                state.u8("PPU state", 1);
+                state.u8("PPU state mode", mode as u8);
+            }
+            ModeChange(old, new) => {
+                state.u8("PPU state", 2);
+                state.u8("PPU state old mode", old as u8);
+                state.u8("PPU state new mode", new as u8);
+            }
+        }
+        match self.stat_line {
+            Low => state.u8("STAT line", 0),
+            LycInt => state.u8("STAT line", 1),
+            ModeInt(mode) => {
+                state.u8("STAT line", 2);
+                state.u8("STAT line mode", mode as u8);
+            }
+        }
+        state.bool("force IRQ", self.force_irq);
+        state.bool("last LYC check", self.last_lyc_check);
+        state.bool("frame visible", self.frame_visible);
+        state.usize("LCD off ticks", self.off_ticks);
+        state.bool("first line", self.first_line);
+        state.usize("pixel transfer ticks", self.pixel_transfer_ticks);
+        state.u32s("frame buffer bytes", &self.pixels);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        let mode = |state: &mut StateReader| {
+            [OamSearch, PixelTransfer, HBlank, VBlank]
+                .get(state.u8()? as usize)
+                .copied()
+                .ok_or_else(|| invalid_state("PPU mode"))
+        };
+        self.mode = mode(state)?;
+        self.old_mode = mode(state)?;
+        self.dma = [Inactive, Starting, Executing, Finished]
+            .get(state.u8()? as usize)
+            .copied()
+            .ok_or_else(|| invalid_state("DMA state"))?;
+        self.dma_progress = state.usize()?;
+        self.dma_offset = state.usize()?;
+        for memory in [
+            &mut self.tile_block_a[..],
+            &mut self.tile_block_b,
+            &mut self.tile_block_c,
+            &mut self.tile_map_a,
+            &mut self.tile_map_b,
+            &mut self.oam,
+            &mut self.registers,
+        ] {
+            state.bytes(memory)?;
+        }
+        self.lcdc.set(state.u8()?);
+        self.ticks = state.usize()?;
+        self.last_ticks = state.usize()?;
+        self.state = match state.u8()? {
+            0 => LcdOff,
+            1 => ProcessingMode(mode(state)?),
+            2 => ModeChange(mode(state)?, mode(state)?),
+            _ => return Err(invalid_state("PPU state")),
+        };
+        self.stat_line = match state.u8()? {
+            0 => Low,
+            1 => LycInt,
+            2 => ModeInt(mode(state)?),
+            _ => return Err(invalid_state("STAT line")),
+        };
+        self.force_irq = state.bool()?;
+        self.last_lyc_check = state.bool()?;
+        self.frame_visible = state.bool()?;
+        self.off_ticks = state.usize()?;
+        self.first_line = state.bool()?;
+        self.pixel_transfer_ticks = state.usize()?;
+        for pixel in self.pixels.iter_mut() {
+            *pixel = state.u32()?;
+        }
+        self.oam_corruption = None;
+        Ok(())
+    }
+
+    // The LCD runs 4 dots to a machine cycle. Each dot is stepped on its own, so modes change and LY moves on
+    // on the exact dot, but the CPU only sees the STAT line and registers as they are once the cycle is over.
     pub fn machine_cycle(&mut self) -> RenderCycle {
         self.old_mode = self.mode;
-        self.ticks += 4;
+        let mut dots = DOTS_PER_CYCLE;
 
         if self.dma != Inactive {
             self.dma_cycle();
-            self.ticks -= 4;
+            dots = 0;
         }
 
         if !self.lcdc.enabled() {
+            if self.state != LcdOff {
+                self.frame_visible = false;
+                self.off_ticks = 0;
+                self.present();
+            }
             self.reset_state();
+            self.present_while_off();
             return Normal(self.state);
         } else if self.state == LcdOff {
-            self.mode = OamSearch;
-            self.old_mode = OamSearch;
+            self.mode = HBlank;
+            self.old_mode = HBlank;
+            self.first_line = true;
         }
 
-        self.handle_mode_transition();
+        for _ in 0..dots {
+            self.dot();
+        }
         self.handle_oam_corruption();
         self.handle_lcd_startup();
 
-        let ret = self.cycle_result(self.old_mode);
+        let ret = self.cycle_result();
         //println!("STAT: {} | LYC: {} | LY: {}", self.stat(), self.lyc(), self.ly());
         ret
     }
@@ -155,6 +326,7 @@ impl PPU {
         self.oam_corruption = None;
         self.force_irq = false;
         self.ticks = 0;
+        self.pixel_transfer_ticks = 175;
     }
 
     fn handle_lcd_startup(&mut self) {
@@ -171,6 +343,12 @@ impl PPU {
         }
     }
 
+    fn dot(&mut self) {
+        self.ticks += 1;
+        self.handle_mode_transition();
+    }
+
+    // Ticks count dots into the current mode, so a mode ends on the dot its length is reached.
     fn handle_mode_transition(&mut self) {
         self.ticks -= match self.mode {
             OamSearch => {
@@ -178,21 +356,35 @@ impl PPU {
                     0
                 } else {
                     self.mode = PixelTransfer;
+                    self.pixel_transfer_ticks = self.pixel_transfer_length();
                     80
                 }
             }
 
             PixelTransfer => {
-                if self.ticks < 172 {
+                if self.ticks < self.pixel_transfer_ticks {
                     0
                 } else {
                     self.mode = HBlank;
-                    172
+                    self.pixel_transfer_ticks
+                }
+            }
+
+            // The first line after turning the LCD on skips the OAM scan and stays in mode 0 instead.
+            HBlank if self.first_line => {
+                if self.ticks < 80 {
+                    0
+                } else {
+                    self.first_line = false;
+                    self.mode = PixelTransfer;
+                    self.pixel_transfer_ticks = self.pixel_transfer_length();
+                    80
                 }
             }
 
             HBlank => {
-                if self.ticks < 204 {
+                let hblank_ticks = 376 - self.pixel_transfer_ticks;
+                if self.ticks < hblank_ticks {
                     0
                 } else {
                     *self.ly_mut() += 1;
@@ -200,10 +392,12 @@ impl PPU {
                     self.mode = if self.ly() == 144 {
                         VBlank
                     } else {
-                        self.draw_scanline();
+                        if self.skipped == 0 {
+                            self.draw_scanline();
+                        }
                         OamSearch
                     };
-                    204
+                    hblank_ticks
                 }
             }
 
@@ -215,9 +409,8 @@ impl PPU {
                     self.last_lyc_check = self.lyc_check();
                     *self.ly_mut() %= 154;
                     self.mode = if *self.ly_mut() == 0 {
-                        self.window
-                            .update_with_buffer(&self.pixels, 160, 144)
-                            .unwrap();
+                        self.end_frame();
+                        self.frame_visible = true;
                         OamSearch
                     } else {
                         VBlank
@@ -228,13 +421,47 @@ impl PPU {
         };
     }
 
-    fn cycle_result(&mut self, old_mode: PpuMode) -> RenderCycle {
+    // Mode 3 is stretched by discarding SCX % 8 pixels and by every object fetched on the line; mode 0 shrinks to match.
+    // The 172-dot minimum is padded so mode 0 starts on the right dot relative to the mode 2 interrupt.
+    fn pixel_transfer_length(&self) -> usize {
+        let scx = *self.scx() as usize;
+        let mut length = 175 + scx % 8;
+        if !self.lcdc.sprite_enabled() {
+            return length;
+        }
+
+        let ly = self.ly();
+        let tile_length = self.lcdc.object_size() as u8;
+        let mut positions = (0..160)
+            .step_by(4)
+            .filter(|&index| {
+                let sprite = Sprite::new(self, index);
+                ly >= sprite.vertical_position
+                    && ly < sprite.vertical_position.wrapping_add(tile_length)
+            })
+            .take(10)
+            .map(|index| self.oam[index + 1] as usize)
+            .filter(|&x| x < 168)
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+
+        // Each object costs 6 dots, plus up to 5 more while the first object in a background tile waits for its fetch.
+        let mut fetched_tiles = Vec::new();
+        for x in positions {
+            length += 6;
+            let tile = (x + scx) / 8;
+            if !fetched_tiles.contains(&tile) {
+                fetched_tiles.push(tile);
+                length += 5_usize.saturating_sub((x + scx) % 8);
+            }
+        }
+        length
+    }
+
+    fn cycle_result(&mut self) -> RenderCycle {
         let new_interrupts = self.stat_interrupts();
-        let trigger_stat_interrupt = match (self.stat_line, new_interrupts) {
-            (Low, [.., ModeInt(m), _]) if m == self.mode && old_mode != m => true,
-            (Low, [.., LycInt]) => true,
-            _ => false,
-        };
+        let trigger_stat_interrupt =
+            self.stat_line == Low && new_interrupts.iter().any(|i| i != &Low);
         self.stat_line = *new_interrupts.iter().find(|&i| i != &Low).unwrap_or(&Low);
         self.force_irq = false;
         if trigger_stat_interrupt {
@@ -247,17 +474,17 @@ impl PPU {
     fn stat_interrupts(&mut self) -> [StatInterrupt; 4] {
         let stat = self.stat();
         [
-            if stat & 0x08 != 0 || self.force_irq {
+            if (stat & 0x20 != 0 || self.force_irq) && self.mode == OamSearch {
                 ModeInt(OamSearch)
             } else {
                 Low
             },
-            if stat & 0x10 != 0 || self.force_irq {
+            if (stat & 0x10 != 0 || self.force_irq) && self.mode == VBlank {
                 ModeInt(VBlank)
             } else {
                 Low
             },
-            if stat & 0x20 != 0 || self.force_irq {
+            if (stat & 0x08 != 0 || self.force_irq) && self.mode == HBlank {
                 ModeInt(HBlank)
             } else {
                 Low
@@ -284,12 +511,8 @@ impl PPU {
                 Some(self.oam[address - 0xFE00])
             }
 
-            (0xFE00..=0xFE9F, ..) => {
-                self.oam_corruption = match self.oam_corruption {
-                    None => Some(Read),
-                    Some(IncDec) => Some(ReadWrite),
-                    _ => panic!(),
-                };
+            (0xFE00..=0xFEFF, OamSearch, ..) => {
+                self.trigger_oam_corruption(Read);
                 Some(0xFF)
             }
 
@@ -311,9 +534,7 @@ impl PPU {
             (0x9800..=0x9BFF, ..) => self.tile_map_a[address - 0x9800] = value,
             (0x9C00..=0x9FFF, ..) => self.tile_map_b[address - 0x9C00] = value,
 
-            (0xFE00..=0xFEFF, OamSearch, ..) => {
-                self.oam_corruption = Some(Write);
-            }
+            (0xFE00..=0xFEFF, OamSearch, ..) => self.trigger_oam_corruption(Write),
 
             (0xFE00..=0xFE9F, HBlank | VBlank, Inactive | Starting) => {
                 self.oam[address - 0xFE00] = value
@@ -414,12 +635,16 @@ impl PPU {
         if self.state == LcdOff {
             return self.last_lyc_check;
         }
-        self.ticks > 4
-            && (match (self.mode, self.ticks) {
-                (VBlank, 5..=8) => 153,
-                (VBlank, 9..=12) => !self.lyc(),
-                (..) => self.ly(),
-            }) == *self.lyc()
+        let lyc = *self.lyc();
+        match (self.mode, self.registers[3], self.ticks) {
+            // LY reads 153 for one cycle on the last line, then compares against nothing before settling on 0.
+            (VBlank, 153, 5..=8) => lyc == 153,
+            (VBlank, 153, 9..=12) => false,
+            // LY already reads 0 for most of line 153, so a match against 0 carries over into line 0.
+            (OamSearch, 0, _) => lyc == 0,
+            (OamSearch | VBlank, _, 0..=4) => false,
+            _ => self.ly() == lyc,
+        }
     }
 
     fn bgp(&self) -> &u8 {
@@ -466,7 +691,8 @@ impl PPU {
         let tile_row = (vertical_position / 8) as usize * 32;
 
         for pixel in 0..160 {
-            let horizontal_position = if use_window && pixel >= wx {
+            let in_window = use_window && pixel >= wx;
+            let horizontal_position = if in_window {
                 pixel.wrapping_sub(wx)
             } else {
                 pixel.wrapping_add(scx)
@@ -496,6 +722,12 @@ impl PPU {
 
             let color_num = ((data2 >> color_bit) & 0b1) << 1;
             let color_num = color_num | ((data1 >> color_bit) & 0b1);
+            let shown = if in_window {
+                self.layers.window
+            } else {
+                self.layers.background
+            };
+            let color_num = if shown { color_num } else { 0 };
 
             let color = self.get_color(color_num, *self.bgp());
             self.set_pixel(pixel as u32, ly as u32, color)
@@ -503,10 +735,11 @@ impl PPU {
     }
 
     fn draw_scanline(&mut self) {
+        self.drawn_line = Some(self.ly()).filter(|line| *line < 144);
         if self.lcdc.background_window_enabled() {
             self.render_background_window()
         }
-        if self.lcdc.sprite_enabled() {
+        if self.lcdc.sprite_enabled() && self.layers.sprites {
             self.render_sprites()
         }
     }
@@ -586,12 +819,80 @@ impl PPU {
         let color = ((palette_num >> hi) & 0b1) << 1;
         let color = color | ((palette_num >> lo) & 0b1);
 
-        match color {
-            0 => WHITE,
-            1 => LIGHT_GRAY,
-            2 => DARK_GRAY,
-            3 => BLACK,
-            _ => panic!("Invalid color: 0x{:x}", color),
+        self.palette[color as usize]
+    }
+
+    // A blank frame still goes out at the usual rate while the LCD is off, so frontends keep refreshing.
+    fn present_while_off(&mut self) {
+        self.off_ticks += 4;
+        if self.off_ticks >= FRAME_TICKS {
+            self.off_ticks -= FRAME_TICKS;
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        if self.skipped == 0 {
+            self.present();
+        }
+        self.skipped = (self.skipped + 1) % self.frame_skip;
+    }
+
+    pub fn set_frame_skip(&mut self, frame_skip: usize) {
+        self.frame_skip = frame_skip.max(1);
+        self.skipped = 0;
+    }
+
+    // The screen shows blank while the LCD is off and for the first frame after it's turned back on. The
+    // frame is copied out since rendering carries on into `pixels` before the frontend gets to it.
+    fn present(&mut self) {
+        self.frame = Some(if self.frame_visible {
+            self.pixels.to_vec()
+        } else {
+            vec![self.palette()[0]; 160 * 144]
+        });
+    }
+
+    // The most recent frame ready for display, if one was finished since the last call.
+    pub fn take_frame(&mut self) -> Option<Vec<u32>> {
+        self.frame.take()
+    }
+
+    pub fn take_scanline(&mut self) -> Option<u8> {
+        self.drawn_line.take()
+    }
+
+    // Every sprite in OAM as x, y, width and height on screen, which can run off any edge.
+    pub fn sprite_bounds(&self) -> Vec<(i32, i32, usize, usize)> {
+        let height = self.lcdc.object_size() as usize;
+        self.oam
+            .chunks(4)
+            .map(|sprite| (sprite[1] as i32 - 8, sprite[0] as i32 - 16, 8, height))
+            .collect()
+    }
+
+    // The window's top left corner on screen, if it's switched on.
+    pub fn window_position(&self) -> Option<(i32, i32)> {
+        if self.lcdc.window_enabled() {
+            Some((*self.wx() as i32 - 7, *self.wy() as i32))
+        } else {
+            None
+        }
+    }
+
+    pub fn scroll(&self) -> (u8, u8) {
+        (*self.scx(), *self.scy())
+    }
+
+    pub fn palette(&self) -> [u32; 4] {
+        self.palette
+            .map(|c| u32::from_be_bytes([c.a, c.r, c.g, c.b]))
+    }
+
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        for (color, rgb) in self.palette.iter_mut().zip(palette.iter()) {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            *color = Color { r, g, b, a: 255 };
         }
     }
 
@@ -600,7 +901,7 @@ impl PPU {
         let [a, r, g, b] = self.pixels[offset].to_be_bytes();
         let pixel = Color { a, r, g, b };
 
-        if pixel != WHITE && pri {
+        if pixel != self.palette[0] && pri {
         } else {
             self.set_pixel(x, y, color)
         }
@@ -612,6 +913,14 @@ impl PPU {
         self.pixels[offset] = u32::from_be_bytes([color.a, color.r, color.g, color.b]);
     }
 
+    // A read in the same cycle as an increment/decrement on the bus has its own pattern; otherwise the last access wins.
+    pub fn trigger_oam_corruption(&mut self, cause: OamCorruptionCause) {
+        self.oam_corruption = Some(match (self.oam_corruption.take(), cause) {
+            (Some(IncDec), Read) | (Some(Read), IncDec) => ReadWrite,
+            (_, cause) => cause,
+        });
+    }
+
     fn handle_oam_corruption(&mut self) {
         if self.mode != OamSearch {
             self.oam_corruption = None;
@@ -629,7 +938,18 @@ impl PPU {
     }
 
     fn handle_oam_read_write_corruption(&mut self) {
-        return; // TODO: ReadWrite behavior seems different than Read/Write/IncDec
+        let row = self.oam_row();
+        if (4..19).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 1, 2);
+            self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            let preceding_row: [u8; 8] = self.oam[(row - 1) * 8..row * 8].try_into().unwrap();
+            self.oam[(row - 2) * 8..(row - 1) * 8].copy_from_slice(&preceding_row);
+            self.oam[row * 8..(row + 1) * 8].copy_from_slice(&preceding_row);
+        }
+        self.handle_oam_read_corruption();
     }
 
     fn handle_oam_read_corruption(&mut self) {
@@ -641,25 +961,36 @@ impl PPU {
     }
 
     fn handle_oam_pattern_corruption(&mut self, pattern: fn(u16, u16, u16) -> u16) {
-        let oam_row = min(19, self.ticks / 4);
-        if oam_row == 0 {
+        let row = self.oam_row();
+        if row == 0 {
             return;
         }
-        let mut rows = self.oam.chunks_mut(8);
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        self.set_oam_word(row, 0, pattern(a, b, c));
+        for word in 1..4 {
+            self.set_oam_word(row, word, self.oam_word(row - 1, word));
+        }
+    }
 
-        let (previous_row, current_row) = (rows.nth(oam_row - 1).unwrap(), rows.nth(0).unwrap());
+    // OAM is scanned one 8-byte row per machine cycle during mode 2.
+    fn oam_row(&self) -> usize {
+        min(19, self.ticks / 4)
+    }
 
-        let a = u16::from_le_bytes(current_row[0..2].as_ref().try_into().unwrap());
-        let b = u16::from_le_bytes(previous_row[0..2].as_ref().try_into().unwrap());
-        let c = u16::from_le_bytes(previous_row[4..6].as_ref().try_into().unwrap());
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let offset = row * 8 + word * 2;
+        u16::from_le_bytes([self.oam[offset], self.oam[offset + 1]])
+    }
 
-        let pattern = pattern(a, b, c).to_le_bytes();
-        current_row[0..2].clone_from_slice(pattern.as_slice());
-        current_row[2..].clone_from_slice(&previous_row[2..]);
+    fn set_oam_word(&mut self, row: usize, word: usize, value: u16) {
+        let offset = row * 8 + word * 2;
+        self.oam[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 struct Color {
     r: u8,
     g: u8,
@@ -806,3 +1137,69 @@ impl SpriteAttributes {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::PpuMode::{HBlank, OamSearch, PixelTransfer, VBlank};
+    use crate::ppu::{FRAME_TICKS, PPU};
+
+    #[test]
+    fn modes_change_on_the_exact_dot() {
+        let mut ppu = PPU::new();
+        ppu.write(0xFF40, 0x80);
+        ppu.machine_cycle();
+        // The first line runs from the frame after the LCD is turned on.
+        while !(ppu.mode == OamSearch && ppu.ly() == 0) {
+            ppu.dot();
+        }
+        let mut lengths = vec![];
+        let mut dots = 0;
+        for _ in 0..FRAME_TICKS {
+            // The raw register, since LY reads 0 for most of line 153.
+            let (mode, ly) = (ppu.mode, ppu.registers[3]);
+            ppu.dot();
+            dots += 1;
+            if ppu.mode != mode || ppu.registers[3] != ly {
+                assert_eq!(ppu.ticks, 0);
+                lengths.push((mode, dots));
+                dots = 0;
+            }
+        }
+        // Without SCX or objects mode 3 is padded to 175 dots and every line is 456.
+        assert_eq!(
+            lengths[..3],
+            [(OamSearch, 80), (PixelTransfer, 175), (HBlank, 201)]
+        );
+        assert_eq!(lengths[432..], vec![(VBlank, 456); 10]);
+        assert_eq!(ppu.ly(), 0);
+    }
+
+    #[test]
+    fn hides_layers_from_the_compositor() {
+        let mut ppu = PPU::new();
+        // Tile 0 is solid color 3 and fills both maps. A sprite covers the first 8 pixels and the window
+        // the right half of the line.
+        for address in 0x8000..0x8010 {
+            ppu.write(address, 0xFF);
+        }
+        ppu.write(0xFE00, 16);
+        ppu.write(0xFE01, 8);
+        ppu.write(0xFF40, 0xB3);
+        ppu.write(0xFF47, 0xE4);
+        ppu.write(0xFF48, 0xE4);
+        ppu.write(0xFF4B, 87);
+        let [light, _, _, dark] = ppu.palette();
+        let line = |ppu: &mut PPU| {
+            ppu.draw_scanline();
+            [ppu.pixels[0], ppu.pixels[40], ppu.pixels[120]]
+        };
+        assert_eq!(line(&mut ppu), [dark, dark, dark]);
+
+        ppu.layers.background = false;
+        assert_eq!(line(&mut ppu), [dark, light, dark]);
+        ppu.layers.window = false;
+        assert_eq!(line(&mut ppu), [dark, light, light]);
+        ppu.layers.sprites = false;
+        assert_eq!(line(&mut ppu), [light, light, light]);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::interrupt::InterruptId;
+use crate::interrupt::InterruptId::{StatInt, VBlankInt};
+use crate::ppu::PpuMode;
+use crate::ppu::PpuMode::{HBlank, OamSearch, PixelTransfer, VBlank};
+use crate::prelude::*;
+
+// Handlers that never return, e.g. ones that reset the stack, are given up on past this many.
+const MAX_NESTED: usize = 8;
+
+// A V-blank or STAT handler, from its dispatch to the return that takes its address back off the stack.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HandlerRun {
+    pub interrupt: InterruptId,
+    // The mode the PPU was in when the handler was dispatched.
+    pub started: PpuMode,
+    pub cycles: u64,
+    // Started in H-blank or V-blank and was still running once the PPU went back to drawing, when VRAM
+    // and OAM are locked again.
+    pub overran: bool,
+}
+
+impl HandlerRun {
+    pub fn name(&self) -> &'static str {
+        match self.interrupt {
+            VBlankInt => "V-blank",
+            _ => "STAT",
+        }
+    }
+}
+
+struct Running {
+    run: HandlerRun,
+    start: u64,
+    // Where the return address was pushed.
+    sp: u16,
+}
+
+// Times the V-blank and STAT handlers so homebrew can check them against the blanking period they're meant
+// to fit in.
+pub struct InterruptProfiler {
+    running: Vec<Running>,
+    finished: Vec<HandlerRun>,
+}
+
+impl InterruptProfiler {
+    pub fn new() -> Self {
+        Self {
+            running: vec![],
+            finished: vec![],
+        }
+    }
+
+    pub fn dispatched(&mut self, interrupt: InterruptId, sp: u16, mode: PpuMode, cycles: u64) {
+        if interrupt != VBlankInt && interrupt != StatInt {
+            return;
+        }
+        if self.running.len() == MAX_NESTED {
+            self.running.remove(0);
+        }
+        self.running.push(Running {
+            run: HandlerRun {
+                interrupt,
+                started: mode,
+                cycles: 0,
+                overran: false,
+            },
+            start: cycles,
+            sp,
+        });
+    }
+
+    // Called after every instruction. Handlers interrupted by another finish after it, so only the
+    // innermost can return.
+    pub fn executed(&mut self, sp: u16, mode: PpuMode, cycles: u64) {
+        let drawing = mode == OamSearch || mode == PixelTransfer;
+        for handler in &mut self.running {
+            let blanking = handler.run.started == HBlank || handler.run.started == VBlank;
+            handler.run.overran |= blanking && drawing;
+        }
+        if let Some(handler) = self.running.last() {
+            if sp == handler.sp.wrapping_add(2) {
+                let mut run = handler.run;
+                run.cycles = cycles - handler.start;
+                self.finished.push(run);
+                self.running.pop();
+            }
+        }
+    }
+
+    // The handlers that returned since the last call, e.g. over the last frame.
+    pub fn take_runs(&mut self) -> Vec<HandlerRun> {
+        core::mem::take(&mut self.finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interrupt::InterruptId::{StatInt, TimerInt, VBlankInt};
+    use crate::ppu::PpuMode::{HBlank, OamSearch, PixelTransfer, VBlank};
+    use crate::profiler::{HandlerRun, InterruptProfiler};
+
+    #[test]
+    fn times_handlers_and_flags_overruns() {
+        let mut profiler = InterruptProfiler::new();
+        profiler.dispatched(VBlankInt, 0xDFFC, VBlank, 100);
+        profiler.executed(0xDFFA, VBlank, 120);
+        // An LY=LYC handler nested inside returns first.
+        profiler.dispatched(StatInt, 0xDFF8, VBlank, 140);
+        profiler.executed(0xDFFA, VBlank, 200);
+        profiler.executed(0xDFFE, VBlank, 300);
+        // An H-blank handler still running as the next line is drawn.
+        profiler.dispatched(StatInt, 0xDFFC, HBlank, 400);
+        profiler.executed(0xDFFC, OamSearch, 480);
+        profiler.executed(0xDFFE, PixelTransfer, 500);
+        profiler.dispatched(TimerInt, 0xDFFC, HBlank, 600);
+        profiler.executed(0xDFFE, HBlank, 620);
+        let run = |interrupt, started, cycles, overran| HandlerRun {
+            interrupt,
+            started,
+            cycles,
+            overran,
+        };
+        assert_eq!(
+            profiler.take_runs(),
+            [
+                run(StatInt, VBlank, 60, false),
+                run(VBlankInt, VBlank, 200, false),
+                run(StatInt, HBlank, 100, true),
+            ]
+        );
+        assert!(profiler.take_runs().is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+// The parts of the address space, each owned by whatever backs it on real hardware.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Region {
+    // Banked through the cartridge's mapper.
+    Rom,
+    // Owned by the PPU, along with OAM.
+    Vram,
+    CartridgeRam,
+    // Echo RAM at E000-FDFF mirrors the start of it.
+    Wram,
+    // Including the unusable stretch after it up to FEFF.
+    Oam,
+    // Registers no device claims, sound for now, are kept as plain bytes.
+    Io,
+    Hram,
+    InterruptEnable,
+}
+
+impl Region {
+    pub fn start(&self) -> usize {
+        match self {
+            Region::Rom => 0x0000,
+            Region::Vram => 0x8000,
+            Region::CartridgeRam => 0xA000,
+            Region::Wram => 0xC000,
+            Region::Oam => 0xFE00,
+            Region::Io => 0xFF00,
+            Region::Hram => 0xFF80,
+            Region::InterruptEnable => 0xFFFF,
+        }
+    }
+}
+
+// Dispatch by the high byte of the address; only the last page is split further.
+const PAGES: [Region; 0x100] = pages();
+
+const fn pages() -> [Region; 0x100] {
+    let mut pages = [Region::Rom; 0x100];
+    let mut page = 0;
+    while page < pages.len() {
+        pages[page] = match page {
+            0x00..=0x7F => Region::Rom,
+            0x80..=0x9F => Region::Vram,
+            0xA0..=0xBF => Region::CartridgeRam,
+            0xC0..=0xFD => Region::Wram,
+            0xFE => Region::Oam,
+            _ => Region::Io,
+        };
+        page += 1;
+    }
+    pages
+}
+
+pub fn region(address: usize) -> Region {
+    match address {
+        0xFF80..=0xFFFE => Region::Hram,
+        0xFFFF => Region::InterruptEnable,
+        _ => PAGES[(address >> 8) & 0xFF],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regions::{region, Region};
+
+    #[test]
+    fn pages_map_to_their_regions() {
+        let expected = [
+            (0x0000, Region::Rom),
+            (0x7FFF, Region::Rom),
+            (0x8000, Region::Vram),
+            (0xA000, Region::CartridgeRam),
+            (0xC000, Region::Wram),
+            (0xFDFF, Region::Wram),
+            (0xFEA0, Region::Oam),
+            (0xFF0F, Region::Io),
+            (0xFF7F, Region::Io),
+            (0xFF80, Region::Hram),
+            (0xFFFE, Region::Hram),
+            (0xFFFF, Region::InterruptEnable),
+        ];
+        for (address, expected) in expected {
+            assert_eq!(region(address), expected, "{:04X}", address);
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use crate::memory_map::MemoryMap;
 use crate::register::RegisterId::{A, B, C, D, E, H, L};
 use crate::register::WordRegister::StackPointer;
-use std::ops::{Index, IndexMut};
+use core::ops::{Index, IndexMut};
 use WordRegister::{AccFlag, Double, ProgramCounter};
 
+// Where each flag sits in F. The low nibble doesn't exist and always reads as 0.
+pub const ZERO_FLAG: u8 = 0x80;
+pub const SUBTRACT_FLAG: u8 = 0x40;
+pub const HALF_CARRY_FLAG: u8 = 0x20;
+pub const CARRY_FLAG: u8 = 0x10;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum RegisterId {
     A,
@@ -109,11 +115,7 @@ impl Register {
     }
 
     pub fn set_flag(&mut self, flag: u8) {
-        let flags = flag & 0xF0;
-        self.flags.z = flags & 0x80 == 0x80;
-        self.flags.n = flags & 0x40 == 0x40;
-        self.flags.c = flags & 0x20 == 0x20;
-        self.flags.h = flags & 0x10 == 0x10;
+        self.flags.set(flag);
     }
 }
 
@@ -158,19 +160,23 @@ pub struct FlagRegister {
 
 impl FlagRegister {
     pub fn value(&self) -> u8 {
-        [self.c, self.h, self.n, self.z]
-            .iter()
-            .map(|f| if *f { 1 } else { 0 })
-            .enumerate()
-            .map(|(i, n)| (n << (i + 4)) as u8)
-            .sum()
+        [
+            (self.z, ZERO_FLAG),
+            (self.n, SUBTRACT_FLAG),
+            (self.h, HALF_CARRY_FLAG),
+            (self.c, CARRY_FLAG),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, bit)| bit)
+        .sum()
     }
 
     pub fn set(&mut self, v: u8) {
-        self.z = 0x80 & v != 0;
-        self.n = 0x40 & v != 0;
-        self.h = 0x20 & v != 0;
-        self.c = 0x10 & v != 0;
+        self.z = v & ZERO_FLAG != 0;
+        self.n = v & SUBTRACT_FLAG != 0;
+        self.h = v & HALF_CARRY_FLAG != 0;
+        self.c = v & CARRY_FLAG != 0;
     }
 }
 
@@ -192,13 +198,7 @@ impl WordRegister {
     pub fn value(self) -> u16 {
         match self {
             Double(h, l) => u16::from_le_bytes([l.value, h.value]),
-            AccFlag(a, FlagRegister { z, n, h, c }) => {
-                let bit_flag = |b: bool, v: u32| 2u8.pow(v) as u8 * if b { 1 } else { 0 };
-                u16::from_le_bytes([
-                    bit_flag(z, 3) + bit_flag(n, 2) + bit_flag(h, 1) + bit_flag(c, 0),
-                    a.value,
-                ])
-            }
+            AccFlag(a, flags) => u16::from_le_bytes([flags.value(), a.value]),
             StackPointer(n) | ProgramCounter(n) => n,
         }
     }
@@ -214,3 +214,33 @@ pub enum ConditionCode {
     C,
     NC,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::memory_map::MemoryMap;
+    use crate::register::{FlagRegister, Register, CARRY_FLAG, HALF_CARRY_FLAG};
+
+    #[test]
+    fn flags_round_trip_through_f_and_af() {
+        let mut flags = FlagRegister {
+            z: false,
+            n: false,
+            h: false,
+            c: false,
+        };
+        for f in 0..=0xFF {
+            flags.set(f);
+            assert_eq!(flags.value(), f & 0xF0);
+        }
+        flags.set(CARRY_FLAG);
+        assert!(flags.c && !flags.h);
+
+        let mut mem = MemoryMap::blank();
+        let mut reg = Register::new();
+        reg.set_word_register(0x12FF, reg.af(), &mut mem);
+        assert_eq!(reg.af().value(), 0x12F0);
+        reg.set_flag(HALF_CARRY_FLAG);
+        assert!(reg.flags.h && !reg.flags.c);
+        assert_eq!(reg.af().value(), 0x1220);
+    }
+}
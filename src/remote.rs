@@ -0,0 +1,213 @@
+use crate::joypad::Button;
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// One JSON object per line, e.g. {"command": "press", "buttons": ["a", "start"]}. States default to the
+// game's usual state file when no path is given.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Command {
+    Pause,
+    Resume,
+    LoadRom(String),
+    SaveState(Option<PathBuf>),
+    LoadState(Option<PathBuf>),
+    // Buttons are held until released, on top of whatever the player holds.
+    Press(u8),
+    Release(u8),
+    Read { address: usize, length: usize },
+    Screenshot,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let request: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let path = || request["path"].as_str().map(PathBuf::from);
+        let buttons = || {
+            request["buttons"]
+                .as_array()
+                .ok_or("buttons must be a list")?
+                .iter()
+                .map(|name| {
+                    let name = name.as_str().unwrap_or_default();
+                    Button::parse(name)
+                        .map(|button| 1 << button as u8)
+                        .ok_or(format!("unknown button {}", name))
+                })
+                .try_fold(0, |held, button| button.map(|button| held | button))
+        };
+        match request["command"].as_str().unwrap_or_default() {
+            "pause" => Ok(Command::Pause),
+            "resume" => Ok(Command::Resume),
+            "load_rom" => request["path"]
+                .as_str()
+                .map(|path| Command::LoadRom(path.to_owned()))
+                .ok_or_else(|| "load_rom needs a path".to_owned()),
+            "save_state" => Ok(Command::SaveState(path())),
+            "load_state" => Ok(Command::LoadState(path())),
+            "press" => buttons().map(Command::Press),
+            "release" => buttons().map(Command::Release),
+            "read" => {
+                let address = request["address"].as_u64().ok_or("read needs an address")?;
+                let length = request["length"].as_u64().unwrap_or(1);
+                Ok(Command::Read {
+                    address: address as usize,
+                    length: length.min(0x10000) as usize,
+                })
+            }
+            "screenshot" => Ok(Command::Screenshot),
+            command => Err(format!("unknown command {:?}", command)),
+        }
+    }
+}
+
+// Anything besides success a command sends back: the memory it read, or the screen as a base64 PNG.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Reply {
+    Done,
+    Data(Vec<u8>),
+    Png(Vec<u8>),
+    Failed(String),
+}
+
+impl Reply {
+    pub fn json(&self) -> String {
+        match self {
+            Reply::Done => r#"{"ok":true}"#.to_owned(),
+            Reply::Data(data) => {
+                let bytes = data.iter().map(u8::to_string).collect::<Vec<_>>();
+                format!(r#"{{"ok":true,"data":[{}]}}"#, bytes.join(","))
+            }
+            Reply::Png(png) => format!(r#"{{"ok":true,"png":"{}"}}"#, base64(png)),
+            Reply::Failed(error) => format!(r#"{{"ok":false,"error":"{}"}}"#, escape(error)),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{}", c),
+            c if c.is_control() => format!("\\u{:04x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0_u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            encoded.push(match i <= chunk.len() {
+                true => BASE64[(bits >> (18 - 6 * i) & 0x3F) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    encoded
+}
+
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Reply>,
+}
+
+impl Request {
+    pub fn reply(self, reply: Reply) {
+        let _ = self.reply.send(reply);
+    }
+}
+
+// Listens on a TCP address for tools to drive the emulator, one thread per connection. Each command waits
+// for the emulation thread to pick it up, which happens once a frame, paused or not.
+pub struct Remote {
+    requests: Receiver<Request>,
+}
+
+impl Remote {
+    pub fn listen(address: &str) -> io::Result<Remote> {
+        let listener = TcpListener::bind(address)?;
+        let (sender, requests) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(Result::ok) {
+                let sender = sender.clone();
+                thread::spawn(move || serve(stream, sender));
+            }
+        });
+        Ok(Remote { requests })
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.try_iter().collect()
+    }
+}
+
+fn serve(stream: TcpStream, requests: Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match Command::parse(&line) {
+            Ok(command) => {
+                let (reply, receiver) = channel();
+                if requests.send(Request { command, reply }).is_err() {
+                    break;
+                }
+                receiver
+                    .recv()
+                    .unwrap_or_else(|_| Reply::Failed("emulation stopped".to_owned()))
+            }
+            Err(e) => Reply::Failed(e),
+        };
+        writeln!(writer, "{}", reply.json())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remote::{base64, Command, Reply};
+
+    #[test]
+    fn parses_commands_and_encodes_replies() {
+        assert_eq!(
+            Command::parse(r#"{"command": "press", "buttons": ["a", "Start"]}"#),
+            Ok(Command::Press(0b1001))
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "read", "address": 49152, "length": 2}"#),
+            Ok(Command::Read {
+                address: 0xC000,
+                length: 2
+            })
+        );
+        assert_eq!(
+            Command::parse(r#"{"command": "save_state"}"#),
+            Ok(Command::SaveState(None))
+        );
+        assert!(Command::parse(r#"{"command": "press", "buttons": ["z"]}"#).is_err());
+        assert!(Command::parse(r#"{"command": "load_rom"}"#).is_err());
+
+        assert_eq!(
+            Reply::Data(vec![1, 255]).json(),
+            r#"{"ok":true,"data":[1,255]}"#
+        );
+        assert_eq!(
+            Reply::Failed("no \"state\"".to_owned()).json(),
+            r#"{"ok":false,"error":"no \"state\""}"#
+        );
+        assert_eq!(base64(b"PNG"), "UE5H");
+        assert_eq!(base64(b"PN"), "UE4=");
+        assert_eq!(base64(b"P"), "UA==");
+    }
+}
@@ -0,0 +1,70 @@
+use crate::error::FeboyError;
+use crate::patch::apply_patch;
+use flate2::read::GzDecoder;
+use std::fs::read;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+const PATCH_EXTENSIONS: [&str; 2] = ["ips", "bps"];
+
+pub fn load_rom(rom_name: &str, patch_name: Option<&str>) -> Result<Vec<u8>, FeboyError> {
+    let rom = read_rom(rom_name)?;
+    let patch_name = patch_name
+        .map(|name| name.to_owned())
+        .or_else(|| find_patch(rom_name));
+    match patch_name {
+        Some(patch_name) => {
+            println!("Applying patch {}", patch_name);
+            apply_patch(&rom, &read(patch_name)?)
+        }
+        None => Ok(rom),
+    }
+}
+
+fn find_patch(rom_name: &str) -> Option<String> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|ext| Path::new(rom_name).with_extension(ext))
+        .find(|path| path.exists())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+fn read_rom(rom_name: &str) -> Result<Vec<u8>, FeboyError> {
+    let file = read(rom_name)?;
+    if file.starts_with(&ZIP_MAGIC) {
+        unzip_rom(file)
+    } else if file.starts_with(&GZIP_MAGIC) {
+        gunzip_rom(file)
+    } else {
+        Ok(file)
+    }
+}
+
+fn unzip_rom(file: Vec<u8>) -> Result<Vec<u8>, FeboyError> {
+    let invalid_archive = |e: ZipError| FeboyError::InvalidArchive(e.to_string());
+    let mut archive = ZipArchive::new(Cursor::new(file)).map_err(invalid_archive)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(invalid_archive)?;
+        let entry_name = entry.name().to_lowercase();
+        if !ROM_EXTENSIONS.iter().any(|ext| entry_name.ends_with(ext)) {
+            continue;
+        }
+        let mut rom = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut rom)?;
+        return Ok(rom);
+    }
+    Err(FeboyError::InvalidArchive(
+        "no .gb/.gbc file found inside ZIP archive".to_owned(),
+    ))
+}
+
+fn gunzip_rom(file: Vec<u8>) -> Result<Vec<u8>, FeboyError> {
+    let mut rom = vec![];
+    GzDecoder::new(file.as_slice()).read_to_end(&mut rom)?;
+    Ok(rom)
+}
@@ -0,0 +1,279 @@
+use crate::error::FeboyError;
+use crate::prelude::*;
+use crate::state::{StateReader, StateWriter};
+use core::convert::TryInto;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+const SECONDS_PER_DAY: u64 = 86_400;
+const DAYS: u64 = 512;
+// Bits of DH, the day counter's high register, besides day bit 8.
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+// The clock as it trails a battery save: every register as a little-endian u32, live then latched, then the
+// Unix time it was saved at as a u64. VBA and BGB write the same layout.
+pub const BATTERY_LEN: usize = 48;
+
+// What moves the cartridge clock forward.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RtcClock {
+    // Emulated cycles, so it stops while paused and runs faster while fast-forwarding. The same inputs always
+    // see the same times, which TAS and speedruns depend on.
+    Emulated,
+    // The host's clock, so in-game time keeps pace with the real world, between sessions too, like a real
+    // cartridge's. Without std there's no clock to follow, and it stands still.
+    Host,
+}
+
+impl RtcClock {
+    pub fn parse(value: &str) -> Option<RtcClock> {
+        match value.to_lowercase().as_str() {
+            "emulated" => Some(RtcClock::Emulated),
+            "host" => Some(RtcClock::Host),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn host_time() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|time| time.as_secs())
+}
+
+#[cfg(not(feature = "std"))]
+fn host_time() -> Option<u64> {
+    None
+}
+
+// MBC3's real time clock. Games read a latched copy of the counters, taken when 00 then 01 is written to
+// 6000-7FFF, so they never see it tick over halfway through a read.
+pub struct Rtc {
+    // Seconds, minutes, hours, the day counter's low byte and DH.
+    registers: [u8; 5],
+    latched: [u8; 5],
+    // Whether 00 was the last value written to the latch register.
+    latch_armed: bool,
+    // Cycles toward the next second, in emulated mode.
+    cycles: u64,
+    clock: RtcClock,
+    // The host time the registers were last brought up to, in host mode.
+    synced: u64,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 5],
+            latched: [0; 5],
+            latch_armed: false,
+            cycles: 0,
+            clock: RtcClock::Host,
+            synced: host_time().unwrap_or(0),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+        self.synced = host_time().unwrap_or(0);
+    }
+
+    pub fn clock(&self) -> RtcClock {
+        self.clock
+    }
+
+    fn halted(&self) -> bool {
+        self.registers[4] & HALT != 0
+    }
+
+    pub fn machine_cycle(&mut self) {
+        if self.clock != RtcClock::Emulated || self.halted() {
+            return;
+        }
+        self.cycles += 4;
+        if self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.advance(1);
+        }
+    }
+
+    // Catches up with the host clock. Time spent halted, or going backwards, is skipped.
+    fn sync(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        if let Some(now) = host_time() {
+            if !self.halted() {
+                self.advance(now.saturating_sub(self.synced));
+            }
+            self.synced = now;
+        }
+    }
+
+    // Out of range values a game wrote wrap within their register's bits, like the hardware's.
+    fn advance(&mut self, seconds: u64) {
+        let [s, m, h, dl, dh] = self.registers;
+        let days = ((dh as u64 & 0x01) << 8) | dl as u64;
+        let total = (s & 0x3F) as u64
+            + (m & 0x3F) as u64 * 60
+            + (h & 0x1F) as u64 * 3600
+            + days * SECONDS_PER_DAY
+            + seconds;
+        let days = total / SECONDS_PER_DAY;
+        let mut dh = dh & !0x01;
+        if days >= DAYS {
+            dh |= DAY_CARRY;
+        }
+        let days = days % DAYS;
+        let time = total % SECONDS_PER_DAY;
+        self.registers = [
+            (time % 60) as u8,
+            (time / 60 % 60) as u8,
+            (time / 3600) as u8,
+            days as u8,
+            dh | (days >> 8) as u8,
+        ];
+    }
+
+    pub fn latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.sync();
+            self.latched = self.registers;
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    // Register 08-0C, as selected through 4000-5FFF.
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.sync();
+        let index = (register - 0x08) as usize;
+        self.registers[index] = value;
+        self.latched[index] = value;
+        // Writing the seconds restarts the second in progress.
+        if index == 0 {
+            self.cycles = 0;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes("RTC registers", &self.registers);
+        state.bytes("RTC latched registers", &self.latched);
+        state.bool("RTC latch armed", self.latch_armed);
+        state.u64("RTC cycles", self.cycles);
+    }
+
+    // A state is a moment in the past, so the host clock picks up from when it's loaded.
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        state.bytes(&mut self.registers)?;
+        state.bytes(&mut self.latched)?;
+        self.latch_armed = state.bool()?;
+        self.cycles = state.u64()?;
+        self.synced = host_time().unwrap_or(0);
+        Ok(())
+    }
+
+    pub fn battery_data(&self) -> Vec<u8> {
+        let saved_at = match self.clock {
+            RtcClock::Host => self.synced,
+            RtcClock::Emulated => host_time().unwrap_or(0),
+        };
+        let mut data = Vec::with_capacity(BATTERY_LEN);
+        for register in self.registers.iter().chain(&self.latched) {
+            data.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&saved_at.to_le_bytes());
+        data
+    }
+
+    // In host mode the clock catches up on the time since the save was written.
+    pub fn load_battery_data(&mut self, data: &[u8]) {
+        if data.len() < BATTERY_LEN {
+            return;
+        }
+        let words = data[..40]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()) as u8)
+            .collect::<Vec<_>>();
+        self.registers.copy_from_slice(&words[..5]);
+        self.latched.copy_from_slice(&words[5..]);
+        let saved_at = u64::from_le_bytes(data[40..48].try_into().unwrap());
+        if self.clock == RtcClock::Host {
+            self.synced = saved_at;
+            self.sync();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtc::{Rtc, RtcClock, BATTERY_LEN, CYCLES_PER_SECOND};
+
+    #[test]
+    fn counts_emulated_seconds_into_days() {
+        let mut rtc = Rtc::new();
+        rtc.set_clock(RtcClock::Emulated);
+        // 23:59:58 on day 511.
+        for (register, value) in [
+            (0x08, 58),
+            (0x09, 59),
+            (0x0A, 23),
+            (0x0B, 0xFF),
+            (0x0C, 0x01),
+        ] {
+            rtc.write(register, value);
+        }
+        for _ in 0..CYCLES_PER_SECOND / 4 {
+            rtc.machine_cycle();
+        }
+        rtc.latch(0x01);
+        assert_eq!(rtc.read(0x08), 58);
+        rtc.latch(0x00);
+        rtc.latch(0x01);
+        assert_eq!(rtc.read(0x08), 59);
+
+        // The day counter overflows into the carry bit, which stays set.
+        for _ in 0..CYCLES_PER_SECOND / 4 {
+            rtc.machine_cycle();
+        }
+        rtc.latch(0x00);
+        rtc.latch(0x01);
+        let time = (0x08..=0x0C).map(|r| rtc.read(r)).collect::<Vec<_>>();
+        assert_eq!(time, [0, 0, 0, 0, 0x80]);
+
+        // Halted, it stops.
+        rtc.write(0x0C, 0x40);
+        for _ in 0..CYCLES_PER_SECOND / 4 {
+            rtc.machine_cycle();
+        }
+        rtc.latch(0x00);
+        rtc.latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+
+        // Saves carry the clock, and host mode catches up with the time since.
+        let mut data = rtc.battery_data();
+        assert_eq!(data.len(), BATTERY_LEN);
+        data[0] = 10;
+        data[16] = 0;
+        data[40..].copy_from_slice(&0_u64.to_le_bytes());
+        let mut loaded = Rtc::new();
+        loaded.set_clock(RtcClock::Emulated);
+        loaded.load_battery_data(&data);
+        loaded.latch(0x00);
+        loaded.latch(0x01);
+        assert_eq!(loaded.read(0x08), 10);
+        let mut loaded = Rtc::new();
+        loaded.load_battery_data(&data);
+        loaded.latch(0x00);
+        loaded.latch(0x01);
+        assert_eq!(loaded.read(0x0C) & 0x80, 0x80);
+        assert_eq!(RtcClock::parse("Host"), Some(RtcClock::Host));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::FeboyError;
+use crate::prelude::*;
+use crate::rtc::BATTERY_LEN;
+
+// Older VBA versions saved the time as 32 bits, leaving the clock four bytes short.
+const SHORT_RTC_LEN: usize = BATTERY_LEN - 4;
+const MBC2_RAM: usize = 512;
+
+// The battery save layouts other emulators use. feboy's own is VBA's.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SavFormat {
+    // Cartridge RAM alone.
+    Raw,
+    // RAM followed by the MBC3 clock, if the cartridge has one, as VBA and BGB write it.
+    Vba,
+    // MBC2's 512 half bytes packed two to a byte, the low nibble first.
+    Mbc2Packed,
+}
+
+impl SavFormat {
+    pub fn parse(value: &str) -> Option<SavFormat> {
+        match value.to_lowercase().as_str() {
+            "raw" => Some(SavFormat::Raw),
+            "vba" => Some(SavFormat::Vba),
+            "mbc2" => Some(SavFormat::Mbc2Packed),
+            _ => None,
+        }
+    }
+}
+
+fn invalid_save(message: String) -> FeboyError {
+    FeboyError::InvalidSave(message)
+}
+
+// Cartridge RAM always comes in a power of two, or not at all on MBC3 clocks without RAM, so whatever follows
+// it is the clock. A short clock is padded out to the current layout.
+fn split(data: &[u8]) -> Result<(&[u8], Option<Vec<u8>>), FeboyError> {
+    let ram_len = [0, BATTERY_LEN, SHORT_RTC_LEN]
+        .iter()
+        .map(|rtc_len| data.len().wrapping_sub(*rtc_len))
+        .find(|len| *len == 0 || len.is_power_of_two())
+        .ok_or_else(|| invalid_save(format!("unexpected size of {} bytes", data.len())))?;
+    let (ram, rtc) = data.split_at(ram_len);
+    let rtc = Some(rtc)
+        .filter(|rtc| !rtc.is_empty())
+        .map(|rtc| [rtc, &[0; 4][..BATTERY_LEN - rtc.len()]].concat());
+    Ok((ram, rtc))
+}
+
+// Converts one of feboy's saves to another format.
+pub fn export_sav(data: &[u8], format: SavFormat) -> Result<Vec<u8>, FeboyError> {
+    let (ram, rtc) = split(data)?;
+    match format {
+        SavFormat::Raw => Ok(ram.to_vec()),
+        SavFormat::Vba => Ok([ram, &rtc.unwrap_or_default()].concat()),
+        SavFormat::Mbc2Packed if ram.len() == MBC2_RAM => Ok(ram
+            .chunks(2)
+            .map(|pair| pair[0] & 0x0F | pair[1] << 4)
+            .collect()),
+        SavFormat::Mbc2Packed => Err(invalid_save(format!(
+            "MBC2 RAM is {} bytes, not {}",
+            MBC2_RAM,
+            ram.len()
+        ))),
+    }
+}
+
+// Converts a save from another format to feboy's.
+pub fn import_sav(data: &[u8], format: SavFormat) -> Result<Vec<u8>, FeboyError> {
+    match format {
+        SavFormat::Raw if data.len().is_power_of_two() => Ok(data.to_vec()),
+        SavFormat::Raw => Err(invalid_save(format!(
+            "{} bytes isn't a RAM size, try the vba format",
+            data.len()
+        ))),
+        SavFormat::Vba => export_sav(data, SavFormat::Vba),
+        // The upper half of every byte is open bus on the cartridge and reads back as 1s.
+        SavFormat::Mbc2Packed if data.len() == MBC2_RAM / 2 => Ok(data
+            .iter()
+            .flat_map(|byte| [byte | 0xF0, byte >> 4 | 0xF0])
+            .collect()),
+        SavFormat::Mbc2Packed => Err(invalid_save(format!(
+            "packed MBC2 RAM is {} bytes, not {}",
+            MBC2_RAM / 2,
+            data.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtc::BATTERY_LEN;
+    use crate::sav::{export_sav, import_sav, SavFormat};
+
+    #[test]
+    fn converts_between_save_formats() {
+        let mut save = vec![0xAB; 0x2000];
+        save.extend(1..=BATTERY_LEN as u8);
+        assert_eq!(export_sav(&save, SavFormat::Raw).unwrap(), &save[..0x2000]);
+        assert_eq!(export_sav(&save, SavFormat::Vba).unwrap(), save);
+        assert!(import_sav(&save, SavFormat::Raw).is_err());
+
+        // A short clock from an older VBA gets a 64-bit timestamp.
+        let short = &save[..save.len() - 4];
+        let imported = import_sav(short, SavFormat::Vba).unwrap();
+        assert_eq!(imported.len(), save.len());
+        assert_eq!(imported[0x2000 + BATTERY_LEN - 4..], [0; 4]);
+        assert!(import_sav(&save[..100], SavFormat::Vba).is_err());
+
+        let mbc2 = (0..512).map(|i| 0xF0 | (i % 16) as u8).collect::<Vec<_>>();
+        let packed = export_sav(&mbc2, SavFormat::Mbc2Packed).unwrap();
+        assert_eq!(packed[..2], [0x10, 0x32]);
+        assert_eq!(import_sav(&packed, SavFormat::Mbc2Packed).unwrap(), mbc2);
+        assert!(export_sav(&save, SavFormat::Mbc2Packed).is_err());
+        assert_eq!(SavFormat::parse("MBC2"), Some(SavFormat::Mbc2Packed));
+    }
+
+    // MBC3 + TIMER + BATTERY carts without RAM save nothing but the clock.
+    #[test]
+    fn saves_can_be_only_a_clock() {
+        let clock = (1..=BATTERY_LEN as u8).collect::<Vec<_>>();
+        assert!(export_sav(&clock, SavFormat::Raw).unwrap().is_empty());
+        assert_eq!(export_sav(&clock, SavFormat::Vba).unwrap(), clock);
+        let short = &clock[..BATTERY_LEN - 4];
+        let imported = import_sav(short, SavFormat::Vba).unwrap();
+        assert_eq!(imported[..BATTERY_LEN - 4], *short);
+        assert_eq!(imported[BATTERY_LEN - 4..], [0; 4]);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::cartridge::Cartridge;
+use std::fs::{read, rename, File};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const FLUSH_DELAY: Duration = Duration::from_secs(3);
+
+pub struct BatterySave {
+    // None when the battery RAM is neither loaded nor written, e.g. for deterministic runs.
+    path: Option<PathBuf>,
+    last_write: Option<Instant>,
+}
+
+impl BatterySave {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            last_write: None,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            last_write: None,
+        }
+    }
+
+    pub fn load(&self, cartridge: &mut Cartridge) {
+        let path = match &self.path {
+            Some(path) if cartridge.battery_ram().is_some() => path,
+            _ => return,
+        };
+        if let Ok(data) = read(path) {
+            println!("Loaded save file {}", path.display());
+            cartridge.load_battery_ram(&data);
+        }
+    }
+
+    // Flushes once the game has stopped writing to SRAM for a while, so saves made mid-frame aren't torn.
+    pub fn update(&mut self, cartridge: &mut Cartridge) {
+        if cartridge.take_ram_dirty() {
+            self.last_write = Some(Instant::now());
+        }
+        match self.last_write {
+            Some(last_write) if last_write.elapsed() >= FLUSH_DELAY => self.flush(cartridge),
+            _ => (),
+        }
+    }
+
+    pub fn flush(&mut self, cartridge: &mut Cartridge) {
+        if cartridge.take_ram_dirty() {
+            self.last_write = Some(Instant::now());
+        }
+        if self.last_write.take().is_none() {
+            return;
+        }
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        if let Some(ram) = cartridge.battery_ram() {
+            if let Err(e) = write_atomically(path, &ram) {
+                println!("Failed to write save file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// The data reaches the disk before the rename, and the rename does too where directories can be synced, so
+// a crash leaves either the old save or the new one.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let temp_path = path.with_extension("sav.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    rename(&temp_path, path)?;
+    #[cfg(unix)]
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use crate::dot_matrix::{DotMatrix, SCALE};
+use crate::frames::FrameReceiver;
+use crate::osd::{Osd, OsdSettings};
+use crate::viewport::{blit, Scaling};
+use minifb::{Key, Scale, ScaleMode, Window, WindowOptions};
+use std::borrow::Cow;
+use std::time::Duration;
+
+// The emulator window and everything drawn on it besides the game, which arrives finished from the
+// emulation thread.
+pub struct Screen {
+    pub window: Window,
+    frames: FrameReceiver,
+    osd: Osd,
+    dot_matrix: Option<DotMatrix>,
+    scaling: Scaling,
+}
+
+impl Screen {
+    pub fn open(rom_name: &str, frames: FrameReceiver) -> Self {
+        let window = Window::new(
+            format!("{} - ESC to exit", rom_name).as_str(),
+            160,
+            144,
+            WindowOptions {
+                borderless: false,
+                transparency: false,
+                title: true,
+                resize: true,
+                scale: Scale::X1,
+                scale_mode: ScaleMode::UpperLeft,
+                topmost: false,
+                none: false,
+            },
+        )
+        .unwrap();
+        Self {
+            window,
+            frames,
+            osd: Osd::new(OsdSettings::new()),
+            dot_matrix: None,
+            scaling: Scaling::Aspect,
+        }
+    }
+
+    pub fn set_osd(&mut self, settings: OsdSettings) {
+        self.osd = Osd::new(settings);
+    }
+
+    pub fn toggle_input_display(&mut self) -> bool {
+        self.osd.toggle_input()
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    pub fn set_dot_matrix(&mut self, intensity: Option<f64>) {
+        self.dot_matrix = intensity.map(DotMatrix::new);
+    }
+
+    pub fn show_message(&mut self, message: String) {
+        println!("{}", message);
+        self.osd.show(message);
+    }
+
+    pub fn wait_for_frame(&self, timeout: Duration) {
+        self.frames.wait(timeout);
+    }
+
+    // Shows the latest frame, or the previous one again while paused or between frames so the window keeps
+    // handling events. The OSD is drawn over a copy of the frame so it never leaks into screenshots.
+    pub fn update(&mut self) {
+        // Counted before this update's frame is taken, which is close enough for the FPS widget.
+        let received = self.frames.received();
+        let frame = self.frames.latest();
+        let mut pixels = Cow::Borrowed(&frame.pixels[..]);
+        self.osd
+            .draw(&mut pixels, frame.palette, received, frame.buttons);
+        // The filter runs over the OSD too, so it looks like part of the screen.
+        let (pixels, size) = match &self.dot_matrix {
+            Some(dot_matrix) => (
+                Cow::Owned(dot_matrix.apply(&pixels)),
+                (160 * SCALE, 144 * SCALE),
+            ),
+            None => (pixels, (160, 144)),
+        };
+        // The window's size is checked every update, so a resize never deforms more than one frame.
+        let window = self.window.get_size();
+        // A minimized window has nothing to draw into, but still needs its events handled.
+        if window.0 == 0 || window.1 == 0 {
+            self.window.update();
+            return;
+        }
+        let viewport = self.scaling.viewport(window);
+        let output = blit(&pixels, size, window, &viewport);
+        self.window
+            .update_with_buffer(&output, window.0, window.1)
+            .unwrap();
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+}
@@ -0,0 +1,49 @@
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::fs::write;
+use std::io::Write;
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+pub fn save_screenshot(pixels: &[u32], path: &Path) {
+    match write(path, encode_png(pixels, 160, 144)) {
+        Ok(_) => println!("Saved screenshot {}", path.display()),
+        Err(e) => println!("Failed to save screenshot {}: {}", path.display(), e),
+    }
+}
+
+// Frames are 0RGB words, written out as an 8-bit RGB PNG with one unfiltered scanline per row.
+pub fn encode_png(pixels: &[u32], width: usize, height: usize) -> Vec<u8> {
+    let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width).take(height) {
+        scanlines.push(0);
+        for pixel in row {
+            scanlines.extend(&pixel.to_be_bytes()[1..]);
+        }
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let _ = encoder.write_all(&scanlines);
+    let compressed = encoder.finish().unwrap_or_default();
+
+    let mut header = vec![];
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    for (kind, data) in [
+        (b"IHDR", &header),
+        (b"IDAT", &compressed),
+        (b"IEND", &vec![]),
+    ] {
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        png.extend((data.len() as u32).to_be_bytes());
+        png.extend(kind);
+        png.extend(data);
+        png.extend(crc.sum().to_be_bytes());
+    }
+    png
+}
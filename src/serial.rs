@@ -0,0 +1,193 @@
+use crate::error::FeboyError;
+use crate::prelude::*;
+use crate::state::{StateReader, StateWriter};
+
+pub struct SerialInterrupt;
+
+// Anything plugged into the link port, for embedders to add peripherals of their own. Cycles are T-cycles
+// since the device was plugged in.
+pub trait SerialDevice {
+    // The console drove the clock and shifted `outgoing` out; returns the byte shifted back in.
+    fn exchange(&mut self, outgoing: u8, cycle: i64) -> u8;
+
+    // Called as the console runs, for devices that drive the clock themselves. A byte returned here only
+    // gets through while the console is `armed`, waiting on the external clock, and the console's own byte
+    // comes back through `received`.
+    fn drive(&mut self, _cycle: i64, _armed: bool) -> Option<u8> {
+        None
+    }
+
+    fn received(&mut self, _incoming: u8, _cycle: i64) {}
+}
+
+impl<T: SerialDevice + ?Sized> SerialDevice for Box<T> {
+    fn exchange(&mut self, outgoing: u8, cycle: i64) -> u8 {
+        (**self).exchange(outgoing, cycle)
+    }
+
+    fn drive(&mut self, cycle: i64, armed: bool) -> Option<u8> {
+        (**self).drive(cycle, armed)
+    }
+
+    fn received(&mut self, incoming: u8, cycle: i64) {
+        (**self).received(incoming, cycle)
+    }
+}
+
+// A plug wiring the port's output back to its input, so every byte the console sends comes straight back,
+// as link cable test ROMs expect.
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn exchange(&mut self, outgoing: u8, _cycle: i64) -> u8 {
+        outgoing
+    }
+}
+
+// The internal clock shifts one bit every 128 machine cycles (8192Hz).
+const TRANSFER_CYCLES: u16 = 8 * 128;
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    ticks: u16,
+    interrupt: bool,
+    pending: bool,
+    pub linked: bool,
+}
+
+impl Serial {
+    const SB: usize = 0xFF01;
+    const SC: usize = 0xFF02;
+
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            ticks: 0,
+            interrupt: false,
+            pending: false,
+            linked: false,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8("SB", self.sb);
+        state.u8("SC", self.sc);
+        state.u16("serial ticks", self.ticks);
+        state.bool("serial interrupt", self.interrupt);
+        state.bool("serial pending", self.pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        self.sb = state.u8()?;
+        self.sc = state.u8()?;
+        self.ticks = state.u16()?;
+        self.interrupt = state.bool()?;
+        self.pending = state.bool()?;
+        Ok(())
+    }
+
+    fn transferring(&self, internal_clock: bool) -> bool {
+        self.sc & 0x80 != 0 && (self.sc & 0x01 != 0) == internal_clock
+    }
+
+    // With nothing on the other end of the cable the line floats high and every bit reads back as 1.
+    pub fn machine_cycle(&mut self) -> Option<SerialInterrupt> {
+        if self.transferring(true) && !self.pending {
+            self.ticks += 1;
+            if self.ticks == TRANSFER_CYCLES {
+                self.ticks = 0;
+                if self.linked {
+                    self.pending = true;
+                } else {
+                    self.complete(0xFF);
+                }
+            }
+        }
+        if core::mem::replace(&mut self.interrupt, false) {
+            Some(SerialInterrupt)
+        } else {
+            None
+        }
+    }
+
+    // The byte a clock master has finished shifting out, waiting for the cable to deliver the reply.
+    pub fn take_outgoing(&mut self) -> Option<u8> {
+        if core::mem::replace(&mut self.pending, false) {
+            Some(self.sb)
+        } else {
+            None
+        }
+    }
+
+    pub fn complete(&mut self, incoming: u8) {
+        self.sb = incoming;
+        self.sc &= 0x7F;
+        self.interrupt = true;
+    }
+
+    pub fn armed(&self) -> bool {
+        self.transferring(false)
+    }
+
+    // The partner's clock only moves data once this side has a transfer armed on the external clock.
+    pub fn exchange(&mut self, incoming: u8) -> u8 {
+        if !self.armed() {
+            return 0xFF;
+        }
+        let outgoing = self.sb;
+        self.complete(incoming);
+        outgoing
+    }
+
+    pub fn read(&self, address: usize) -> Option<u8> {
+        match address {
+            Serial::SB => Some(self.sb),
+            Serial::SC => Some(self.sc | 0x7E),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) -> bool {
+        match address {
+            Serial::SB => self.sb = value,
+            Serial::SC => {
+                self.sc = value;
+                self.ticks = 0;
+                self.pending = false;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::{Serial, TRANSFER_CYCLES};
+
+    #[test]
+    fn linked_transfer_swaps_bytes() {
+        let (mut master, mut slave) = (Serial::new(), Serial::new());
+        master.linked = true;
+        master.write(0xFF01, 0x12);
+        slave.write(0xFF01, 0x34);
+        slave.write(0xFF02, 0x80);
+        master.write(0xFF02, 0x81);
+
+        for _ in 0..TRANSFER_CYCLES {
+            assert!(master.machine_cycle().is_none());
+        }
+        let outgoing = master.take_outgoing().unwrap();
+        let incoming = slave.exchange(outgoing);
+        master.complete(incoming);
+
+        assert!(master.machine_cycle().is_some());
+        assert!(slave.machine_cycle().is_some());
+        assert_eq!(master.read(0xFF01), Some(0x34));
+        assert_eq!(slave.read(0xFF01), Some(0x12));
+        assert_eq!(master.read(0xFF02), Some(0x7F));
+        assert_eq!(slave.exchange(0x56), 0xFF);
+    }
+}
@@ -0,0 +1,166 @@
+// Runs the community SM83 single instruction test vectors (one JSON file per opcode, e.g. sm83/v1/3e.json)
+// against the CPU on a flat bus, comparing registers, memory and the access made on every machine cycle.
+use crate::gameboy::{step, Gameboy};
+use crate::interrupt::IF_ADDRESS;
+use crate::memory_map::FlatBus;
+use crate::memory_map::MemoryMap;
+use crate::register::RegisterId;
+use crate::register::RegisterId::{A, B, C, D, E, H, L};
+use crate::register::WordRegister::{ProgramCounter, StackPointer};
+use serde_json::Value;
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+const TEST_DIR: &str = "sm83/v1";
+const REGISTERS: [(&str, RegisterId); 7] = [
+    ("a", A),
+    ("b", B),
+    ("c", C),
+    ("d", D),
+    ("e", E),
+    ("h", H),
+    ("l", L),
+];
+
+// A malformed vector fails the test rather than running with made up values.
+fn number(value: &Value) -> u16 {
+    match value.as_u64() {
+        Some(number) if number <= 0xFFFF => number as u16,
+        _ => panic!("Expected a 16-bit number, found {}", value),
+    }
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .unwrap_or_else(|| panic!("Expected a RAM list, found {}", state["ram"]))
+        .iter()
+        .map(|entry| (number(&entry[0]), number(&entry[1]) as u8))
+        .collect()
+}
+
+// Some vectors model the fetch overlap: the opcode sits just before pc and the last cycle fetches the next
+// one. Those are shifted back so every instruction starts with its own opcode fetch, which is how feboy
+// runs them.
+fn prefetched(test: &Value) -> Option<(u16, u8)> {
+    let opcode = u8::from_str_radix(test["name"].as_str()?.split(' ').next()?, 16).ok()?;
+    let pc = number(&test["initial"]["pc"]).wrapping_sub(1);
+    ram(&test["initial"])
+        .into_iter()
+        .find(|entry| *entry == (pc, opcode))
+}
+
+fn load(gameboy: &mut Gameboy, state: &Value, fetch: Option<(u16, u8)>) {
+    for (name, id) in REGISTERS {
+        gameboy.reg[id].value = number(&state[name]) as u8;
+    }
+    gameboy.reg.flags.set(number(&state["f"]) as u8);
+    gameboy.reg.sp = StackPointer(number(&state["sp"]));
+    gameboy.reg.pc = ProgramCounter(fetch.map_or(number(&state["pc"]), |(pc, _)| pc));
+    gameboy.ime = number(&state["ime"]) != 0;
+    gameboy.ei_counter = -1;
+    gameboy.halted = false;
+
+    gameboy.mem.interrupt_handler.write(IF_ADDRESS, 0);
+    let mut bus = FlatBus::new();
+    for (address, value) in ram(state) {
+        bus.memory[address as usize] = value;
+    }
+    gameboy.mem.flat_bus = Some(bus);
+}
+
+// Lists every way the CPU ended up somewhere other than the expected final state.
+fn compare(gameboy: &Gameboy, test: &Value, fetch: Option<(u16, u8)>) -> Vec<String> {
+    let expected = &test["final"];
+    let mut errors = vec![];
+    let mut check = |name: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            errors.push(format!("{} is {:X}, expected {:X}", name, actual, expected));
+        }
+    };
+    for (name, id) in REGISTERS {
+        check(name, gameboy.reg[id].value as u16, number(&expected[name]));
+    }
+    check(
+        "f",
+        gameboy.reg.flags.value() as u16,
+        number(&expected["f"]) & 0xF0,
+    );
+    check("sp", gameboy.reg.sp.value(), number(&expected["sp"]));
+    check(
+        "pc",
+        gameboy.reg.pc.value(),
+        number(&expected["pc"]).wrapping_sub(fetch.is_some() as u16),
+    );
+
+    let bus = gameboy.mem.flat_bus.as_ref().unwrap();
+    for (address, value) in ram(expected) {
+        check(
+            &format!("{:04X}", address),
+            bus.memory[address as usize] as u16,
+            value as u16,
+        );
+    }
+
+    let mut cycles = test["cycles"]
+        .as_array()
+        .cloned()
+        .unwrap_or_else(|| panic!("Expected a cycle list, found {}", test["cycles"]));
+    let mut expected_cycles = vec![];
+    if let Some((pc, opcode)) = fetch {
+        cycles.pop();
+        expected_cycles.push(Some((pc, opcode, false)));
+    }
+    expected_cycles.extend(cycles.iter().map(|cycle| match cycle[2].as_str() {
+        Some(kind) if kind.starts_with('r') => {
+            Some((number(&cycle[0]), number(&cycle[1]) as u8, false))
+        }
+        Some(kind) if kind.contains('w') => {
+            Some((number(&cycle[0]), number(&cycle[1]) as u8, true))
+        }
+        _ => None,
+    }));
+    if bus.cycles != expected_cycles {
+        errors.push(format!(
+            "bus activity {:X?}, expected {:X?}",
+            bus.cycles, expected_cycles
+        ));
+    }
+    errors
+}
+
+// The vectors aren't checked in, fetch them first and then run the ignored test:
+// git clone https://github.com/SingleStepTests/sm83 && cargo test sm83 -- --ignored
+#[test]
+#[ignore]
+fn sm83_single_instruction_tests() {
+    let files = read_dir(Path::new(TEST_DIR))
+        .unwrap_or_else(|e| panic!("No SM83 vectors in {}: {}", TEST_DIR, e));
+    let mut gameboy = Gameboy::new(MemoryMap::blank());
+    let mut failures = vec![];
+    for path in files.filter_map(|file| file.ok()).map(|file| file.path()) {
+        let tests: Value = serde_json::from_str(&read_to_string(&path).unwrap()).unwrap();
+        for test in tests.as_array().into_iter().flatten() {
+            let fetch = prefetched(test);
+            load(&mut gameboy, &test["initial"], fetch);
+            if let Err(e) = step(&mut gameboy) {
+                failures.push(format!("{}: {}", test["name"].as_str().unwrap_or("?"), e));
+                continue;
+            }
+            let errors = compare(&gameboy, test, fetch);
+            if !errors.is_empty() {
+                failures.push(format!(
+                    "{}: {}",
+                    test["name"].as_str().unwrap_or("?"),
+                    errors.join(", ")
+                ));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} failures, first ones:\n{}",
+        failures.len(),
+        failures[..failures.len().min(20)].join("\n")
+    );
+}
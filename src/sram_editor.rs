@@ -0,0 +1,365 @@
+use crate::font::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::labels::{load_label_file, Labels};
+use crate::paths::DataPaths;
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use std::fs::{read, write};
+
+pub const BANK_SIZE: usize = 0x2000;
+const COLUMNS: usize = 16;
+const ROWS: usize = 24;
+// An address, the hex columns, a gap and the ASCII columns.
+const LINE_LENGTH: usize = 4 + COLUMNS * 3 + 2 + COLUMNS;
+const WIDTH: usize = (LINE_LENGTH + 1) * GLYPH_WIDTH;
+// The title, the rows and the label under the cursor.
+const HEIGHT: usize = (ROWS + 3) * GLYPH_HEIGHT;
+const BACKGROUND: u32 = 0x101018;
+const TEXT: u32 = 0xC0C0C0;
+const CURSOR: u32 = 0xFFD040;
+
+// The editing state of the SRAM editor, kept apart from its window. Offsets handed out are into the whole
+// of cartridge RAM, bank * BANK_SIZE + offset in the bank.
+pub struct SramView {
+    ram: Vec<u8>,
+    bank: usize,
+    cursor: usize,
+    top: usize,
+    // The first digit typed over the byte under the cursor, waiting for the second.
+    high_nibble: Option<u8>,
+}
+
+impl SramView {
+    pub fn new() -> Self {
+        Self {
+            ram: vec![],
+            bank: 0,
+            cursor: 0,
+            top: 0,
+            high_nibble: None,
+        }
+    }
+
+    // Carts with 2KB of RAM have a single short bank.
+    fn bank_size(&self) -> usize {
+        self.ram.len().min(BANK_SIZE)
+    }
+
+    fn banks(&self) -> usize {
+        self.ram.len().div_ceil(BANK_SIZE)
+    }
+
+    fn bank_start(&self) -> usize {
+        self.bank * BANK_SIZE
+    }
+
+    pub fn refresh(&mut self, ram: Vec<u8>) {
+        self.ram = ram;
+        self.bank = self.bank.min(self.banks().saturating_sub(1));
+        self.cursor = self.cursor.min(self.bank_size().saturating_sub(1));
+    }
+
+    pub fn bank(&self) -> &[u8] {
+        let start = self.bank_start();
+        &self.ram[start..start + self.bank_size()]
+    }
+
+    // Overwrites the current bank, returning the bytes that changed.
+    pub fn replace_bank(&mut self, data: &[u8]) -> Vec<(usize, u8)> {
+        let start = self.bank_start();
+        let mut edits = vec![];
+        for (offset, value) in data.iter().take(self.bank_size()).enumerate() {
+            if self.ram[start + offset] != *value {
+                self.ram[start + offset] = *value;
+                edits.push((start + offset, *value));
+            }
+        }
+        edits
+    }
+
+    // Arrows and Page Up/Down move around the bank, Tab and Shift+Tab switch banks and hex digits overwrite
+    // the byte under the cursor, which moves on once both digits are in.
+    pub fn press(&mut self, key: Key, shift: bool) -> Option<(usize, u8)> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let last = self.bank_size() - 1;
+        let step = match key {
+            Key::Left => -1,
+            Key::Right => 1,
+            Key::Up => -(COLUMNS as isize),
+            Key::Down => COLUMNS as isize,
+            Key::PageUp => -((COLUMNS * ROWS) as isize),
+            Key::PageDown => (COLUMNS * ROWS) as isize,
+            Key::Home => -(self.cursor as isize),
+            Key::End => (last - self.cursor) as isize,
+            Key::Tab => {
+                let banks = self.banks();
+                self.bank = if shift {
+                    (self.bank + banks - 1) % banks
+                } else {
+                    (self.bank + 1) % banks
+                };
+                self.high_nibble = None;
+                return None;
+            }
+            _ => return hex_digit(key).and_then(|digit| self.type_digit(digit)),
+        };
+        self.move_cursor(step);
+        None
+    }
+
+    fn move_cursor(&mut self, step: isize) {
+        let last = self.bank_size() - 1;
+        self.cursor = self.cursor.saturating_add_signed(step).min(last);
+        self.high_nibble = None;
+        let row = self.cursor / COLUMNS;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + ROWS {
+            self.top = row + 1 - ROWS;
+        }
+    }
+
+    fn type_digit(&mut self, digit: u8) -> Option<(usize, u8)> {
+        let high = match self.high_nibble.take() {
+            Some(high) => high,
+            None => {
+                self.high_nibble = Some(digit);
+                return None;
+            }
+        };
+        let offset = self.bank_start() + self.cursor;
+        self.ram[offset] = high << 4 | digit;
+        self.move_cursor(1);
+        Some((offset, self.ram[offset]))
+    }
+
+    // One line per row shown, with the byte under the cursor in brackets.
+    pub fn lines(&self) -> Vec<String> {
+        let bank = self.bank();
+        (self.top..self.top + ROWS)
+            .map(|row| row * COLUMNS)
+            .take_while(|start| *start < bank.len())
+            .map(|start| {
+                let bytes = &bank[start..(start + COLUMNS).min(bank.len())];
+                let mut line = format!("{:04X}", 0xA000 + start);
+                for (i, byte) in bytes.iter().enumerate() {
+                    let offset = start + i;
+                    line.push(match offset {
+                        _ if offset == self.cursor => '[',
+                        _ if offset == self.cursor + 1 && i > 0 => ']',
+                        _ => ' ',
+                    });
+                    match (offset == self.cursor, self.high_nibble) {
+                        (true, Some(high)) => line.push_str(&format!("{:X}_", high)),
+                        _ => line.push_str(&format!("{:02X}", byte)),
+                    }
+                }
+                let last = start + bytes.len() - 1 == self.cursor;
+                line.push_str(if last { "] " } else { "  " });
+                line.extend(bytes.iter().map(|byte| match *byte as char {
+                    c if c.is_ascii_alphanumeric() => c,
+                    _ => '.',
+                }));
+                line
+            })
+            .collect()
+    }
+
+    // The label the byte under the cursor belongs to, with its value, if it has one. Labels name CPU
+    // addresses, so one in A000-BFFF names that spot in every bank.
+    pub fn cursor_label(&self, labels: &Labels) -> Option<String> {
+        let bank = self.bank();
+        let label = labels.at((0xA000 + self.cursor) as u16)?;
+        let value = label.value(|address| {
+            let offset = (address as usize).wrapping_sub(0xA000);
+            bank.get(offset).copied().unwrap_or(0xFF)
+        });
+        Some(format!(
+            "{:04X} {} ({:?}) = {}",
+            label.address, label.name, label.kind, value
+        ))
+    }
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    let keys = [
+        Key::Key0,
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+    ];
+    keys.iter().position(|k| *k == key).map(|digit| digit as u8)
+}
+
+// A debug window for viewing and editing cartridge RAM while the game runs. Ctrl+S exports the current bank
+// to a file next to the battery save and Ctrl+O imports it back, e.g. to repair a corrupted save.
+pub struct SramEditor {
+    window: Window,
+    view: SramView,
+    paths: DataPaths,
+    labels: Labels,
+}
+
+impl SramEditor {
+    pub fn open(paths: DataPaths) -> Option<Self> {
+        let options = WindowOptions {
+            scale: Scale::X2,
+            ..WindowOptions::default()
+        };
+        match Window::new("SRAM", WIDTH, HEIGHT, options) {
+            Ok(window) => Some(Self {
+                window,
+                view: SramView::new(),
+                labels: load_label_file(&paths.labels()),
+                paths,
+            }),
+            Err(e) => {
+                println!("Failed to open SRAM editor: {}", e);
+                None
+            }
+        }
+    }
+
+    // Shows the latest RAM snapshot, if one came in, and returns the bytes edited since the last update.
+    // Returns None once the window has been closed.
+    pub fn update(&mut self, ram: Option<Vec<u8>>) -> Option<Vec<(usize, u8)>> {
+        if !self.window.is_open() {
+            return None;
+        }
+        if let Some(ram) = ram {
+            self.view.refresh(ram);
+        }
+        let ctrl =
+            self.window.is_key_down(Key::LeftCtrl) || self.window.is_key_down(Key::RightCtrl);
+        let shift =
+            self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+        let mut edits = vec![];
+        for key in self
+            .window
+            .get_keys_pressed(KeyRepeat::Yes)
+            .unwrap_or_default()
+        {
+            match key {
+                Key::S if ctrl => self.export(),
+                Key::O if ctrl => edits.extend(self.import()),
+                _ => edits.extend(self.view.press(key, shift)),
+            }
+        }
+        self.draw();
+        Some(edits)
+    }
+
+    fn export(&self) {
+        if self.view.ram.is_empty() {
+            return;
+        }
+        let path = self.paths.sram_bank(self.view.bank);
+        match write(&path, self.view.bank()) {
+            Ok(_) => println!(
+                "Exported SRAM bank {} to {}",
+                self.view.bank,
+                path.display()
+            ),
+            Err(e) => println!("Failed to export SRAM bank to {}: {}", path.display(), e),
+        }
+    }
+
+    fn import(&mut self) -> Vec<(usize, u8)> {
+        if self.view.ram.is_empty() {
+            return vec![];
+        }
+        let path = self.paths.sram_bank(self.view.bank);
+        match read(&path) {
+            Ok(data) => {
+                println!(
+                    "Imported SRAM bank {} from {}",
+                    self.view.bank,
+                    path.display()
+                );
+                self.view.replace_bank(&data)
+            }
+            Err(e) => {
+                println!("Failed to import SRAM bank from {}: {}", path.display(), e);
+                vec![]
+            }
+        }
+    }
+
+    fn draw(&mut self) {
+        let mut buffer = vec![BACKGROUND; WIDTH * HEIGHT];
+        let title = match self.view.banks() {
+            0 => "No cartridge RAM".to_owned(),
+            banks => format!(
+                "Bank {} of {} - Tab switches, Ctrl+S exports, Ctrl+O imports",
+                self.view.bank, banks
+            ),
+        };
+        draw_text(&mut buffer, WIDTH, 2, 1, &title, CURSOR);
+        for (row, line) in self.view.lines().iter().enumerate() {
+            let y = (row + 1) * GLYPH_HEIGHT + 2;
+            draw_text(&mut buffer, WIDTH, 2, y, line, TEXT);
+        }
+        if let Some(label) = self.view.cursor_label(&self.labels) {
+            let y = (ROWS + 1) * GLYPH_HEIGHT + 2;
+            draw_text(&mut buffer, WIDTH, 2, y, &label, CURSOR);
+        }
+        let _ = self.window.update_with_buffer(&buffer, WIDTH, HEIGHT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::{Label, Labels};
+    use crate::sram_editor::{SramView, BANK_SIZE};
+    use minifb::Key;
+
+    #[test]
+    fn edits_bytes_across_banks() {
+        let mut view = SramView::new();
+        let mut ram = vec![0; BANK_SIZE * 2];
+        ram[0..4].copy_from_slice(b"SAVE");
+        view.refresh(ram);
+        assert!(view.lines()[0].starts_with("A000[53]41 56 45 00"));
+        assert!(view.lines()[0].ends_with("SAVE............"));
+
+        assert_eq!(view.press(Key::Right, false), None);
+        assert_eq!(view.press(Key::F, false), None);
+        assert!(view.lines()[0].starts_with("A000 53[F_]56"));
+        assert_eq!(view.press(Key::Key3, false), Some((1, 0xF3)));
+        assert!(view.lines()[0].starts_with("A000 53 F3[56]"));
+
+        view.press(Key::Tab, false);
+        view.press(Key::Down, false);
+        view.press(Key::A, false);
+        assert_eq!(view.press(Key::B, false), Some((BANK_SIZE + 0x12, 0xAB)));
+        view.press(Key::End, false);
+        assert!(view.lines().last().unwrap().starts_with("BFF0"));
+        assert!(view.lines().last().unwrap().contains("[00] ...."));
+
+        view.press(Key::Tab, true);
+        assert_eq!(&view.bank()[..3], &[0x53, 0xF3, 0x56]);
+        assert_eq!(view.replace_bank(&[0x53, 0x41]), vec![(1, 0x41)]);
+
+        let labels = Labels::new(vec![Label::parse("A001 u16le checksum").unwrap()]);
+        assert_eq!(view.cursor_label(&labels), None);
+        view.press(Key::Home, false);
+        view.press(Key::Right, false);
+        view.press(Key::Right, false);
+        assert_eq!(
+            view.cursor_label(&labels).unwrap(),
+            "A001 checksum (U16Le) = 22081"
+        );
+    }
+}
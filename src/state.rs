@@ -0,0 +1,370 @@
+use crate::error::FeboyError;
+use crate::gameboy::Gameboy;
+use crate::prelude::*;
+use alloc::collections::VecDeque;
+
+const MAGIC: &[u8; 4] = b"FBST";
+// Bumped whenever the layout changes. Version 6 added the feboy version to the header, which from then on
+// always starts the same way, so a state from any later format can at least say where it came from.
+// Version 7 added the thumbnail after it.
+const VERSION: u8 = 7;
+// Version 5 states are version 6 without the feboy version, and version 6 ones are 7 without the
+// thumbnail, so they still load.
+const OLDEST_VERSION: u8 = 5;
+// Thumbnails are the screen at half size, as 8-bit RGB.
+pub const THUMBNAIL_WIDTH: usize = 80;
+pub const THUMBNAIL_HEIGHT: usize = 72;
+const FEBOY_VERSION: &str = env!("CARGO_PKG_VERSION");
+const REWIND_INTERVAL: usize = 5;
+const REWIND_CAPACITY: usize = 120;
+
+// A field as a labelled writer records it, for tools like --diff-states that name what differs.
+pub enum StateValue {
+    // Registers print as hex padded to their width, counters and flags (width 0) in decimal.
+    Scalar(u64, usize),
+    // Buffers with a base address list every differing byte, the rest only report how many differ.
+    Bytes(Option<usize>, Vec<u8>),
+}
+
+pub struct StateField {
+    pub name: &'static str,
+    pub value: StateValue,
+}
+
+// Save states are a flat little-endian dump of every component, read back in the order it was written.
+// Components name every field they write, which only a labelled writer keeps.
+pub struct StateWriter {
+    data: Vec<u8>,
+    fields: Option<Vec<StateField>>,
+}
+
+impl StateWriter {
+    pub fn new(checksum: u16) -> Self {
+        Self::with_thumbnail(checksum, &[])
+    }
+
+    // States the player picks from later carry a small picture of the screen. Rewind snapshots and the like
+    // leave it empty.
+    pub fn with_thumbnail(checksum: u16, screen: &[u32]) -> Self {
+        let mut state = Self {
+            data: MAGIC.to_vec(),
+            fields: None,
+        };
+        state.u8("format version", VERSION);
+        state.u16("checksum", checksum);
+        state.bytes("feboy version", FEBOY_VERSION.as_bytes());
+        state.bytes("thumbnail", &thumbnail(screen));
+        state
+    }
+
+    // Keeps the name and value of every field after the header, see fields.
+    pub fn labelled(checksum: u16) -> Self {
+        let mut state = Self::new(checksum);
+        state.fields = Some(vec![]);
+        state
+    }
+
+    fn field(&mut self, name: &'static str, value: impl FnOnce() -> StateValue) {
+        if let Some(fields) = &mut self.fields {
+            fields.push(StateField {
+                name,
+                value: value(),
+            });
+        }
+    }
+
+    pub fn u8(&mut self, name: &'static str, value: u8) {
+        self.field(name, || StateValue::Scalar(value as u64, 2));
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, name: &'static str, value: u16) {
+        self.field(name, || StateValue::Scalar(value as u64, 4));
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, name: &'static str, value: u64) {
+        self.field(name, || StateValue::Scalar(value, 0));
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, name: &'static str, value: usize) {
+        self.u64(name, value as u64);
+    }
+
+    pub fn bool(&mut self, name: &'static str, value: bool) {
+        self.field(name, || StateValue::Scalar(value as u64, 0));
+        self.data.push(value as u8);
+    }
+
+    pub fn bytes(&mut self, name: &'static str, value: &[u8]) {
+        self.field(name, || StateValue::Bytes(None, value.to_vec()));
+        self.data.extend((value.len() as u64).to_le_bytes());
+        self.data.extend(value);
+    }
+
+    // A buffer that maps to addresses, so the ones that differ can be listed.
+    pub fn memory(&mut self, name: &'static str, base: usize, value: &[u8]) {
+        self.field(name, || StateValue::Bytes(Some(base), value.to_vec()));
+        self.data.extend((value.len() as u64).to_le_bytes());
+        self.data.extend(value);
+    }
+
+    // Written one after another with no length, so they're read back one u32 at a time.
+    pub fn u32s(&mut self, name: &'static str, values: &[u32]) {
+        let bytes = values.iter().flat_map(|value| value.to_le_bytes());
+        self.field(name, || StateValue::Bytes(None, bytes.clone().collect()));
+        self.data.extend(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn fields(self) -> Vec<StateField> {
+        self.fields.unwrap_or_default()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    // States only load into the game they were saved from, and formats this build doesn't know are turned
+    // away naming the version that wrote them rather than misread.
+    pub fn new(data: &'a [u8], checksum: u16) -> Result<Self, FeboyError> {
+        let mut state = Self { data };
+        if state.take(MAGIC.len())? != MAGIC {
+            return Err(invalid_state("not a feboy save state"));
+        }
+        let version = state.u8()?;
+        let saved_checksum = state.u16()?;
+        let saved_by = match version {
+            0..=5 => "an older feboy".to_owned(),
+            _ => format!("feboy {}", String::from_utf8_lossy(state.buffer()?)),
+        };
+        if !(OLDEST_VERSION..=VERSION).contains(&version) {
+            return Err(FeboyError::InvalidState(format!(
+                "saved by {} in format {}, feboy {} reads formats {} to {}",
+                saved_by, version, FEBOY_VERSION, OLDEST_VERSION, VERSION
+            )));
+        }
+        if saved_checksum != checksum {
+            return Err(invalid_state("saved from a different game"));
+        }
+        if version >= 7 {
+            state.buffer()?;
+        }
+        Ok(state)
+    }
+
+    // The picture of the screen a state was saved with, if it has one.
+    pub fn thumbnail(data: &[u8]) -> Option<Vec<u32>> {
+        let mut state = StateReader { data };
+        if state.take(MAGIC.len()).ok()? != MAGIC || !(7..=VERSION).contains(&state.u8().ok()?) {
+            return None;
+        }
+        state.u16().ok()?;
+        state.buffer().ok()?;
+        let thumbnail = state.buffer().ok()?;
+        if thumbnail.len() != THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3 {
+            return None;
+        }
+        Some(
+            thumbnail
+                .chunks(3)
+                .map(|rgb| u32::from_be_bytes([0xFF, rgb[0], rgb[1], rgb[2]]))
+                .collect(),
+        )
+    }
+
+    // The game a state belongs to, for tools that open states without the ROM.
+    pub fn checksum(data: &[u8]) -> Option<u16> {
+        let checksum = data.get(MAGIC.len() + 1..MAGIC.len() + 3)?;
+        Some(u16::from_le_bytes([checksum[0], checksum[1]]))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FeboyError> {
+        if self.data.len() < len {
+            return Err(invalid_state("truncated"));
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, FeboyError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, FeboyError> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, FeboyError> {
+        Ok(u32::from_le_bytes([
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+            self.u8()?,
+        ]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, FeboyError> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, FeboyError> {
+        Ok(self.u64()? as usize)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, FeboyError> {
+        Ok(self.u8()? != 0)
+    }
+
+    // Buffers are sized by the ROM header or the hardware, so a length mismatch means a corrupt state.
+    pub fn bytes(&mut self, target: &mut [u8]) -> Result<(), FeboyError> {
+        if self.usize()? != target.len() {
+            return Err(invalid_state("buffer size mismatch"));
+        }
+        target.copy_from_slice(self.take(target.len())?);
+        Ok(())
+    }
+
+    pub fn buffer(&mut self) -> Result<&'a [u8], FeboyError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+}
+
+// Every other pixel of every other line, so the 160x144 screen comes out at 80x72.
+fn thumbnail(screen: &[u32]) -> Vec<u8> {
+    if screen.len() != THUMBNAIL_WIDTH * 2 * THUMBNAIL_HEIGHT * 2 {
+        return vec![];
+    }
+    screen
+        .chunks(THUMBNAIL_WIDTH * 2)
+        .step_by(2)
+        .flat_map(|line| line.iter().step_by(2))
+        .flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b]
+        })
+        .collect()
+}
+
+pub fn invalid_state(message: &str) -> FeboyError {
+    FeboyError::InvalidState(message.to_owned())
+}
+
+// Keeps a snapshot every few frames, tagged with the cycle it was taken at; rewinding steps back through
+// them one per frame.
+pub struct Rewind {
+    snapshots: VecDeque<(u64, Vec<u8>)>,
+    frames: usize,
+}
+
+impl Rewind {
+    pub fn new() -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            frames: 0,
+        }
+    }
+
+    pub fn record(&mut self, gameboy: &Gameboy) {
+        self.frames += 1;
+        if self.frames < REWIND_INTERVAL {
+            return;
+        }
+        self.frames = 0;
+        if self.snapshots.len() == REWIND_CAPACITY {
+            self.snapshots.pop_front();
+        }
+        self.snapshots
+            .push_back((gameboy.clock().cycles, gameboy.save_state()));
+    }
+
+    pub fn step_back(&mut self, gameboy: &mut Gameboy) -> bool {
+        self.frames = 0;
+        match self.snapshots.pop_back() {
+            Some((_, snapshot)) => gameboy.load_state(&snapshot).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn snapshot_before(&self, cycles: u64) -> Option<&[u8]> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|(taken, _)| *taken < cycles)
+            .map(|(_, snapshot)| snapshot.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::FeboyError;
+    use crate::state::{
+        StateReader, StateWriter, FEBOY_VERSION, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH,
+    };
+
+    #[test]
+    fn round_trip_and_rejects_other_games() {
+        let mut writer = StateWriter::new(0x0A6B);
+        writer.u8("byte", 0x12);
+        writer.usize("counter", 70224);
+        writer.bool("flag", true);
+        writer.bytes("buffer", &[1, 2, 3]);
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data, 0x0A6B).unwrap();
+        let mut buffer = [0; 3];
+        assert_eq!(reader.u8().unwrap(), 0x12);
+        assert_eq!(reader.usize().unwrap(), 70224);
+        assert!(reader.bool().unwrap());
+        reader.bytes(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+        assert!(reader.u8().is_err());
+
+        assert!(StateReader::new(&data, 0x0000).is_err());
+        assert!(StateReader::new(&data[..4], 0x0A6B).is_err());
+
+        // Version 5 had no feboy version in the header, and 6 no thumbnail, and they're otherwise the same.
+        let header = 4 + 1 + 2;
+        let body = header + 8 + FEBOY_VERSION.len() + 8;
+        let mut old = data[..body - 8].to_vec();
+        old[4] = 6;
+        old.extend(&data[body..]);
+        let mut reader = StateReader::new(&old, 0x0A6B).unwrap();
+        assert_eq!(reader.u8().unwrap(), 0x12);
+        let mut old = data[..header].to_vec();
+        old[4] = 5;
+        old.extend(&data[body..]);
+        let mut reader = StateReader::new(&old, 0x0A6B).unwrap();
+        assert_eq!(reader.u8().unwrap(), 0x12);
+        assert_eq!(StateReader::thumbnail(&data), None);
+
+        let screen = (0..160 * 144).map(|i| 0xFF000000 | i).collect::<Vec<_>>();
+        let data = StateWriter::with_thumbnail(0x0A6B, &screen).finish();
+        let thumbnail = StateReader::thumbnail(&data).unwrap();
+        assert_eq!(thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        assert_eq!((thumbnail[1], thumbnail[80]), (screen[2], screen[320]));
+        assert!(StateReader::new(&data, 0x0A6B).unwrap().u8().is_err());
+
+        let mut newer = data.clone();
+        newer[4] = 8;
+        let message = match StateReader::new(&newer, 0x0A6B) {
+            Err(FeboyError::InvalidState(message)) => message,
+            _ => panic!("loaded a state from a newer format"),
+        };
+        assert!(message.starts_with(&format!("saved by feboy {} in format 8", FEBOY_VERSION)));
+    }
+}
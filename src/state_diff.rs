@@ -0,0 +1,121 @@
+use crate::error::FeboyError;
+use crate::gameboy::Gameboy;
+use crate::prelude::*;
+use crate::state::StateField;
+use crate::state::StateValue::{Bytes, Scalar};
+use core::fmt::Write;
+
+const LISTED_BYTES: usize = 16;
+
+// The state as the game's components write it back out, so the fields are named by whatever saves them.
+fn fields(gameboy: &mut Gameboy, state: &[u8]) -> Result<Vec<StateField>, FeboyError> {
+    gameboy.load_state(state)?;
+    Ok(gameboy.state_fields())
+}
+
+// Lists every CPU field, memory byte and register that differs between two states of the game.
+pub fn diff_states(gameboy: &mut Gameboy, a: &[u8], b: &[u8]) -> Result<String, FeboyError> {
+    let (a, b) = (fields(gameboy, a)?, fields(gameboy, b)?);
+    let mut report = String::new();
+    for a in &a {
+        // Fields like the PPU's sub-state only exist in some states.
+        let b = match b.iter().find(|b| b.name == a.name) {
+            Some(b) => b,
+            None => {
+                let _ = writeln!(report, "{}: only in the first state", a.name);
+                continue;
+            }
+        };
+        match (&a.value, &b.value) {
+            (Scalar(x, 0), Scalar(y, _)) if x != y => {
+                let _ = writeln!(report, "{}: {} -> {}", a.name, x, y);
+            }
+            (Scalar(x, width), Scalar(y, _)) if x != y => {
+                let _ = writeln!(report, "{}: {:0w$X} -> {:0w$X}", a.name, x, y, w = width);
+            }
+            (Bytes(base, x), Bytes(_, y)) if x != y => {
+                let differing = (0..x.len().max(y.len()))
+                    .filter(|i| x.get(*i) != y.get(*i))
+                    .collect::<Vec<usize>>();
+                let _ = writeln!(report, "{}: {} bytes differ", a.name, differing.len());
+                let base = match base {
+                    Some(base) if x.len() == y.len() => base,
+                    _ => continue,
+                };
+                for i in differing.iter().take(LISTED_BYTES) {
+                    let _ = writeln!(report, "  {:04X}: {:02X} -> {:02X}", base + i, x[*i], y[*i]);
+                }
+                if differing.len() > LISTED_BYTES {
+                    let _ = writeln!(report, "  ... {} more", differing.len() - LISTED_BYTES);
+                }
+            }
+            _ => (),
+        }
+    }
+    for b in b.iter().filter(|b| a.iter().all(|a| a.name != b.name)) {
+        let _ = writeln!(report, "{}: only in the second state", b.name);
+    }
+    if report.is_empty() {
+        report.push_str("States are identical\n");
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::LoadOptions;
+    use crate::gameboy::Gameboy;
+    use crate::memory_map::MemoryMap;
+    use crate::register::RegisterId::A;
+    use crate::state_diff::diff_states;
+
+    #[test]
+    fn reports_registers_and_memory() {
+        let mut gameboy = Gameboy::new(MemoryMap::blank());
+        let before = gameboy.save_state();
+        assert_eq!(
+            diff_states(&mut Gameboy::new(MemoryMap::blank()), &before, &before).unwrap(),
+            "States are identical\n"
+        );
+
+        let a = gameboy.reg[A].value;
+        gameboy.reg[A].value = 0x42;
+        gameboy.mem.wram[0x10] = 0x05;
+        let report = diff_states(
+            &mut Gameboy::new(MemoryMap::blank()),
+            &before,
+            &gameboy.save_state(),
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "A: {:02X} -> 42\nWRAM: 1 bytes differ\n  C010: 00 -> 05\n",
+                a
+            )
+        );
+        assert!(diff_states(
+            &mut Gameboy::new(MemoryMap::blank()),
+            &before,
+            &before[..100]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn names_mapper_registers() {
+        // MBC1 with 4 banks.
+        let mut rom = vec![0; 0x10000];
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x01;
+        let mem = MemoryMap::new(&rom, &"diff".to_owned(), &LoadOptions::default()).unwrap();
+        let mut gameboy = Gameboy::new(mem);
+        let before = gameboy.save_state();
+        gameboy.mem.cartridge.write(0x2000, 0x03);
+        let after = gameboy.save_state();
+        assert_eq!(
+            diff_states(&mut gameboy, &before, &after).unwrap(),
+            "BANK1: 01 -> 03\n"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use crate::font::draw_text;
+use crate::joypad::Button;
+use crate::state::{StateReader, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use std::fs::{metadata, read};
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const SLOTS: usize = 9;
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+const COLUMNS: usize = 3;
+const CELL_WIDTH: usize = WIDTH / COLUMNS;
+const CELL_HEIGHT: usize = HEIGHT / 3;
+// Thumbnails are drawn at half their stored size, to fit three to a row with a label under each.
+const SHOWN_WIDTH: usize = THUMBNAIL_WIDTH / 2;
+const SHOWN_HEIGHT: usize = THUMBNAIL_HEIGHT / 2;
+
+// What the player chose, with slots counted from 1 like their files.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Pick {
+    Load(usize),
+    Save(usize),
+    Close,
+}
+
+struct Slot {
+    thumbnail: Option<Vec<u32>>,
+    // How long ago the state was saved, if there is one.
+    age: Option<Duration>,
+}
+
+// A 3x3 grid of state slots, each with its thumbnail and age, driven with the Game Boy's own buttons: the
+// D-pad picks a slot, A loads it, Start saves over it and B closes the picker.
+pub struct StatePicker {
+    slots: Vec<Slot>,
+    selected: usize,
+    held: u8,
+}
+
+impl StatePicker {
+    // Takes every slot's file in order and the slot to start on.
+    pub fn open(paths: &[PathBuf], slot: usize) -> Self {
+        let slots = paths
+            .iter()
+            .map(|path| Slot {
+                thumbnail: read(path)
+                    .ok()
+                    .and_then(|state| StateReader::thumbnail(&state)),
+                age: metadata(path)
+                    .and_then(|file| file.modified())
+                    .ok()
+                    .map(|saved| saved.elapsed().unwrap_or_default()),
+            })
+            .collect::<Vec<_>>();
+        Self {
+            selected: (slot - 1).min(slots.len() - 1),
+            slots,
+            // Whatever is held as the picker opens has to be let go first.
+            held: 0xFF,
+        }
+    }
+
+    // Buttons act as they go down, so holding one doesn't race across the grid.
+    pub fn update(&mut self, buttons: u8) -> Option<Pick> {
+        let pressed = buttons & !self.held;
+        self.held = buttons;
+        let is_pressed = |button: Button| pressed & (1 << button as u8) != 0;
+        let rows = self.slots.len() / COLUMNS;
+        let (column, row) = (self.selected % COLUMNS, self.selected / COLUMNS);
+        if is_pressed(Button::Right) {
+            self.selected = row * COLUMNS + (column + 1) % COLUMNS;
+        }
+        if is_pressed(Button::Left) {
+            self.selected = row * COLUMNS + (column + COLUMNS - 1) % COLUMNS;
+        }
+        if is_pressed(Button::Down) {
+            self.selected = (row + 1) % rows * COLUMNS + column;
+        }
+        if is_pressed(Button::Up) {
+            self.selected = (row + rows - 1) % rows * COLUMNS + column;
+        }
+        let slot = self.selected + 1;
+        if is_pressed(Button::A) && self.slots[self.selected].age.is_some() {
+            Some(Pick::Load(slot))
+        } else if is_pressed(Button::Start) {
+            Some(Pick::Save(slot))
+        } else if is_pressed(Button::B) {
+            Some(Pick::Close)
+        } else {
+            None
+        }
+    }
+
+    // Empty slots and states from before thumbnails are a blank box.
+    pub fn draw(&self, palette: [u32; 4]) -> Vec<u32> {
+        let [light, _, mid, dark] = palette;
+        let mut pixels = vec![dark; WIDTH * HEIGHT];
+        for (i, slot) in self.slots.iter().enumerate() {
+            let x = i % COLUMNS * CELL_WIDTH + (CELL_WIDTH - SHOWN_WIDTH) / 2;
+            let y = i / COLUMNS * CELL_HEIGHT + 2;
+            if i == self.selected {
+                fill(
+                    &mut pixels,
+                    (x - 1, y - 1, SHOWN_WIDTH + 2, SHOWN_HEIGHT + 2),
+                    light,
+                );
+            }
+            fill(&mut pixels, (x, y, SHOWN_WIDTH, SHOWN_HEIGHT), mid);
+            if let Some(thumbnail) = &slot.thumbnail {
+                for row in 0..SHOWN_HEIGHT {
+                    for column in 0..SHOWN_WIDTH {
+                        pixels[(y + row) * WIDTH + x + column] =
+                            thumbnail[row * 2 * THUMBNAIL_WIDTH + column * 2];
+                    }
+                }
+            }
+            let age = slot.age.map_or("empty".to_owned(), age);
+            let label = format!("{} {}", i + 1, age);
+            draw_text(&mut pixels, WIDTH, x, y + SHOWN_HEIGHT + 2, &label, light);
+        }
+        pixels
+    }
+}
+
+fn age(age: Duration) -> String {
+    match age.as_secs() {
+        seconds @ 0..=59 => format!("{}s ago", seconds),
+        seconds @ 60..=3599 => format!("{}m ago", seconds / 60),
+        seconds @ 3600..=86399 => format!("{}h ago", seconds / 3600),
+        seconds => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn fill(pixels: &mut [u32], (x, y, width, height): (usize, usize, usize, usize), color: u32) {
+    for row in y..y + height {
+        pixels[row * WIDTH + x..row * WIDTH + x + width].fill(color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::StateWriter;
+    use crate::state_picker::{Pick, StatePicker, SLOTS};
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn picks_slots_from_a_grid() {
+        let paths = (1..=SLOTS)
+            .map(|slot| temp_dir().join(format!("feboy_picker_test.{}.state", slot)))
+            .collect::<Vec<_>>();
+        for path in &paths {
+            let _ = remove_file(path);
+        }
+        let screen = vec![7; 160 * 144];
+        write(&paths[5], StateWriter::with_thumbnail(0, &screen).finish()).unwrap();
+
+        let mut picker = StatePicker::open(&paths, 2);
+        // A held while opening does nothing until it's let go.
+        assert_eq!(picker.update(0b0001), None);
+        assert_eq!(picker.update(0b0000), None);
+        // Right from slot 2 is 3, then left twice is 1.
+        assert_eq!(picker.update(0b0001_0000), None);
+        assert_eq!(picker.update(0b0010_0000), None);
+        assert_eq!(picker.update(0b0000), None);
+        assert_eq!(picker.update(0b0010_0000), None);
+        // An empty slot has nothing to load, but can be saved into.
+        assert_eq!(picker.update(0b0001), None);
+        assert_eq!(picker.update(0b1000), Some(Pick::Save(1)));
+        assert_eq!(picker.update(0b0000), None);
+        // Left wraps around to 3, and down from there is 6.
+        assert_eq!(picker.update(0b0010_0000), None);
+        assert_eq!(picker.update(0b1000_0000), None);
+        assert_eq!(picker.update(0b0001), Some(Pick::Load(6)));
+        assert_eq!(picker.update(0b0010), Some(Pick::Close));
+
+        let pixels = picker.draw([1, 2, 3, 4]);
+        let at = |x: usize, y: usize| pixels[y * 160 + x];
+        // Slot 6 has its thumbnail and a highlight, slot 5 is blank.
+        assert_eq!(at(112, 50), 0xFF000007);
+        assert_eq!(at(111, 49), 1);
+        assert_eq!(at(59, 50), 3);
+        assert_eq!(at(0, 0), 4);
+        let _ = remove_file(&paths[5]);
+    }
+}
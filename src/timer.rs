@@ -1,3 +1,6 @@
+use crate::error::FeboyError;
+use crate::state::{StateReader, StateWriter};
+
 pub struct TimerInterrupt;
 
 pub struct Timer {
@@ -26,6 +29,25 @@ impl Timer {
         }
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8("TIMA", self.tima);
+        state.u8("TMA", self.tma);
+        state.u8("TAC", self.tac);
+        state.u16("timer ticks", self.ticks);
+        state.bool("timer interrupt", self.interrupt);
+        state.bool("timer interrupt served", self.interrupt_served);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), FeboyError> {
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()?;
+        self.ticks = state.u16()?;
+        self.interrupt = state.bool()?;
+        self.interrupt_served = state.bool()?;
+        Ok(())
+    }
+
     pub fn machine_cycle(&mut self) -> Option<TimerInterrupt> {
         self.interrupt_served = false;
         let interrupt = if self.interrupt {
@@ -0,0 +1,86 @@
+use crate::cartridge::BankedAddress;
+use crate::prelude::*;
+use core::fmt::Write;
+
+// About a second and a half of a busy game.
+pub const TRACE_LENGTH: usize = 100_000;
+
+// An instruction as it was fetched, with the registers it started from.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct TraceEntry {
+    pub pc: BankedAddress,
+    pub opcode: u8,
+    // A, F, B, C, D, E, H and L.
+    pub registers: [u8; 8],
+    pub sp: u16,
+}
+
+// The last instructions executed, overwriting the oldest once full, so there's always an answer to how the
+// CPU got where it is without logging every instruction to a file.
+pub struct Trace {
+    entries: Vec<TraceEntry>,
+    recorded: usize,
+}
+
+impl Trace {
+    pub fn new(length: usize) -> Self {
+        Self {
+            entries: vec![TraceEntry::default(); length],
+            recorded: 0,
+        }
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        let length = self.entries.len();
+        self.entries[self.recorded % length] = entry;
+        self.recorded += 1;
+    }
+
+    // Oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        let length = self.entries.len();
+        let start = self.recorded.saturating_sub(length);
+        (start..self.recorded).map(move |i| &self.entries[i % length])
+    }
+
+    // One instruction per line, e.g. "01:4A10 3E A=01 F=B0 B=00 C=13 D=00 E=D8 H=01 L=4D SP=FFFE".
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        for entry in self.entries() {
+            let _ = write!(dump, "{} {:02X}", entry.pc, entry.opcode);
+            for (name, value) in ["A", "F", "B", "C", "D", "E", "H", "L"]
+                .iter()
+                .zip(entry.registers)
+            {
+                let _ = write!(dump, " {}={:02X}", name, value);
+            }
+            let _ = writeln!(dump, " SP={:04X}", entry.sp);
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::BankedAddress;
+    use crate::trace::{Trace, TraceEntry};
+
+    #[test]
+    fn keeps_the_latest_instructions() {
+        let mut trace = Trace::new(3);
+        for address in 0x100..0x105 {
+            trace.record(TraceEntry {
+                pc: BankedAddress { bank: 1, address },
+                opcode: address as u8,
+                registers: [1, 0xB0, 0, 0x13, 0, 0xD8, 1, 0x4D],
+                sp: 0xFFFE,
+            });
+        }
+        let addresses = trace.entries().map(|entry| entry.pc.address);
+        assert_eq!(addresses.collect::<Vec<_>>(), [0x102, 0x103, 0x104]);
+        assert_eq!(
+            trace.dump().lines().next(),
+            Some("01:0102 02 A=01 F=B0 B=00 C=13 D=00 E=D8 H=01 L=4D SP=FFFE")
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::apu::SOUND_REGISTERS;
+use std::cmp::min;
+use std::fs::write;
+use std::path::PathBuf;
+
+const CLOCK: u64 = 4194304;
+const SAMPLE_RATE: u64 = 44100;
+const HEADER_SIZE: usize = 0x100;
+const VERSION: u32 = 0x161;
+const DMG_WRITE: u8 = 0xB3;
+const WAIT: u8 = 0x61;
+const END: u8 = 0x66;
+
+// Records sound register writes as a VGM 1.61 stream, with timestamps turned into 44.1kHz sample waits.
+pub struct VgmLog {
+    path: PathBuf,
+    commands: Vec<u8>,
+    cycles: u64,
+    samples: u64,
+}
+
+impl VgmLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            commands: vec![],
+            cycles: 0,
+            samples: 0,
+        }
+    }
+
+    pub fn machine_cycle(&mut self) {
+        self.cycles += 4;
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        self.wait();
+        self.commands
+            .extend([DMG_WRITE, (address - SOUND_REGISTERS.start()) as u8, value]);
+    }
+
+    pub fn finish(&mut self) {
+        let data = self.encode();
+        match write(&self.path, data) {
+            Ok(_) => println!("Wrote sound log {}", self.path.display()),
+            Err(e) => println!("Failed to write sound log {}: {}", self.path.display(), e),
+        }
+    }
+
+    fn wait(&mut self) {
+        let samples = self.cycles * SAMPLE_RATE / CLOCK;
+        let mut pending = samples - self.samples;
+        while pending > 0 {
+            let chunk = min(pending, 0xFFFF);
+            self.commands.push(WAIT);
+            self.commands.extend((chunk as u16).to_le_bytes());
+            pending -= chunk;
+        }
+        self.samples = samples;
+    }
+
+    fn encode(&mut self) -> Vec<u8> {
+        self.wait();
+        let mut data = vec![0; HEADER_SIZE];
+        data.extend(&self.commands);
+        data.push(END);
+        let header = [
+            (0x04, data.len() as u32 - 0x04),
+            (0x08, VERSION),
+            (0x18, self.samples as u32),
+            (0x34, HEADER_SIZE as u32 - 0x34),
+            (0x80, CLOCK as u32),
+        ];
+        data[..4].copy_from_slice(b"Vgm ");
+        for (offset, value) in header {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vgm::VgmLog;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_encode() {
+        let mut log = VgmLog::new(PathBuf::new());
+        log.write(0xFF26, 0x80);
+        for _ in 0..4389 {
+            log.machine_cycle();
+        }
+        log.write(0xFF30, 0x12);
+
+        let data = log.encode();
+        assert_eq!(&data[..4], b"Vgm ");
+        assert_eq!(data[0x04..0x08], (data.len() as u32 - 4).to_le_bytes());
+        assert_eq!(data[0x18..0x1C], 184_u32.to_le_bytes());
+        assert_eq!(
+            data[0x100..],
+            [0xB3, 0x16, 0x80, 0x61, 184, 0, 0xB3, 0x20, 0x12, 0x66]
+        );
+    }
+}
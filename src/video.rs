@@ -0,0 +1,102 @@
+use crate::gameboy::Gameboy;
+use crate::prelude::*;
+
+// A finished frame and what the frontend shows along with it.
+pub struct VideoFrame<'a> {
+    // Counted like the clock counts frames, so skipped frames leave gaps.
+    pub number: u64,
+    pub pixels: &'a [u32],
+    pub palette: [u32; 4],
+    // The buttons held as the frame finished, for the input display.
+    pub buttons: u8,
+}
+
+// Anything that takes the picture the PPU produces, e.g. the window or the frame hash stream. New outputs
+// implement this and are handed to deliver, without the PPU or the emulation loop knowing about them.
+pub trait VideoSink {
+    fn frame(&mut self, frame: &VideoFrame);
+
+    // Each line as soon as it's drawn, ahead of the rest of the frame. Most outputs only want whole frames.
+    fn scanline(&mut self, _line: u8, _pixels: &[u32]) {}
+}
+
+impl<T: VideoSink + ?Sized> VideoSink for Box<T> {
+    fn frame(&mut self, frame: &VideoFrame) {
+        (**self).frame(frame)
+    }
+
+    fn scanline(&mut self, line: u8, pixels: &[u32]) {
+        (**self).scanline(line, pixels)
+    }
+}
+
+// Several outputs at once, e.g. the window and a recording, each getting every frame in turn.
+impl<T: VideoSink> VideoSink for Vec<T> {
+    fn frame(&mut self, frame: &VideoFrame) {
+        self.iter_mut().for_each(|sink| sink.frame(frame));
+    }
+
+    fn scanline(&mut self, line: u8, pixels: &[u32]) {
+        self.iter_mut().for_each(|sink| sink.scanline(line, pixels));
+    }
+}
+
+// Hands the sink whatever the PPU finished since the last call. Sinks that want every scanline need this
+// after every step; whole frames only need it once a frame.
+pub fn deliver(gameboy: &mut Gameboy, sink: &mut dyn VideoSink) {
+    let ppu = &mut gameboy.mem.ppu;
+    if let Some(line) = ppu.take_scanline() {
+        let start = line as usize * 160;
+        sink.scanline(line, &ppu.pixels[start..start + 160]);
+    }
+    if let Some(pixels) = ppu.take_frame() {
+        sink.frame(&VideoFrame {
+            number: gameboy.clock().frames,
+            pixels: &pixels,
+            palette: gameboy.mem.ppu.palette(),
+            buttons: gameboy.mem.joypad.pressed(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gameboy::{step, Gameboy};
+    use crate::memory_map::MemoryMap;
+    use crate::video::{deliver, VideoFrame, VideoSink};
+
+    #[derive(Default)]
+    struct Counter {
+        lines: Vec<u8>,
+        frames: Vec<u64>,
+    }
+
+    impl VideoSink for Counter {
+        fn frame(&mut self, frame: &VideoFrame) {
+            assert_eq!(frame.pixels.len(), 160 * 144);
+            self.frames.push(frame.number);
+        }
+
+        fn scanline(&mut self, line: u8, pixels: &[u32]) {
+            assert_eq!(pixels.len(), 160);
+            self.lines.push(line);
+        }
+    }
+
+    #[test]
+    fn every_sink_gets_lines_and_frames() {
+        let mem = MemoryMap::blank();
+        let mut gameboy = Gameboy::new(mem);
+        let mut sinks = vec![Counter::default(), Counter::default()];
+        while sinks[0].frames.len() < 2 {
+            step(&mut gameboy).unwrap();
+            deliver(&mut gameboy, &mut sinks);
+        }
+        for sink in sinks {
+            assert_eq!(sink.frames, [1, 2]);
+            // Lines are drawn as the PPU moves onto them, which line 0 never is.
+            assert_eq!(sink.lines.len(), 2 * 143);
+            assert_eq!(sink.lines[..3], [1, 2, 3]);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+// How the picture fills a window of any shape. Whatever it doesn't cover is left black.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Scaling {
+    // The largest whole multiple of the Game Boy's resolution that fits, so every pixel is the same size.
+    Integer,
+    // As large as fits without changing the aspect ratio.
+    Aspect,
+    // The whole window, deformed if need be.
+    Stretch,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Scaling {
+    pub fn parse(value: &str) -> Option<Scaling> {
+        match value.to_lowercase().as_str() {
+            "integer" => Some(Scaling::Integer),
+            "aspect" => Some(Scaling::Aspect),
+            "stretch" => Some(Scaling::Stretch),
+            _ => None,
+        }
+    }
+
+    // Where the picture goes in a window of the given size. Windows smaller than the Game Boy's screen fall
+    // back from integer scaling to the aspect-correct fit.
+    pub fn viewport(&self, (window_width, window_height): (usize, usize)) -> Viewport {
+        let factor = (window_width / WIDTH).min(window_height / HEIGHT);
+        let (width, height) = match self {
+            Scaling::Integer if factor > 0 => (WIDTH * factor, HEIGHT * factor),
+            Scaling::Stretch => (window_width, window_height),
+            _ => {
+                let factor =
+                    (window_width as f64 / WIDTH as f64).min(window_height as f64 / HEIGHT as f64);
+                let width = (WIDTH as f64 * factor).round() as usize;
+                let height = (HEIGHT as f64 * factor).round() as usize;
+                (width.min(window_width), height.min(window_height))
+            }
+        };
+        Viewport {
+            x: (window_width - width) / 2,
+            y: (window_height - height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
+// Scales the picture into the viewport of a window sized buffer, nearest neighbour.
+pub fn blit(
+    source: &[u32],
+    (source_width, source_height): (usize, usize),
+    (window_width, window_height): (usize, usize),
+    viewport: &Viewport,
+) -> Vec<u32> {
+    let mut output = vec![0; window_width * window_height];
+    let columns = (0..viewport.width)
+        .map(|x| x * source_width / viewport.width)
+        .collect::<Vec<_>>();
+    for y in 0..viewport.height {
+        let source_row = &source[y * source_height / viewport.height * source_width..];
+        let start = (viewport.y + y) * window_width + viewport.x;
+        for (pixel, column) in output[start..start + viewport.width]
+            .iter_mut()
+            .zip(&columns)
+        {
+            *pixel = source_row[*column];
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::viewport::{blit, Scaling, Viewport};
+
+    #[test]
+    fn fits_the_picture_to_the_window() {
+        let viewport = |x, y, width, height| Viewport {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            Scaling::Integer.viewport((500, 300)),
+            viewport(90, 6, 320, 288)
+        );
+        assert_eq!(
+            Scaling::Aspect.viewport((500, 300)),
+            viewport(83, 0, 333, 300)
+        );
+        assert_eq!(
+            Scaling::Stretch.viewport((500, 300)),
+            viewport(0, 0, 500, 300)
+        );
+        assert_eq!(
+            Scaling::Integer.viewport((100, 90)),
+            viewport(0, 0, 100, 90)
+        );
+
+        let mut source = vec![1; 160 * 144];
+        source[160 * 144 - 1] = 2;
+        let output = blit(&source, (160, 144), (500, 300), &viewport(90, 6, 320, 288));
+        assert_eq!(output[6 * 500 + 89], 0);
+        assert_eq!(output[6 * 500 + 90], 1);
+        assert_eq!(output[293 * 500 + 409], 2);
+        assert_eq!(output[294 * 500 + 409], 0);
+        assert_eq!(Scaling::parse("Integer"), Some(Scaling::Integer));
+    }
+}
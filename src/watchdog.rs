@@ -0,0 +1,70 @@
+use crate::gameboy::Gameboy;
+use crate::interrupt::IE_ADDRESS;
+
+const STUCK_FRAMES: u32 = 3 * 60;
+const LOOP_SPAN: u16 = 0x10;
+
+// Flags a CPU that has spent several seconds with IME off either spinning within a few bytes of code,
+// halted with nothing in IE to wake it, or locked up by an undefined opcode.
+pub struct Watchdog {
+    frames: u32,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self { frames: 0 }
+    }
+
+    // Returns the stuck PC once per lock-up, on the frame it crosses the threshold.
+    pub fn update(&mut self, gameboy: &mut Gameboy) -> Option<u16> {
+        let range = gameboy.take_pc_range();
+        let stuck = !gameboy.ime
+            && match range {
+                Some((low, high)) => high - low < LOOP_SPAN,
+                None => {
+                    let ie = gameboy.mem.interrupt_handler.read(IE_ADDRESS);
+                    gameboy.locked() || (gameboy.halted && ie.unwrap_or(0) & 0x1F == 0)
+                }
+            };
+        self.frames = if stuck { self.frames + 1 } else { 0 };
+        if self.frames != STUCK_FRAMES {
+            return None;
+        }
+        Some(range.map_or(gameboy.reg.pc.value(), |(low, _)| low))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::LoadOptions;
+    use crate::gameboy::{step, Gameboy};
+    use crate::memory_map::MemoryMap;
+    use crate::watchdog::{Watchdog, STUCK_FRAMES};
+
+    fn stuck_after(program: &[u8]) -> Option<u32> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mut gameboy = Gameboy::new(
+            MemoryMap::new(&rom, &"watchdog".to_owned(), &LoadOptions::default()).unwrap(),
+        );
+        let mut watchdog = Watchdog::new();
+        for frame in 1..=STUCK_FRAMES + 1 {
+            // A short burst per update keeps the test fast, the watchdog only counts calls.
+            for _ in 0..100 {
+                step(&mut gameboy).unwrap();
+            }
+            if watchdog.update(&mut gameboy) == Some(0x101) {
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn flags_tight_loops_with_interrupts_off() {
+        // DI; JR -2
+        assert_eq!(stuck_after(&[0xF3, 0x18, 0xFE]), Some(STUCK_FRAMES));
+        // EI; JR -2
+        assert_eq!(stuck_after(&[0xFB, 0x18, 0xFE]), None);
+    }
+}
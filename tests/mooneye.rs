@@ -0,0 +1,38 @@
+use feboy::cartridge::LoadOptions;
+use feboy::gameboy::{step, Gameboy};
+use feboy::memory_map::MemoryMap;
+use feboy::register::RegisterId::{B, C, D, E, H, L};
+use std::fs::{read, read_to_string};
+use std::path::Path;
+
+const MANIFEST: &str = "tests/mooneye.txt";
+const FRAME_CYCLES: u64 = 70224;
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+// Mooneye's test ROMs report through the registers rather than the screen, which lets the mapper tests
+// check every bank they switch to without a reference screenshot.
+fn registers(rom_name: &str, frames: u64) -> [u8; 6] {
+    let rom = read(Path::new("test_rom").join(rom_name)).unwrap();
+    let mut gameboy =
+        Gameboy::new(MemoryMap::new(&rom, &rom_name.to_owned(), &LoadOptions::default()).unwrap());
+    while gameboy.clock().cycles < frames * FRAME_CYCLES {
+        step(&mut gameboy).unwrap();
+    }
+    [B, C, D, E, H, L].map(|id| gameboy.reg[id].value)
+}
+
+#[test]
+fn mooneye_roms_pass() {
+    let failures = read_to_string(MANIFEST)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (rom, frames) = line.rsplit_once(' ').unwrap();
+            let registers = registers(rom, frames.parse().unwrap());
+            Some(format!("{}: registers {:?}", rom, registers)).filter(|_| registers != PASSED)
+        })
+        .collect::<Vec<_>>();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,91 @@
+use feboy::cartridge::LoadOptions;
+use feboy::gameboy::{step, Gameboy};
+use feboy::memory_map::MemoryMap;
+use image::io::Reader;
+use image::RgbaImage;
+use std::fs::{read, read_to_string};
+use std::path::Path;
+
+const MANIFEST: &str = "tests/screenshots.txt";
+const FRAME_CYCLES: u64 = 70224;
+
+struct Case {
+    rom: String,
+    frames: u64,
+    tolerance: usize,
+}
+
+// Each line names a ROM in test_rom, how many frames to run it for and how many pixels may differ from its
+// reference screenshot in test_ok.
+fn cases() -> Vec<Case> {
+    read_to_string(MANIFEST)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.rsplitn(3, ' ');
+            let tolerance = fields.next().unwrap().parse().unwrap();
+            let frames = fields.next().unwrap().parse().unwrap();
+            let rom = fields.next().unwrap().to_owned();
+            Case {
+                rom,
+                frames,
+                tolerance,
+            }
+        })
+        .collect()
+}
+
+// Frames are counted in cycles rather than V-blanks so ROMs that turn the LCD off still finish.
+fn screenshot(case: &Case) -> Vec<u8> {
+    let rom = read(Path::new("test_rom").join(&case.rom)).unwrap();
+    let mut gameboy =
+        Gameboy::new(MemoryMap::new(&rom, &case.rom, &LoadOptions::default()).unwrap());
+    while gameboy.clock().cycles < case.frames * FRAME_CYCLES {
+        step(&mut gameboy).unwrap();
+    }
+    gameboy
+        .mem
+        .ppu
+        .pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [a, r, g, b] = pixel.to_be_bytes();
+            [r, g, b, a]
+        })
+        .collect()
+}
+
+#[test]
+fn screenshots_match_references() {
+    let mut failures = vec![];
+    for case in cases() {
+        let actual = screenshot(&case);
+        let reference = Path::new("test_ok").join(format!("{}.png", case.rom));
+        let expected = Reader::open(&reference)
+            .unwrap()
+            .decode()
+            .unwrap()
+            .to_rgba8();
+        let differing = actual
+            .chunks(4)
+            .zip(expected.as_raw().chunks(4))
+            .filter(|(actual, expected)| actual != expected)
+            .count();
+        if differing > case.tolerance {
+            let output = Path::new("test_output").join(format!("{}.png", case.rom));
+            RgbaImage::from_raw(160, 144, actual)
+                .unwrap()
+                .save(&output)
+                .unwrap();
+            failures.push(format!(
+                "{}: {} pixels differ, see {}",
+                case.rom,
+                differing,
+                output.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}